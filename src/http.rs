@@ -0,0 +1,100 @@
+use anyhow::{bail, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Bodies accepted by the servers without a limit of their own
+pub const DEFAULT_MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+/// Bytes of the request line and the headers
+const MAX_HEAD_BYTES: u64 = 64 * 1024;
+/// Time a client has to send each part of its request
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request whose `Content-Length` is over the limit of the server, answered with 413
+#[derive(Error, Debug)]
+#[error("Request body of {0} bytes is over the limit of {1} bytes")]
+pub struct BodyTooLarge(pub usize, pub usize);
+
+/// Minimal HTTP/1.1 handling for the small servers of the proxy. Each connection carries a
+/// single request.
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// Value of the `Authorization` header
+    pub authorization: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Clients can not make the server wait forever or allocate more than `max_body_bytes` for the
+    /// body
+    pub fn read(stream: &TcpStream, max_body_bytes: usize) -> Result<Self> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?.take(MAX_HEAD_BYTES));
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut content_length = 0;
+        let mut authorization = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 {
+                bail!("Request ended before its headers");
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse()?;
+                } else if name.eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                }
+            }
+        }
+        if content_length > max_body_bytes {
+            return Err(BodyTooLarge(content_length, max_body_bytes).into());
+        }
+        reader.get_mut().set_limit(content_length as u64);
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let mut request_line = request_line.split_whitespace();
+        Ok(Self {
+            method: request_line.next().unwrap_or_default().to_string(),
+            path: request_line.next().unwrap_or_default().to_string(),
+            authorization,
+            body,
+        })
+    }
+
+    /// If the request has the `Bearer` token. Tokens are compared in constant time, so they can
+    /// not be guessed by timing the answers.
+    pub fn has_bearer_token(&self, token: &str) -> bool {
+        let expected = format!("Bearer {token}");
+        self.authorization.as_ref().is_some_and(|authorization| {
+            authorization.len() == expected.len()
+                && authorization
+                    .bytes()
+                    .zip(expected.bytes())
+                    .fold(0, |difference, (left, right)| difference | (left ^ right))
+                    == 0
+        })
+    }
+
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.trim_start_matches('/').split('/').collect()
+    }
+}
+
+pub fn write_response(stream: &mut TcpStream, status: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(stream.flush()?)
+}
@@ -1,29 +1,363 @@
+use encoding_rs::{Encoding, UTF_8};
 use msql_srv::*;
 use mysql_query_executor_backend::Backend;
 use postgres_query_executor_backend::PostgresBackend;
 use postgres_shim::PostgressIntermediary;
 use query_executor::{
-    DataTypeInfo, InMemoryQueryStorage, QueryAccumulator, QueryCache, QueryDataType, QueryExecutor,
-    QueryFilter, QueryResult, QuerySanitizer, RunopsApi,
+    deserialize_query_patterns, export_snapshot, import_snapshot, load_cache_list, log_dialect,
+    loggable, next_connection_id, query_pattern, redact_logs, AuditLog, AuditedConnection,
+    CacheOptions, CacheRules, CacheStats, Compression, DataTypeInfo, FileQueryStorage, FilterRule,
+    GeometryFormat, Guardrails, IdentifierCase, InMemoryQueryStorage, Masking, QueryAccessControl,
+    QueryAccumulator, QueryAuditor, QueryCache, QueryDataType, QueryExecutor, QueryFilter,
+    QueryGuardrails, QueryMasker, QueryPaginator, QueryResult, QueryRewriter, QueryRowGuard,
+    QuerySanitizer, QueryStorageServer, QueryTransaction, QueryTypeSniffer, QueryVariables,
+    Refresher, RemoteQueryStorage, ResultFormat, ResultLayout, Rewrite, RunopsApi, SchemaLoader,
+    SessionState, SharedDataTypeInfo, SharedQueryStorage, SqlDialect, TransactionMode,
+    TypeStrictness, DEFAULT_MAX_SET_STATEMENTS, DEFAULT_NULL_TOKEN,
 };
+use regex::Regex;
 use serde::Deserialize;
-use sqlparser::dialect::MySqlDialect;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::Duration;
 
+mod http;
+#[cfg(feature = "mock-server")]
+mod mock_server;
 mod mysql_query_executor_backend;
 mod postgres_query_executor_backend;
 mod query_executor;
 
 #[derive(Deserialize)]
+#[serde(untagged)]
+enum YamlConfigFile {
+    Listeners(Vec<YamlTargetConfig>),
+    Config(YamlConfig),
+}
+
+#[derive(Deserialize)]
+struct YamlConfig {
+    /// Default cache ttl, in seconds, for listeners that do not configure one
+    cache_ttl: Option<u64>,
+    /// Queries are logged without their literals
+    redact_logs: Option<bool>,
+    listeners: Vec<YamlTargetConfig>,
+}
+
+impl From<YamlConfigFile> for YamlConfig {
+    fn from(config_file: YamlConfigFile) -> Self {
+        match config_file {
+            YamlConfigFile::Listeners(listeners) => YamlConfig {
+                cache_ttl: None,
+                redact_logs: None,
+                listeners,
+            },
+            YamlConfigFile::Config(config) => config,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
 struct YamlTargetConfig {
     port: u16,
     target: String,
     with_type_discovery: Option<bool>,
     query_cache: Option<Vec<String>>,
     target_type: Option<TargetType>,
+    runops_api_url: Option<String>,
+    cache_file: Option<String>,
+    cache_ttl: Option<u64>,
+    cache_max_bytes: Option<usize>,
+    cache_all: Option<bool>,
+    warm_cache: Option<bool>,
+    cache_scope: Option<CacheScope>,
+    negative_cache_ttl: Option<u64>,
+    refresh_mode: Option<RefreshMode>,
+    cache_max_entry_rows: Option<usize>,
+    cache_max_entry_bytes: Option<usize>,
+    cache_spill_bytes: Option<usize>,
+    /// Directory of the snapshots of `FAKESQL CACHE EXPORT` and `IMPORT`
+    cache_snapshot_dir: Option<String>,
+    cache_prepared_statements: Option<bool>,
+    cache_max_entries_per_template: Option<usize>,
+    cache_compression: Option<Compression>,
+    cache_provenance_column: Option<bool>,
+    cache_high_water_bytes: Option<usize>,
+    /// Seconds between logs of the cache memory usage
+    cache_stats_interval: Option<u64>,
+    /// Address where the query storage of this listener is exposed to other instances
+    cache_server: Option<String>,
+    /// Url of another instance's `cache_server`, used instead of a local query storage
+    remote_cache: Option<String>,
+    /// Bearer token required by `cache_server` and sent to `remote_cache`
+    cache_token: Option<String>,
+    /// File where the discovered database structure is saved
+    schema_file: Option<String>,
+    /// Seconds the structure of `schema_file` is used before being discovered again
+    schema_max_age: Option<u64>,
+    /// Seconds between discoveries of the database structure in the background
+    schema_refresh_interval: Option<u64>,
+    /// What to do with result columns of `with_type_discovery` whose type is unknown
+    type_strictness: Option<TypeStrictness>,
+    /// How values of spatial columns are answered with `with_type_discovery`
+    geometry_format: Option<GeometryFormat>,
+    /// Names of `binary(16)` result columns answered as uuids with `with_type_discovery`
+    uuid_columns: Option<Vec<String>>,
+    /// How identifiers of queries are matched with the discovered database structure
+    identifier_case: Option<IdentifierCase>,
+    /// Rows of each result used to guess the types of its columns without `with_type_discovery`
+    type_sniffing_rows: Option<usize>,
+    /// Selects are run in pages of this many rows
+    page_size: Option<usize>,
+    /// Selects without `LIMIT` are limited to this many rows
+    max_rows_guard: Option<usize>,
+    /// Queries longer than this many characters are rejected
+    max_query_length: Option<usize>,
+    /// Queries with more joins than this are rejected
+    max_joins: Option<usize>,
+    /// Queries combining more selects than this with `UNION` are rejected
+    max_union_branches: Option<usize>,
+    /// Tables, like `schema.table`, that can not be read without a `WHERE` or a `LIMIT`, nor
+    /// updated or deleted without a `WHERE`
+    require_where: Option<Vec<String>>,
+    /// If versioned comments of MySQL, like `/*!40101 SET NAMES utf8 */`, are sent to the target.
+    /// It defaults to true.
+    forward_versioned_comments: Option<bool>,
+    /// SET statements of a connection replayed before each query. The oldest are forgotten first.
+    max_session_statements: Option<usize>,
+    /// Statements without a result answered with an OK packet, like the entries of `query_cache`
+    #[serde(default, deserialize_with = "deserialize_query_patterns")]
+    acknowledged_statements: Option<Vec<Regex>>,
+    /// Values of system variables, like `version`, answered without running the query
+    session_variables: Option<HashMap<String, String>>,
+    /// Columns whose values are masked in the results, by `schema.table.column`
+    masked_columns: Option<HashMap<String, Masking>>,
+    /// Key of the hashes of the columns masked with `hash`
+    masking_secret: Option<String>,
+    /// Replacements of system variables the target does not have, like `@@language`
+    variable_substitutions: Option<HashMap<String, String>>,
+    /// File where every statement of the clients is appended, one JSON object per line
+    audit_log: Option<String>,
+    /// If the queries sent to the target start with a comment naming the connection. It defaults
+    /// to false.
+    annotate_queries: Option<bool>,
+    /// Schemas the clients can use. Every schema can be used without it.
+    allow_schemas: Option<Vec<String>>,
+    /// Tables the clients can not use, as `table` or `schema.table`
+    deny_tables: Option<Vec<String>>,
+    /// What is done with `BEGIN`, `COMMIT` and `ROLLBACK`
+    transaction_mode: Option<TransactionMode>,
+    /// Queries answered without running them, with a canned result or no result
+    filter_rules: Option<Vec<FilterRule>>,
+    /// Rewrites of the constructs the target can not run, applied in order to every query
+    rewrites: Option<Vec<Rewrite>>,
+    /// Dialect used to parse queries. It defaults to the one of `target_type`.
+    dialect: Option<SqlDialect>,
+    /// Return types of functions, like user defined ones, by their name
+    function_types: Option<HashMap<String, String>>,
+    /// How the target writes results. It defaults to the one of `target_type`.
+    result_format: Option<ResultFormat>,
+    /// Value that the target writes for NULL
+    null_token: Option<String>,
+    /// Between the fields of results. It defaults to the one of `result_format`.
+    delimiter: Option<char>,
+    /// If results start with a line with the names of their columns. It defaults to true.
+    has_header: Option<bool>,
+    /// Lines before the header of results
+    skip_lines: Option<usize>,
+    /// Encoding of the output of the target, like `latin1` or `cp1251`
+    output_encoding: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum RefreshMode {
+    /// Expired results are executed again before answering
+    Blocking,
+    /// Expired results are answered right away and refreshed in the background
+    StaleWhileRevalidate,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum CacheScope {
+    Global,
+    PerTarget,
+    PerListener,
+}
+
+impl YamlTargetConfig {
+    fn cache_options(&self) -> CacheOptions {
+        CacheOptions {
+            ttl: self.cache_ttl.map(Duration::from_secs),
+            cache_all: self.cache_all.unwrap_or(false),
+            negative_ttl: self.negative_cache_ttl.map(Duration::from_secs),
+            refresher: None,
+            max_entry_rows: self.cache_max_entry_rows,
+            max_entry_bytes: self.cache_max_entry_bytes,
+            cache_prepared_statements: self.cache_prepared_statements.unwrap_or(false),
+            max_entries_per_template: self.cache_max_entries_per_template,
+            provenance_column: self.cache_provenance_column.unwrap_or(false),
+            schema_version: None,
+            spill_bytes: self.cache_spill_bytes,
+            snapshot_dir: self.cache_snapshot_dir.as_ref().map(PathBuf::from),
+        }
+    }
+
+    /// Listeners with the same key share their query storage
+    fn cache_scope_key(&self) -> String {
+        match self.cache_scope.unwrap_or(CacheScope::PerListener) {
+            CacheScope::Global => "global".to_string(),
+            CacheScope::PerTarget => format!("target {}", self.target),
+            CacheScope::PerListener => format!("port {}", self.port),
+        }
+    }
+
+    /// Discovers the database structure, unless it was saved to `schema_file` recently
+    fn data_type_info(&self, runops_api: &mut RunopsApi) -> DataTypeInfo {
+        if let Some(path) = &self.schema_file {
+            let max_age = self.schema_max_age.map(Duration::from_secs);
+            match DataTypeInfo::read(path, &self.target, max_age) {
+                Ok(Some(data_type_info)) => return self.with_settings(data_type_info),
+                Ok(None) => {}
+                Err(error) => println!("Ignoring schema file {path}: {error}"),
+            }
+        }
+        let data_type_info = DataTypeInfo::load(runops_api, self.dialect().dialect().as_ref())
+            .expect("Error loading datatype");
+        self.save_data_type_info(&data_type_info);
+        self.with_settings(data_type_info)
+    }
+
+    /// Settings of the listener that are not part of the database structure
+    fn with_settings(&self, data_type_info: DataTypeInfo) -> DataTypeInfo {
+        data_type_info
+            .with_identifier_case(self.identifier_case())
+            .with_function_types(self.function_types.clone().unwrap_or_default())
+    }
+
+    fn dialect(&self) -> SqlDialect {
+        self.dialect.unwrap_or(match self.target_type {
+            Some(TargetType::Postgres) => SqlDialect::Postgres,
+            _ => SqlDialect::MySql,
+        })
+    }
+
+    /// It defaults to UTF-8
+    fn output_encoding(&self) -> &'static Encoding {
+        match &self.output_encoding {
+            Some(label) => Encoding::for_label(label.as_bytes())
+                .unwrap_or_else(|| panic!("Unknown output_encoding {}", label)),
+            None => UTF_8,
+        }
+    }
+
+    /// Every SET statement by default
+    fn acknowledged_statements(&self) -> Vec<Regex> {
+        self.acknowledged_statements.clone().unwrap_or_else(|| {
+            vec![query_pattern(r"regex:(?i)SET\s.*").expect("Invalid default pattern")]
+        })
+    }
+
+    fn masker<T>(&self, executor: T) -> QueryMasker<T> {
+        QueryMasker::new(
+            executor,
+            self.dialect(),
+            self.masked_columns.clone().unwrap_or_default(),
+        )
+        .with_secret(self.masking_secret.clone().unwrap_or_default())
+    }
+
+    fn access_control<T>(&self, executor: T, session_state: SessionState) -> QueryAccessControl<T> {
+        QueryAccessControl::new(executor, self.dialect(), session_state)
+            .with_allow_schemas(self.allow_schemas.clone())
+            .with_deny_tables(self.deny_tables.clone().unwrap_or_default())
+    }
+
+    fn guardrails<T>(&self, executor: T) -> QueryGuardrails<T> {
+        QueryGuardrails::new(
+            executor,
+            self.dialect(),
+            Guardrails {
+                max_query_length: self.max_query_length,
+                max_joins: self.max_joins,
+                max_union_branches: self.max_union_branches,
+            },
+        )
+        .with_require_where(self.require_where.clone().unwrap_or_default())
+    }
+
+    fn rewriter<T>(&self, executor: T) -> QueryRewriter<T> {
+        QueryRewriter::new(
+            executor,
+            self.dialect().dialect(),
+            self.rewrites.clone().unwrap_or_default(),
+        )
+    }
+
+    /// Postgres folds unquoted identifiers to lowercase, MySQL ignores their case
+    fn identifier_case(&self) -> IdentifierCase {
+        self.identifier_case.unwrap_or(match self.target_type {
+            Some(TargetType::Postgres) => IdentifierCase::Lowercase,
+            _ => IdentifierCase::Insensitive,
+        })
+    }
+
+    fn shared_data_type_info(&self, runops_api: &mut RunopsApi) -> SharedDataTypeInfo {
+        Arc::new(RwLock::new(self.data_type_info(runops_api)))
+    }
+
+    /// Discovers the database structure again, ignoring and replacing `schema_file`
+    fn schema_loader(&self) -> SchemaLoader {
+        let config = self.clone();
+        Arc::new(move || {
+            let data_type_info = DataTypeInfo::load(
+                &mut new_runops_api(&config),
+                config.dialect().dialect().as_ref(),
+            )?;
+            config.save_data_type_info(&data_type_info);
+            Ok(config.with_settings(data_type_info))
+        })
+    }
+
+    fn save_data_type_info(&self, data_type_info: &DataTypeInfo) {
+        if let Some(path) = &self.schema_file {
+            if let Err(error) = data_type_info.save(path, &self.target) {
+                println!("Error saving schema file {path}: {error}");
+            }
+        }
+    }
+
+    fn query_storage(&self) -> SharedQueryStorage {
+        if self.cache_all == Some(true)
+            && self.cache_ttl.is_none()
+            && self.cache_max_bytes.is_none()
+        {
+            println!(
+                "Listener {} caches every select without cache_ttl or cache_max_bytes. The cache will grow forever",
+                self.port
+            );
+        }
+        if let Some(url) = &self.remote_cache {
+            return Arc::new(RemoteQueryStorage::new(
+                url,
+                self.cache_token.as_deref().unwrap_or_default(),
+            ));
+        }
+        let memory = InMemoryQueryStorage::with_max_bytes(self.cache_max_bytes)
+            .with_high_water_bytes(self.cache_high_water_bytes)
+            .with_compression(self.cache_compression);
+        match &self.cache_file {
+            Some(path) => Arc::new(
+                FileQueryStorage::load(path, memory).expect("Error loading the query cache file"),
+            ),
+            None => Arc::new(memory),
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, PartialEq)]
@@ -33,132 +367,577 @@ enum TargetType {
 }
 
 fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        #[cfg(feature = "mock-server")]
+        Some("mock-server") => mock_server::run(&args[1..]),
+        Some("cache") => run_cache_command(&args[1..]),
+        Some("schema") => run_schema_command(&args[1..]),
+        _ => run_proxy(),
+    }
+}
+
+fn load_config() -> std::io::Result<YamlConfig> {
     let file = File::open("config.yml")?;
-    let configs: Vec<YamlTargetConfig> = serde_yaml::from_reader(file).expect("Error parsing yaml");
-    for config in configs {
-        thread::spawn(move || tcp_listener(config));
+    let config_file: YamlConfigFile = serde_yaml::from_reader(file).expect("Error parsing yaml");
+    Ok(YamlConfig::from(config_file))
+}
+
+/// Exports or imports a snapshot of the cache file of a listener. Without a port, the first
+/// listener with a `cache_file` is used.
+fn run_cache_command(args: &[String]) -> std::io::Result<()> {
+    let usage = "Usage: cache <export|import> <file> [port]";
+    let (command, path) = match args {
+        [command, path, ..] => (command.as_str(), path),
+        _ => {
+            println!("{usage}");
+            return Ok(());
+        }
+    };
+    let port = args
+        .get(2)
+        .map(|port| port.parse::<u16>().expect("Invalid port"));
+    let config = load_config()?;
+    let listener = config
+        .listeners
+        .iter()
+        .find(|listener| {
+            listener.cache_file.is_some() && (port.is_none() || port == Some(listener.port))
+        })
+        .expect("No listener with a cache_file configured");
+    let storage = listener.query_storage();
+    let result = match command {
+        "export" => export_snapshot(&storage, path)
+            .map(|exported| println!("Exported {exported} cache entries to {path}")),
+        "import" => import_snapshot(&storage, path)
+            .map(|imported| println!("Imported {imported} cache entries from {path}")),
+        _ => {
+            println!("{usage}");
+            return Ok(());
+        }
+    };
+    if let Err(error) = result {
+        println!("Error running cache {command}: {error}");
+    }
+    Ok(())
+}
+
+/// Discovers the database structure again for the listeners with a `schema_file`, or only the
+/// one of the port
+fn run_schema_command(args: &[String]) -> std::io::Result<()> {
+    if args.first().map(String::as_str) != Some("refresh") {
+        println!("Usage: schema refresh [port]");
+        return Ok(());
+    }
+    let port = args
+        .get(1)
+        .map(|port| port.parse::<u16>().expect("Invalid port"));
+    let config = load_config()?;
+    for listener in config.listeners.iter().filter(|listener| {
+        listener.schema_file.is_some() && (port.is_none() || port == Some(listener.port))
+    }) {
+        let data_type_info = DataTypeInfo::load(
+            &mut new_runops_api(listener),
+            listener.dialect().dialect().as_ref(),
+        )
+        .expect("Error loading datatype");
+        listener.save_data_type_info(&data_type_info);
+        println!("Saved the database structure of port {}", listener.port);
+    }
+    Ok(())
+}
+
+fn run_proxy() -> std::io::Result<()> {
+    let config = load_config()?;
+    redact_logs(config.redact_logs.unwrap_or(false));
+    let mut query_storages: HashMap<String, SharedQueryStorage> = HashMap::new();
+    for mut listener_config in config.listeners {
+        listener_config.cache_ttl = listener_config.cache_ttl.or(config.cache_ttl);
+        let hashes_columns = listener_config
+            .masked_columns
+            .iter()
+            .flatten()
+            .any(|(_, masking)| *masking == Masking::Hash);
+        assert!(
+            !hashes_columns || listener_config.masking_secret.is_some(),
+            "The listener of port {} masks columns with hash without a masking_secret",
+            listener_config.port
+        );
+        assert!(
+            (listener_config.cache_server.is_none() && listener_config.remote_cache.is_none())
+                || listener_config.cache_token.is_some(),
+            "The listener of port {} shares its cache without a cache_token",
+            listener_config.port
+        );
+        let query_storage = query_storages
+            .entry(listener_config.cache_scope_key())
+            .or_insert_with(|| listener_config.query_storage())
+            .clone();
+        if let Some(interval) = listener_config.cache_stats_interval {
+            let port = listener_config.port;
+            let query_storage = query_storage.clone();
+            thread::spawn(move || log_cache_stats(port, query_storage, interval));
+        }
+        if let Some(address) = &listener_config.cache_server {
+            let token = listener_config.cache_token.as_deref().unwrap_or_default();
+            let server = QueryStorageServer::bind(address, query_storage.clone(), token)?
+                .with_cache_max_bytes(listener_config.cache_max_bytes);
+            println!("Cache server listening on {}", server.local_addr()?);
+            thread::spawn(move || server.run());
+        }
+        thread::spawn(move || tcp_listener(listener_config, query_storage));
     }
     loop {
         thread::park();
     }
 }
 
-fn tcp_listener(config: YamlTargetConfig) -> std::io::Result<()> {
+fn log_cache_stats(port: u16, storage: SharedQueryStorage, interval: u64) {
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        let stats = CacheStats::of(&storage);
+        println!(
+            "Cache of port {port} uses {} bytes with {} entries and {} rows",
+            stats.bytes, stats.entries, stats.rows
+        );
+    }
+}
+
+fn tcp_listener(
+    config: YamlTargetConfig,
+    query_storage: SharedQueryStorage,
+) -> std::io::Result<()> {
+    log_dialect(config.dialect());
     let error_msg = format!("Error binding to port {}", config.port);
     let listener = TcpListener::bind(format!("127.0.0.1:{}", config.port)).expect(&error_msg);
-    let queries_connection_cache: HashSet<String> = match &config.query_cache {
-        None => HashSet::new(),
-        Some(paths) => paths
-            .iter()
-            .flat_map(|path| {
-                std::fs::read_to_string(path)
-                    .expect("")
-                    .split("|\n")
-                    .map(|query| query.to_string())
-                    .collect::<Vec<String>>()
-            })
-            .collect(),
+    let queries_connection_cache = match &config.query_cache {
+        None => CacheRules::default(),
+        Some(paths) => {
+            let rules = paths
+                .iter()
+                .flat_map(|path| load_cache_list(path).expect("Error reading the query cache"));
+            CacheRules::from_rules(rules).expect("Invalid pattern in the query cache")
+        }
     };
 
-    let query_storage = InMemoryQueryStorage::new();
-    let mut data_type_info = None;
+    // Loaded once and shared by every connection of the listener
+    let data_type_info = (config.with_type_discovery == Some(true)
+        || config.target_type == Some(TargetType::Postgres))
+    .then(|| config.shared_data_type_info(&mut new_runops_api(&config)));
+    if let (Some(data_type_info), Some(interval)) =
+        (&data_type_info, config.schema_refresh_interval)
+    {
+        let schema_loader = config.schema_loader();
+        let data_type_info = data_type_info.clone();
+        thread::spawn(move || refresh_schema(schema_loader, data_type_info, interval));
+    }
+    if let Some(true) = config.warm_cache {
+        let config = config.clone();
+        let queries_connection_cache = queries_connection_cache.clone();
+        let query_storage = query_storage.clone();
+        let data_type_info = data_type_info.clone();
+        thread::spawn(move || {
+            warm_cache(
+                &config,
+                queries_connection_cache,
+                query_storage,
+                data_type_info,
+            )
+        });
+    }
+    let cache_options = CacheOptions {
+        refresher: match config.refresh_mode {
+            Some(RefreshMode::StaleWhileRevalidate) => Some(background_refresher(
+                &config,
+                queries_connection_cache.clone(),
+                query_storage.clone(),
+                data_type_info.clone(),
+            )),
+            _ => None,
+        },
+        ..config.cache_options()
+    };
+    let audit_log = config
+        .audit_log
+        .as_ref()
+        .map(|path| AuditLog::open(path).expect("Error opening the audit log"));
     while let Ok((s, _)) = listener.accept() {
         spawn_sql_processor(
             &config,
             s,
             queries_connection_cache.clone(),
             query_storage.clone(),
-            &mut data_type_info,
-        )
+            cache_options.clone(),
+            data_type_info.clone(),
+            audit_log.clone(),
+        );
     }
     Ok(())
 }
 
+fn refresh_schema(schema_loader: SchemaLoader, data_type_info: SharedDataTypeInfo, interval: u64) {
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        match schema_loader() {
+            Ok(refreshed) => *data_type_info.write().unwrap() = refreshed,
+            Err(error) => println!("Error refreshing the database structure: {error}"),
+        }
+    }
+}
+
+/// Refreshes expired results in a new thread, with its own connection to Runops. Queries that
+/// are already being refreshed are ignored.
+fn background_refresher(
+    config: &YamlTargetConfig,
+    queries_connection_cache: CacheRules,
+    storage: SharedQueryStorage,
+    data_type_info: Option<SharedDataTypeInfo>,
+) -> Refresher {
+    let config = config.clone();
+    let refreshing = Arc::new(Mutex::new(HashSet::new()));
+    Arc::new(move |query: String, statements: Vec<String>| {
+        let refreshed = (query.clone(), statements.clone());
+        if !refreshing.lock().unwrap().insert(refreshed.clone()) {
+            return;
+        }
+        let config = config.clone();
+        let queries_connection_cache = queries_connection_cache.clone();
+        let storage = storage.clone();
+        let refreshing = refreshing.clone();
+        let data_type_info = data_type_info.clone();
+        thread::spawn(move || {
+            log_dialect(config.dialect());
+            let cache_options = config.cache_options();
+            let session_state = SessionState::default()
+                .with_max_set_statements(
+                    config
+                        .max_session_statements
+                        .unwrap_or(DEFAULT_MAX_SET_STATEMENTS),
+                )
+                .with_statements(&statements);
+            let result = match (config.with_type_discovery, data_type_info) {
+                (Some(true), Some(data_type_info)) => construct_query_executor_with_data_type(
+                    &config,
+                    queries_connection_cache,
+                    storage,
+                    cache_options,
+                    session_state,
+                    data_type_info,
+                )
+                .refresh(&query),
+                _ => construct_query_executor(
+                    &config,
+                    queries_connection_cache,
+                    storage,
+                    cache_options,
+                    session_state,
+                )
+                .refresh(&query),
+            };
+            if let Err(error) = result {
+                println!(
+                    "Error refreshing cached query {}: {error}",
+                    loggable(&query)
+                );
+            }
+            refreshing.lock().unwrap().remove(&refreshed);
+        });
+    })
+}
+
 fn spawn_sql_processor(
     config: &YamlTargetConfig,
     s: TcpStream,
-    queries_connection_cache: HashSet<String>,
-    storage: InMemoryQueryStorage,
-    data_type_info: &mut Option<DataTypeInfo>,
+    queries_connection_cache: CacheRules,
+    storage: SharedQueryStorage,
+    cache_options: CacheOptions,
+    data_type_info: Option<SharedDataTypeInfo>,
+    audit_log: Option<AuditLog>,
 ) {
-    let target = config.target.clone();
-    let with_type_discovery = config.with_type_discovery;
     let target_type = config.target_type.clone().unwrap_or(TargetType::MySql);
+    let connection = AuditedConnection {
+        listener: config.port,
+        client_address: s
+            .peer_addr()
+            .map(|address| address.to_string())
+            .unwrap_or_default(),
+        connection_id: next_connection_id(),
+    };
+    let mut session_state = SessionState::default().with_max_set_statements(
+        config
+            .max_session_statements
+            .unwrap_or(DEFAULT_MAX_SET_STATEMENTS),
+    );
+    if config.annotate_queries == Some(true) {
+        session_state = session_state.with_annotated_connection(connection.clone());
+    }
 
-    if let Some(true) = with_type_discovery {
-        let query_executor = construct_query_executor_with_data_type(
-            target,
-            queries_connection_cache,
-            storage,
-            data_type_info,
-        );
-        spawn_intermediary(s, query_executor, target_type, data_type_info.clone())
-    } else if target_type == TargetType::Postgres {
-        let mut runops_api = RunopsApi::new(target.clone()).expect("Error creating runops client");
-        *data_type_info = data_type_info
-            .take()
-            .or_else(|| Some(DataTypeInfo::load(&mut runops_api).expect("Error loading datatype")));
-        let query_executor = construct_query_executor(target, queries_connection_cache, storage);
-        spawn_intermediary(s, query_executor, target_type, data_type_info.clone())
-    } else {
-        let query_executor = construct_query_executor(target, queries_connection_cache, storage);
-        spawn_intermediary(s, query_executor, target_type, data_type_info.clone())
+    match (config.with_type_discovery, data_type_info) {
+        (Some(true), Some(data_type_info)) => {
+            let query_executor = construct_query_executor_with_data_type(
+                config,
+                queries_connection_cache,
+                storage,
+                cache_options,
+                session_state.clone(),
+                data_type_info.clone(),
+            );
+            spawn_intermediary(
+                s,
+                QueryAuditor::new(
+                    config.access_control(config.guardrails(query_executor), session_state.clone()),
+                    audit_log,
+                    connection,
+                    session_state.clone(),
+                ),
+                target_type,
+                config.dialect(),
+                session_state,
+                Some(data_type_info),
+                config.acknowledged_statements(),
+            )
+        }
+        (_, data_type_info) => {
+            let query_executor = construct_query_executor(
+                config,
+                queries_connection_cache,
+                storage,
+                cache_options,
+                session_state.clone(),
+            );
+            spawn_intermediary(
+                s,
+                QueryAuditor::new(
+                    config.access_control(config.guardrails(query_executor), session_state.clone()),
+                    audit_log,
+                    connection,
+                    session_state.clone(),
+                ),
+                target_type,
+                config.dialect(),
+                session_state,
+                data_type_info,
+                config.acknowledged_statements(),
+            )
+        }
+    }
+}
+
+fn warm_cache(
+    config: &YamlTargetConfig,
+    queries_connection_cache: CacheRules,
+    storage: SharedQueryStorage,
+    data_type_info: Option<SharedDataTypeInfo>,
+) {
+    log_dialect(config.dialect());
+    let queries: Vec<String> = queries_connection_cache
+        .exact_queries()
+        .filter(|query| !query.trim().is_empty())
+        .cloned()
+        .collect();
+    println!(
+        "Warming {} cached queries for port {}",
+        queries.len(),
+        config.port
+    );
+    match (config.with_type_discovery, data_type_info) {
+        (Some(true), Some(data_type_info)) => run_queries(
+            construct_query_executor_with_data_type(
+                config,
+                queries_connection_cache,
+                storage,
+                config.cache_options(),
+                SessionState::default(),
+                data_type_info,
+            ),
+            &queries,
+        ),
+        _ => run_queries(
+            construct_query_executor(
+                config,
+                queries_connection_cache,
+                storage,
+                config.cache_options(),
+                SessionState::default(),
+            ),
+            &queries,
+        ),
     }
+    println!("Finished warming the cache for port {}", config.port);
+}
+
+fn run_queries(
+    mut query_executor: impl QueryExecutor<QueryResult = impl QueryResult>,
+    queries: &[String],
+) {
+    for query in queries {
+        match query_executor.query(query) {
+            Ok(Some(result)) => {
+                let (_, rows) = result.get_data();
+                rows.for_each(drop);
+            }
+            Ok(None) => {}
+            Err(error) => println!("Error warming query {}: {error}", loggable(query)),
+        }
+    }
+}
+
+fn new_runops_api(config: &YamlTargetConfig) -> RunopsApi {
+    RunopsApi::new(config.target.clone(), config.runops_api_url.clone())
+        .expect("Error creating runops client")
+        .with_result_format(config.result_format.unwrap_or(match config.target_type {
+            Some(TargetType::Postgres) => ResultFormat::Tsv,
+            _ => ResultFormat::MysqlBatch,
+        }))
+        .with_null_token(
+            config
+                .null_token
+                .clone()
+                .unwrap_or_else(|| DEFAULT_NULL_TOKEN.to_string()),
+        )
+        .with_result_layout(ResultLayout {
+            delimiter: config.delimiter,
+            has_header: config.has_header.unwrap_or(true),
+            skip_lines: config.skip_lines.unwrap_or(0),
+            status_lines: Some(config.dialect()),
+        })
+        .with_output_encoding(config.output_encoding())
 }
 
 fn construct_query_executor_with_data_type(
-    target: String,
-    queries_connection_cache: HashSet<String>,
-    storage: InMemoryQueryStorage,
-    data_type_info: &mut Option<DataTypeInfo>,
-) -> impl QueryExecutor<QueryResult = impl QueryResult> {
-    let mut runops_api = RunopsApi::new(target).expect("Error creating runops client");
-    *data_type_info = data_type_info
-        .take()
-        .or_else(|| Some(DataTypeInfo::load(&mut runops_api).expect("Error loading datatype")));
-    let data_type_info_clone = data_type_info.clone().unwrap();
+    config: &YamlTargetConfig,
+    queries_connection_cache: CacheRules,
+    storage: SharedQueryStorage,
+    cache_options: CacheOptions,
+    session_state: SessionState,
+    data_type_info: SharedDataTypeInfo,
+) -> QueryCache<impl QueryExecutor<QueryResult = impl QueryResult>, SharedQueryStorage> {
+    let current_data_type_info = data_type_info.clone();
+    let cache_options = CacheOptions {
+        schema_version: Some(Arc::new(move || {
+            current_data_type_info.read().unwrap().version()
+        })),
+        ..cache_options
+    };
     QueryCache::new(
-        QuerySanitizer::new(QueryFilter::new(QueryDataType::new(
-            QueryAccumulator::new(runops_api),
-            MySqlDialect {},
-            data_type_info_clone,
-        ))),
+        QuerySanitizer::new(
+            config.masker(
+                config.rewriter(QueryTransaction::new(
+                    QueryFilter::new(QueryVariables::new(
+                        QueryRowGuard::new(
+                            QueryDataType::new(
+                                QueryPaginator::new(
+                                    QueryAccumulator::new(
+                                        new_runops_api(config)
+                                            .with_session_state(session_state.clone()),
+                                        session_state.clone(),
+                                    ),
+                                    config.page_size.unwrap_or(0),
+                                ),
+                                config.dialect(),
+                                data_type_info,
+                                session_state.clone(),
+                            )
+                            .with_schema_loader(config.schema_loader())
+                            .with_type_strictness(config.type_strictness.unwrap_or_default())
+                            .with_geometry_format(config.geometry_format.unwrap_or_default())
+                            .with_uuid_columns(config.uuid_columns.clone().unwrap_or_default()),
+                            config.dialect().dialect(),
+                            config.max_rows_guard.unwrap_or(0),
+                            session_state.clone(),
+                        ),
+                        config.session_variables.clone().unwrap_or_default(),
+                    ))
+                    .with_rules(config.filter_rules.clone().unwrap_or_default()),
+                    config.transaction_mode.unwrap_or_default(),
+                    session_state.clone(),
+                )),
+            ),
+        )
+        .with_dialect(config.dialect())
+        .with_versioned_comments(config.forward_versioned_comments.unwrap_or(true))
+        .with_variable_substitutions(config.variable_substitutions.clone().unwrap_or_default()),
         storage,
         queries_connection_cache,
+        cache_options,
     )
+    .with_session_state(session_state)
+    .with_dialect(config.dialect())
 }
 
 fn construct_query_executor(
-    target: String,
-    queries_connection_cache: HashSet<String>,
-    storage: InMemoryQueryStorage,
-) -> impl QueryExecutor<QueryResult = impl QueryResult> {
+    config: &YamlTargetConfig,
+    queries_connection_cache: CacheRules,
+    storage: SharedQueryStorage,
+    cache_options: CacheOptions,
+    session_state: SessionState,
+) -> QueryCache<impl QueryExecutor<QueryResult = impl QueryResult>, SharedQueryStorage> {
     QueryCache::new(
-        QuerySanitizer::new(QueryFilter::new(QueryAccumulator::new(
-            RunopsApi::new(target).expect("Error creating runops client"),
-        ))),
+        QuerySanitizer::new(
+            config.masker(
+                config.rewriter(QueryTransaction::new(
+                    QueryFilter::new(QueryVariables::new(
+                        QueryRowGuard::new(
+                            QueryTypeSniffer::new(
+                                QueryPaginator::new(
+                                    QueryAccumulator::new(
+                                        new_runops_api(config)
+                                            .with_session_state(session_state.clone()),
+                                        session_state.clone(),
+                                    ),
+                                    config.page_size.unwrap_or(0),
+                                ),
+                                config.type_sniffing_rows.unwrap_or(0),
+                                session_state.clone(),
+                            ),
+                            config.dialect().dialect(),
+                            config.max_rows_guard.unwrap_or(0),
+                            session_state.clone(),
+                        ),
+                        config.session_variables.clone().unwrap_or_default(),
+                    ))
+                    .with_rules(config.filter_rules.clone().unwrap_or_default()),
+                    config.transaction_mode.unwrap_or_default(),
+                    session_state.clone(),
+                )),
+            ),
+        )
+        .with_dialect(config.dialect())
+        .with_versioned_comments(config.forward_versioned_comments.unwrap_or(true))
+        .with_variable_substitutions(config.variable_substitutions.clone().unwrap_or_default()),
         storage,
         queries_connection_cache,
+        cache_options,
     )
+    .with_session_state(session_state)
+    .with_dialect(config.dialect())
 }
 
 fn spawn_intermediary(
     s: TcpStream,
     query_executor: impl QueryExecutor<QueryResult = impl QueryResult> + Send + 'static,
     target_type: TargetType,
-    data_type_info: Option<DataTypeInfo>,
+    dialect: SqlDialect,
+    session_state: SessionState,
+    data_type_info: Option<SharedDataTypeInfo>,
+    acknowledged_statements: Vec<Regex>,
 ) {
-    thread::spawn(move || match target_type {
-        TargetType::MySql => {
-            MysqlIntermediary::run_on_tcp(Backend::new(query_executor), s).unwrap();
-        }
-        TargetType::Postgres => {
-            PostgressIntermediary::new(
-                PostgresBackend::new(query_executor, data_type_info.unwrap()),
-                s,
-            )
-            .run()
-            .unwrap();
+    thread::spawn(move || {
+        log_dialect(dialect);
+        match target_type {
+            TargetType::MySql => {
+                let backend = Backend::new(query_executor, session_state)
+                    .with_acknowledged_statements(acknowledged_statements);
+                MysqlIntermediary::run_on_tcp(backend, s).unwrap();
+            }
+            TargetType::Postgres => {
+                PostgressIntermediary::new(
+                    PostgresBackend::new(query_executor, data_type_info.unwrap(), dialect),
+                    s,
+                )
+                .run()
+                .unwrap();
+            }
         }
     });
 }
@@ -0,0 +1,193 @@
+use crate::http::{write_response, HttpRequest, DEFAULT_MAX_BODY_BYTES};
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Deserialize, Clone)]
+pub struct CannedResponse {
+    query: String,
+    logs: String,
+    // Slow responses are answered with "Your task is running." and must be polled for
+    #[serde(default)]
+    slow: bool,
+}
+
+#[derive(Deserialize)]
+struct TaskRequest {
+    script: String,
+}
+
+pub struct MockRunopsServer {
+    listener: TcpListener,
+    state: Arc<MockState>,
+}
+
+struct MockState {
+    responses: Vec<CannedResponse>,
+    next_task_id: AtomicU64,
+    pending_logs: Mutex<HashMap<u64, String>>,
+}
+
+pub fn run(args: &[String]) -> std::io::Result<()> {
+    let (port, responses_path) = match args {
+        [port, responses_path] => (
+            port.parse::<u16>()
+                .expect("Invalid port for the mock server"),
+            responses_path,
+        ),
+        _ => panic!("Usage: my-fake-sql mock-server <port> <responses.yml>"),
+    };
+    let file = std::fs::File::open(responses_path)?;
+    let responses: Vec<CannedResponse> =
+        serde_yaml::from_reader(file).expect("Error parsing mock responses yaml");
+    let server = MockRunopsServer::bind(port, responses)?;
+    println!("Mock Runops server listening on {}", server.local_addr()?);
+    server.run();
+    Ok(())
+}
+
+impl MockRunopsServer {
+    pub fn bind(port: u16, responses: Vec<CannedResponse>) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(format!("127.0.0.1:{}", port))?,
+            state: Arc::new(MockState {
+                responses,
+                next_task_id: AtomicU64::new(1),
+                pending_logs: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(self) {
+        let address = self.local_addr().expect("Mock server without address");
+        while let Ok((stream, _)) = self.listener.accept() {
+            let state = self.state.clone();
+            thread::spawn(move || {
+                if let Err(error) = state.handle_connection(stream, address) {
+                    println!("Mock server error: {error}");
+                }
+            });
+        }
+    }
+}
+
+impl MockState {
+    fn handle_connection(&self, mut stream: TcpStream, address: SocketAddr) -> Result<()> {
+        let request = HttpRequest::read(&stream, DEFAULT_MAX_BODY_BYTES)?;
+        let (status, response) = match (request.method.as_str(), request.segments().as_slice()) {
+            ("POST", ["v1", "tasks"]) => ("200 OK", self.create_task(&request.body)?),
+            ("GET", ["v1", "tasks", id, "logs"]) => match self.pending_logs(id)? {
+                Some(_) => (
+                    "200 OK",
+                    json!({ "logs_url": format!("http://{}/logs/{}", address, id) }).to_string(),
+                ),
+                None => ("404 Not Found", String::new()),
+            },
+            ("GET", ["logs", id]) => match self.pending_logs(id)? {
+                Some(logs) => ("200 OK", logs),
+                None => ("404 Not Found", String::new()),
+            },
+            _ => ("404 Not Found", String::new()),
+        };
+        write_response(&mut stream, status, &response)
+    }
+
+    fn create_task(&self, body: &[u8]) -> Result<String> {
+        let request: TaskRequest = serde_json::from_slice(body)?;
+        let id = self.next_task_id.fetch_add(1, Ordering::SeqCst);
+        let task_logs = match self.responses.iter().find(|response| {
+            normalize_whitespace(&response.query) == normalize_whitespace(&request.script)
+        }) {
+            None => format!(
+                "ERROR: mock server has no canned response for query {}",
+                request.script
+            ),
+            Some(response) if response.logs.is_empty() => "Task returned empty logs".to_string(),
+            Some(response) if response.slow => {
+                self.pending_logs
+                    .lock()
+                    .map_err(|_| anyhow!("Poisoned mock server state"))?
+                    .insert(id, response.logs.clone());
+                "Your task is running.".to_string()
+            }
+            Some(response) => response.logs.clone(),
+        };
+        Ok(json!({ "task_logs": task_logs, "id": id }).to_string())
+    }
+
+    fn pending_logs(&self, id: &str) -> Result<Option<String>> {
+        let id: u64 = match id.parse() {
+            Ok(id) => id,
+            Err(_) => bail!("Invalid task id {id}"),
+        };
+        Ok(self
+            .pending_logs
+            .lock()
+            .map_err(|_| anyhow!("Poisoned mock server state"))?
+            .get(&id)
+            .cloned())
+    }
+}
+
+fn normalize_whitespace(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::RunopsApi;
+    use crate::query_executor::{ColumnValue, QueryExecutor, QueryResult};
+
+    fn spawn_with_client(responses: Vec<CannedResponse>) -> Result<RunopsApi> {
+        let server = MockRunopsServer::bind(0, responses)?;
+        let api_url = format!("http://{}", server.local_addr()?);
+        thread::spawn(move || server.run());
+        RunopsApi::new("mock".to_string(), Some(api_url))
+    }
+
+    fn canned(query: &str, logs: &str) -> CannedResponse {
+        CannedResponse {
+            query: query.to_string(),
+            logs: logs.to_string(),
+            slow: false,
+        }
+    }
+
+    #[test]
+    fn test_runops_api_against_mock_server() {
+        let mut runops_api = spawn_with_client(vec![
+            canned(
+                "select id, name from users;",
+                "id\tname\n1\tAlice\n2\tNULL\n",
+            ),
+            canned("set @a = 1;", ""),
+        ])
+        .unwrap();
+
+        let (columns, rows) = runops_api
+            .query("select id, name from users;")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        let columns = columns.unwrap();
+        assert_eq!(2, columns.len());
+        assert_eq!("name", columns[1].name);
+        let rows = rows.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(2, rows.len());
+        assert!(matches!(&rows[0][1], ColumnValue::String(name) if name == "Alice"));
+        assert!(matches!(rows[1][1], ColumnValue::Null));
+
+        assert!(runops_api.query("set @a = 1;").unwrap().is_none());
+        assert!(runops_api.query("select 1;").is_err());
+    }
+}
@@ -1,42 +1,71 @@
-use super::query_executor::{Column, ColumnValue, QueryExecutor, QueryResult, SqlError};
+use super::query_executor::{
+    loggable, pipelined, Column, ColumnKey, ColumnValue, PreparedQuery, QueryExecutor, QueryResult,
+    SessionState, SqlError, UnknownDatabase, GEOMETRY_TYPES,
+};
 use anyhow::{bail, Result};
 use chrono::{NaiveDate, NaiveDateTime};
 use msql_srv::*;
 use msql_srv::{Column as MySqlColumn, ColumnFlags};
+use regex::Regex;
 use std::io::{Error, Read, Write};
 
 pub struct Backend<T> {
     executor: T,
     prepared_statements: Vec<String>,
+    session_state: SessionState,
+    /// Statements answered with an OK packet when the executor has no result for them
+    acknowledged_statements: Vec<Regex>,
 }
 
+// msql-srv always sends a length of 1024 and no decimals, so the length, precision and scale of
+// the column attributes are only known locally, like in `SHOW CREATE TABLE`
 impl From<Column> for MySqlColumn {
     fn from(column: Column) -> Self {
+        let mut colflags = ColumnFlags::empty();
+        if column.attributes.not_null {
+            colflags |= ColumnFlags::NOT_NULL_FLAG;
+        }
+        if column.attributes.unsigned {
+            colflags |= ColumnFlags::UNSIGNED_FLAG;
+        }
+        colflags |= match column.attributes.key {
+            Some(ColumnKey::Primary) => ColumnFlags::PRI_KEY_FLAG,
+            Some(ColumnKey::Unique) => ColumnFlags::UNIQUE_KEY_FLAG,
+            Some(ColumnKey::Multiple) => ColumnFlags::MULTIPLE_KEY_FLAG,
+            None => ColumnFlags::empty(),
+        };
         MySqlColumn {
             table: String::new(),
             column: column.name,
-            colflags: ColumnFlags::empty(),
+            colflags,
             coltype: match column.ty.as_deref() {
                 Some("bigint") => ColumnType::MYSQL_TYPE_LONGLONG,
                 Some("varchar") => ColumnType::MYSQL_TYPE_VAR_STRING,
-                Some("tinyint") => ColumnType::MYSQL_TYPE_TINY,
+                Some("tinyint") | Some("boolean") | Some("bool") => ColumnType::MYSQL_TYPE_TINY,
                 Some("datetime") => ColumnType::MYSQL_TYPE_DATETIME,
                 Some("int") => ColumnType::MYSQL_TYPE_LONG,
                 Some("mediumint") => ColumnType::MYSQL_TYPE_LONG,
                 Some("text") => ColumnType::MYSQL_TYPE_STRING,
                 Some("enum") => ColumnType::MYSQL_TYPE_ENUM,
-                Some("decimal") => ColumnType::MYSQL_TYPE_NEWDECIMAL,
+                Some("decimal") | Some("numeric") => ColumnType::MYSQL_TYPE_NEWDECIMAL,
                 Some("date") => ColumnType::MYSQL_TYPE_DATE,
                 Some("binary") => ColumnType::MYSQL_TYPE_BLOB,
                 Some("double") => ColumnType::MYSQL_TYPE_DOUBLE,
                 Some("char") => ColumnType::MYSQL_TYPE_STRING,
                 Some("tinytext") => ColumnType::MYSQL_TYPE_STRING,
+                Some("uuid") => ColumnType::MYSQL_TYPE_STRING,
+                Some("json") => ColumnType::MYSQL_TYPE_JSON,
+                Some(data_type) if GEOMETRY_TYPES.contains(&data_type) => {
+                    ColumnType::MYSQL_TYPE_GEOMETRY
+                }
                 Some("time") => ColumnType::MYSQL_TYPE_TIME,
                 Some("timestamp") => ColumnType::MYSQL_TYPE_TIMESTAMP,
                 Some("smallint") => ColumnType::MYSQL_TYPE_INT24,
                 Some("blob") => ColumnType::MYSQL_TYPE_BLOB,
                 Some("float") => ColumnType::MYSQL_TYPE_FLOAT,
                 Some("mediumblob") => ColumnType::MYSQL_TYPE_MEDIUM_BLOB,
+                Some("tinyblob") => ColumnType::MYSQL_TYPE_TINY_BLOB,
+                Some("longblob") => ColumnType::MYSQL_TYPE_LONG_BLOB,
                 Some("longtext") => ColumnType::MYSQL_TYPE_STRING,
                 Some("mediumtext") => ColumnType::MYSQL_TYPE_STRING,
                 Some("varbinary") => ColumnType::MYSQL_TYPE_BLOB,
@@ -61,14 +90,25 @@ impl ToMysqlValue for ColumnValue {
         match self {
             ColumnValue::Null => w.write_all(&[0xFB]),
             ColumnValue::String(string) => string.to_mysql_text(w),
+            // MySQL booleans are `tinyint(1)`
+            ColumnValue::Bool(boolean) => (*boolean as i8).to_mysql_text(w),
             ColumnValue::I64(number) => number.to_mysql_text(w),
             ColumnValue::I32(number) => number.to_mysql_text(w),
             ColumnValue::I16(number) => number.to_mysql_text(w),
             ColumnValue::I8(number) => number.to_mysql_text(w),
+            ColumnValue::U64(number) => number.to_mysql_text(w),
+            ColumnValue::U32(number) => number.to_mysql_text(w),
+            ColumnValue::U16(number) => number.to_mysql_text(w),
+            ColumnValue::U8(number) => number.to_mysql_text(w),
             ColumnValue::Double(number) => number.to_mysql_text(w),
             ColumnValue::Float(number) => number.to_mysql_text(w),
+            ColumnValue::Decimal(decimal) => decimal.to_string().to_mysql_text(w),
             ColumnValue::DateTime(date_time) => date_time.to_mysql_text(w),
             ColumnValue::Date(date) => date.to_mysql_text(w),
+            ColumnValue::Uuid(uuid) => uuid.to_string().to_mysql_text(w),
+            ColumnValue::Json(document) => document.get().to_mysql_text(w),
+            ColumnValue::Time(time) => time.to_mysql_text(w),
+            ColumnValue::Bytes(bytes) => bytes.to_mysql_text(w),
         }
     }
 
@@ -76,24 +116,70 @@ impl ToMysqlValue for ColumnValue {
         match self {
             ColumnValue::Null => unreachable!(),
             ColumnValue::String(string) => string.to_mysql_bin(w, c),
+            ColumnValue::Bool(boolean) => (*boolean as i8).to_mysql_bin(w, c),
             ColumnValue::I64(number) => number.to_mysql_bin(w, c),
             ColumnValue::I32(number) => number.to_mysql_bin(w, c),
             ColumnValue::I16(number) => number.to_mysql_bin(w, c),
             ColumnValue::I8(number) => number.to_mysql_bin(w, c),
+            ColumnValue::U64(number) => number.to_mysql_bin(w, c),
+            ColumnValue::U32(number) => number.to_mysql_bin(w, c),
+            ColumnValue::U16(number) => number.to_mysql_bin(w, c),
+            ColumnValue::U8(number) => number.to_mysql_bin(w, c),
             ColumnValue::Double(number) => number.to_mysql_bin(w, c),
             ColumnValue::Float(number) => number.to_mysql_bin(w, c),
+            // Decimals are sent as text in the binary protocol too
+            ColumnValue::Decimal(decimal) => decimal.to_string().to_mysql_bin(w, c),
             ColumnValue::DateTime(date_time) => date_time.to_mysql_bin(w, c),
             ColumnValue::Date(date) => date.to_mysql_bin(w, c),
+            // MariaDB sends `uuid` values as text in both protocols
+            ColumnValue::Uuid(uuid) => uuid.to_string().to_mysql_bin(w, c),
+            ColumnValue::Json(document) => document.get().to_mysql_bin(w, c),
+            ColumnValue::Time(time) => time.to_mysql_bin(w, c),
+            ColumnValue::Bytes(bytes) => bytes.to_mysql_bin(w, c),
         }
     }
 }
 
 impl<T> Backend<T> {
-    pub fn new(executor: T) -> Self {
+    pub fn new(executor: T, session_state: SessionState) -> Self {
         Self {
             executor,
             prepared_statements: Vec::new(),
+            session_state,
+            acknowledged_statements: Vec::new(),
+        }
+    }
+
+    pub fn with_acknowledged_statements(mut self, acknowledged_statements: Vec<Regex>) -> Self {
+        self.acknowledged_statements = acknowledged_statements;
+        self
+    }
+
+    fn show_warnings<W: Write + Read>(&self, results: QueryResultWriter<W>) -> Result<()> {
+        let columns: Vec<MySqlColumn> = [
+            ("Level", "varchar"),
+            ("Code", "int"),
+            ("Message", "varchar"),
+        ]
+        .into_iter()
+        .map(|(name, ty)| {
+            Column {
+                name: name.to_string(),
+                ty: Some(ty.to_string()),
+                attributes: Default::default(),
+            }
+            .into()
+        })
+        .collect();
+        let mut rw = results.start(&columns)?;
+        for warning in self.session_state.warnings() {
+            rw.write_row(vec![
+                ColumnValue::String("Warning".to_string()),
+                ColumnValue::I32(warning.code.into()),
+                ColumnValue::String(warning.message),
+            ])?;
         }
+        Ok(rw.finish()?)
     }
 
     fn do_query<W: Write + Read, R>(
@@ -105,39 +191,86 @@ impl<T> Backend<T> {
         T: QueryExecutor<QueryResult = R>,
         R: QueryResult,
     {
-        println!("Query {}", query);
+        println!("Query {}", loggable(query));
+        if is_show_warnings(query) {
+            return self.show_warnings(results);
+        }
+        self.session_state.clear_warnings();
         let result = self.executor.query(query);
-        match result {
-            Ok(Some(query_result)) => {
-                let (columns, rows) = query_result.get_data();
-                let columns = columns?
-                    .into_iter()
-                    .map(|column| column.into())
-                    .collect::<Vec<MySqlColumn>>();
-                if columns.is_empty() {
-                    return Ok(results.completed(0, 0)?);
-                }
-                let mut rw = results.start(&columns)?;
-                let mut i = 0;
-                for row in rows {
-                    i += 1;
-                    rw.write_row(row?)?;
-                }
-                println!("Number of rows: {}", i);
-                Ok(rw.finish()?)
+        if matches!(result, Ok(None))
+            && self
+                .acknowledged_statements
+                .iter()
+                .any(|pattern| pattern.is_match(query.trim()))
+        {
+            // Statements like `SET NAMES` are still run by the executor, to be kept in the session
+            return Ok(results.completed(0, 0)?);
+        }
+        write_result(result, results)
+    }
+
+    fn do_query_prepared<W: Write + Read, R>(
+        &mut self,
+        prepared: &PreparedQuery,
+        results: QueryResultWriter<W>,
+    ) -> Result<()>
+    where
+        T: QueryExecutor<QueryResult = R>,
+        R: QueryResult,
+    {
+        println!("Query {}", loggable(&prepared.query));
+        self.session_state.clear_warnings();
+        let result = self.executor.query_prepared(prepared);
+        write_result(result, results)
+    }
+}
+
+fn write_result<W: Write + Read, R: QueryResult>(
+    result: Result<Option<R>>,
+    results: QueryResultWriter<W>,
+) -> Result<()> {
+    match result {
+        Ok(Some(query_result)) => {
+            let (columns, rows) = query_result.get_data();
+            let columns = columns?
+                .into_iter()
+                .map(|column| column.into())
+                .collect::<Vec<MySqlColumn>>();
+            if columns.is_empty() {
+                return Ok(results.completed(0, 0)?);
             }
-            Ok(None) => Ok(results.start(&[])?.finish()?),
-            Err(error) => match error.downcast_ref::<SqlError>() {
-                Some(sql_error) => {
-                    println!("Sql Error: {sql_error}");
-                    Ok(results.error(
-                        ErrorKind::ER_ERROR_ON_READ, // Using this as a generic error. Doing a mapping here would be too difficult
-                        sql_error.to_string().as_bytes(),
-                    )?)
+            let mut rows = pipelined(rows).peekable();
+            // Errors of the target are only answered as errors before the first row. After it,
+            // the connection is closed, so they are not taken as complete results.
+            if matches!(rows.peek(), Some(Err(error)) if error.is::<SqlError>()) {
+                if let Some(Err(error)) = rows.next() {
+                    return write_result::<W, R>(Err(error), results);
                 }
-                None => bail!("{error}"),
-            },
+            }
+            let mut rw = results.start(&columns)?;
+            let mut i = 0;
+            for row in rows {
+                i += 1;
+                rw.write_row(row?)?;
+            }
+            println!("Number of rows: {}", i);
+            Ok(rw.finish()?)
         }
+        Ok(None) => Ok(results.start(&[])?.finish()?),
+        Err(error) if error.is::<UnknownDatabase>() => {
+            println!("Sql Error: {}", loggable(&error.to_string()));
+            Ok(results.error(ErrorKind::ER_BAD_DB_ERROR, error.to_string().as_bytes())?)
+        }
+        Err(error) => match error.downcast_ref::<SqlError>() {
+            Some(sql_error) => {
+                println!("Sql Error: {}", loggable(&sql_error.to_string()));
+                Ok(results.error(
+                    ErrorKind::ER_ERROR_ON_READ, // Using this as a generic error. Doing a mapping here would be too difficult
+                    sql_error.to_string().as_bytes(),
+                )?)
+            }
+            None => bail!("{error}"),
+        },
     }
 }
 
@@ -148,6 +281,14 @@ where
 {
     type Error = Error;
 
+    /// The credentials are checked by Runops, so every client is accepted. The user is kept for
+    /// the comment of the queries.
+    fn authenticate(&self, _: &str, username: &[u8], _: &[u8], _: &[u8]) -> bool {
+        self.session_state
+            .set_client_user(&String::from_utf8_lossy(username));
+        true
+    }
+
     fn on_prepare(&mut self, query: &str, info: StatementMetaWriter<W>) -> std::io::Result<()> {
         self.prepared_statements.push(query.to_string());
         let params: Vec<MySqlColumn> = query
@@ -168,9 +309,11 @@ where
         pp: ParamParser,
         results: QueryResultWriter<W>,
     ) -> std::io::Result<()> {
-        let query = self.prepared_statements.get(statement_id as usize).cloned();
-        match query {
-            Some(mut query) => {
+        let template = self.prepared_statements.get(statement_id as usize).cloned();
+        match template {
+            Some(template) => {
+                let mut query = template.clone();
+                let mut parameters = Vec::new();
                 for param in pp.into_iter() {
                     // This is a mess. msql-srv has some very bad ways of handling this types and conversions
                     let value_str = match param.value.into_inner() {
@@ -189,11 +332,18 @@ where
                             let date_time = NaiveDateTime::from(value);
                             escaped(&date_time.to_string())
                         }
-                        ValueInner::Time(_) => panic!("Not sure how to parse this yet"),
+                        ValueInner::Time(data) => escaped(&time_parameter(data)),
                     };
                     query = query.replacen('?', &value_str, 1);
+                    parameters.push(value_str);
                 }
-                self.on_query(&query, results)
+                let prepared = PreparedQuery {
+                    template,
+                    parameters,
+                    query,
+                };
+                self.do_query_prepared(&prepared, results).unwrap();
+                Ok(())
             }
             None => results.error(
                 ErrorKind::ER_STMT_HAS_NO_OPEN_CURSOR,
@@ -205,12 +355,74 @@ where
         self.prepared_statements.remove(statement_id as usize);
     }
 
+    /// Sent by clients that connect to a database, instead of running `USE`. It is checked like
+    /// `USE`, by running one.
+    fn on_init(&mut self, schema: &str, writer: InitWriter<W>) -> std::io::Result<()> {
+        println!("Using schema {}", schema);
+        match self
+            .executor
+            .query(&format!("use `{}`", schema.replace('`', "``")))
+        {
+            Ok(_) => writer.ok(),
+            Err(error) if error.is::<UnknownDatabase>() => {
+                println!("Sql Error: {}", loggable(&error.to_string()));
+                writer.error(ErrorKind::ER_BAD_DB_ERROR, error.to_string().as_bytes())
+            }
+            Err(error) => match error.downcast_ref::<SqlError>() {
+                Some(sql_error) => {
+                    println!("Sql Error: {}", loggable(&sql_error.to_string()));
+                    writer.error(
+                        ErrorKind::ER_ERROR_ON_READ,
+                        sql_error.to_string().as_bytes(),
+                    )
+                }
+                None => Err(Error::new(std::io::ErrorKind::Other, error.to_string())),
+            },
+        }
+    }
+
     fn on_query(&mut self, query: &str, results: QueryResultWriter<W>) -> std::io::Result<()> {
         self.do_query(query, results).unwrap();
         Ok(())
     }
 }
 
+fn is_show_warnings(query: &str) -> bool {
+    query
+        .trim()
+        .trim_end_matches(';')
+        .eq_ignore_ascii_case("show warnings")
+}
+
 fn escaped(value: &str) -> String {
     format!("'{}'", value)
 }
+
+/// Time parameters of the binary protocol: a sign, days, hours, minutes, seconds and
+/// microseconds. Trailing zeros are left out.
+fn time_parameter(data: &[u8]) -> String {
+    let byte = |index: usize| data.get(index).copied().unwrap_or_default();
+    let number = |start: usize| u32::from_le_bytes([0, 1, 2, 3].map(|offset| byte(start + offset)));
+    let sign = if byte(0) == 1 { "-" } else { "" };
+    let hours = u64::from(number(1)) * 24 + u64::from(byte(5));
+    let time = format!("{}{:02}:{:02}:{:02}", sign, hours, byte(6), byte(7));
+    match number(8) {
+        0 => time,
+        micros => format!("{}.{:06}", time, micros),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_time_parameters() {
+        assert_eq!("00:00:00", time_parameter(&[]));
+        assert_eq!("12:30:05", time_parameter(&[0, 0, 0, 0, 0, 12, 30, 5]));
+        assert_eq!(
+            "-34:00:01.000500",
+            time_parameter(&[1, 1, 0, 0, 0, 10, 0, 1, 0xf4, 0x01, 0, 0])
+        );
+    }
+}
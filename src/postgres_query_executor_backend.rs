@@ -1,27 +1,36 @@
-use crate::query_executor::{ColumnValue, QueryExecutor, QueryResult};
-use crate::DataTypeInfo;
+use crate::query_executor::{
+    loggable, pipelined, ColumnValue, PreparedQuery, QueryExecutor, QueryResult, Rows, SqlDialect,
+    SqlError, UnknownDatabase,
+};
+use crate::SharedDataTypeInfo;
 use anyhow::Result;
 use bytes::BytesMut;
+use chrono::{NaiveDate, NaiveDateTime};
 use postgres_shim::{
     Column as PostgresColumn, DefaultServerParameters, FromSql, ParameterValue, PostgresShim,
     ResultWriter, ToSqlValue, Type,
 };
 // use postgres_types::{Type, FromSql};
 use std::collections::HashMap;
-use std::io::{Result as IoResult, Write};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
 
 pub struct PostgresBackend<T> {
     executor: T,
     queries: HashMap<String, String>,
-    data_type_info: DataTypeInfo,
+    /// Prepared statements bound into a portal, by portal
+    bound_portals: HashMap<String, PreparedQuery>,
+    data_type_info: SharedDataTypeInfo,
+    dialect: SqlDialect,
 }
 
 impl<T> PostgresBackend<T> {
-    pub fn new(executor: T, data_type_info: DataTypeInfo) -> Self {
+    pub fn new(executor: T, data_type_info: SharedDataTypeInfo, dialect: SqlDialect) -> Self {
         Self {
             executor,
             queries: HashMap::new(),
+            bound_portals: HashMap::new(),
             data_type_info,
+            dialect,
         }
     }
 
@@ -36,7 +45,11 @@ impl<T> PostgresBackend<T> {
         T: QueryExecutor<QueryResult = R>,
         R: QueryResult,
     {
-        match self.executor.query(query)? {
+        let result = match self.bound_portals.remove(query) {
+            Some(prepared) => self.executor.query_prepared(&prepared)?,
+            None => self.executor.query(query)?,
+        };
+        match result {
             None => {
                 result_writer.empty_result()?;
             }
@@ -51,18 +64,17 @@ impl<T> PostgresBackend<T> {
                         })
                         .collect(),
                 );
+                let mut rows = pipelined(rows).peekable();
+                // Errors of the target are only answered as errors before the first row
+                if matches!(rows.peek(), Some(Err(error)) if error.is::<SqlError>()) {
+                    if let Some(Err(error)) = rows.next() {
+                        return Err(error);
+                    }
+                }
                 let mut row_writer = result_writer.start_writing(&columns)?;
                 let mut n_rows = 0;
                 for row in rows {
-                    let row = row?;
-                    if row.len() == 1 {
-                        if let ColumnValue::String(string) = &row[0] {
-                            if string.ends_with("row)") || string.ends_with("rows)") {
-                                continue;
-                            }
-                        }
-                    }
-                    row_writer.write_row(row)?;
+                    row_writer.write_row(row?)?;
                     n_rows += 1;
                 }
                 println!("Number of rows: {}", n_rows);
@@ -79,12 +91,11 @@ impl<T> PostgresBackend<T> {
             .replace("from pg_user", "from pg_catalog.pg_user")
             .replace("order by inhseqno)", ")")
             .replace("order by inhrelid)", ")");
-        let ast = sqlparser::parser::Parser::parse_sql(
-            &sqlparser::dialect::PostgreSqlDialect {},
-            &query,
-        )?;
+        let ast = sqlparser::parser::Parser::parse_sql(self.dialect.dialect().as_ref(), &query)?;
         let columns_types = self
             .data_type_info
+            .read()
+            .unwrap()
             .get_columns_types_from_ast("public", ast)?;
         println!("Expected column types : {:?}", columns_types);
         match columns_types.len() {
@@ -94,10 +105,17 @@ impl<T> PostgresBackend<T> {
                     .into_iter()
                     .map(|(column_name, column_type)| PostgresColumn {
                         name: column_name,
-                        column_type: match column_type.map(|ty| ty.to_lowercase()).as_deref() {
+                        // Row descriptions have no nullability, so only the type is described
+                        column_type: match column_type
+                            .data_type
+                            .map(|ty| ty.to_lowercase())
+                            .as_deref()
+                        {
                             Some("text") | Some("name") | None => Type::TEXT,
                             Some("bigint") => Type::INT8,
                             Some("uuid") => Type::UUID,
+                            Some("json") => Type::JSON,
+                            Some("jsonb") => Type::JSONB,
                             Some("oid") => Type::OID,
                             Some("character varying") => Type::VARCHAR,
                             Some("bytea") => Type::BYTEA,
@@ -131,12 +149,16 @@ where
         Ok(())
     }
     fn bind(&mut self, query_name: String, parameters: Vec<ParameterValue>) -> IoResult<Portal> {
-        let mut query = self.queries.get(&query_name).unwrap().clone();
+        let template = self.queries.get(&query_name).unwrap().clone();
+        let mut query = template.clone();
+        let mut bound_parameters = Vec::new();
         for (index, value) in parameters.into_iter().enumerate() {
             match value {
                 ParameterValue::Text(text) => {
                     // query = query.replacen("?", &format!("'{}'", text), 1);
-                    query = query.replacen(&format!("${}", index + 1), &format!("'{}'", text), 1);
+                    let parameter = format!("'{}'", text);
+                    query = query.replacen(&format!("${}", index + 1), &parameter, 1);
+                    bound_parameters.push(parameter);
                 }
                 ParameterValue::Binary(value) => {
                     // This is very wrong :)
@@ -151,11 +173,20 @@ where
                         }
                     };
                     query = query.replacen(&format!("${}", index + 1), &parameter, 1);
+                    bound_parameters.push(parameter);
                     // unimplemented!("We dont know how to handle binary types yet {:?}", value)
                 }
             }
         }
-        println!("Query {query}");
+        println!("Query {}", loggable(&query));
+        self.bound_portals.insert(
+            query.clone(),
+            PreparedQuery {
+                template,
+                parameters: bound_parameters,
+                query: query.clone(),
+            },
+        );
         Ok(query)
     }
 
@@ -163,16 +194,12 @@ where
         match self.describe_by_parsing_query(portal) {
             Ok(result) => Ok(result),
             Err(err) => {
-                println!("Error during describing {err}");
-                match self
-                    .executor
-                    .query(portal)
-                    .expect("Error getting version from target")
-                {
+                println!("Error during describing {}", loggable(&err.to_string()));
+                match self.executor.query(portal).map_err(query_error)? {
                     Some(result) => {
                         let (columns, _) = result.get_data();
                         let columns: Vec<PostgresColumn> = columns
-                            .unwrap()
+                            .map_err(query_error)?
                             .into_iter()
                             .map(|column| PostgresColumn {
                                 name: column.name,
@@ -197,40 +224,45 @@ where
     where
         S: Write,
     {
-        self.do_execute(&portal, result_writer, columns).unwrap();
-        Ok(())
+        self.do_execute(&portal, result_writer, columns)
+            .map_err(query_error)
     }
 
     fn default_parameters(&mut self) -> DefaultServerParameters {
-        let query = r#"
-show server_version;
-show server_encoding;
-show client_encoding;
-show application_name;
-show default_transaction_read_only;
-show is_superuser;
-show session_authorization;
-show DateStyle;
-show IntervalStyle;
-show TimeZone;
-show integer_datetimes;
-show standard_conforming_strings;
-        "#;
-        let result = self
-            .executor
-            .query(query)
-            .expect("Error getting version from target")
-            .unwrap();
-        let (_, rows) = result.get_data();
-        let parameters: Vec<String> = rows
-            .map(|value| value.unwrap()[0].clone())
-            .enumerate()
-            .filter(|(i, _)| i % 3 == 0)
-            .map(|(_, value)| match value {
-                ColumnValue::String(value) => value.clone(),
-                _ => unreachable!("We should always have a string here"),
-            })
+        let batch: String = DEFAULT_PARAMETERS
+            .iter()
+            .map(|parameter| format!("show {parameter};\n"))
             .collect();
+        // One result set per `show`, with the value in its first row
+        let parameters: Option<Vec<String>> =
+            self.executor
+                .query(&batch)
+                .ok()
+                .flatten()
+                .and_then(|result| {
+                    result
+                        .result_sets()
+                        .into_iter()
+                        .map(|(_, rows)| first_value(rows))
+                        .collect()
+                });
+        // Results answered as one set, like cached or sniffed ones, and batches rejected, like by
+        // the guardrails, are run one `show` at a time
+        let parameters = match parameters {
+            Some(parameters) if parameters.len() == DEFAULT_PARAMETERS.len() => parameters,
+            _ => DEFAULT_PARAMETERS
+                .iter()
+                .map(|parameter| {
+                    let result = self
+                        .executor
+                        .query(&format!("show {parameter};"))
+                        .expect("Error getting version from target")
+                        .unwrap();
+                    let (_, rows) = result.get_data();
+                    first_value(rows).expect("We should always have a string here")
+                })
+                .collect(),
+        };
         DefaultServerParameters {
             server_version: parameters[0].clone(),
             server_encoding: parameters[1].clone(),
@@ -249,9 +281,103 @@ show standard_conforming_strings;
     }
 }
 
+/// Parameters of `default_parameters`, in the order of their fields
+const DEFAULT_PARAMETERS: [&str; 12] = [
+    "server_version",
+    "server_encoding",
+    "client_encoding",
+    "application_name",
+    "default_transaction_read_only",
+    "is_superuser",
+    "session_authorization",
+    "DateStyle",
+    "IntervalStyle",
+    "TimeZone",
+    "integer_datetimes",
+    "standard_conforming_strings",
+];
+
+/// Errors of the queries, like the ones of the target or of an unknown schema, are returned to
+/// the shim with their message instead of ending the connection thread
+fn query_error(error: anyhow::Error) -> IoError {
+    if error.is::<UnknownDatabase>() || error.is::<SqlError>() {
+        println!("Sql Error: {}", loggable(&error.to_string()));
+        return IoError::new(ErrorKind::InvalidInput, error.to_string());
+    }
+    match error.downcast::<IoError>() {
+        Ok(error) => error,
+        Err(error) => IoError::new(ErrorKind::Other, error.to_string()),
+    }
+}
+
+/// Text in the first column of the first row
+fn first_value(mut rows: Rows) -> Option<String> {
+    match rows.next()?.ok()?.into_iter().next()? {
+        ColumnValue::String(value) => Some(value),
+        _ => None,
+    }
+}
+
 impl ToSqlValue for ColumnValue {
-    fn as_bin_value(&self, _: &Type) -> Option<BytesMut> {
-        todo!("Not implemented bin serialization")
+    fn as_bin_value(&self, ty: &Type) -> Option<BytesMut> {
+        match self {
+            ColumnValue::Uuid(uuid) => Some(BytesMut::from(&uuid.as_bytes()[..])),
+            // Binary `jsonb` is the text with a version before it
+            ColumnValue::Json(document) => {
+                let mut buffer = BytesMut::new();
+                if *ty == Type::JSONB {
+                    buffer.extend_from_slice(&[1]);
+                }
+                buffer.extend_from_slice(document.get().as_bytes());
+                Some(buffer)
+            }
+            ColumnValue::Null => None,
+            ColumnValue::Bool(boolean) if *ty == Type::BOOL => {
+                Some(BytesMut::from(&[u8::from(*boolean)][..]))
+            }
+            ColumnValue::Bytes(bytes) if *ty == Type::BYTEA => Some(BytesMut::from(&bytes[..])),
+            ColumnValue::Double(number) if *ty == Type::FLOAT8 => {
+                Some(BytesMut::from(&number.to_be_bytes()[..]))
+            }
+            ColumnValue::Float(number) if *ty == Type::FLOAT4 => {
+                Some(BytesMut::from(&number.to_be_bytes()[..]))
+            }
+            // Dates and timestamps are counted from 2000-01-01
+            ColumnValue::Date(date) if *ty == Type::DATE => {
+                let days = (*date - postgres_epoch().date()).num_days();
+                Some(BytesMut::from(&i32::try_from(days).ok()?.to_be_bytes()[..]))
+            }
+            ColumnValue::DateTime(date_time)
+                if *ty == Type::TIMESTAMP || *ty == Type::TIMESTAMPTZ =>
+            {
+                let micros = (*date_time - postgres_epoch()).num_microseconds()?;
+                Some(BytesMut::from(&micros.to_be_bytes()[..]))
+            }
+            value => {
+                let number = value.as_integer();
+                let bytes = match *ty {
+                    Type::INT8 => number
+                        .and_then(|number| i64::try_from(number).ok())
+                        .map(|number| number.to_be_bytes().to_vec()),
+                    Type::INT4 => number
+                        .and_then(|number| i32::try_from(number).ok())
+                        .map(|number| number.to_be_bytes().to_vec()),
+                    Type::INT2 => number
+                        .and_then(|number| i16::try_from(number).ok())
+                        .map(|number| number.to_be_bytes().to_vec()),
+                    Type::OID => number
+                        .and_then(|number| u32::try_from(number).ok())
+                        .map(|number| number.to_be_bytes().to_vec()),
+                    _ => None,
+                };
+                match bytes {
+                    Some(bytes) => Some(BytesMut::from(&bytes[..])),
+                    // The binary format of the text types is their text, like the columns that
+                    // are described as text
+                    None => self.as_str_value(ty),
+                }
+            }
+        }
     }
     fn as_str_value(&self, _: &Type) -> Option<BytesMut> {
         let mut buffer = BytesMut::new();
@@ -260,7 +386,177 @@ impl ToSqlValue for ColumnValue {
                 buffer.extend_from_slice(value.as_bytes());
                 Some(buffer)
             }
-            _ => todo!("Handle more type representations"),
+            ColumnValue::Uuid(uuid) => Some(BytesMut::from(uuid.to_string().as_str())),
+            ColumnValue::Json(document) => Some(BytesMut::from(document.get())),
+            ColumnValue::Bool(boolean) => Some(BytesMut::from(if *boolean { "t" } else { "f" })),
+            ColumnValue::I64(number) => Some(BytesMut::from(number.to_string().as_str())),
+            ColumnValue::I32(number) => Some(BytesMut::from(number.to_string().as_str())),
+            ColumnValue::I16(number) => Some(BytesMut::from(number.to_string().as_str())),
+            ColumnValue::I8(number) => Some(BytesMut::from(number.to_string().as_str())),
+            // Unsigned ids above the range of `bigint` are only written as text
+            ColumnValue::U64(number) => Some(BytesMut::from(number.to_string().as_str())),
+            ColumnValue::U32(number) => Some(BytesMut::from(number.to_string().as_str())),
+            ColumnValue::U16(number) => Some(BytesMut::from(number.to_string().as_str())),
+            ColumnValue::U8(number) => Some(BytesMut::from(number.to_string().as_str())),
+            ColumnValue::Decimal(decimal) => {
+                buffer.extend_from_slice(decimal.to_string().as_bytes());
+                Some(buffer)
+            }
+            ColumnValue::Time(time) => {
+                let seconds = time.as_secs();
+                buffer.extend_from_slice(
+                    format!(
+                        "{:02}:{:02}:{:02}",
+                        seconds / 3600,
+                        seconds / 60 % 60,
+                        seconds % 60
+                    )
+                    .as_bytes(),
+                );
+                if time.subsec_micros() > 0 {
+                    buffer.extend_from_slice(format!(".{:06}", time.subsec_micros()).as_bytes());
+                }
+                Some(buffer)
+            }
+            // Hex format of bytea
+            ColumnValue::Bytes(bytes) => {
+                buffer.extend_from_slice(b"\\x");
+                for byte in bytes {
+                    buffer.extend_from_slice(format!("{:02x}", byte).as_bytes());
+                }
+                Some(buffer)
+            }
+            ColumnValue::Double(number) => Some(BytesMut::from(float_text(*number).as_str())),
+            ColumnValue::Float(number) => {
+                Some(BytesMut::from(float_text(f64::from(*number)).as_str()))
+            }
+            ColumnValue::DateTime(date_time) => Some(BytesMut::from(
+                date_time
+                    .format("%Y-%m-%d %H:%M:%S%.f")
+                    .to_string()
+                    .as_str(),
+            )),
+            ColumnValue::Date(date) => {
+                Some(BytesMut::from(date.format("%Y-%m-%d").to_string().as_str()))
+            }
+            ColumnValue::Null => None,
+        }
+    }
+}
+
+impl ColumnValue {
+    /// Value of the integer variants
+    fn as_integer(&self) -> Option<i128> {
+        match self {
+            ColumnValue::I64(number) => Some(i128::from(*number)),
+            ColumnValue::I32(number) => Some(i128::from(*number)),
+            ColumnValue::I16(number) => Some(i128::from(*number)),
+            ColumnValue::I8(number) => Some(i128::from(*number)),
+            ColumnValue::U64(number) => Some(i128::from(*number)),
+            ColumnValue::U32(number) => Some(i128::from(*number)),
+            ColumnValue::U16(number) => Some(i128::from(*number)),
+            ColumnValue::U8(number) => Some(i128::from(*number)),
+            _ => None,
         }
     }
 }
+
+/// Start of the dates and timestamps of the binary format
+fn postgres_epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2000, 1, 1)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .expect("2000-01-01 is a valid date")
+}
+
+/// Text of a `double precision` value, with the names Postgres gives to the special values
+fn float_text(number: f64) -> String {
+    if number.is_nan() {
+        "NaN".to_string()
+    } else if number.is_infinite() {
+        if number > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        }
+        .to_string()
+    } else {
+        number.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_binary_values_of_the_described_types() {
+        let date = NaiveDate::from_ymd_opt(2000, 1, 2).unwrap();
+        for (value, ty, expected) in [
+            (ColumnValue::I8(1), Type::INT8, 1i64.to_be_bytes().to_vec()),
+            (ColumnValue::U32(7), Type::INT4, 7i32.to_be_bytes().to_vec()),
+            (ColumnValue::Bool(true), Type::BOOL, vec![1]),
+            (
+                ColumnValue::Double(1.5),
+                Type::FLOAT8,
+                1.5f64.to_be_bytes().to_vec(),
+            ),
+            (
+                ColumnValue::Date(date),
+                Type::DATE,
+                1i32.to_be_bytes().to_vec(),
+            ),
+            (ColumnValue::Bytes(vec![1, 2]), Type::BYTEA, vec![1, 2]),
+            (
+                ColumnValue::U64(u64::MAX),
+                Type::TEXT,
+                b"18446744073709551615".to_vec(),
+            ),
+            (ColumnValue::I64(10), Type::TEXT, b"10".to_vec()),
+        ] {
+            assert_eq!(
+                expected,
+                value.as_bin_value(&ty).unwrap().to_vec(),
+                "{:?}",
+                value
+            );
+        }
+        assert!(ColumnValue::Null.as_bin_value(&Type::INT8).is_none());
+    }
+
+    #[test]
+    fn test_text_values() {
+        let date_time = NaiveDate::from_ymd_opt(2022, 3, 4)
+            .unwrap()
+            .and_hms_micro_opt(5, 6, 7, 8)
+            .unwrap();
+        for (value, expected) in [
+            (ColumnValue::Double(2.5), "2.5"),
+            (ColumnValue::Float(f32::NAN), "NaN"),
+            (ColumnValue::Double(f64::NEG_INFINITY), "-Infinity"),
+            (
+                ColumnValue::DateTime(date_time),
+                "2022-03-04 05:06:07.000008",
+            ),
+            (ColumnValue::Date(date_time.date()), "2022-03-04"),
+        ] {
+            assert_eq!(
+                expected.as_bytes(),
+                &value.as_str_value(&Type::TEXT).unwrap()[..],
+                "{:?}",
+                value
+            );
+        }
+        assert!(ColumnValue::Null.as_str_value(&Type::TEXT).is_none());
+    }
+
+    #[test]
+    fn test_errors_of_the_queries() {
+        let error = query_error(SqlError::new("relation \"users\" does not exist").into());
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+        assert_eq!("relation \"users\" does not exist", error.to_string());
+        let error = query_error(UnknownDatabase("typo".to_string()).into());
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+        let error = query_error(IoError::new(ErrorKind::BrokenPipe, "closed").into());
+        assert_eq!(ErrorKind::BrokenPipe, error.kind());
+    }
+}
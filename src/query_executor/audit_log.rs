@@ -0,0 +1,404 @@
+use super::runops::SqlError;
+use super::session_state::QueryAudit;
+use super::{Columns, PreparedQuery, QueryExecutor, QueryResult, Rows, SessionState};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a client connection in the audit log. Ids are unique in the process.
+pub fn next_connection_id() -> u64 {
+    CONNECTIONS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// File where every statement of the clients is appended, one JSON object per line
+#[derive(Clone)]
+pub struct AuditLog(Arc<Mutex<AuditFile>>);
+
+struct AuditFile {
+    file: File,
+    /// First error writing the file. After it, no statement is run until fakesql is restarted.
+    failure: Option<String>,
+}
+
+impl AuditLog {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self(Arc::new(Mutex::new(AuditFile {
+            file,
+            failure: None,
+        }))))
+    }
+
+    /// Statements can only be run while their entries can be written
+    fn check(&self) -> Result<()> {
+        match &self.0.lock().unwrap().failure {
+            Some(failure) => Err(SqlError::new(failure.clone()).into()),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&self, entry: &AuditEntry) -> Result<()> {
+        let mut audit_file = self.0.lock().unwrap();
+        if let Some(failure) = &audit_file.failure {
+            return Err(SqlError::new(failure.clone()).into());
+        }
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        if let Err(error) = audit_file.file.write_all(&line) {
+            let failure = format!("Error writing the audit log: {error}");
+            println!("{failure}");
+            audit_file.failure = Some(failure.clone());
+            return Err(SqlError::new(failure).into());
+        }
+        Ok(())
+    }
+}
+
+/// Client connection whose statements are logged
+#[derive(Clone)]
+pub struct AuditedConnection {
+    pub listener: u16,
+    pub client_address: String,
+    pub connection_id: u64,
+}
+
+impl AuditedConnection {
+    /// Comment that traces the queries of the connection back to it, like
+    /// `/* fakesql conn=42 user=alice client=127.0.0.1:50000 listener=3307 */`. The user is the
+    /// one the client logged in with, when the protocol tells it.
+    pub fn query_comment(&self, user: Option<&str>) -> String {
+        let user = user
+            .map(|user| format!(" user={}", user.replace("*/", "")))
+            .unwrap_or_default();
+        format!(
+            "/* fakesql conn={}{user} client={} listener={} */",
+            self.connection_id, self.client_address, self.listener
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct AuditEntry {
+    timestamp: DateTime<Utc>,
+    listener: u16,
+    client_address: String,
+    connection_id: u64,
+    /// Statement as the client sent it
+    query: String,
+    /// Runops tasks run for it, with the final queries sent to the target
+    tasks: Vec<AuditedTask>,
+    cached: bool,
+    rows: usize,
+    duration_ms: u128,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuditedTask {
+    id: u64,
+    query: String,
+}
+
+/// Entry of a statement, written when the rows of its result are read or dropped, since pages
+/// are only run while they are read
+struct PendingEntry {
+    audit_log: AuditLog,
+    connection: AuditedConnection,
+    session_state: SessionState,
+    query: String,
+    /// When the statement was received
+    timestamp: DateTime<Utc>,
+    started: Instant,
+    rows: AtomicUsize,
+    error: Option<String>,
+    written: AtomicBool,
+}
+
+impl PendingEntry {
+    /// Writes the entry once. The result of a statement that could not be logged ends with the
+    /// error.
+    fn write(&self) -> Result<()> {
+        if self.written.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let QueryAudit { tasks, cached } = self.session_state.take_audit();
+        self.audit_log.write(&AuditEntry {
+            timestamp: self.timestamp,
+            listener: self.connection.listener,
+            client_address: self.connection.client_address.clone(),
+            connection_id: self.connection.connection_id,
+            query: self.query.clone(),
+            tasks: tasks
+                .into_iter()
+                .map(|(id, query)| AuditedTask { id, query })
+                .collect(),
+            cached,
+            rows: self.rows.load(Ordering::Relaxed),
+            duration_ms: self.started.elapsed().as_millis(),
+            error: self.error.clone(),
+        })
+    }
+}
+
+impl Drop for PendingEntry {
+    /// Results dropped before their last row. A failure is answered to the next statements.
+    fn drop(&mut self) {
+        let _ = self.write();
+    }
+}
+
+/// Writes every statement of a connection to the audit log. Without an audit log, statements
+/// are run as they are.
+pub struct QueryAuditor<T> {
+    executor: T,
+    audit_log: Option<AuditLog>,
+    connection: AuditedConnection,
+    session_state: SessionState,
+}
+
+impl<T> QueryAuditor<T> {
+    pub fn new(
+        executor: T,
+        audit_log: Option<AuditLog>,
+        connection: AuditedConnection,
+        session_state: SessionState,
+    ) -> Self {
+        Self {
+            executor,
+            audit_log,
+            connection,
+            session_state,
+        }
+    }
+}
+
+impl<T, R> QueryAuditor<T>
+where
+    T: QueryExecutor<QueryResult = R>,
+{
+    fn audited(
+        &mut self,
+        query: &str,
+        run: impl FnOnce(&mut T) -> Result<Option<R>>,
+    ) -> Result<Option<AuditedResult<R>>> {
+        let audit_log = match &self.audit_log {
+            Some(audit_log) => audit_log.clone(),
+            None => {
+                return Ok(run(&mut self.executor)?.map(|result| AuditedResult {
+                    result,
+                    entry: None,
+                }))
+            }
+        };
+        audit_log.check()?;
+        // Tasks of statements that were not logged, like the ones of other executors
+        self.session_state.take_audit();
+        let mut entry = PendingEntry {
+            audit_log,
+            connection: self.connection.clone(),
+            session_state: self.session_state.clone(),
+            query: query.to_string(),
+            timestamp: Utc::now(),
+            started: Instant::now(),
+            rows: AtomicUsize::new(0),
+            error: None,
+            written: AtomicBool::new(false),
+        };
+        match run(&mut self.executor) {
+            Ok(Some(result)) => Ok(Some(AuditedResult {
+                result,
+                entry: Some(Arc::new(entry)),
+            })),
+            Ok(None) => {
+                entry.write()?;
+                Ok(None)
+            }
+            Err(error) => {
+                entry.error = Some(error.to_string());
+                entry.write()?;
+                Err(error)
+            }
+        }
+    }
+}
+
+impl<T, R> QueryExecutor for QueryAuditor<T>
+where
+    T: QueryExecutor<QueryResult = R>,
+    R: QueryResult,
+{
+    type QueryResult = AuditedResult<R>;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        self.audited(query, |executor| executor.query(query))
+    }
+
+    fn query_prepared(&mut self, prepared: &PreparedQuery) -> Result<Option<Self::QueryResult>> {
+        self.audited(&prepared.query, |executor| {
+            executor.query_prepared(prepared)
+        })
+    }
+}
+
+pub struct AuditedResult<R> {
+    result: R,
+    entry: Option<Arc<PendingEntry>>,
+}
+
+impl<R> AuditedResult<R> {
+    fn counted(entry: &Option<Arc<PendingEntry>>, rows: Rows) -> Rows {
+        match entry.clone() {
+            Some(entry) => Box::new(rows.inspect(move |row| {
+                if row.is_ok() {
+                    entry.rows.fetch_add(1, Ordering::Relaxed);
+                }
+            })),
+            None => rows,
+        }
+    }
+
+    /// The entry is written after the last row, so the client does not get a complete result
+    /// of a statement that was not logged
+    fn logged(entry: &Option<Arc<PendingEntry>>, rows: Rows) -> Rows {
+        match entry.clone() {
+            Some(entry) => {
+                let mut rows = Self::counted(&Some(entry.clone()), rows);
+                Box::new(std::iter::from_fn(move || match rows.next() {
+                    Some(row) => Some(row),
+                    None => entry.write().err().map(Err),
+                }))
+            }
+            None => rows,
+        }
+    }
+}
+
+impl<R: QueryResult> QueryResult for AuditedResult<R> {
+    fn get_data(self) -> (Result<Columns>, Rows) {
+        let (columns, rows) = self.result.get_data();
+        (columns, Self::logged(&self.entry, rows))
+    }
+
+    fn result_sets(self) -> Vec<(Result<Columns>, Rows)> {
+        let result_sets = self.result.result_sets();
+        let last = result_sets.len().saturating_sub(1);
+        result_sets
+            .into_iter()
+            .enumerate()
+            .map(|(index, (columns, rows))| {
+                if index == last {
+                    (columns, Self::logged(&self.entry, rows))
+                } else {
+                    (columns, Self::counted(&self.entry, rows))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::{reader_result, FakeQueryExecutor};
+
+    #[test]
+    fn test_statements_are_written_to_the_audit_log() {
+        let path = std::env::temp_dir().join(format!("fakesql-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let audit_log = AuditLog::open(path.to_str().unwrap()).unwrap();
+        let session_state = SessionState::default();
+        let executor =
+            FakeQueryExecutor::new(vec![Ok(None), Ok(Some(reader_result("id\n1\n2\n")))]);
+        let mut auditor = QueryAuditor::new(
+            executor,
+            Some(audit_log),
+            AuditedConnection {
+                listener: 3306,
+                client_address: "127.0.0.1:50000".to_string(),
+                connection_id: 7,
+            },
+            session_state.clone(),
+        );
+        let result = auditor.query("select id from users").unwrap().unwrap();
+        let received = Utc::now();
+        session_state.add_task(42, "USE app;\nselect id from users");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let (_, rows) = result.get_data();
+        assert_eq!(2, rows.count());
+        auditor.query("select 1").unwrap();
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(2, lines.len());
+        let timestamp: DateTime<Utc> =
+            serde_json::from_value(lines[0]["timestamp"].clone()).unwrap();
+        assert!(timestamp <= received, "{timestamp} > {received}");
+        assert_eq!(3306, lines[0]["listener"]);
+        assert_eq!("127.0.0.1:50000", lines[0]["client_address"]);
+        assert_eq!(7, lines[0]["connection_id"]);
+        assert_eq!("select id from users", lines[0]["query"]);
+        assert_eq!(42, lines[0]["tasks"][0]["id"]);
+        assert_eq!(
+            "USE app;\nselect id from users",
+            lines[0]["tasks"][0]["query"]
+        );
+        assert_eq!(Some(false), lines[0]["cached"].as_bool());
+        assert_eq!(2, lines[0]["rows"]);
+        assert_eq!("select 1", lines[1]["query"]);
+        assert_eq!(0, lines[1]["rows"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_statements_are_refused_when_the_audit_log_can_not_be_written() {
+        let audit_log = AuditLog::open("/dev/full").unwrap();
+        let executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result("id\n1\n")))]);
+        let mut auditor = QueryAuditor::new(
+            executor,
+            Some(audit_log),
+            AuditedConnection {
+                listener: 3306,
+                client_address: "127.0.0.1:50000".to_string(),
+                connection_id: 7,
+            },
+            SessionState::default(),
+        );
+        let (_, rows) = auditor
+            .query("select id from users")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        let rows: Vec<_> = rows.collect();
+        assert_eq!(2, rows.len());
+        assert!(rows[0].is_ok());
+        assert!(rows[1].as_ref().unwrap_err().is::<SqlError>());
+
+        let error = auditor.query("select 1").err().unwrap();
+        assert!(error.to_string().contains("audit log"), "{}", error);
+    }
+
+    #[test]
+    fn test_query_comment_names_the_connection() {
+        let comment = AuditedConnection {
+            listener: 3307,
+            client_address: "127.0.0.1:50000".to_string(),
+            connection_id: 42,
+        }
+        .query_comment(Some("alice"));
+        assert_eq!(
+            "/* fakesql conn=42 user=alice client=127.0.0.1:50000 listener=3307 */",
+            comment
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Decides which queries can be cached. Entries are matched against the exact query text, unless
+/// they start with `regex:` or `glob:`, in which case the whole query must match the pattern.
+#[derive(Clone, Default)]
+pub struct CacheRules {
+    exact: HashMap<String, RuleOptions>,
+    /// Entries with `normalize`, by their normalized text
+    normalized: HashMap<String, (String, RuleOptions)>,
+    patterns: Vec<(Regex, RuleOptions)>,
+}
+
+/// One entry of a structured cache list
+#[derive(Deserialize, Clone)]
+pub struct CacheRule {
+    pub query: String,
+    /// Seconds the results of this entry stay valid, overriding the listener `cache_ttl`
+    pub ttl: Option<u64>,
+    /// Ignores case and whitespace differences when matching the query
+    pub normalize: Option<bool>,
+}
+
+#[derive(Clone, Default)]
+pub struct RuleOptions {
+    pub ttl: Option<Duration>,
+    normalize: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CacheListEntry {
+    Query(String),
+    Rule(CacheRule),
+}
+
+impl From<String> for CacheRule {
+    fn from(query: String) -> Self {
+        Self {
+            query,
+            ttl: None,
+            normalize: None,
+        }
+    }
+}
+
+impl CacheRules {
+    pub fn new(entries: impl IntoIterator<Item = String>) -> Result<Self> {
+        Self::from_rules(entries.into_iter().map(CacheRule::from))
+    }
+
+    pub fn from_rules(rules: impl IntoIterator<Item = CacheRule>) -> Result<Self> {
+        let mut cache_rules = Self::default();
+        for rule in rules {
+            let options = RuleOptions {
+                ttl: rule.ttl.map(Duration::from_secs),
+                normalize: rule.normalize.unwrap_or(false),
+            };
+            if let Some(regex) = entry_pattern(&rule.query, options.normalize) {
+                cache_rules.patterns.push((regex?, options));
+            } else if options.normalize {
+                cache_rules
+                    .normalized
+                    .insert(normalize_query(&rule.query), (rule.query, options));
+            } else {
+                cache_rules.exact.insert(rule.query, options);
+            }
+        }
+        Ok(cache_rules)
+    }
+
+    /// Queries that are cached by their exact text. Patterns are not included.
+    pub fn exact_queries(&self) -> impl Iterator<Item = &String> {
+        self.exact
+            .keys()
+            .chain(self.normalized.values().map(|(query, _)| query))
+    }
+
+    pub fn matches(&self, query: &str) -> bool {
+        self.find(query).is_some()
+    }
+
+    /// Options of the first entry matching the query
+    pub fn find(&self, query: &str) -> Option<&RuleOptions> {
+        if let Some(options) = self.exact.get(query) {
+            return Some(options);
+        }
+        let normalized_query = normalize_query(query);
+        if let Some((_, options)) = self.normalized.get(&normalized_query) {
+            return Some(options);
+        }
+        self.patterns
+            .iter()
+            .find(|(regex, options)| {
+                if options.normalize {
+                    regex.is_match(&normalized_query)
+                } else {
+                    regex.is_match(query)
+                }
+            })
+            .map(|(_, options)| options)
+    }
+}
+
+/// Reads a cache list file. `.yml`, `.yaml` and `.json` files are a list of queries or
+/// `CacheRule`s. Any other file has the queries separated by `|` followed by a new line.
+pub fn load_cache_list(path: impl AsRef<Path>) -> Result<Vec<CacheRule>> {
+    let content = std::fs::read_to_string(&path)?;
+    let structured = matches!(
+        path.as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str()),
+        Some("yml" | "yaml" | "json")
+    );
+    if !structured {
+        return Ok(content
+            .split("|\n")
+            .map(|query| CacheRule::from(query.to_string()))
+            .collect());
+    }
+    let entries: Vec<CacheListEntry> = serde_yaml::from_str(&content)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| match entry {
+            CacheListEntry::Query(query) => CacheRule::from(query),
+            CacheListEntry::Rule(rule) => rule,
+        })
+        .collect())
+}
+
+fn normalize_query(query: &str) -> String {
+    query
+        .trim()
+        .trim_end_matches(';')
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Regex matching the whole query for entries starting with `regex:` or `glob:`
+fn entry_pattern(entry: &str, case_insensitive: bool) -> Option<Result<Regex>> {
+    if let Some(pattern) = entry.trim_start().strip_prefix("regex:") {
+        Some(build_regex(pattern.trim(), case_insensitive))
+    } else {
+        let pattern = entry.trim_start().strip_prefix("glob:")?;
+        Some(build_regex(
+            &glob_to_regex(pattern.trim()),
+            case_insensitive,
+        ))
+    }
+}
+
+/// Regex of an entry written like the ones of cache lists, which matches the exact query unless it
+/// starts with `regex:` or `glob:`
+pub fn query_pattern(entry: &str) -> Result<Regex> {
+    entry_pattern(entry, false).unwrap_or_else(|| build_regex(&regex::escape(entry), false))
+}
+
+/// Deserializes a list of entries written like the ones of cache lists
+pub fn deserialize_query_patterns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<Regex>>, D::Error> {
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|entries| entries.iter().map(|entry| query_pattern(entry)).collect())
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn build_regex(pattern: &str, case_insensitive: bool) -> Result<Regex> {
+    Ok(RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .dot_matches_new_line(true)
+        .case_insensitive(case_insensitive)
+        .build()?)
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    glob.chars()
+        .map(|character| match character {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            character => regex::escape(&character.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rules_matching() {
+        let rules = CacheRules::new([
+            "select 1".to_string(),
+            r"regex:SELECT \* FROM information_schema\..*".to_string(),
+            "glob:show full columns from `?`".to_string(),
+        ])
+        .unwrap();
+        let rules_data_provider = [
+            ("select 1", true),
+            ("select 12", false),
+            ("SELECT * FROM information_schema.tables", true),
+            ("SELECT * FROM information_schema.columns\nWHERE 1", true),
+            ("SELECT id FROM information_schema.columns", false),
+            ("show full columns from `a`", true),
+            ("show full columns from `ab`", false),
+        ];
+        for (query, should_match) in rules_data_provider {
+            assert_eq!(should_match, rules.matches(query), "{}", query);
+        }
+        assert!(CacheRules::new(["regex:(".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_structured_cache_list() {
+        let path =
+            std::env::temp_dir().join(format!("my-fake-sql-cache-list-{}.yml", std::process::id()));
+        std::fs::write(
+            &path,
+            "- select a | b from users\n- query: SELECT *  FROM payments;\n  ttl: 60\n  normalize: true\n",
+        )
+        .unwrap();
+        let rules = CacheRules::from_rules(load_cache_list(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(rules.matches("select a | b from users"));
+        assert!(rules.find("select a | b from users").unwrap().ttl.is_none());
+        let options = rules.find("select *\nfrom PAYMENTS").unwrap();
+        assert_eq!(Some(Duration::from_secs(60)), options.ttl);
+        assert!(!rules.matches("select * from payments where id = 1"));
+    }
+}
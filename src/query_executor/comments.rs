@@ -0,0 +1,330 @@
+use super::SqlDialect;
+
+/// Removes the comments of a query: `/* ... */` blocks, `--` until the end of the line, and `#`
+/// until the end of the line, as the dialect writes them. Quoted strings and identifiers,
+/// including dollar quoted strings of Postgres, are copied as they are. Optimizer hints, like
+/// `/*+ NO_INDEX(t) */`, are kept, and so are versioned comments of MySQL, like
+/// `/*!40101 SET NAMES utf8 */`, with `keep_versioned`.
+pub fn strip_comments(query: &str, keep_versioned: bool, dialect: SqlDialect) -> String {
+    let chars: Vec<char> = query.chars().collect();
+    let mut stripped = String::with_capacity(query.len());
+    let mut index = 0;
+    while index < chars.len() {
+        let rest = &chars[index..];
+        let kept_length = literal_length(rest, stripped.chars().last(), dialect)
+            .or_else(|| kept_comment_length(rest, keep_versioned));
+        if let Some(length) = kept_length {
+            stripped.extend(&rest[..length]);
+            index += length;
+        } else if let Some(length) = comment_length(rest, at_line_start(&stripped), dialect) {
+            index += length;
+            stripped.truncate(stripped.trim_end_matches([' ', '\t']).len());
+            match chars.get(index) {
+                // Comments on their own line are removed with the line
+                Some('\n') if stripped.is_empty() || stripped.ends_with('\n') => index += 1,
+                // Words around the comment stay apart
+                Some(next)
+                    if !next.is_whitespace()
+                        && !stripped.is_empty()
+                        && !stripped.ends_with(char::is_whitespace) =>
+                {
+                    stripped.push(' ')
+                }
+                _ => {}
+            }
+        } else {
+            stripped.push(rest[0]);
+            index += 1;
+        }
+    }
+    stripped
+}
+
+/// The query as MySQL runs it, to check what the target gets. Versioned comments, like
+/// `/*!50000 SELECT 1 */`, are replaced by their text, and the other comments are removed.
+pub fn unwrap_versioned_comments(query: &str, dialect: SqlDialect) -> String {
+    let stripped = strip_comments(query, true, dialect);
+    replace_outside_literals(&stripped, dialect, |rest, _| {
+        if !matches!(rest, ['/', '*', '!', ..]) {
+            return None;
+        }
+        let length = block_comment_length(rest)?;
+        let terminated = length >= 5 && rest[..length].ends_with(&['*', '/']);
+        let end = if terminated { length - 2 } else { length };
+        let text: String = rest[3..end]
+            .iter()
+            .skip_while(|character| character.is_ascii_digit())
+            .collect();
+        Some((length, format!(" {text} ")))
+    })
+}
+
+/// Copies the query, letting `replace` change the text outside of quoted strings and identifiers.
+/// It gets the rest of the query and the last copied character, and answers how many characters it
+/// replaces and their replacement.
+pub fn replace_outside_literals(
+    query: &str,
+    dialect: SqlDialect,
+    mut replace: impl FnMut(&[char], Option<char>) -> Option<(usize, String)>,
+) -> String {
+    let chars: Vec<char> = query.chars().collect();
+    let mut replaced = String::with_capacity(query.len());
+    let mut index = 0;
+    while index < chars.len() {
+        let rest = &chars[index..];
+        let previous = replaced.chars().last();
+        if let Some(length) = literal_length(rest, previous, dialect) {
+            replaced.extend(&rest[..length]);
+            index += length;
+        } else if let Some((length, replacement)) = replace(rest, previous) {
+            replaced.push_str(&replacement);
+            index += length;
+        } else {
+            replaced.push(rest[0]);
+            index += 1;
+        }
+    }
+    replaced
+}
+
+/// Names written in the query, outside of strings. Quoted identifiers are unquoted. Strings are
+/// found like the generic dialect does, for the queries of any target.
+pub fn words(query: &str) -> Vec<Vec<String>> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut text = String::with_capacity(query.len());
+    let mut index = 0;
+    while index < chars.len() {
+        let rest = &chars[index..];
+        match literal_length(rest, text.chars().last(), SqlDialect::Generic) {
+            Some(length) if rest[0] == '`' => {
+                text.extend(
+                    rest[1..length]
+                        .iter()
+                        .filter(|character| **character != '`'),
+                );
+                index += length;
+            }
+            Some(length) => {
+                text.push(' ');
+                index += length;
+            }
+            None => {
+                text.push(rest[0]);
+                index += 1;
+            }
+        }
+    }
+    text.split(|character: char| {
+        !(character.is_alphanumeric() || matches!(character, '_' | '$' | '.' | '@'))
+    })
+    // Numbers and variables, like `1.5` or `@@session.autocommit`, are not tables
+    .filter(|word| {
+        !word.is_empty()
+            && !word.starts_with(|character: char| character.is_ascii_digit() || character == '@')
+    })
+    .map(|word| word.split('.').map(str::to_string).collect())
+    .collect()
+}
+
+fn at_line_start(stripped: &str) -> bool {
+    stripped
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .is_empty()
+}
+
+/// Length of the quoted string or identifier at the start of the text. Unterminated ones last
+/// until the end. Backslashes escape quotes in the strings of MySQL, but only in the `E'...'`
+/// strings of Postgres, which is also the only one with dollar quoted strings.
+pub fn literal_length(rest: &[char], previous: Option<char>, dialect: SqlDialect) -> Option<usize> {
+    let escapes = |quote: char| match dialect {
+        SqlDialect::MySql | SqlDialect::MariaDb | SqlDialect::Generic => quote != '`',
+        SqlDialect::Postgres => quote == '\'' && matches!(previous, Some('e' | 'E')),
+    };
+    match *rest.first()? {
+        quote @ ('\'' | '"' | '`') => {
+            let escapes = escapes(quote);
+            let mut index = 1;
+            while index < rest.len() {
+                match rest[index] {
+                    '\\' if escapes => index += 2,
+                    // Doubled quotes are part of the literal
+                    character if character == quote && rest.get(index + 1) == Some(&quote) => {
+                        index += 2
+                    }
+                    character if character == quote => return Some(index + 1),
+                    _ => index += 1,
+                }
+            }
+            Some(rest.len())
+        }
+        // Dollar quotes, like `$$` or `$body$`, that are not part of an identifier or a parameter
+        '$' if !matches!(dialect, SqlDialect::MySql | SqlDialect::MariaDb)
+            && !previous.is_some_and(|previous| previous.is_alphanumeric() || previous == '_') =>
+        {
+            let tag_length = rest[1..].iter().position(|character| *character == '$')? + 2;
+            let tag = &rest[..tag_length];
+            let name = &tag[1..tag_length - 1];
+            if name.first().is_some_and(char::is_ascii_digit)
+                || !name
+                    .iter()
+                    .all(|character| character.is_alphanumeric() || *character == '_')
+            {
+                return None;
+            }
+            let end = rest[tag_length..]
+                .windows(tag_length)
+                .position(|window| window == tag);
+            Some(end.map_or(rest.len(), |end| end + 2 * tag_length))
+        }
+        _ => None,
+    }
+}
+
+/// Length of the hint or versioned comment at the start of the text, which the target runs
+fn kept_comment_length(rest: &[char], keep_versioned: bool) -> Option<usize> {
+    match rest {
+        ['/', '*', '+', ..] => block_comment_length(rest),
+        ['/', '*', '!', ..] if keep_versioned => block_comment_length(rest),
+        _ => None,
+    }
+}
+
+/// Length of the comment at the start of the text, without the new line that ends it. MySQL only
+/// takes `--` followed by a space as a comment, since `5--3` is `5 - -3`. `#` is a comment
+/// anywhere in MySQL and an operator of Postgres, so the generic dialect only takes it at the
+/// start of a line.
+fn comment_length(rest: &[char], at_line_start: bool, dialect: SqlDialect) -> Option<usize> {
+    let line_end = || {
+        rest.iter()
+            .position(|character| *character == '\n')
+            .unwrap_or(rest.len())
+    };
+    let is_mysql = matches!(dialect, SqlDialect::MySql | SqlDialect::MariaDb);
+    match rest {
+        ['-', '-', next, ..] if is_mysql && !next.is_whitespace() && !next.is_control() => None,
+        ['-', '-', ..] => Some(line_end()),
+        ['#', ..] => match dialect {
+            SqlDialect::MySql | SqlDialect::MariaDb => Some(line_end()),
+            SqlDialect::Postgres => None,
+            SqlDialect::Generic => at_line_start.then(line_end),
+        },
+        ['/', '*', ..] => block_comment_length(rest),
+        _ => None,
+    }
+}
+
+/// Length of the `/* ... */` comment at the start of the text. Unterminated ones last until the end.
+fn block_comment_length(rest: &[char]) -> Option<usize> {
+    match rest {
+        ['/', '*', ..] => Some(
+            rest[2..]
+                .windows(2)
+                .position(|window| window == ['*', '/'])
+                .map_or(rest.len(), |end| end + 4),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_comments_are_stripped_outside_literals() {
+        let comments_data_provider = [
+            ("/* app */ SELECT 1", " SELECT 1"),
+            ("SELECT a/* b */FROM t", "SELECT a FROM t"),
+            (
+                "SELECT 1 -- one\nFROM t # not a comment",
+                "SELECT 1\nFROM t # not a comment",
+            ),
+            ("-- first\n# second\nSELECT 1", "SELECT 1"),
+            (
+                "SELECT '-- no', \"/* no */\", `#`",
+                "SELECT '-- no', \"/* no */\", `#`",
+            ),
+            (
+                "SELECT 'it''s -- kept', 'a\\' -- b' -- c",
+                "SELECT 'it''s -- kept', 'a\\' -- b'",
+            ),
+            ("SELECT $$ -- kept $$, $1 -- c", "SELECT $$ -- kept $$, $1"),
+            (
+                "SELECT $body$ /* kept */ $body$ /* c */",
+                "SELECT $body$ /* kept */ $body$",
+            ),
+            (
+                "SELECT 'unterminated -- kept",
+                "SELECT 'unterminated -- kept",
+            ),
+        ];
+        for (query, expected) in comments_data_provider {
+            assert_eq!(
+                expected,
+                strip_comments(query, true, SqlDialect::Generic),
+                "{}",
+                query
+            );
+        }
+        assert_eq!(
+            "SELECT /*+ NO_ICP(t) */ 1   , 2   FROM   t  ",
+            unwrap_versioned_comments(
+                "SELECT /*+ NO_ICP(t) */ 1 /*!50001 , 2 */ FROM /*! t */ -- c",
+                SqlDialect::MySql
+            )
+        );
+        let query = "SELECT /*+ MAX_EXECUTION_TIME(1000) */ 1 /*!50001 , 2 */";
+        assert_eq!(query, strip_comments(query, true, SqlDialect::MySql));
+        assert_eq!(
+            "SELECT /*+ MAX_EXECUTION_TIME(1000) */ 1",
+            strip_comments(query, false, SqlDialect::MySql)
+        );
+    }
+
+    #[test]
+    fn test_comments_and_strings_of_each_dialect() {
+        let comments_data_provider = [
+            (SqlDialect::MySql, "SELECT 5--3 -- c", "SELECT 5--3"),
+            (
+                SqlDialect::MySql,
+                "SELECT 1 # c\nFROM t",
+                "SELECT 1\nFROM t",
+            ),
+            (
+                SqlDialect::MySql,
+                "SELECT 'a\\' -- b' -- c",
+                "SELECT 'a\\' -- b'",
+            ),
+            (SqlDialect::MySql, "SELECT $a$ -- c $a$", "SELECT $a$"),
+            (
+                SqlDialect::Postgres,
+                "SELECT 5--3\nFROM t",
+                "SELECT 5\nFROM t",
+            ),
+            (SqlDialect::Postgres, "SELECT 1 # 2 -- c", "SELECT 1 # 2"),
+            (SqlDialect::Postgres, "SELECT 'a\\' -- c", "SELECT 'a\\'"),
+            (
+                SqlDialect::Postgres,
+                "SELECT E'a\\' -- b' -- c",
+                "SELECT E'a\\' -- b'",
+            ),
+            (
+                SqlDialect::Postgres,
+                "SELECT \"a\\\" -- c",
+                "SELECT \"a\\\"",
+            ),
+        ];
+        for (dialect, query, expected) in comments_data_provider {
+            assert_eq!(
+                expected,
+                strip_comments(query, true, dialect),
+                "{:?} {}",
+                dialect,
+                query
+            );
+        }
+    }
+}
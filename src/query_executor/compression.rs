@@ -0,0 +1,53 @@
+use super::Row;
+use anyhow::Result;
+use serde::Deserialize;
+
+/// Algorithm used to compress the rows of cached results
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub fn compress(&self, rows: &[Row]) -> Result<Vec<u8>> {
+        let serialized = serde_json::to_vec(rows)?;
+        Ok(match self {
+            Compression::Lz4 => lz4_flex::compress_prepend_size(&serialized),
+            Compression::Zstd => zstd::encode_all(serialized.as_slice(), 0)?,
+        })
+    }
+
+    pub fn decompress(&self, compressed: &[u8]) -> Result<Vec<Row>> {
+        let serialized = match self {
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(compressed)?,
+            Compression::Zstd => zstd::decode_all(compressed)?,
+        };
+        Ok(serde_json::from_slice(&serialized)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::ColumnValue;
+
+    #[test]
+    fn test_compressed_rows_round_trip() {
+        let rows = vec![
+            vec![
+                ColumnValue::I64(1),
+                ColumnValue::String("Alice".to_string()),
+            ],
+            vec![ColumnValue::I64(2), ColumnValue::Null],
+        ];
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let compressed = compression.compress(&rows).unwrap();
+            let decompressed = compression.decompress(&compressed).unwrap();
+            assert_eq!(2, decompressed.len());
+            assert!(matches!(&decompressed[0][1], ColumnValue::String(name) if name == "Alice"));
+            assert!(matches!(decompressed[1][1], ColumnValue::Null));
+        }
+    }
+}
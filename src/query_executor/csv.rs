@@ -0,0 +1,83 @@
+use std::io::{BufRead, Error, ErrorKind, Result};
+
+/// Field of a record of a result. Quoted fields are always text, even when they look like the null
+/// token.
+pub struct Field {
+    pub value: String,
+    pub quoted: bool,
+}
+
+impl Field {
+    pub fn new(value: String) -> Self {
+        Self {
+            value,
+            quoted: false,
+        }
+    }
+}
+
+/// Reads the next record of RFC 4180 CSV. Quoted fields can have delimiters, quotes written as
+/// `""` and new lines, so a record can take more than one line.
+pub fn read_record(reader: &mut dyn BufRead, delimiter: char) -> Result<Option<Vec<Field>>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut fields = Vec::new();
+    let mut field = Field::new(String::new());
+    let mut in_quotes = false;
+    loop {
+        let mut characters = line.chars().peekable();
+        while let Some(character) = characters.next() {
+            match (in_quotes, character) {
+                (true, '"') if characters.peek() == Some(&'"') => {
+                    characters.next();
+                    field.value.push('"');
+                }
+                (true, '"') => in_quotes = false,
+                (true, character) => field.value.push(character),
+                (false, '"') if field.value.is_empty() && !field.quoted => {
+                    in_quotes = true;
+                    field.quoted = true;
+                }
+                (false, character) if character == delimiter => {
+                    fields.push(std::mem::replace(&mut field, Field::new(String::new())))
+                }
+                (false, '\r' | '\n') => {}
+                (false, character) => field.value.push(character),
+            }
+        }
+        if !in_quotes {
+            break;
+        }
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Quoted field of CSV result is not closed",
+            ));
+        }
+    }
+    fields.push(field);
+    Ok(Some(fields))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_quoted_fields_can_have_commas_quotes_and_new_lines() {
+        let mut reader = Cursor::new("1,\"a, \"\"b\"\"\r\nc\",NULL,\"NULL\"\r\n2,,x,\n");
+        let record = read_record(&mut reader, ',').unwrap().unwrap();
+        let values: Vec<&str> = record.iter().map(|field| field.value.as_str()).collect();
+        assert_eq!(vec!["1", "a, \"b\"\r\nc", "NULL", "NULL"], values);
+        assert!(!record[2].quoted && record[3].quoted);
+        let record = read_record(&mut reader, ',').unwrap().unwrap();
+        let values: Vec<&str> = record.iter().map(|field| field.value.as_str()).collect();
+        assert_eq!(vec!["2", "", "x", ""], values);
+        assert!(read_record(&mut reader, ',').unwrap().is_none());
+        assert!(read_record(&mut Cursor::new("\"open\n"), ',').is_err());
+    }
+}
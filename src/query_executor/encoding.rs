@@ -0,0 +1,77 @@
+use encoding_rs::{Decoder, Encoding};
+use std::io::{Read, Result};
+
+/// Reads the output of targets that do not write UTF-8 as UTF-8
+pub struct DecodingReader<R> {
+    reader: R,
+    decoder: Decoder,
+    decoded: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R> DecodingReader<R> {
+    pub fn new(reader: R, encoding: &'static Encoding) -> Self {
+        Self {
+            reader,
+            decoder: encoding.new_decoder(),
+            decoded: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        while self.position == self.decoded.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            let mut input = [0; 8192];
+            let read = self.reader.read(&mut input)?;
+            self.finished = read == 0;
+            let mut decoded = String::with_capacity(
+                self.decoder
+                    .max_utf8_buffer_length(read)
+                    .unwrap_or(read * 3 + 16),
+            );
+            // Characters split between reads are kept by the decoder until the next one
+            let _ = self
+                .decoder
+                .decode_to_string(&input[..read], &mut decoded, self.finished);
+            self.decoded = decoded.into_bytes();
+            self.position = 0;
+        }
+        let length = buffer.len().min(self.decoded.len() - self.position);
+        buffer[..length].copy_from_slice(&self.decoded[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_output_is_read_as_utf8() {
+        let mut latin1 = String::new();
+        DecodingReader::new(
+            Cursor::new(b"caf\xe9\tna\xefve\n".to_vec()),
+            encoding_rs::WINDOWS_1252,
+        )
+        .read_to_string(&mut latin1)
+        .unwrap();
+        assert_eq!("café\tnaïve\n", latin1);
+        let mut cyrillic = String::new();
+        DecodingReader::new(
+            Cursor::new(b"\xcf\xf0\xe8\xe2\xe5\xf2".to_vec()),
+            encoding_rs::WINDOWS_1251,
+        )
+        .read_to_string(&mut cyrillic)
+        .unwrap();
+        assert_eq!("Привет", cyrillic);
+    }
+}
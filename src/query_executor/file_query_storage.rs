@@ -0,0 +1,246 @@
+use super::query_cache::{CachedEntryInfo, CachedResult, QueryStorage};
+use super::InMemoryQueryStorage;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Query storage that keeps every stored result in memory and appends it to a file, so the
+/// cache survives restarts. Each line of the file is one json encoded entry and later entries
+/// override earlier ones for the same query.
+pub struct FileQueryStorage {
+    memory: InMemoryQueryStorage,
+    path: PathBuf,
+    file: Mutex<CacheFile>,
+}
+
+struct CacheFile {
+    file: File,
+    /// Lines written to the file, including the ones of replaced, evicted and removed entries
+    lines: usize,
+}
+
+/// The file is rewritten with the entries in memory when it has more than this many lines and
+/// more than twice as many lines as entries
+const MIN_COMPACTION_LINES: usize = 1000;
+
+#[derive(Serialize, Deserialize)]
+struct FileEntry {
+    query: String,
+    #[serde(flatten)]
+    result: CachedResult,
+}
+
+impl FileQueryStorage {
+    /// Loads the file into `memory`, which keeps the results while the proxy runs. The file is
+    /// compacted, so expired and evicted entries are left out of it.
+    pub fn load(path: impl AsRef<Path>, memory: InMemoryQueryStorage) -> Result<Self> {
+        let exists = path.as_ref().exists();
+        if exists {
+            for entry in read_entries(&path)? {
+                memory.insert(entry.query, entry.result);
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let storage = Self {
+            memory,
+            path: path.as_ref().to_path_buf(),
+            file: Mutex::new(CacheFile { file, lines: 0 }),
+        };
+        if exists {
+            storage.compact()?;
+        }
+        Ok(storage)
+    }
+
+    /// Rewrites the file with only the entries that are currently in memory
+    fn compact(&self) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let temporary_path = self.path.with_extension("compacting");
+        let lines = write_entries(&temporary_path, self.memory.entries())?;
+        std::fs::rename(&temporary_path, &self.path)?;
+        *file = CacheFile {
+            file: OpenOptions::new().append(true).open(&self.path)?,
+            lines,
+        };
+        Ok(())
+    }
+
+    fn append(&self, entry: &FileEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.file.write_all(line.as_bytes())?;
+        file.lines += 1;
+        Ok(file.file.flush()?)
+    }
+
+    fn needs_compaction(&self) -> bool {
+        let lines = self.file.lock().unwrap().lines;
+        lines > MIN_COMPACTION_LINES && lines > 2 * self.memory.entry_count()
+    }
+}
+
+/// Reads the valid and not expired entries of a cache file
+fn read_entries(path: impl AsRef<Path>) -> Result<Vec<FileEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        match serde_json::from_str::<FileEntry>(&line) {
+            Ok(entry) if entry.result.is_expired() => {}
+            Ok(entry) => entries.push(entry),
+            // A crash in the middle of a write can leave a truncated last line
+            Err(error) => println!("Ignoring invalid cache entry: {error}"),
+        }
+    }
+    Ok(entries)
+}
+
+fn write_entries(path: impl AsRef<Path>, entries: Vec<(String, CachedResult)>) -> Result<usize> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let written = entries.len();
+    for (query, result) in entries {
+        serde_json::to_writer(&mut writer, &FileEntry { query, result })?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+/// Writes every cached result of the storage to a snapshot file, using the same format as the
+/// cache file. Returns how many entries were exported.
+pub fn export_snapshot<S: QueryStorage + ?Sized>(
+    storage: &S,
+    path: impl AsRef<Path>,
+) -> Result<usize> {
+    write_entries(path, storage.entries())
+}
+
+/// Stores every not expired entry of a snapshot file. Returns how many entries were imported.
+pub fn import_snapshot<S: QueryStorage + ?Sized>(
+    storage: &S,
+    path: impl AsRef<Path>,
+) -> Result<usize> {
+    let entries = read_entries(path)?;
+    let imported = entries.len();
+    for entry in entries {
+        storage.store(&entry.query, entry.result);
+    }
+    Ok(imported)
+}
+
+impl QueryStorage for FileQueryStorage {
+    fn store(&self, query: &str, result: CachedResult) {
+        let entry = FileEntry {
+            query: query.to_string(),
+            result,
+        };
+        if let Err(error) = self.append(&entry) {
+            println!("Error persisting cached query: {error}");
+        }
+        self.memory.insert(entry.query, entry.result);
+        if self.needs_compaction() {
+            if let Err(error) = self.compact() {
+                println!("Error compacting the cache file: {error}");
+            }
+        }
+    }
+
+    fn get(&self, query: &str) -> Option<CachedResult> {
+        self.memory.get(query)
+    }
+
+    fn get_stale(&self, query: &str) -> Option<CachedResult> {
+        self.memory.get_stale(query)
+    }
+
+    fn remove_where(&self, predicate: &dyn Fn(&str) -> bool) -> usize {
+        let removed = self.memory.remove_where(predicate);
+        if removed > 0 {
+            if let Err(error) = self.compact() {
+                println!("Error compacting the cache file: {error}");
+            }
+        }
+        removed
+    }
+
+    fn list(&self) -> Vec<CachedEntryInfo> {
+        self.memory.list()
+    }
+
+    fn entries(&self) -> Vec<(String, CachedResult)> {
+        self.memory.entries()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::{Column, ColumnValue};
+
+    #[test]
+    fn test_results_survive_reload() {
+        let path = std::env::temp_dir().join(format!(
+            "my-fake-sql-file-storage-{}.cache",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let storage =
+            FileQueryStorage::load(&path, InMemoryQueryStorage::with_max_bytes(None)).unwrap();
+        storage.store(
+            "select id from users",
+            CachedResult::new(
+                vec![Column {
+                    name: "id".to_string(),
+                    ty: Some("bigint".to_string()),
+                    attributes: Default::default(),
+                }],
+                vec![vec![ColumnValue::I64(1)], vec![ColumnValue::Null]],
+                None,
+            ),
+        );
+        drop(storage);
+
+        let storage =
+            FileQueryStorage::load(&path, InMemoryQueryStorage::with_max_bytes(None)).unwrap();
+        let result = storage.get("select id from users").unwrap();
+        assert_eq!("id", result.columns[0].name);
+        assert_eq!(2, result.rows.len());
+        assert!(matches!(result.rows[0][0], ColumnValue::I64(1)));
+        assert!(storage.get("select name from users").is_none());
+
+        assert_eq!(1, storage.remove_where(&|query| query.contains("users")));
+        drop(storage);
+        let storage =
+            FileQueryStorage::load(&path, InMemoryQueryStorage::with_max_bytes(None)).unwrap();
+        assert!(storage.get("select id from users").is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replaced_results_are_compacted() {
+        let path = std::env::temp_dir().join(format!(
+            "my-fake-sql-file-storage-compaction-{}.cache",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let storage =
+            FileQueryStorage::load(&path, InMemoryQueryStorage::with_max_bytes(None)).unwrap();
+        for _ in 0..=MIN_COMPACTION_LINES * 2 {
+            storage.store("select 1", CachedResult::new(vec![], vec![], None));
+        }
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines <= MIN_COMPACTION_LINES, "{lines} lines");
+        drop(storage);
+
+        // Loading leaves the file with the entries in memory alone
+        let storage =
+            FileQueryStorage::load(&path, InMemoryQueryStorage::with_max_bytes(None)).unwrap();
+        assert!(storage.get("select 1").is_some());
+        assert_eq!(1, std::fs::read_to_string(&path).unwrap().lines().count());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+/// Types of spatial columns of MySQL and PostGIS
+pub const GEOMETRY_TYPES: &[&str] = &[
+    "geometry",
+    "geography",
+    "point",
+    "linestring",
+    "polygon",
+    "multipoint",
+    "multilinestring",
+    "multipolygon",
+    "geometrycollection",
+    "geomcollection",
+];
+
+/// WKT of geometries written as WKB, like PostGIS does, or with the SRID before the WKB, like MySQL
+/// does. Geometries with Z or M coordinates are not converted.
+pub fn wkt(bytes: &[u8]) -> Option<String> {
+    [bytes, bytes.get(4..).unwrap_or_default()]
+        .into_iter()
+        .find_map(|bytes| {
+            let mut reader = Reader { bytes, position: 0 };
+            let (name, body) = reader.geometry()?;
+            (reader.position == bytes.len()).then(|| format!("{}{}", name, body))
+        })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    /// Name and coordinates of the next geometry
+    fn geometry(&mut self) -> Option<(&'static str, String)> {
+        let little_endian = match self.take::<1>()? {
+            [0] => false,
+            [1] => true,
+            _ => return None,
+        };
+        let mut ty = self.u32(little_endian)?;
+        // Extended WKB of PostGIS has the SRID after the type
+        if ty & 0x2000_0000 != 0 {
+            self.u32(little_endian)?;
+            ty &= !0x2000_0000;
+        }
+        let body = match ty {
+            1 => format!("({})", self.point(little_endian)?),
+            2 => format!("({})", self.points(little_endian)?),
+            3 => self.rings(little_endian)?,
+            4..=7 => {
+                let count = self.u32(little_endian)?;
+                let geometries = (0..count)
+                    .map(|_| {
+                        let (name, body) = self.geometry()?;
+                        Some(match ty {
+                            7 => format!("{}{}", name, body),
+                            _ => body,
+                        })
+                    })
+                    .collect::<Option<Vec<String>>>()?;
+                format!("({})", geometries.join(","))
+            }
+            _ => return None,
+        };
+        let name = [
+            "POINT",
+            "LINESTRING",
+            "POLYGON",
+            "MULTIPOINT",
+            "MULTILINESTRING",
+            "MULTIPOLYGON",
+            "GEOMETRYCOLLECTION",
+        ][ty as usize - 1];
+        Some((name, body))
+    }
+
+    fn rings(&mut self, little_endian: bool) -> Option<String> {
+        let count = self.u32(little_endian)?;
+        let rings = (0..count)
+            .map(|_| Some(format!("({})", self.points(little_endian)?)))
+            .collect::<Option<Vec<String>>>()?;
+        Some(format!("({})", rings.join(",")))
+    }
+
+    fn points(&mut self, little_endian: bool) -> Option<String> {
+        let count = self.u32(little_endian)?;
+        let points = (0..count)
+            .map(|_| self.point(little_endian))
+            .collect::<Option<Vec<String>>>()?;
+        Some(points.join(","))
+    }
+
+    fn point(&mut self, little_endian: bool) -> Option<String> {
+        Some(format!(
+            "{} {}",
+            self.f64(little_endian)?,
+            self.f64(little_endian)?
+        ))
+    }
+
+    fn u32(&mut self, little_endian: bool) -> Option<u32> {
+        let bytes = self.take()?;
+        Some(match little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn f64(&mut self, little_endian: bool) -> Option<f64> {
+        let bytes = self.take()?;
+        Some(match little_endian {
+            true => f64::from_le_bytes(bytes),
+            false => f64::from_be_bytes(bytes),
+        })
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(self.position..self.position + N)?;
+        self.position += N;
+        bytes.try_into().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_wkt_of_geometries() {
+        // MySQL, with the SRID before the WKB
+        let point = "00000000010100000000000000000000000000000000000040";
+        assert_eq!(Some("POINT(0 2)".to_string()), wkt(&hex(point)));
+        // Extended WKB of PostGIS, with SRID 4326
+        let line = "0102000020E6100000020000000000000000000000000000000000F03F000000000000F83F0000000000000040";
+        assert_eq!(Some("LINESTRING(0 1,1.5 2)".to_string()), wkt(&hex(line)));
+        let multipoint = "000000000400000002000000000100000000000000000000000000000000000000000140000000000000004008000000000000";
+        assert_eq!(
+            Some("MULTIPOINT((0 0),(2 3))".to_string()),
+            wkt(&hex(multipoint))
+        );
+        assert_eq!(None, wkt(&hex(&multipoint[..multipoint.len() - 2])));
+        assert_eq!(None, wkt(b"not a geometry"));
+    }
+}
@@ -0,0 +1,401 @@
+use super::query_data_type::{ColumnType, DataTypeInfo, LocalResult};
+use super::ColumnValue;
+use itertools::Itertools;
+use regex::Regex;
+use sqlparser::ast::{BinaryOperator, Expr, SelectItem, SetExpr, Statement, TableFactor, Value};
+use std::cmp::Ordering;
+
+/// Columns of a table of `INFORMATION_SCHEMA` that are known, with their types
+const COLUMNS: VirtualColumns = &[
+    ("TABLE_CATALOG", "varchar"),
+    ("TABLE_SCHEMA", "varchar"),
+    ("TABLE_NAME", "varchar"),
+    ("COLUMN_NAME", "varchar"),
+    ("ORDINAL_POSITION", "bigint"),
+    ("IS_NULLABLE", "varchar"),
+    ("DATA_TYPE", "varchar"),
+    ("CHARACTER_MAXIMUM_LENGTH", "bigint"),
+    ("NUMERIC_PRECISION", "bigint"),
+    ("NUMERIC_SCALE", "bigint"),
+    ("COLUMN_TYPE", "varchar"),
+    ("COLUMN_KEY", "varchar"),
+];
+const TABLES: VirtualColumns = &[
+    ("TABLE_CATALOG", "varchar"),
+    ("TABLE_SCHEMA", "varchar"),
+    ("TABLE_NAME", "varchar"),
+];
+const SCHEMATA: VirtualColumns = &[("CATALOG_NAME", "varchar"), ("SCHEMA_NAME", "varchar")];
+
+/// Names and types of the columns of a table
+type VirtualColumns = &'static [(&'static str, &'static str)];
+type VirtualRow = Vec<(&'static str, ColumnValue)>;
+
+/// Answers selects of `INFORMATION_SCHEMA.COLUMNS`, `TABLES` and `SCHEMATA` with the known
+/// structure. Only known columns, simple filters, `ORDER BY` and `LIMIT` are answered. Anything
+/// else is `None`, to be left to the database.
+pub fn answer_information_schema(
+    data_type_info: &DataTypeInfo,
+    default_schema: &str,
+    statement: &Statement,
+) -> Option<LocalResult> {
+    let query = match statement {
+        Statement::Query(query) => query,
+        _ => return None,
+    };
+    if query.with.is_some() || query.offset.is_some() || query.fetch.is_some() {
+        return None;
+    }
+    let select = match &query.body {
+        SetExpr::Select(select) => select,
+        _ => return None,
+    };
+    if select.distinct
+        || select.top.is_some()
+        || !select.group_by.is_empty()
+        || select.having.is_some()
+        || select.from.len() != 1
+        || !select.from[0].joins.is_empty()
+    {
+        return None;
+    }
+    let (columns, mut rows) = match &select.from[0].relation {
+        TableFactor::Table { name, args, .. } if args.is_empty() => match name.0.as_slice() {
+            [schema, table] if is(&schema.value, "information_schema") => {
+                virtual_table(data_type_info, &table.value)?
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    if let Some(selection) = &select.selection {
+        let mut filtered = Vec::new();
+        for row in rows {
+            if matches(&row, selection, default_schema)? {
+                filtered.push(row);
+            }
+        }
+        rows = filtered;
+    }
+    for order_by in query.order_by.iter().rev() {
+        let mut keys = Vec::new();
+        for row in &rows {
+            keys.push(value(row, &order_by.expr, default_schema)?);
+        }
+        let mut keyed: Vec<(ColumnValue, VirtualRow)> = keys.into_iter().zip(rows).collect();
+        // Stable, so the earlier keys of `ORDER BY` win
+        keyed.sort_by(|(left, _), (right, _)| {
+            let ordering = order(left, right);
+            match order_by.asc {
+                Some(false) => ordering.reverse(),
+                _ => ordering,
+            }
+        });
+        rows = keyed.into_iter().map(|(_, row)| row).collect();
+    }
+    if let Some(limit) = &query.limit {
+        match limit {
+            Expr::Value(Value::Number(limit, _)) => rows.truncate(limit.parse().ok()?),
+            _ => return None,
+        }
+    }
+
+    let mut projection = Vec::new();
+    for item in &select.projection {
+        let (expr, alias) = match item {
+            SelectItem::UnnamedExpr(expr) => (expr, None),
+            SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias.value.clone())),
+            // Our tables do not have every column, so `*` would be different from the database
+            _ => return None,
+        };
+        let column_name = column_name(expr)?;
+        let (name, ty) = columns.iter().find(|(name, _)| is(name, column_name))?;
+        projection.push((name, alias.unwrap_or_else(|| name.to_string()), ty));
+    }
+    Some((
+        projection
+            .iter()
+            .map(|(_, alias, ty)| (alias.clone(), ColumnType::of(ty)))
+            .collect(),
+        rows.into_iter()
+            .map(|row| {
+                projection
+                    .iter()
+                    .map(|(name, _, _)| column_value(&row, name).unwrap_or(ColumnValue::Null))
+                    .collect()
+            })
+            .collect(),
+    ))
+}
+
+fn virtual_table(
+    data_type_info: &DataTypeInfo,
+    table: &str,
+) -> Option<(VirtualColumns, Vec<VirtualRow>)> {
+    let text = |value: &str| ColumnValue::String(value.to_string());
+    let number = |value: Option<u64>| match value {
+        Some(value) => ColumnValue::I64(value as i64),
+        None => ColumnValue::Null,
+    };
+    if is(table, "columns") {
+        let mut position = 0;
+        let rows = data_type_info
+            .iter()
+            .enumerate()
+            .map(|(index, (schema, table_name, column_name, column_type))| {
+                let first_of_table = index == 0
+                    || matches!(
+                        data_type_info.get(index - 1),
+                        Some((previous_schema, previous_table, _, _))
+                            if previous_schema != schema || previous_table != table_name
+                    );
+                position = if first_of_table { 1 } else { position + 1 };
+                let attributes = &column_type.attributes;
+                vec![
+                    ("TABLE_CATALOG", text("def")),
+                    ("TABLE_SCHEMA", text(schema)),
+                    ("TABLE_NAME", text(table_name)),
+                    ("COLUMN_NAME", text(column_name)),
+                    ("ORDINAL_POSITION", ColumnValue::I64(position)),
+                    (
+                        "IS_NULLABLE",
+                        text(if attributes.not_null { "NO" } else { "YES" }),
+                    ),
+                    (
+                        "DATA_TYPE",
+                        match &column_type.data_type {
+                            Some(data_type) => text(data_type),
+                            None => ColumnValue::Null,
+                        },
+                    ),
+                    ("CHARACTER_MAXIMUM_LENGTH", number(attributes.length)),
+                    ("NUMERIC_PRECISION", number(attributes.precision)),
+                    ("NUMERIC_SCALE", number(attributes.scale)),
+                    (
+                        "COLUMN_TYPE",
+                        match column_type.definition() {
+                            Some(definition) => text(&definition),
+                            None => ColumnValue::Null,
+                        },
+                    ),
+                    (
+                        "COLUMN_KEY",
+                        text(attributes.key.map(|key| key.name()).unwrap_or_default()),
+                    ),
+                ]
+            })
+            .collect();
+        Some((COLUMNS, rows))
+    } else if is(table, "tables") {
+        let rows = data_type_info
+            .iter()
+            .map(|(schema, table_name, _, _)| (schema, table_name))
+            .unique()
+            .map(|(schema, table_name)| {
+                vec![
+                    ("TABLE_CATALOG", text("def")),
+                    ("TABLE_SCHEMA", text(schema)),
+                    ("TABLE_NAME", text(table_name)),
+                ]
+            })
+            .collect();
+        Some((TABLES, rows))
+    } else if is(table, "schemata") {
+        let rows = data_type_info
+            .iter()
+            .map(|(schema, _, _, _)| schema)
+            .unique()
+            .map(|schema| vec![("CATALOG_NAME", text("def")), ("SCHEMA_NAME", text(schema))])
+            .collect();
+        Some((SCHEMATA, rows))
+    } else {
+        None
+    }
+}
+
+/// Whether the row passes the filter. It is `None` for filters we can not evaluate.
+fn matches(row: &VirtualRow, expr: &Expr, default_schema: &str) -> Option<bool> {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => Some(matches(row, left, default_schema)? && matches(row, right, default_schema)?),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Or,
+            right,
+        } => Some(matches(row, left, default_schema)? || matches(row, right, default_schema)?),
+        Expr::BinaryOp { left, op, right } => {
+            let left = value(row, left, default_schema)?;
+            let right = value(row, right, default_schema)?;
+            let ordering = compare(&left, &right);
+            Some(match op {
+                BinaryOperator::Eq => ordering == Some(Ordering::Equal),
+                BinaryOperator::NotEq => matches!(ordering, Some(ordering) if ordering.is_ne()),
+                BinaryOperator::Lt => ordering == Some(Ordering::Less),
+                BinaryOperator::LtEq => matches!(ordering, Some(ordering) if ordering.is_le()),
+                BinaryOperator::Gt => ordering == Some(Ordering::Greater),
+                BinaryOperator::GtEq => matches!(ordering, Some(ordering) if ordering.is_ge()),
+                BinaryOperator::Like => like(&left, &right)?,
+                BinaryOperator::NotLike => !like(&left, &right)?,
+                _ => return None,
+            })
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let value = value(row, expr, default_schema)?;
+            let mut found = false;
+            for item in list {
+                let item = self::value(row, item, default_schema)?;
+                found |= compare(&value, &item) == Some(Ordering::Equal);
+            }
+            Some(found != *negated)
+        }
+        Expr::IsNull(expr) => Some(matches!(
+            value(row, expr, default_schema)?,
+            ColumnValue::Null
+        )),
+        Expr::IsNotNull(expr) => Some(!matches!(
+            value(row, expr, default_schema)?,
+            ColumnValue::Null
+        )),
+        Expr::Nested(expr) => matches(row, expr, default_schema),
+        _ => None,
+    }
+}
+
+/// Value of a column or literal. It is `None` for expressions we can not evaluate.
+fn value(row: &VirtualRow, expr: &Expr, default_schema: &str) -> Option<ColumnValue> {
+    match expr {
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) => column_value(row, column_name(expr)?),
+        Expr::Value(Value::SingleQuotedString(value))
+        | Expr::Value(Value::DoubleQuotedString(value)) => Some(ColumnValue::String(value.clone())),
+        Expr::Value(Value::Number(number, _)) => number.parse().ok().map(ColumnValue::I64),
+        Expr::Value(Value::Null) => Some(ColumnValue::Null),
+        Expr::Function(function)
+            if function.args.is_empty()
+                && (is(&function.name.to_string(), "database")
+                    || is(&function.name.to_string(), "schema")) =>
+        {
+            Some(ColumnValue::String(default_schema.to_string()))
+        }
+        Expr::Nested(expr) => value(row, expr, default_schema),
+        _ => None,
+    }
+}
+
+fn column_name(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Identifier(ident) => Some(&ident.value),
+        Expr::CompoundIdentifier(idents) => idents.last().map(|ident| ident.value.as_str()),
+        _ => None,
+    }
+}
+
+fn column_value(row: &VirtualRow, column_name: &str) -> Option<ColumnValue> {
+    row.iter()
+        .find(|(name, _)| is(name, column_name))
+        .map(|(_, value)| value.clone())
+}
+
+/// Comparison of `INFORMATION_SCHEMA`, which ignores case. NULL is not comparable.
+fn compare(left: &ColumnValue, right: &ColumnValue) -> Option<Ordering> {
+    match (left, right) {
+        (ColumnValue::String(left), ColumnValue::String(right)) => {
+            Some(left.to_lowercase().cmp(&right.to_lowercase()))
+        }
+        (ColumnValue::I64(left), ColumnValue::I64(right)) => Some(left.cmp(right)),
+        (ColumnValue::I64(left), ColumnValue::String(right)) => {
+            Some(left.cmp(&right.parse().ok()?))
+        }
+        (ColumnValue::String(left), ColumnValue::I64(right)) => {
+            Some(left.parse::<i64>().ok()?.cmp(right))
+        }
+        _ => None,
+    }
+}
+
+/// Order of `ORDER BY`, with NULL first
+fn order(left: &ColumnValue, right: &ColumnValue) -> Ordering {
+    match (left, right) {
+        (ColumnValue::Null, ColumnValue::Null) => Ordering::Equal,
+        (ColumnValue::Null, _) => Ordering::Less,
+        (_, ColumnValue::Null) => Ordering::Greater,
+        (left, right) => compare(left, right).unwrap_or(Ordering::Equal),
+    }
+}
+
+fn like(value: &ColumnValue, pattern: &ColumnValue) -> Option<bool> {
+    let (value, pattern) = match (value, pattern) {
+        (ColumnValue::String(value), ColumnValue::String(pattern)) => (value, pattern),
+        _ => return Some(false),
+    };
+    let mut regex = String::from("(?is)^");
+    let mut characters = pattern.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            '\\' => regex.push_str(&regex::escape(&characters.next()?.to_string())),
+            character => regex.push_str(&regex::escape(&character.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).ok().map(|regex| regex.is_match(value))
+}
+
+fn is(word: &str, keyword: &str) -> bool {
+    word.eq_ignore_ascii_case(keyword)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::{reader_result, FakeQueryExecutor};
+    use sqlparser::dialect::MySqlDialect;
+    use sqlparser::parser::Parser;
+
+    fn answer(query: &str) -> Option<LocalResult> {
+        let mut executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(
+            "TABLE_SCHEMA\tTABLE_NAME\tCOLUMN_NAME\tDATA_TYPE\tIS_NULLABLE\tCOLUMN_KEY\n\
+            shop\tusers\tid\tbigint\tNO\tPRI\n\
+            shop\tusers\tname\tvarchar\tYES\t\n\
+            shop\torders\tid\tbigint\tNO\tPRI\n",
+        )))]);
+        let data_type_info = DataTypeInfo::load_table(&mut executor, "shop", "users").unwrap();
+        let ast = Parser::parse_sql(&MySqlDialect {}, query).unwrap();
+        answer_information_schema(&data_type_info, "shop", &ast[0])
+    }
+
+    #[test]
+    fn test_answer_columns_of_a_table() {
+        let (columns, rows) = answer(
+            "select column_name, ordinal_position as position from information_schema.columns \
+            where table_schema = database() and table_name in ('USERS') order by column_name desc",
+        )
+        .unwrap();
+        assert_eq!("COLUMN_NAME", columns[0].0);
+        assert_eq!("position", columns[1].0);
+        assert_eq!(2, rows.len());
+        assert!(matches!(
+            rows[0].as_slice(),
+            [ColumnValue::String(name), ColumnValue::I64(2)] if name == "name"
+        ));
+    }
+
+    #[test]
+    fn test_leave_what_can_not_be_answered_to_the_database() {
+        assert!(answer("select * from information_schema.tables").is_none());
+        assert!(answer("select table_name from information_schema.views").is_none());
+        assert!(answer(
+            "select table_name from information_schema.tables where table_type = 'BASE TABLE'"
+        )
+        .is_none());
+        assert!(answer("select table_name from shop.users").is_none());
+    }
+}
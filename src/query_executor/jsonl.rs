@@ -0,0 +1,116 @@
+use super::{Column, ColumnAttributes, ColumnValue, Columns, Rows};
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use std::io::BufRead;
+
+type Object = Map<String, Value>;
+
+/// Reads results with one JSON object per row. The columns are the keys of the first object, typed
+/// by its values. Values that do not fit the type of their column are answered as text.
+pub fn get_data(reader: Box<dyn BufRead + Send>) -> (Result<Columns>, Rows) {
+    let mut objects = reader
+        .lines()
+        .filter(|line| match line {
+            Ok(line) => !line.trim().is_empty(),
+            Err(_) => true,
+        })
+        .map(|line| parse_object(&line?));
+    let first = match objects.next() {
+        Some(Ok(first)) => first,
+        Some(Err(error)) => return (Err(error), Box::new(std::iter::empty())),
+        None => return (Ok(Vec::new()), Box::new(std::iter::empty())),
+    };
+    let columns: Vec<(String, Option<&'static str>)> = first
+        .iter()
+        .map(|(name, value)| (name.clone(), json_type(value)))
+        .collect();
+    let result_columns = columns
+        .iter()
+        .map(|(name, ty)| Column {
+            name: name.clone(),
+            ty: ty.map(str::to_string),
+            attributes: ColumnAttributes::default(),
+        })
+        .collect();
+    let rows = std::iter::once(Ok(first))
+        .chain(objects)
+        .map(move |object| {
+            object.map(|mut object| {
+                columns
+                    .iter()
+                    .map(|(name, ty)| convert(object.remove(name).unwrap_or(Value::Null), *ty))
+                    .collect()
+            })
+        });
+    (Ok(result_columns), Box::new(rows))
+}
+
+fn parse_object(line: &str) -> Result<Object> {
+    match serde_json::from_str(line)? {
+        Value::Object(object) => Ok(object),
+        value => bail!("Expected a JSON object per row, got {}", value),
+    }
+}
+
+fn json_type(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Number(number) if number.is_i64() => Some("bigint"),
+        Value::Number(_) => Some("double"),
+        Value::Bool(_) => Some("tinyint"),
+        _ => None,
+    }
+}
+
+fn convert(value: Value, ty: Option<&str>) -> ColumnValue {
+    let converted = match (&value, ty) {
+        (Value::Null, _) => Some(ColumnValue::Null),
+        (Value::Number(number), Some("bigint")) => number.as_i64().map(ColumnValue::I64),
+        (Value::Number(number), Some("double")) => number.as_f64().map(ColumnValue::Double),
+        (Value::Bool(boolean), Some("tinyint")) => Some(ColumnValue::I8(*boolean as i8)),
+        _ => None,
+    };
+    converted.unwrap_or_else(|| match value {
+        Value::String(string) => ColumnValue::String(string),
+        value => ColumnValue::String(value.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::Row;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn test_columns_and_types_come_from_the_first_object() {
+        let jsonl =
+            "{\"id\": 1, \"total\": 2.5, \"active\": true, \"name\": \"a\", \"tags\": [1]}\n\
+            \n\
+            {\"id\": \"x\", \"total\": 3, \"name\": null, \"extra\": 1}\n";
+        let (columns, rows) = get_data(Box::new(BufReader::new(Cursor::new(jsonl))));
+        let columns: Vec<(String, Option<String>)> = columns
+            .unwrap()
+            .into_iter()
+            .map(|column| (column.name, column.ty))
+            .collect();
+        assert_eq!(
+            vec![
+                ("id".to_string(), Some("bigint".to_string())),
+                ("total".to_string(), Some("double".to_string())),
+                ("active".to_string(), Some("tinyint".to_string())),
+                ("name".to_string(), None),
+                ("tags".to_string(), None),
+            ],
+            columns
+        );
+        let rows: Vec<Row> = rows.map(Result::unwrap).collect();
+        assert!(matches!(rows[0][0], ColumnValue::I64(1)));
+        assert!(matches!(rows[0][2], ColumnValue::I8(1)));
+        assert!(matches!(&rows[0][4], ColumnValue::String(tags) if tags == "[1]"));
+        assert!(matches!(&rows[1][0], ColumnValue::String(id) if id == "x"));
+        assert!(matches!(rows[1][1], ColumnValue::Double(total) if total == 3.0));
+        assert!(matches!(rows[1][2], ColumnValue::Null));
+        assert!(matches!(rows[1][3], ColumnValue::Null));
+        assert_eq!(5, rows[1].len());
+    }
+}
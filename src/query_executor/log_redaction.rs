@@ -0,0 +1,72 @@
+use super::SqlDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REDACT_LOGS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Dialect of the queries logged by the thread, which is the one of its listener
+    static LOG_DIALECT: Cell<SqlDialect> = const { Cell::new(SqlDialect::MySql) };
+}
+
+/// When enabled, queries are written to the logs without their literals. They are still sent to
+/// the target as they are.
+pub fn redact_logs(enabled: bool) {
+    REDACT_LOGS.store(enabled, Ordering::Relaxed);
+}
+
+/// Sets the dialect used to find the literals of the queries logged by the current thread
+pub fn log_dialect(dialect: SqlDialect) {
+    LOG_DIALECT.with(|log_dialect| log_dialect.set(dialect));
+}
+
+/// The query as it is written to the logs
+pub fn loggable(query: &str) -> String {
+    if REDACT_LOGS.load(Ordering::Relaxed) {
+        redacted(query)
+    } else {
+        query.to_string()
+    }
+}
+
+/// The query with its strings and numbers replaced by `?`. Double quoted text is replaced too
+/// when it is a string, like in MySQL. Queries that can not be tokenized are left out.
+fn redacted(query: &str) -> String {
+    let dialect = LOG_DIALECT.with(Cell::get).dialect();
+    match Tokenizer::new(dialect.as_ref(), query).tokenize() {
+        Ok(tokens) => tokens
+            .iter()
+            .map(|token| match token {
+                Token::Number(..)
+                | Token::SingleQuotedString(_)
+                | Token::DoubleQuotedString(_)
+                | Token::NationalStringLiteral(_)
+                | Token::HexStringLiteral(_) => "?".to_string(),
+                token => token.to_string(),
+            })
+            .collect(),
+        Err(_) => format!("[query of {} characters]", query.chars().count()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_literals_are_redacted() {
+        assert_eq!(
+            "SELECT id, `name` FROM users WHERE email = ? AND age > ? AND note = ?",
+            redacted(
+                "SELECT id, `name` FROM users WHERE email = 'a@b.com' AND age > 30 AND note = \"x\""
+            )
+        );
+        assert_eq!("[query of 10 characters]", redacted("SELECT 'a "));
+        log_dialect(SqlDialect::Postgres);
+        assert_eq!(
+            "SELECT \"name\" FROM users WHERE id = ?",
+            redacted("SELECT \"name\" FROM users WHERE id = 1")
+        );
+    }
+}
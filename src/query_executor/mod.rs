@@ -1,132 +1,611 @@
 use anyhow::Result;
 use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::io::{BufRead, BufReader, Read};
+use std::time::Duration;
+use uuid::Uuid;
 
+pub use audit_log::{next_connection_id, AuditLog, AuditedConnection, QueryAuditor};
+pub use cache_rules::{deserialize_query_patterns, load_cache_list, query_pattern, CacheRules};
+pub use compression::Compression;
+pub use file_query_storage::{export_snapshot, import_snapshot, FileQueryStorage};
+pub use geometry::GEOMETRY_TYPES;
+pub use log_redaction::{log_dialect, loggable, redact_logs};
+pub use pipeline::pipelined;
+pub use query_access::QueryAccessControl;
 pub use query_accumulator::QueryAccumulator;
-pub use query_cache::{InMemoryQueryStorage, QueryCache};
-pub use query_data_type::{DataTypeInfo, QueryDataType};
-pub use query_filter::QueryFilter;
+pub use query_cache::{
+    CacheOptions, CacheStats, InMemoryQueryStorage, QueryCache, Refresher, SharedQueryStorage,
+};
+pub use query_data_type::{
+    DataTypeInfo, GeometryFormat, IdentifierCase, QueryDataType, SchemaLoader, SharedDataTypeInfo,
+    SqlDialect, TypeStrictness, UnknownDatabase,
+};
+pub use query_filter::{FilterRule, QueryFilter};
+pub use query_guardrails::{Guardrails, QueryGuardrails};
+pub use query_masker::{Masking, QueryMasker};
+pub use query_paginator::QueryPaginator;
+pub use query_rewriter::{QueryRewriter, Rewrite};
+pub use query_row_guard::QueryRowGuard;
 pub use query_sanitizer::QuerySanitizer;
+pub use query_transaction::{QueryTransaction, TransactionMode};
+pub use query_variables::QueryVariables;
+pub use remote_query_storage::{QueryStorageServer, RemoteQueryStorage};
 pub use runops::{RunopsApi, SqlError};
+pub use session_state::{SessionState, DEFAULT_MAX_SET_STATEMENTS};
+pub use type_sniffer::QueryTypeSniffer;
 
+mod audit_log;
+mod cache_rules;
+mod comments;
+mod compression;
+mod csv;
+mod encoding;
+mod file_query_storage;
+mod geometry;
+mod information_schema;
+mod jsonl;
+mod log_redaction;
+mod mysql_syntax;
+mod pipeline;
+mod progress;
+mod query_access;
 mod query_accumulator;
 mod query_cache;
 mod query_data_type;
 mod query_filter;
+mod query_guardrails;
+mod query_masker;
+mod query_paginator;
+mod query_rewriter;
+mod query_row_guard;
 mod query_sanitizer;
+mod query_transaction;
+mod query_variables;
+mod remote_query_storage;
 mod runops;
+mod session_state;
+mod spill;
+mod table_references;
+mod type_sniffer;
+mod vertical;
 
 pub type Row = Vec<ColumnValue>;
 type Columns = Vec<Column>;
+/// Rows are sent so they can be read in another thread than the one writing them
+pub type Rows = Box<dyn Iterator<Item = Result<Row>> + Send>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ColumnValue {
     Null,
     String(String),
+    Bool(bool),
     I64(i64),
     I32(i32),
     I16(i16),
     I8(i8),
+    U64(u64),
+    U32(u32),
+    U16(u16),
+    U8(u8),
     Double(f64),
     Float(f32),
+    /// Values of decimal columns, with their digits after the point
+    Decimal(Decimal),
     DateTime(NaiveDateTime),
     Date(NaiveDate),
+    /// Documents of `json` columns, checked to be complete and kept as the target wrote them
+    Json(Box<serde_json::value::RawValue>),
+    /// Values of `uuid` columns of Postgres and MariaDB
+    Uuid(Uuid),
+    /// `TIME` values, that can be longer than a day
+    Time(Duration),
+    Bytes(Vec<u8>),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
     pub ty: Option<String>,
+    #[serde(default)]
+    pub attributes: ColumnAttributes,
+}
+
+/// What is known about the values of a column from the database structure
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColumnAttributes {
+    pub not_null: bool,
+    /// Maximum length of text and binary columns
+    pub length: Option<u64>,
+    /// Digits of decimal columns
+    pub precision: Option<u64>,
+    /// Digits after the point of decimal columns
+    pub scale: Option<u64>,
+    /// Integer columns declared `UNSIGNED`
+    pub unsigned: bool,
+    pub key: Option<ColumnKey>,
+    /// Default value, from `COLUMN_DEFAULT`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// Extra information, like `auto_increment`, from `EXTRA`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra: Option<String>,
+}
+
+/// Index of a column, from `COLUMN_KEY`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ColumnKey {
+    Primary,
+    Unique,
+    /// First column of an index that allows repeated values
+    Multiple,
+}
+
+impl ColumnKey {
+    /// Name used by `COLUMN_KEY`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnKey::Primary => "PRI",
+            ColumnKey::Unique => "UNI",
+            ColumnKey::Multiple => "MUL",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [ColumnKey::Primary, ColumnKey::Unique, ColumnKey::Multiple]
+            .into_iter()
+            .find(|key| key.name() == name)
+    }
 }
 
 pub trait QueryExecutor {
     type QueryResult;
     fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>>;
+
+    /// Executes a prepared statement. Executors that do not care about the template just run
+    /// the query with the parameters already bound.
+    fn query_prepared(&mut self, prepared: &PreparedQuery) -> Result<Option<Self::QueryResult>> {
+        self.query(&prepared.query)
+    }
+}
+
+/// Result of the database, or one answered without running the query
+pub enum AnsweredResult<R> {
+    Executed(R),
+    Local(Columns, Vec<Row>),
+}
+
+impl<R: QueryResult> QueryResult for AnsweredResult<R> {
+    fn get_data(self) -> (Result<Columns>, Rows) {
+        match self {
+            AnsweredResult::Executed(result) => result.get_data(),
+            AnsweredResult::Local(columns, rows) => {
+                (Ok(columns), Box::new(rows.into_iter().map(Ok)))
+            }
+        }
+    }
+
+    fn result_sets(self) -> Vec<(Result<Columns>, Rows)> {
+        match self {
+            AnsweredResult::Executed(result) => result.result_sets(),
+            local => vec![local.get_data()],
+        }
+    }
+}
+
+/// A prepared statement execution
+pub struct PreparedQuery {
+    pub template: String,
+    pub parameters: Vec<String>,
+    /// The template with the parameters bound
+    pub query: String,
 }
 
 pub trait QueryResult {
-    fn get_data(self) -> (Result<Columns>, Box<dyn Iterator<Item = Result<Row>>>);
+    fn get_data(self) -> (Result<Columns>, Rows);
+
+    /// Results of each statement, for outputs with several of them. Results that can not be split
+    /// are answered as one.
+    fn result_sets(self) -> Vec<(Result<Columns>, Rows)>
+    where
+        Self: Sized,
+    {
+        vec![self.get_data()]
+    }
 }
 
 pub struct ReaderQueryResult {
-    reader: Box<dyn BufRead>,
+    reader: Box<dyn BufRead + Send>,
+    format: ResultFormat,
+    /// Value that the target writes for NULL
+    null_token: String,
+    layout: ResultLayout,
+}
+
+/// What `mysql --batch` writes for NULL. Text that is also `NULL` can not be told apart from it.
+pub const DEFAULT_NULL_TOKEN: &str = "NULL";
+
+/// How the target writes the values of results
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultFormat {
+    /// Tab separated, with tabs, new lines and backslashes of values escaped, like `mysql --batch`
+    #[default]
+    MysqlBatch,
+    /// Tab separated, without escapes
+    Tsv,
+    /// Comma separated, with quoted fields like RFC 4180. Quoted fields are never NULL.
+    Csv,
+    /// One JSON object per row. Columns and their types come from the first one.
+    Jsonl,
+}
+
+/// Where the fields of results are, for the formats other than `jsonl`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResultLayout {
+    /// Between the fields of a row. Defaults to a tab, or to a comma for `csv`.
+    pub delimiter: Option<char>,
+    /// Without a header, columns are named `column_1`, `column_2`, ...
+    pub has_header: bool,
+    /// Lines before the header, like banners of the target
+    pub skip_lines: usize,
+    /// Status lines that the CLI of the target writes with results of `mysql_batch` and `tsv`,
+    /// like `3 rows in set (0.01 sec)` of mysql, are left out for this dialect
+    pub status_lines: Option<SqlDialect>,
+}
+
+impl Default for ResultLayout {
+    fn default() -> Self {
+        Self {
+            delimiter: None,
+            has_header: true,
+            skip_lines: 0,
+            status_lines: None,
+        }
+    }
 }
 
 impl QueryResult for ReaderQueryResult {
-    fn get_data(mut self) -> (Result<Columns>, Box<dyn Iterator<Item = Result<Row>>>) {
-        (self.get_columns(), self.get_rows())
+    fn get_data(mut self) -> (Result<Columns>, Rows) {
+        if let Err(error) = self.skip_lines() {
+            return (Err(error), Box::new(std::iter::empty()));
+        }
+        if self.format == ResultFormat::Jsonl {
+            return jsonl::get_data(self.reader);
+        }
+        if matches!(self.format, ResultFormat::MysqlBatch | ResultFormat::Tsv)
+            && vertical::is_vertical(self.reader.fill_buf().unwrap_or_default())
+        {
+            return vertical::get_data(self.reader, self.null_token);
+        }
+        let (format, layout) = (self.format, self.layout);
+        let null_token = self.null_token.clone();
+        let mut records = self.records().peekable();
+        skip_statement_echoes(&mut records);
+        // Header as the target wrote it, to leave out the headers of the next statements
+        let mut header: Vec<String> = Vec::new();
+        let columns = match (layout.has_header, records.peek()) {
+            (true, _) => records.next().transpose().and_then(|fields| {
+                let fields = fields.unwrap_or_default();
+                // The target failed before writing the result
+                if let Some(error) = error_line(format, &fields) {
+                    return Err(SqlError::new(error.to_string()).into());
+                }
+                header = fields
+                    .into_iter()
+                    .map(|field| field.value.trim().to_string())
+                    .collect();
+                Ok(header
+                    .iter()
+                    .map(|name| format.decode(name))
+                    .filter(|name| !name.is_empty())
+                    .map(column)
+                    .collect())
+            }),
+            (false, Some(Ok(fields))) => Ok((1..=fields.len())
+                .map(|index| column(format!("column_{}", index)))
+                .collect()),
+            (false, _) => Ok(Vec::new()),
+        };
+        // After a blank or status line, where the result of the next statement can start
+        let mut at_boundary = false;
+        let mut rows = records
+            .filter(move |record| {
+                let fields = match record {
+                    Ok(fields) => fields,
+                    Err(_) => return true,
+                };
+                if let [field] = fields.as_slice() {
+                    let is_boundary = !field.quoted
+                        && (field.value.is_empty()
+                            || matches!(layout.status_lines, Some(dialect)
+                                if format != ResultFormat::Csv
+                                    && is_status_line(dialect, &field.value)));
+                    if is_boundary {
+                        at_boundary = true;
+                        return false;
+                    }
+                }
+                // Rows like the header are only headers of the next statements after a boundary
+                let is_repeated_header = at_boundary && is_header(fields, &header);
+                at_boundary = false;
+                !is_repeated_header
+            })
+            .peekable();
+        let rows = std::iter::from_fn(move || {
+            let fields = match rows.next()? {
+                Ok(fields) => fields,
+                Err(error) => return Some(Err(error)),
+            };
+            // The target failed after writing part of the result. Only the last line is its
+            // error, the other ones are rows.
+            if rows.peek().is_none() {
+                if let Some(error) = error_line(format, &fields) {
+                    return Some(Err(SqlError::new(error.to_string()).into()));
+                }
+            }
+            Some(Ok(fields
+                .into_iter()
+                .map(|field| format.value(field, &null_token))
+                .collect()))
+        });
+        (columns, Box::new(rows))
+    }
+
+    fn result_sets(self) -> Vec<(Result<Columns>, Rows)> {
+        match self.split() {
+            Ok(results) => results.into_iter().map(QueryResult::get_data).collect(),
+            Err(error) => vec![(Err(error), Box::new(std::iter::empty()))],
+        }
     }
 }
 
 impl ReaderQueryResult {
-    fn new(reader: impl Read + 'static) -> Self {
+    fn new(reader: impl Read + Send + 'static) -> Self {
         Self {
             reader: Box::new(BufReader::new(reader)),
+            format: ResultFormat::default(),
+            null_token: DEFAULT_NULL_TOKEN.to_string(),
+            layout: ResultLayout::default(),
         }
     }
 
-    fn get_columns(&mut self) -> Result<Vec<Column>> {
-        let mut header = String::new();
-        self.reader.read_line(&mut header)?;
-        // Necessary because of postgress. Maybe we will need a new way of handling this
-        while header == "SET\n" {
-            header.clear();
-            self.reader.read_line(&mut header)?;
+    fn with_format(self, format: ResultFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    fn with_null_token(self, null_token: String) -> Self {
+        Self { null_token, ..self }
+    }
+
+    fn with_layout(self, layout: ResultLayout) -> Self {
+        Self { layout, ..self }
+    }
+
+    /// Splits outputs of `mysql_batch` and `tsv` with tables separated by blank lines into a result
+    /// per table. The whole output is read at once.
+    fn split(mut self) -> Result<Vec<ReaderQueryResult>> {
+        if !matches!(self.format, ResultFormat::MysqlBatch | ResultFormat::Tsv) {
+            return Ok(vec![self]);
+        }
+        self.skip_lines()?;
+        let mut blocks = vec![String::new()];
+        for line in self.reader.lines() {
+            let line = line?;
+            let block = blocks.last_mut().expect("There is always a block");
+            if !line.trim().is_empty() {
+                block.push_str(&line);
+                block.push('\n');
+            } else if !block.is_empty() {
+                blocks.push(String::new());
+            }
         }
-        Ok(header
-            .split('\t')
-            .map(|column_name| column_name.trim())
-            .filter(|column_name| !column_name.is_empty())
-            .map(|column| Column {
-                name: column.to_string(),
-                ty: None,
+        if blocks.len() > 1 && blocks.last().is_some_and(String::is_empty) {
+            blocks.pop();
+        }
+        let layout = ResultLayout {
+            skip_lines: 0,
+            ..self.layout
+        };
+        Ok(blocks
+            .into_iter()
+            .map(|block| {
+                ReaderQueryResult::new(std::io::Cursor::new(block))
+                    .with_format(self.format)
+                    .with_null_token(self.null_token.clone())
+                    .with_layout(layout)
             })
             .collect())
     }
 
-    fn get_rows(self) -> Box<dyn Iterator<Item = Result<Row>>> {
-        Box::new(
-            self.reader
-                .lines()
-                .filter(|result_row| match result_row {
-                    Ok(row) => !row.is_empty(),
-                    Err(_) => true,
-                })
-                .map(|result_row| {
-                    result_row
-                        .map(|row| {
-                            row.split('\t')
-                                .map(|value| match value {
-                                    "NULL" => ColumnValue::Null,
-                                    value => ColumnValue::String(value.to_string()),
-                                })
-                                .collect()
-                        })
-                        .map_err(|io_error| io_error.into())
-                }),
+    fn skip_lines(&mut self) -> Result<()> {
+        let mut line = String::new();
+        for _ in 0..self.layout.skip_lines {
+            self.reader.read_line(&mut line)?;
+        }
+        Ok(())
+    }
+
+    /// The fields of each line, or of each record of `csv`, as the target wrote them
+    fn records(self) -> Box<dyn Iterator<Item = Result<Vec<csv::Field>>> + Send> {
+        let delimiter = self.layout.delimiter.unwrap_or(match self.format {
+            ResultFormat::Csv => ',',
+            _ => '\t',
+        });
+        let mut reader = self.reader;
+        match self.format {
+            ResultFormat::Csv => Box::new(std::iter::from_fn(move || {
+                csv::read_record(&mut reader, delimiter)
+                    .map_err(|io_error| io_error.into())
+                    .transpose()
+            })),
+            _ => Box::new(reader.lines().map(move |line| {
+                Ok(line?
+                    .split(delimiter)
+                    .map(|value| csv::Field::new(value.to_string()))
+                    .collect())
+            })),
+        }
+    }
+}
+
+/// Statements that the target writes before their results, like the `SET` of psql or the echoes
+/// of `mysql -v` and `psql -e`
+fn skip_statement_echoes(
+    records: &mut std::iter::Peekable<impl Iterator<Item = Result<Vec<csv::Field>>>>,
+) {
+    // Echoes of `mysql -v` are between lines of dashes
+    let mut in_separators = false;
+    // Echoes of `psql -e` can take more than one line, until the `;`
+    let mut in_statement = false;
+    while let Some(Ok(fields)) = records.peek() {
+        let first = fields.first().map_or("", |field| field.value.trim());
+        let last = fields.last().map_or("", |field| field.value.trim());
+        let is_separator = fields.len() == 1 && first.len() > 1 && first.chars().all(|c| c == '-');
+        if is_separator {
+            in_separators = !in_separators;
+        } else if !in_separators && !in_statement {
+            let is_echo = (fields.len() == 1 && matches!(first, "" | "SET"))
+                || (!fields.iter().any(|field| field.quoted) && is_statement_start(first));
+            if !is_echo {
+                return;
+            }
+            in_statement = !last.ends_with(';') && !matches!(first, "" | "SET");
+        } else if in_statement {
+            in_statement = !last.ends_with(';');
+        }
+        records.next();
+    }
+}
+
+fn is_statement_start(line: &str) -> bool {
+    let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let keyword = keyword.trim_end_matches(';').to_lowercase();
+    (!rest.trim().is_empty() || line.ends_with(';'))
+        && matches!(
+            keyword.as_str(),
+            "select" | "with" | "set" | "use" | "show" | "describe" | "desc" | "explain"
         )
+}
+
+/// Headers of the next statements of outputs that were put together, as the first header. Rows
+/// of data can be like it too.
+fn is_header(fields: &[csv::Field], header: &[String]) -> bool {
+    !header.is_empty()
+        && fields.len() == header.len()
+        && fields
+            .iter()
+            .zip(header)
+            .all(|(field, name)| !field.quoted && field.value.trim() == name)
+}
+
+fn is_status_line(dialect: SqlDialect, line: &str) -> bool {
+    let line = line.trim();
+    let is_mysql =
+        (line.contains(" in set") || line.starts_with("Query OK, ")) && line.ends_with(" sec)");
+    let is_psql = line.starts_with('(') && (line.ends_with(" row)") || line.ends_with(" rows)"));
+    match dialect {
+        SqlDialect::MySql | SqlDialect::MariaDb => is_mysql,
+        SqlDialect::Postgres => is_psql,
+        SqlDialect::Generic => is_mysql || is_psql,
+    }
+}
+
+/// Text of a line of fields that is an error of the target
+fn error_line(format: ResultFormat, fields: &[csv::Field]) -> Option<&str> {
+    match fields {
+        [field] if format != ResultFormat::Csv && !field.quoted && is_error_line(&field.value) => {
+            Some(&field.value)
+        }
+        _ => None,
+    }
+}
+
+/// Errors of mysql, like `ERROR 2013 (HY000) at line 1: Lost connection`, or of psql, like
+/// `ERROR:  canceling statement due to statement timeout`
+fn is_error_line(line: &str) -> bool {
+    let is_mysql = line
+        .strip_prefix("ERROR ")
+        .and_then(|error| error.split_once(" ("))
+        .is_some_and(|(code, _)| {
+            !code.is_empty() && code.chars().all(|character| character.is_ascii_digit())
+        });
+    let is_psql =
+        line.starts_with("ERROR:  ") || (line.starts_with("psql:") && line.contains(" ERROR:  "));
+    is_mysql || is_psql
+}
+
+fn column(name: String) -> Column {
+    Column {
+        name,
+        ty: None,
+        attributes: ColumnAttributes::default(),
+    }
+}
+
+impl ResultFormat {
+    fn value(self, field: csv::Field, null_token: &str) -> ColumnValue {
+        match field {
+            field if field.quoted => ColumnValue::String(field.value),
+            field if field.value == null_token => ColumnValue::Null,
+            // Escaped NULL of mysql, that can not be mistaken for text
+            field if self == ResultFormat::MysqlBatch && field.value == "\\N" => ColumnValue::Null,
+            field => ColumnValue::String(self.decode(&field.value)),
+        }
+    }
+
+    fn decode(self, value: &str) -> String {
+        match self {
+            ResultFormat::MysqlBatch if value.contains('\\') => {
+                let mut decoded = String::with_capacity(value.len());
+                let mut characters = value.chars();
+                while let Some(character) = characters.next() {
+                    if character != '\\' {
+                        decoded.push(character);
+                        continue;
+                    }
+                    match characters.next() {
+                        Some('t') => decoded.push('\t'),
+                        Some('n') => decoded.push('\n'),
+                        Some('0') => decoded.push('\0'),
+                        Some('\\') => decoded.push('\\'),
+                        // Unknown escapes are kept as they are
+                        Some(other) => {
+                            decoded.push('\\');
+                            decoded.push(other);
+                        }
+                        None => decoded.push('\\'),
+                    }
+                }
+                decoded
+            }
+            _ => value.to_string(),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::cell::RefCell;
     use std::rc::Rc;
 
     pub struct FakeQueryExecutor {
         result_list: Vec<Result<Option<ReaderQueryResult>>>,
-        query_list: Rc<Vec<String>>,
+        query_list: Rc<RefCell<Vec<String>>>,
     }
 
     impl FakeQueryExecutor {
         pub fn new(result_list: Vec<Result<Option<ReaderQueryResult>>>) -> Self {
             Self {
-                result_list: result_list,
-                query_list: Rc::new(Vec::new()),
+                result_list,
+                query_list: Rc::new(RefCell::new(Vec::new())),
             }
         }
 
-        pub fn get_query_list(&self) -> Rc<Vec<String>> {
+        pub fn get_query_list(&self) -> Rc<RefCell<Vec<String>>> {
             self.query_list.clone()
         }
     }
@@ -134,10 +613,208 @@ mod test {
     impl QueryExecutor for FakeQueryExecutor {
         type QueryResult = ReaderQueryResult;
         fn query(&mut self, query: &str) -> Result<Option<ReaderQueryResult>> {
-            Rc::get_mut(&mut self.query_list)
-                .unwrap()
-                .push(query.to_string());
+            self.query_list.borrow_mut().push(query.to_string());
             self.result_list.pop().unwrap()
         }
     }
+
+    pub fn reader_result(tsv: &str) -> ReaderQueryResult {
+        ReaderQueryResult::new(std::io::Cursor::new(tsv.to_string()))
+    }
+
+    #[test]
+    fn test_null_tokens() {
+        let (_, rows) = reader_result("a\tb\tc\nNULL\t\\N\tnull\n")
+            .with_null_token("null".to_string())
+            .get_data();
+        let row = rows.map(Result::unwrap).next().unwrap();
+        assert!(matches!(&row[0], ColumnValue::String(text) if text == "NULL"));
+        assert!(matches!(row[1], ColumnValue::Null));
+        assert!(matches!(row[2], ColumnValue::Null));
+    }
+
+    #[test]
+    fn test_result_layouts() {
+        let layout = ResultLayout {
+            delimiter: Some('|'),
+            has_header: false,
+            skip_lines: 2,
+            ..ResultLayout::default()
+        };
+        let (columns, rows) = reader_result("Welcome\n\n1|a\tb|NULL\n2||x\n")
+            .with_layout(layout)
+            .get_data();
+        let names: Vec<String> = columns.unwrap().into_iter().map(|c| c.name).collect();
+        assert_eq!(vec!["column_1", "column_2", "column_3"], names);
+        let rows: Vec<Row> = rows.map(Result::unwrap).collect();
+        assert_eq!(2, rows.len());
+        assert!(matches!(&rows[0][1], ColumnValue::String(value) if value == "a\tb"));
+        assert!(matches!(rows[0][2], ColumnValue::Null));
+        assert!(matches!(&rows[1][2], ColumnValue::String(value) if value == "x"));
+    }
+
+    #[test]
+    fn test_status_lines_are_left_out() {
+        let output = "id\n1\n(1 row)\n2 rows in set, 1 warning (0.01 sec)\n";
+        let rows = |status_lines| {
+            let layout = ResultLayout {
+                status_lines,
+                ..ResultLayout::default()
+            };
+            reader_result(output)
+                .with_layout(layout)
+                .get_data()
+                .1
+                .count()
+        };
+        assert_eq!(3, rows(None));
+        assert_eq!(2, rows(Some(SqlDialect::MySql)));
+        assert_eq!(2, rows(Some(SqlDialect::Postgres)));
+        assert_eq!(1, rows(Some(SqlDialect::Generic)));
+    }
+
+    #[test]
+    fn test_errors_after_some_rows() {
+        let output = "id\n1\nERROR 2013 (HY000) at line 1: Lost connection to server\n";
+        let mut rows = reader_result(output).get_data().1;
+        assert!(rows.next().unwrap().is_ok());
+        let error = rows.next().unwrap().unwrap_err();
+        assert!(error.downcast_ref::<SqlError>().is_some());
+        let output = "note\nERROR 1 is not an error\nERROR:  canceling statement\n";
+        let mut rows = reader_result(output)
+            .with_format(ResultFormat::Tsv)
+            .get_data()
+            .1;
+        assert!(rows.next().unwrap().is_ok());
+        assert!(rows.next().unwrap().is_err());
+        // Rows like errors are rows
+        let output = "note\nERROR:  disk full\nfine\n";
+        let rows: Vec<Row> = reader_result(output)
+            .get_data()
+            .1
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(2, rows.len());
+        let (columns, _) = reader_result("ERROR 1045 (28000): Access denied\n").get_data();
+        assert!(columns.unwrap_err().downcast_ref::<SqlError>().is_some());
+    }
+
+    #[test]
+    fn test_statement_echoes_and_repeated_headers_are_left_out() {
+        let names_and_ids = |output: &str| {
+            let (columns, rows) = reader_result(output).get_data();
+            let names: Vec<String> = columns.unwrap().into_iter().map(|c| c.name).collect();
+            let ids: Vec<String> = rows
+                .map(|row| match &row.unwrap()[0] {
+                    ColumnValue::String(id) => id.clone(),
+                    value => panic!("Unexpected {:?}", value),
+                })
+                .collect();
+            (names, ids)
+        };
+        let expected = (
+            vec!["id".to_string()],
+            vec!["1".to_string(), "2".to_string()],
+        );
+        assert_eq!(
+            expected,
+            names_and_ids(
+                "SET
+SET
+id
+1
+
+id
+2
+"
+            )
+        );
+        // Without a boundary, it is a row
+        assert_eq!(
+            (
+                vec!["id".to_string()],
+                vec!["1".to_string(), "id".to_string(), "2".to_string()]
+            ),
+            names_and_ids("id\n1\nid\n2\n")
+        );
+        assert_eq!(
+            expected,
+            names_and_ids(
+                "--------------
+SET NAMES utf8
+--------------
+
+id
+1
+2
+"
+            )
+        );
+        assert_eq!(
+            expected,
+            names_and_ids(
+                "SET search_path TO a;
+SET
+select id
+from t;
+id
+1
+2
+"
+            )
+        );
+        let (names, _) = names_and_ids(
+            "select	name
+1	a
+",
+        );
+        assert_eq!(vec!["select".to_string(), "name".to_string()], names);
+    }
+
+    #[test]
+    fn test_tables_separated_by_blank_lines_are_split() {
+        let output = "server_version\n14.1\n(1 row)\n\nserver_encoding\nUTF8\n(1 row)\n\n";
+        let layout = ResultLayout {
+            status_lines: Some(SqlDialect::Postgres),
+            ..ResultLayout::default()
+        };
+        let result_sets = reader_result(output).with_layout(layout).result_sets();
+        let result_sets: Vec<(String, Vec<Row>)> = result_sets
+            .into_iter()
+            .map(|(columns, rows)| {
+                let name = columns.unwrap().remove(0).name;
+                (name, rows.map(Result::unwrap).collect())
+            })
+            .collect();
+        assert_eq!(2, result_sets.len());
+        assert_eq!("server_encoding", result_sets[1].0);
+        assert_eq!(1, result_sets[1].1.len());
+        assert!(matches!(&result_sets[1].1[0][0], ColumnValue::String(value) if value == "UTF8"));
+        assert_eq!(1, reader_result("").result_sets().len());
+    }
+
+    #[test]
+    fn test_csv_results() {
+        let csv = "id,note\r\n1,\"a,\nb\"\r\n\r\n2,NULL\r\n3,\"NULL\"\r\n";
+        let (columns, rows) = reader_result(csv).with_format(ResultFormat::Csv).get_data();
+        let names: Vec<String> = columns.unwrap().into_iter().map(|c| c.name).collect();
+        assert_eq!(vec!["id".to_string(), "note".to_string()], names);
+        let rows: Vec<Row> = rows.map(Result::unwrap).collect();
+        assert_eq!(3, rows.len());
+        assert!(matches!(&rows[0][1], ColumnValue::String(note) if note == "a,\nb"));
+        assert!(matches!(rows[1][1], ColumnValue::Null));
+        assert!(matches!(&rows[2][1], ColumnValue::String(note) if note == "NULL"));
+    }
+
+    #[test]
+    fn test_escaped_values_of_mysql_batch_results() {
+        let tsv = "note\tpath\nline\\none\\ttwo\tC:\\\\new\\x\n";
+        let (_, rows) = reader_result(tsv).get_data();
+        let row = rows.map(Result::unwrap).next().unwrap();
+        assert!(matches!(&row[0], ColumnValue::String(note) if note == "line\none\ttwo"));
+        assert!(matches!(&row[1], ColumnValue::String(path) if path == "C:\\new\\x"));
+        let (_, rows) = reader_result(tsv).with_format(ResultFormat::Tsv).get_data();
+        let row = rows.map(Result::unwrap).next().unwrap();
+        assert!(matches!(&row[0], ColumnValue::String(note) if note == "line\\none\\ttwo"));
+    }
 }
@@ -0,0 +1,132 @@
+use super::SqlError;
+use sqlparser::ast::Statement;
+use sqlparser::dialect::Dialect;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+/// Select modifiers of MySQL that do not change the columns of the result. Our parser does not
+/// recognise them, so they are left out.
+const IGNORED_MODIFIERS: &[&str] = &[
+    "high_priority",
+    "sql_small_result",
+    "sql_big_result",
+    "sql_buffer_result",
+    "sql_cache",
+    "sql_no_cache",
+    "sql_calc_found_rows",
+];
+
+/// Parses like `Parser::parse_sql`, after rewriting the MySQL syntax our parser does not
+/// recognise. Only keywords are rewritten, so literals and quoted identifiers are kept as written.
+pub fn parse_sql(dialect: &dyn Dialect, query: &str) -> Result<Vec<Statement>, ParserError> {
+    parse_tokens(dialect, tokenize(dialect, query)?)
+}
+
+/// Tokens of the query, with the MySQL syntax our parser does not recognise rewritten
+pub fn tokenize(dialect: &dyn Dialect, query: &str) -> Result<Vec<Token>, ParserError> {
+    Ok(Tokenizer::new(dialect, query)
+        .tokenize()?
+        .into_iter()
+        .filter(|token| !is_keyword(token, IGNORED_MODIFIERS))
+        .map(|token| match token {
+            token if is_keyword(&token, &["straight_join"]) => Token::make_keyword("JOIN"),
+            token => token,
+        })
+        .collect())
+}
+
+pub fn parse_tokens(
+    dialect: &dyn Dialect,
+    tokens: Vec<Token>,
+) -> Result<Vec<Statement>, ParserError> {
+    let mut parser = Parser::new(tokens, dialect);
+    let mut statements = Vec::new();
+    loop {
+        while parser.consume_token(&Token::SemiColon) {}
+        if parser.peek_token() == Token::EOF {
+            return Ok(statements);
+        }
+        statements.push(parser.parse_statement()?);
+        if !matches!(parser.peek_token(), Token::SemiColon | Token::EOF) {
+            return parser.expected("end of statement", parser.peek_token());
+        }
+    }
+}
+
+/// Unquoted words equal to one of the keywords, ignoring case
+/// Schema of a `USE` statement, which is `None` for other statements. Only a single schema, as a
+/// plain or a backquoted name, can follow `USE`, since the schema is sent to the target again
+/// before the queries of the session.
+pub fn used_schema(query: &str) -> Option<Result<String, SqlError>> {
+    let (keyword, schema) = query.trim().split_once(char::is_whitespace)?;
+    if !keyword.eq_ignore_ascii_case("use") {
+        return None;
+    }
+    let schema = schema.trim().trim_end_matches(';').trim_end();
+    let plain = !schema.is_empty()
+        && schema
+            .chars()
+            .all(|character| character.is_alphanumeric() || matches!(character, '_' | '$'));
+    let backquoted = schema
+        .strip_prefix('`')
+        .and_then(|schema| schema.strip_suffix('`'))
+        .filter(|name| !name.is_empty() && !name.replace("``", "").contains('`'));
+    Some(match (plain, backquoted) {
+        (true, _) => Ok(schema.to_string()),
+        (false, Some(name)) => Ok(name.replace("``", "`")),
+        (false, None) => Err(SqlError::new(format!(
+            "USE takes the name of a single schema, not {schema}"
+        ))),
+    })
+}
+
+/// Name written as a backquoted identifier of MySQL
+pub fn backquoted(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+pub fn is_keyword(token: &Token, keywords: &[&str]) -> bool {
+    match token {
+        Token::Word(word) if word.quote_style.is_none() => keywords
+            .iter()
+            .any(|keyword| word.value.eq_ignore_ascii_case(keyword)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sqlparser::dialect::MySqlDialect;
+
+    #[test]
+    fn test_mysql_syntax_is_rewritten_only_where_it_is_a_keyword() {
+        let statements = parse_sql(
+            &MySqlDialect {},
+            "select sql_no_cache a.id from a straight_join `straight_join` b \
+            where a.note = 'straight_join'",
+        )
+        .unwrap();
+        let statement = statements[0].to_string();
+        assert!(!statement.to_lowercase().contains("sql_no_cache"));
+        assert!(statement.contains("FROM a JOIN `straight_join`"));
+        assert!(statement.ends_with("a.note = 'straight_join'"));
+    }
+
+    #[test]
+    fn test_use_takes_a_single_schema() {
+        assert!(used_schema("select 1").is_none());
+        assert!(used_schema("user_id").is_none());
+        assert_eq!("app", used_schema("USE app;").unwrap().unwrap());
+        assert_eq!("my`app", used_schema("use\t`my``app`").unwrap().unwrap());
+        for query in [
+            "use app; select * from app.secrets",
+            "use `app`; select 1; `x`",
+            "use app\\'",
+            "use ;",
+        ] {
+            assert!(used_schema(query).unwrap().is_err(), "{}", query);
+        }
+        assert_eq!("`my``app`", backquoted("my`app"));
+    }
+}
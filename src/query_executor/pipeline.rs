@@ -0,0 +1,70 @@
+use super::{Row, Rows};
+use anyhow::{anyhow, Result};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::JoinHandle;
+
+/// Rows read ahead of the ones being written, so downloading, parsing and converting the rows of
+/// a result overlap with writing them to the client
+const PIPELINE_ROWS: usize = 1024;
+
+/// Reads the rows in another thread. A panic while reading them ends the rows with an error.
+pub fn pipelined(rows: Rows) -> Rows {
+    let (sender, receiver) = sync_channel(PIPELINE_ROWS);
+    let worker = std::thread::spawn(move || {
+        for row in rows {
+            // The rows are no longer needed, like when the client went away
+            if sender.send(row).is_err() {
+                break;
+            }
+        }
+    });
+    Box::new(PipelinedRows {
+        receiver,
+        worker: Some(worker),
+    })
+}
+
+struct PipelinedRows {
+    receiver: Receiver<Result<Row>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Iterator for PipelinedRows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(row) = self.receiver.recv() {
+            return Some(row);
+        }
+        match self.worker.take()?.join() {
+            Ok(()) => None,
+            Err(_) => Some(Err(anyhow!("Reading the rows of the result failed"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::ColumnValue;
+
+    #[test]
+    fn test_rows_are_read_in_order_and_panics_end_them() {
+        let rows = (0..3000).map(|index| Ok(vec![ColumnValue::I64(index)]));
+        let read: Vec<i64> = pipelined(Box::new(rows))
+            .map(|row| match row.unwrap()[0] {
+                ColumnValue::I64(index) => index,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!((0..3000).collect::<Vec<i64>>(), read);
+        let rows = (0..2).map(|index| match index {
+            0 => Ok(Vec::new()),
+            _ => panic!("Broken row"),
+        });
+        let mut rows = pipelined(Box::new(rows));
+        assert!(rows.next().unwrap().is_ok());
+        assert!(rows.next().unwrap().is_err());
+        assert!(rows.next().is_none());
+    }
+}
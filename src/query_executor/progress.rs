@@ -0,0 +1,70 @@
+use std::io::{Read, Result};
+use std::time::Instant;
+
+/// Bytes read between two logs of the progress of a download
+const PROGRESS_BYTES: usize = 10 * 1024 * 1024;
+
+/// Logs how much of the logs of a task was downloaded, so slow downloads can be told apart from
+/// stuck ones
+pub struct ProgressReader<R> {
+    reader: R,
+    task_id: u64,
+    started: Instant,
+    bytes: usize,
+    logged_bytes: usize,
+    finished: bool,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(reader: R, task_id: u64) -> Self {
+        Self {
+            reader,
+            task_id,
+            started: Instant::now(),
+            bytes: 0,
+            logged_bytes: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let read = self.reader.read(buffer)?;
+        self.bytes += read;
+        if read == 0 && !buffer.is_empty() && !self.finished {
+            self.finished = true;
+            println!(
+                "Downloaded {} bytes of the logs of task {} in {:.1}s",
+                self.bytes,
+                self.task_id,
+                self.started.elapsed().as_secs_f64()
+            );
+        } else if self.bytes - self.logged_bytes >= PROGRESS_BYTES {
+            self.logged_bytes = self.bytes;
+            println!(
+                "Downloaded {} bytes of the logs of task {} so far, after {:.1}s",
+                self.bytes,
+                self.task_id,
+                self.started.elapsed().as_secs_f64()
+            );
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_downloaded_bytes_are_counted() {
+        let mut reader = ProgressReader::new(Cursor::new(vec![b'a'; PROGRESS_BYTES + 5]), 1);
+        let mut logs = Vec::new();
+        reader.read_to_end(&mut logs).unwrap();
+        assert_eq!(PROGRESS_BYTES + 5, reader.bytes);
+        assert!(reader.logged_bytes >= PROGRESS_BYTES);
+        assert!(reader.finished);
+    }
+}
@@ -0,0 +1,246 @@
+use super::comments::{unwrap_versioned_comments, words};
+use super::mysql_syntax::{parse_sql, used_schema};
+use super::table_references::{has_known_tables, referenced_tables, TableList};
+use super::{PreparedQuery, QueryExecutor, SessionState, SqlDialect, SqlError};
+use anyhow::Result;
+use sqlparser::ast::Statement;
+
+/// Rejects queries that use tables the clients of the listener can not see. Tables are found in
+/// the parsed query. Queries that can not be parsed, or whose tables are not found in the parsed
+/// statements, are checked by their words, so aliases of tables, like `u` in `u.email`, can be
+/// taken for schemas. Queries are checked as the target runs them, with the text of their versioned
+/// comments.
+pub struct QueryAccessControl<T> {
+    executor: T,
+    dialect: SqlDialect,
+    session_state: SessionState,
+    /// Lowercase schemas that can be used. Every schema can be used without them.
+    allow_schemas: Option<Vec<String>>,
+    /// Tables that can not be used
+    deny_tables: TableList,
+}
+
+impl<T> QueryAccessControl<T> {
+    pub fn new(executor: T, dialect: SqlDialect, session_state: SessionState) -> Self {
+        Self {
+            executor,
+            dialect,
+            session_state,
+            allow_schemas: None,
+            deny_tables: TableList::default(),
+        }
+    }
+
+    pub fn with_allow_schemas(mut self, allow_schemas: Option<Vec<String>>) -> Self {
+        self.allow_schemas = allow_schemas.map(|schemas| {
+            schemas
+                .iter()
+                .map(|schema| schema.trim().to_lowercase())
+                .collect()
+        });
+        self
+    }
+
+    pub fn with_deny_tables(mut self, deny_tables: Vec<String>) -> Self {
+        self.deny_tables = TableList::new(deny_tables);
+        self
+    }
+
+    fn check(&self, query: &str) -> Result<(), SqlError> {
+        let query = unwrap_versioned_comments(query, self.dialect);
+        if let Some(schema) = used_schema(&query) {
+            return self.check_schema(&schema?.to_lowercase());
+        }
+        if self.allow_schemas.is_none() && self.deny_tables.is_empty() {
+            return Ok(());
+        }
+        let lower_case_query = query.trim().to_lowercase();
+        let default_schema = self
+            .session_state
+            .default_schema()
+            .map(|schema| schema.to_lowercase());
+        // Names of the CTEs of parsed queries, which are `None` for queries checked by their words
+        let (names, cte_names): (Vec<Vec<String>>, Option<Vec<String>>) =
+            match parse_sql(self.dialect.dialect().as_ref(), &query) {
+                // Text the parser skips, like comments it does not know, could be run by the target
+                Ok(statements)
+                    if statements.is_empty()
+                        && !lower_case_query.trim_matches([';', ' ', '\n']).is_empty() =>
+                {
+                    return Err(SqlError::new(
+                        "Access denied. The statements of the query could not be found".to_string(),
+                    ))
+                }
+                Ok(statements) if statements.iter().all(has_known_tables) => (
+                    statements
+                        .iter()
+                        .flat_map(referenced_tables)
+                        .map(|name| {
+                            name.0
+                                .iter()
+                                .map(|ident| ident.value.to_lowercase())
+                                .collect()
+                        })
+                        .collect(),
+                    Some(
+                        statements
+                            .iter()
+                            .filter_map(|statement| match statement {
+                                Statement::Query(query) => query.with.as_ref(),
+                                _ => None,
+                            })
+                            .flat_map(|with| &with.cte_tables)
+                            .map(|cte| cte.alias.name.value.to_lowercase())
+                            .collect(),
+                    ),
+                ),
+                _ => (words(&lower_case_query), None),
+            };
+        for name in names {
+            let (schema, table) = match name.as_slice() {
+                [.., schema, table] => (Some(schema.clone()), table),
+                [table] => (default_schema.clone(), table),
+                [] => continue,
+            };
+            // Tables without a schema could be of any schema. Words can be keywords, like `names`
+            // in `SET NAMES utf8`, so only the tables of parsed queries are rejected.
+            let unqualified = schema.is_none()
+                && cte_names
+                    .as_ref()
+                    .is_some_and(|cte_names| !cte_names.contains(table));
+            if unqualified && self.allow_schemas.is_some() {
+                return Err(SqlError::new(format!(
+                    "Access denied to table {}. Select a schema with USE or qualify the table, since this listener restricts schemas",
+                    table
+                )));
+            }
+            if let Some(schema) = &schema {
+                self.check_schema(schema)?;
+            }
+            if self.deny_tables.contains(schema.as_deref(), table) {
+                return Err(SqlError::new(format!(
+                    "Access denied to table {}. It is in the deny_tables of this listener",
+                    name.join(".")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_schema(&self, schema: &str) -> Result<(), SqlError> {
+        match &self.allow_schemas {
+            Some(allow_schemas) if !allow_schemas.iter().any(|allowed| allowed == schema) => {
+                Err(SqlError::new(format!(
+                    "Access denied to schema {}. It is not in the allow_schemas of this listener",
+                    schema
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T> QueryExecutor for QueryAccessControl<T>
+where
+    T: QueryExecutor,
+{
+    type QueryResult = T::QueryResult;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        self.check(query)?;
+        self.executor.query(query)
+    }
+
+    fn query_prepared(&mut self, prepared: &PreparedQuery) -> Result<Option<Self::QueryResult>> {
+        self.check(&prepared.query)?;
+        self.executor.query_prepared(prepared)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::FakeQueryExecutor;
+
+    #[test]
+    fn test_forbidden_tables_are_rejected() {
+        let executor = FakeQueryExecutor::new(vec![Ok(None), Ok(None)]);
+        let executed_queries = executor.get_query_list();
+        let session_state = SessionState::default();
+        let mut access =
+            QueryAccessControl::new(executor, SqlDialect::MySql, session_state.clone())
+                .with_allow_schemas(Some(vec!["app".to_string()]))
+                .with_deny_tables(vec!["app.secrets".to_string()]);
+        session_state.use_schema("app");
+        access.query("select * from users").unwrap();
+        for query in [
+            "select * from billing.invoices",
+            "select * from users where id in (select user_id from secrets)",
+            "use billing",
+            "select * from `app`.`secrets` where 'no' = 'parse",
+        ] {
+            let error = access.query(query).err().unwrap();
+            assert!(error.is::<SqlError>(), "{}", query);
+        }
+        access
+            .query("select 'billing.invoices' from app.users u")
+            .unwrap();
+        assert_eq!(2, executed_queries.borrow().len());
+    }
+
+    #[test]
+    fn test_tables_of_every_statement_are_checked() {
+        let executor = FakeQueryExecutor::new(vec![Ok(None)]);
+        let session_state = SessionState::default();
+        let mut access =
+            QueryAccessControl::new(executor, SqlDialect::MySql, session_state.clone())
+                .with_allow_schemas(Some(vec!["app".to_string()]))
+                .with_deny_tables(vec!["secrets".to_string()]);
+        for query in [
+            "select * from app.users",
+            "select * from users",
+            "describe app.secrets",
+            "show create table app.secrets",
+            "show columns from billing.invoices",
+            "create table app.copy as select * from billing.invoices",
+            "select exists(select 1 from app.secrets)",
+            "set @x = (select total from billing.invoices limit 1)",
+        ] {
+            let error = access.query(query).err().unwrap();
+            assert!(error.is::<SqlError>(), "{}", query);
+        }
+        session_state.use_schema("app");
+        access.query("select * from users").unwrap();
+    }
+
+    #[test]
+    fn test_statements_of_versioned_comments_are_checked() {
+        let executor = FakeQueryExecutor::new(vec![Ok(None)]);
+        let mut access =
+            QueryAccessControl::new(executor, SqlDialect::MySql, SessionState::default())
+                .with_deny_tables(vec!["app.secrets".to_string()]);
+        for query in [
+            "/*!50000 SELECT * FROM app.secrets */",
+            "select 1 /*! union select secret from app.secrets */",
+        ] {
+            let error = access.query(query).err().unwrap();
+            assert!(error.is::<SqlError>(), "{}", query);
+        }
+        access.query("/* app */ select 1").unwrap();
+    }
+
+    #[test]
+    fn test_use_takes_a_single_schema() {
+        let executor = FakeQueryExecutor::new(vec![Ok(None)]);
+        let executed_queries = executor.get_query_list();
+        let mut access =
+            QueryAccessControl::new(executor, SqlDialect::MySql, SessionState::default());
+        let error = access
+            .query("use app; select * from app.secrets")
+            .err()
+            .unwrap();
+        assert!(error.is::<SqlError>());
+        access.query("use `app`").unwrap();
+        assert_eq!(vec!["use `app`"], *executed_queries.borrow());
+    }
+}
@@ -1,18 +1,19 @@
-use super::QueryExecutor;
+use super::mysql_syntax::used_schema;
+use super::{QueryExecutor, SessionState};
 use anyhow::Result;
 use itertools::Itertools;
-use ritelinked::LinkedHashSet;
 
+#[derive(Clone)]
 pub struct QueryAccumulator<T> {
     executor: T,
-    acc: LinkedHashSet<String>,
+    session_state: SessionState,
 }
 
 impl<T> QueryAccumulator<T> {
-    pub fn new(executor: T) -> Self {
+    pub fn new(executor: T, session_state: SessionState) -> Self {
         Self {
             executor,
-            acc: LinkedHashSet::new(),
+            session_state,
         }
     }
 }
@@ -25,14 +26,122 @@ where
     fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
         let lower_case_query = query.to_lowercase();
         if lower_case_query.starts_with("set") {
-            self.acc.insert(query.to_string());
+            self.session_state.add_set_statement(query);
             return Ok(None);
         }
-        if self.acc.is_empty() {
+        // `RESET name` of Postgres. Other statements, like `RESET QUERY CACHE` or `RESET MASTER`
+        // of MySQL, go to the target.
+        if let Some(variable) = lower_case_query.strip_prefix("reset ") {
+            let variable = variable.trim().trim_end_matches(';').trim_end();
+            if !variable.contains(char::is_whitespace)
+                && self.session_state.reset_variable(variable)
+            {
+                return Ok(None);
+            }
+        }
+        if lower_case_query
+            .trim_end_matches(';')
+            .split_whitespace()
+            .eq(["fakesql", "reset", "session"])
+        {
+            println!("Resetting the session");
+            self.session_state.reset();
+            return Ok(None);
+        }
+        if let Some(schema) = used_schema(query) {
+            self.session_state.use_schema(&schema?);
+            return Ok(None);
+        }
+        let statements = self.session_state.target_statements();
+        if statements.is_empty() {
             return self.executor.query(query);
         }
 
         self.executor
-            .query(&(self.acc.iter().join(";\n") + ";\n" + query))
+            .query(&(statements.iter().join(";\n") + ";\n" + query))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::FakeQueryExecutor;
+
+    #[test]
+    fn test_session_statements_are_sent_with_the_query() {
+        let fake_executor = FakeQueryExecutor::new(vec![Ok(None)]);
+        let executed_queries = fake_executor.get_query_list();
+        let session_state = SessionState::default();
+        let mut accumulator = QueryAccumulator::new(fake_executor, session_state.clone());
+        accumulator.query("SET NAMES utf8mb4").unwrap();
+        accumulator.query("use `payments`;").unwrap();
+        accumulator.query("select * from users").unwrap();
+        assert_eq!(
+            "USE `payments`;\nSET NAMES utf8mb4;\nselect * from users",
+            executed_queries.borrow()[0]
+        );
+        assert_eq!(
+            "USE `payments`; SET NAMES utf8mb4",
+            session_state.cache_key()
+        );
+    }
+
+    #[test]
+    fn test_session_statements_are_replaced_and_reset() {
+        let fake_executor = FakeQueryExecutor::new(vec![Ok(None), Ok(None)]);
+        let executed_queries = fake_executor.get_query_list();
+        let session_state = SessionState::default().with_max_set_statements(4);
+        let mut accumulator = QueryAccumulator::new(fake_executor, session_state.clone());
+        for query in [
+            "use payments",
+            "SET sql_mode = ''",
+            "SET @@session.sql_mode = 'ANSI'",
+            "SET time_zone = '+00:00'",
+            "SET search_path TO public",
+            "SET SESSION time_zone = DEFAULT",
+            "RESET search_path",
+            "SET NAMES utf8mb4",
+            "SET autocommit = 1",
+            "SET @x = 1",
+            "SET SQL_SELECT_LIMIT = 501",
+        ] {
+            accumulator.query(query).unwrap();
+        }
+        accumulator.query("select 1").unwrap();
+        accumulator.query("FAKESQL RESET SESSION").unwrap();
+        accumulator.query("select 2").unwrap();
+        assert_eq!(
+            vec![
+                "USE `payments`;\nSET NAMES utf8mb4;\nSET autocommit = 1;\nSET @x = 1;\nselect 1",
+                "select 2",
+            ],
+            *executed_queries.borrow()
+        );
+    }
+
+    #[test]
+    fn test_each_variable_of_a_statement_is_kept() {
+        let fake_executor = FakeQueryExecutor::new(vec![Ok(None), Ok(None), Ok(None)]);
+        let executed_queries = fake_executor.get_query_list();
+        let session_state = SessionState::default();
+        let mut accumulator = QueryAccumulator::new(fake_executor, session_state.clone());
+        for query in [
+            "SET @x = 1, @y = concat('a', ','), GLOBAL wait_timeout = 10, net_write_timeout = 5",
+            "SET @@GLOBAL.wait_timeout = 20",
+            "SET @y = 2",
+            "SET search_path TO a, b",
+            "RESET search_path",
+        ] {
+            accumulator.query(query).unwrap();
+        }
+        accumulator.query("RESET QUERY CACHE").unwrap();
+        accumulator.query("RESET MASTER;").unwrap();
+        assert_eq!(
+            vec![
+                "SET @x = 1;\nSET GLOBAL net_write_timeout = 5;\nSET @@GLOBAL.wait_timeout = 20;\nSET @y = 2;\nRESET QUERY CACHE",
+                "SET @x = 1;\nSET GLOBAL net_write_timeout = 5;\nSET @@GLOBAL.wait_timeout = 20;\nSET @y = 2;\nRESET MASTER;",
+            ],
+            *executed_queries.borrow()
+        );
     }
 }
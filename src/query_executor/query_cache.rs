@@ -1,51 +1,531 @@
-use super::{Columns, QueryExecutor, QueryResult, Row};
+use super::comments::strip_comments;
+use super::file_query_storage::{export_snapshot, import_snapshot};
+use super::log_redaction::loggable;
+use super::mysql_syntax::parse_sql;
+use super::spill::RowBuffer;
+use super::table_references::{modified_tables, referenced_tables, table_name};
+use super::Compression;
+use super::{
+    CacheRules, Column, ColumnValue, Columns, PreparedQuery, QueryExecutor, QueryResult, Row, Rows,
+    SessionState, SqlDialect, SqlError,
+};
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sqlparser::dialect::Dialect;
 use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct QueryCache<T, S> {
     executor: T,
     storage: S,
-    queries_to_cache: HashSet<String>,
+    queries_to_cache: CacheRules,
+    options: CacheOptions,
+    session_state: SessionState,
+    /// Dialect used to find the comments and the tables of the queries that invalidate cached
+    /// results
+    dialect: SqlDialect,
 }
 
+#[derive(Clone, Default)]
+pub struct CacheOptions {
+    /// How long a cached result is valid. Results never expire without it.
+    pub ttl: Option<Duration>,
+    /// Caches every select, not only the ones allowed by the cache rules
+    pub cache_all: bool,
+    /// When set, empty results and queries without a result set are also cached, valid for
+    /// this duration instead of `ttl`
+    pub negative_ttl: Option<Duration>,
+    /// When set, expired results are still returned and this is called to refresh them with the
+    /// statements of the session
+    pub refresher: Option<Refresher>,
+    /// Results with more rows than this are not cached
+    pub max_entry_rows: Option<usize>,
+    /// Results approximately bigger than this are not cached
+    pub max_entry_bytes: Option<usize>,
+    /// Caches prepared statements by their template and parameters. The cache rules are
+    /// matched against the template.
+    pub cache_prepared_statements: bool,
+    /// How many parameter combinations of the same template are kept
+    pub max_entries_per_template: Option<usize>,
+    /// Adds a `fakesql_cached_at` column with the time the result was cached
+    pub provenance_column: bool,
+    /// Version of the database structure used to type the results. Results cached with another
+    /// version are discarded.
+    pub schema_version: Option<SchemaVersion>,
+    /// Rows read while caching a result are written to a temporary file after they are
+    /// approximately bigger than this
+    pub spill_bytes: Option<usize>,
+    /// Directory of the snapshots of `FAKESQL CACHE EXPORT` and `IMPORT`. The statements are
+    /// rejected without it.
+    pub snapshot_dir: Option<PathBuf>,
+}
+
+/// Refreshes the cached result of a query in the background. It takes the query and the
+/// statements that recreate the session it was run in.
+pub type Refresher = Arc<dyn Fn(String, Vec<String>) + Send + Sync>;
+/// Current version of the database structure. It is read on every lookup, so every connection
+/// sees a replaced structure at once.
+pub type SchemaVersion = Arc<dyn Fn() -> u64 + Send + Sync>;
+
 pub trait QueryStorage {
-    fn store(&mut self, query: &str, columns: Columns, rows: Vec<Row>);
+    fn store(&self, query: &str, result: CachedResult);
     fn get(&self, query: &str) -> Option<CachedResult>;
+    /// Same as `get`, but also returns expired results
+    fn get_stale(&self, query: &str) -> Option<CachedResult>;
+    /// Removes every entry whose query matches the predicate, returning how many were removed
+    fn remove_where(&self, predicate: &dyn Fn(&str) -> bool) -> usize;
+    fn list(&self) -> Vec<CachedEntryInfo>;
+    fn entries(&self) -> Vec<(String, CachedResult)>;
 }
 
-#[derive(Clone)]
+pub type SharedQueryStorage = Arc<dyn QueryStorage + Send + Sync>;
+
+impl<S: QueryStorage + ?Sized> QueryStorage for Arc<S> {
+    fn store(&self, query: &str, result: CachedResult) {
+        self.as_ref().store(query, result)
+    }
+    fn get(&self, query: &str) -> Option<CachedResult> {
+        self.as_ref().get(query)
+    }
+    fn get_stale(&self, query: &str) -> Option<CachedResult> {
+        self.as_ref().get_stale(query)
+    }
+    fn remove_where(&self, predicate: &dyn Fn(&str) -> bool) -> usize {
+        self.as_ref().remove_where(predicate)
+    }
+    fn list(&self) -> Vec<CachedEntryInfo> {
+        self.as_ref().list()
+    }
+    fn entries(&self) -> Vec<(String, CachedResult)> {
+        self.as_ref().entries()
+    }
+}
+
+/// Totals of a query storage
+pub struct CacheStats {
+    pub entries: usize,
+    pub rows: usize,
+    pub bytes: usize,
+}
+
+impl CacheStats {
+    pub fn of<S: QueryStorage + ?Sized>(storage: &S) -> Self {
+        storage.list().into_iter().fold(
+            Self {
+                entries: 0,
+                rows: 0,
+                bytes: 0,
+            },
+            |stats, entry| Self {
+                entries: stats.entries + 1,
+                rows: stats.rows + entry.rows,
+                bytes: stats.bytes + entry.size,
+            },
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CachedEntryInfo {
+    pub query: String,
+    pub stored_at: DateTime<Utc>,
+    pub rows: usize,
+    pub size: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CachedResult {
-    columns: Columns,
-    rows: Vec<Row>,
+    pub(super) columns: Columns,
+    pub(super) rows: Vec<Row>,
+    #[serde(default = "Utc::now")]
+    pub(super) stored_at: DateTime<Utc>,
+    #[serde(default)]
+    pub(super) ttl: Option<Duration>,
+    /// The query did not return a result set
+    #[serde(default)]
+    pub(super) no_result_set: bool,
+    #[serde(default)]
+    pub(super) schema_version: Option<u64>,
+}
+
+impl CachedResult {
+    pub fn new(columns: Columns, rows: Vec<Row>, ttl: Option<Duration>) -> Self {
+        Self {
+            columns,
+            rows,
+            stored_at: Utc::now(),
+            ttl,
+            no_result_set: false,
+            schema_version: None,
+        }
+    }
+
+    pub fn no_result_set(ttl: Option<Duration>) -> Self {
+        Self {
+            no_result_set: true,
+            ..Self::new(Vec::new(), Vec::new(), ttl)
+        }
+    }
+
+    /// Rough estimation of how much memory this result is using
+    pub fn approximate_size(&self) -> usize {
+        let columns_size: usize = self
+            .columns
+            .iter()
+            .map(|column| column.name.len() + column.ty.as_ref().map_or(0, String::len))
+            .sum();
+        let rows_size: usize = self.rows.iter().map(approximate_row_size).sum();
+        size_of::<Self>() + columns_size + rows_size
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match (
+            self.ttl,
+            Utc::now().signed_duration_since(self.stored_at).to_std(),
+        ) {
+            (Some(ttl), Ok(age)) => age >= ttl,
+            _ => false,
+        }
+    }
+}
+
+fn approximate_row_size(row: &Row) -> usize {
+    row.iter()
+        .map(|value| match value {
+            ColumnValue::String(string) => size_of::<ColumnValue>() + string.len(),
+            _ => size_of::<ColumnValue>(),
+        })
+        .sum()
 }
 
 pub enum CachedQueryResult<T: QueryResult> {
     CachedResult(CachedResult),
     Result(T),
+    /// Result that was too big to be cached. The rows already read are answered before the rest.
+    Partial(Columns, Rows),
 }
 
 impl<T: QueryResult> QueryResult for CachedQueryResult<T> {
-    fn get_data(self) -> (Result<Columns>, Box<dyn Iterator<Item = Result<Row>>>) {
+    fn get_data(self) -> (Result<Columns>, Rows) {
         match self {
             CachedQueryResult::Result(result) => result.get_data(),
             CachedQueryResult::CachedResult(cached_result) => (
                 Ok(cached_result.columns),
                 Box::new(cached_result.rows.into_iter().map(Ok)),
             ),
+            CachedQueryResult::Partial(columns, rows) => (Ok(columns), rows),
+        }
+    }
+
+    fn result_sets(self) -> Vec<(Result<Columns>, Rows)> {
+        match self {
+            CachedQueryResult::Result(result) => result.result_sets(),
+            cached => vec![cached.get_data()],
         }
     }
 }
 
 impl<T, S> QueryCache<T, S> {
-    pub fn new(executor: T, storage: S, queries_to_cache: HashSet<String>) -> Self {
+    pub fn new(
+        executor: T,
+        storage: S,
+        queries_to_cache: CacheRules,
+        options: CacheOptions,
+    ) -> Self {
         Self {
             executor,
             storage,
             queries_to_cache,
+            options,
+            session_state: SessionState::default(),
+            dialect: SqlDialect::MySql,
         }
     }
+
+    pub fn with_dialect(mut self, dialect: SqlDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Results are cached per session state, so connections using different schemas or
+    /// variables do not share them
+    pub fn with_session_state(mut self, session_state: SessionState) -> Self {
+        self.session_state = session_state;
+        self
+    }
+
+    /// Results typed with another database structure can have wrong types, so they are removed
+    fn discard_other_schema_version(
+        &self,
+        key: &str,
+        cached: Option<CachedResult>,
+    ) -> Option<CachedResult>
+    where
+        S: QueryStorage,
+    {
+        match (&cached, self.schema_version()) {
+            (
+                Some(CachedResult {
+                    schema_version: Some(stored_version),
+                    ..
+                }),
+                Some(version),
+            ) if *stored_version != version => {
+                println!(
+                    "Discarding cached query {}. The database structure changed",
+                    loggable(key)
+                );
+                self.storage.remove_where(&|query| query == key);
+                None
+            }
+            _ => cached,
+        }
+    }
+
+    fn schema_version(&self) -> Option<u64> {
+        self.options
+            .schema_version
+            .as_ref()
+            .map(|version| version())
+    }
+
+    fn cache_key(&self, query: &str) -> String {
+        let session = self.session_state.cache_key();
+        if session.is_empty() {
+            query.to_string()
+        } else {
+            format!("{query}\n-- session: {session}")
+        }
+    }
+
+    fn should_cache(&self, query: &str) -> bool {
+        self.queries_to_cache.matches(query)
+            || (self.options.cache_all && query.trim_start().to_lowercase().starts_with("select"))
+    }
+
+    fn run_command(&self, command: CacheCommand) -> Result<CachedResult>
+    where
+        S: QueryStorage,
+    {
+        match command {
+            CacheCommand::Clear(pattern) => {
+                let removed = match pattern {
+                    None => self.storage.remove_where(&|_| true),
+                    Some(pattern) => {
+                        let rules = CacheRules::new([pattern])?;
+                        self.storage.remove_where(&|query| rules.matches(query))
+                    }
+                };
+                println!("Removed {removed} entries from the cache");
+                Ok(CachedResult::new(
+                    vec![command_column("removed_entries", "bigint")],
+                    vec![vec![ColumnValue::I64(removed as i64)]],
+                    None,
+                ))
+            }
+            CacheCommand::List => Ok(CachedResult::new(
+                vec![
+                    command_column("query", "text"),
+                    command_column("stored_at", "datetime"),
+                    command_column("rows", "bigint"),
+                    command_column("bytes", "bigint"),
+                ],
+                self.storage
+                    .list()
+                    .into_iter()
+                    .map(|entry| {
+                        vec![
+                            ColumnValue::String(entry.query),
+                            ColumnValue::DateTime(entry.stored_at.naive_utc()),
+                            ColumnValue::I64(entry.rows as i64),
+                            ColumnValue::I64(entry.size as i64),
+                        ]
+                    })
+                    .collect(),
+                None,
+            )),
+            CacheCommand::Stats => {
+                let stats = CacheStats::of(&self.storage);
+                Ok(CachedResult::new(
+                    vec![
+                        command_column("entries", "bigint"),
+                        command_column("rows", "bigint"),
+                        command_column("bytes", "bigint"),
+                    ],
+                    vec![vec![
+                        ColumnValue::I64(stats.entries as i64),
+                        ColumnValue::I64(stats.rows as i64),
+                        ColumnValue::I64(stats.bytes as i64),
+                    ]],
+                    None,
+                ))
+            }
+            CacheCommand::Export(name) => {
+                let path = self.snapshot_path(&name)?;
+                let exported = export_snapshot(&self.storage, &path)?;
+                let path = path.display();
+                println!("Exported {exported} cache entries to {path}");
+                Ok(CachedResult::new(
+                    vec![command_column("exported_entries", "bigint")],
+                    vec![vec![ColumnValue::I64(exported as i64)]],
+                    None,
+                ))
+            }
+            CacheCommand::Import(name) => {
+                let path = self.snapshot_path(&name)?;
+                let imported = import_snapshot(&self.storage, &path)?;
+                let path = path.display();
+                println!("Imported {imported} cache entries from {path}");
+                Ok(CachedResult::new(
+                    vec![command_column("imported_entries", "bigint")],
+                    vec![vec![ColumnValue::I64(imported as i64)]],
+                    None,
+                ))
+            }
+        }
+    }
+}
+
+impl<T, S: QueryStorage> QueryCache<T, S> {
+    /// Path of a snapshot named by a client. Only files of the snapshot directory can be named, so
+    /// clients can not read or write other files of the machine running the proxy.
+    fn snapshot_path(&self, name: &str) -> Result<PathBuf, SqlError> {
+        let directory = self.options.snapshot_dir.as_ref().ok_or_else(|| {
+            SqlError::new(
+                "FAKESQL CACHE EXPORT and IMPORT need a cache_snapshot_dir. Use the cache command of fakesql instead".to_string(),
+            )
+        })?;
+        if Path::new(name).file_name() != Some(OsStr::new(name)) {
+            return Err(SqlError::new(format!(
+                "Snapshots are named by a file of the cache_snapshot_dir, not by a path: {name}"
+            )));
+        }
+        Ok(directory.join(name))
+    }
+
+    /// Drops the cached results that read from any table changed by the query
+    fn invalidate_modified_tables(&self, query: &str) {
+        let dialect = self.dialect.dialect();
+        let query = strip_comments(query, false, self.dialect);
+        let statements = match parse_sql(dialect.as_ref(), &query) {
+            Ok(statements) => statements,
+            Err(error) => {
+                println!(
+                    "Could not find the tables changed by the query. Cache is not being invalidated. {}",
+                    loggable(&error.to_string())
+                );
+                return;
+            }
+        };
+        let tables: HashSet<String> = statements
+            .iter()
+            .flat_map(modified_tables)
+            .map(|name| table_name(&name))
+            .collect();
+        if tables.is_empty() {
+            return;
+        }
+        let removed = self.storage.remove_where(&|cached_query| {
+            references_any_table(dialect.as_ref(), cached_query, &tables)
+        });
+        if removed > 0 {
+            println!(
+                "Invalidated {removed} cached results that used {:?}",
+                tables
+            );
+        }
+    }
+}
+
+fn references_any_table(dialect: &dyn Dialect, query: &str, tables: &HashSet<String>) -> bool {
+    match parse_sql(dialect, query) {
+        Ok(statements) => statements
+            .iter()
+            .flat_map(referenced_tables)
+            .any(|name| tables.contains(&table_name(&name))),
+        // Without knowing the tables, it is safer to drop any result that could use them
+        Err(_) => {
+            let query = query.to_lowercase();
+            tables.iter().any(|table| query.contains(table.as_str()))
+        }
+    }
+}
+
+/// Comments before the statement, like the ones of ORMs and of `annotate_queries`, are skipped
+fn is_write_statement(query: &str, dialect: SqlDialect) -> bool {
+    let first_word = strip_comments(query, false, dialect)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    matches!(
+        first_word.as_str(),
+        "insert" | "update" | "delete" | "replace" | "create" | "alter" | "drop" | "truncate"
+    )
+}
+
+enum CacheCommand {
+    Clear(Option<String>),
+    List,
+    Stats,
+    Export(String),
+    Import(String),
+}
+
+/// Parses the virtual `FAKESQL CACHE LIST`, `FAKESQL CACHE STATS`, `FAKESQL CACHE CLEAR ['pattern']`,
+/// `FAKESQL CACHE EXPORT 'name'` and `FAKESQL CACHE IMPORT 'name'` statements.
+/// Patterns are globs unless prefixed by `regex:`.
+fn parse_cache_command(query: &str) -> Option<CacheCommand> {
+    let command = strip_prefix_ignore_case(query.trim().trim_end_matches(';'), "fakesql cache")?;
+    let command = command.trim();
+    if command.eq_ignore_ascii_case("list") {
+        return Some(CacheCommand::List);
+    }
+    if command.eq_ignore_ascii_case("stats") {
+        return Some(CacheCommand::Stats);
+    }
+    if let Some(path) = strip_prefix_ignore_case(command, "export") {
+        return Some(CacheCommand::Export(unquote(path.trim()).to_string()));
+    }
+    if let Some(path) = strip_prefix_ignore_case(command, "import") {
+        return Some(CacheCommand::Import(unquote(path.trim()).to_string()));
+    }
+    let pattern = strip_prefix_ignore_case(command, "clear")?.trim();
+    if pattern.is_empty() {
+        return Some(CacheCommand::Clear(None));
+    }
+    let pattern = unquote(pattern);
+    if pattern.starts_with("regex:") || pattern.starts_with("glob:") {
+        Some(CacheCommand::Clear(Some(pattern.to_string())))
+    } else {
+        Some(CacheCommand::Clear(Some(format!("glob:{pattern}"))))
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim_matches(|character| character == '\'' || character == '"')
+}
+
+fn strip_prefix_ignore_case<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    match value.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&value[prefix.len()..]),
+        _ => None,
+    }
+}
+
+fn command_column(name: &str, ty: &str) -> Column {
+    Column {
+        name: name.to_string(),
+        ty: Some(ty.to_string()),
+        attributes: Default::default(),
+    }
 }
 
 impl<T, S, R> QueryExecutor for QueryCache<T, S>
@@ -56,48 +536,980 @@ where
 {
     type QueryResult = CachedQueryResult<T::QueryResult>;
     fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
-        match self.storage.get(query) {
-            None => match self.executor.query(query) {
-                Ok(Some(result)) => {
-                    if self.queries_to_cache.get(query).is_none() {
-                        return Ok(Some(CachedQueryResult::Result(result)));
+        if let Some(command) = parse_cache_command(query) {
+            return Ok(Some(CachedQueryResult::CachedResult(
+                self.run_command(command)?,
+            )));
+        }
+        if is_write_statement(query, self.dialect) {
+            let result = self.executor.query(query);
+            if result.is_ok() {
+                self.invalidate_modified_tables(query);
+            }
+            return result.map(|result| result.map(CachedQueryResult::Result));
+        }
+        let key = self.cache_key(query);
+        let cached = match &self.options.refresher {
+            Some(refresher) => {
+                let cached = self.storage.get_stale(&key);
+                if let Some(result) = &cached {
+                    if result.is_expired() {
+                        refresher(query.to_string(), self.session_state.statements());
                     }
-                    let (columns, rows) = result.get_data();
-                    let columns = columns?;
-                    let rows = rows.collect::<Result<Vec<Row>>>()?;
-                    self.storage.store(query, columns.clone(), rows.clone());
-                    Ok(Some(CachedQueryResult::CachedResult(CachedResult {
-                        columns,
-                        rows,
-                    })))
                 }
-                Ok(None) => Ok(None),
-                Err(err) => Err(err),
-            },
-            Some(result) => Ok(Some(CachedQueryResult::CachedResult(result))),
+                cached
+            }
+            _ => self.storage.get(&key),
+        };
+        match self.discard_other_schema_version(&key, cached) {
+            None if !self.should_cache(query) => self
+                .executor
+                .query(query)
+                .map(|result| result.map(CachedQueryResult::Result)),
+            None => self.execute_and_store(query, &key, query),
+            Some(result) if result.no_result_set => {
+                self.session_state.set_cached();
+                Ok(None)
+            }
+            Some(result) => Ok(Some(self.answer_from_cache(result))),
         }
     }
+
+    fn query_prepared(&mut self, prepared: &PreparedQuery) -> Result<Option<Self::QueryResult>> {
+        if !self.options.cache_prepared_statements
+            || is_write_statement(&prepared.template, self.dialect)
+            || !self.should_cache(&prepared.template)
+        {
+            return self.query(&prepared.query);
+        }
+        let key = self.cache_key(&format!(
+            "{}{}",
+            template_key_prefix(&prepared.template),
+            prepared.parameters.join(", ")
+        ));
+        let cached = self.storage.get(&key);
+        match self.discard_other_schema_version(&key, cached) {
+            None => {
+                let result = self.execute_and_store(&prepared.query, &key, &prepared.template);
+                self.limit_template_entries(&prepared.template);
+                result
+            }
+            Some(result) if result.no_result_set => {
+                self.session_state.set_cached();
+                Ok(None)
+            }
+            Some(result) => Ok(Some(self.answer_from_cache(result))),
+        }
+    }
+}
+
+fn template_key_prefix(template: &str) -> String {
+    format!("{template}\n-- parameters: ")
 }
 
+impl<T, S, R> QueryCache<T, S>
+where
+    T: QueryExecutor<QueryResult = R>,
+    S: QueryStorage,
+    R: QueryResult,
+{
+    /// Executes the query skipping the cache and stores its result
+    pub fn refresh(&mut self, query: &str) -> Result<()> {
+        let key = self.cache_key(query);
+        if let Some(CachedQueryResult::Partial(..)) = self.execute_and_store(query, &key, query)? {
+            self.storage
+                .remove_where(&|cached_query| cached_query == key);
+        }
+        Ok(())
+    }
+
+    /// Executes the query and stores its result under the key. The ttl comes from the cache
+    /// rule matching `rule_query`.
+    fn execute_and_store(
+        &mut self,
+        query: &str,
+        key: &str,
+        rule_query: &str,
+    ) -> Result<Option<CachedQueryResult<R>>> {
+        match self.executor.query(query)? {
+            Some(result) => {
+                let (columns, mut rows) = result.get_data();
+                let columns = columns?;
+                let mut read_rows = RowBuffer::new(self.options.spill_bytes);
+                let mut read_bytes = 0;
+                for row in rows.by_ref() {
+                    let row = row?;
+                    let size = approximate_row_size(&row);
+                    read_bytes += size;
+                    read_rows.push(row, size)?;
+                    if self.exceeds_entry_limits(read_rows.len(), read_bytes) {
+                        println!(
+                            "Not caching the result of {}. It is over the cache entry limits",
+                            loggable(query)
+                        );
+                        let rows = Box::new(read_rows.into_rows()?.chain(rows));
+                        return Ok(Some(CachedQueryResult::Partial(columns, rows)));
+                    }
+                }
+                let ttl = match self.options.negative_ttl {
+                    Some(negative_ttl) if read_rows.is_empty() => Some(negative_ttl),
+                    _ => self
+                        .queries_to_cache
+                        .find(rule_query)
+                        .and_then(|rule| rule.ttl)
+                        .or(self.options.ttl),
+                };
+                let mut result = CachedResult::new(columns, read_rows.into_vec()?, ttl);
+                result.schema_version = self.schema_version();
+                self.storage.store(key, result.clone());
+                Ok(Some(CachedQueryResult::CachedResult(
+                    self.with_provenance_column(result),
+                )))
+            }
+            None => {
+                if let Some(negative_ttl) = self.options.negative_ttl {
+                    self.storage
+                        .store(key, CachedResult::no_result_set(Some(negative_ttl)));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn answer_from_cache(&self, result: CachedResult) -> CachedQueryResult<R> {
+        self.session_state.set_cached();
+        self.session_state.add_warning(format!(
+            "Served from cache, stored at {}",
+            result.stored_at.format("%Y-%m-%d %H:%M:%S UTC")
+        ));
+        CachedQueryResult::CachedResult(self.with_provenance_column(result))
+    }
+
+    fn with_provenance_column(&self, mut result: CachedResult) -> CachedResult {
+        if self.options.provenance_column {
+            result
+                .columns
+                .push(command_column("fakesql_cached_at", "datetime"));
+            let stored_at = result.stored_at.naive_utc();
+            for row in &mut result.rows {
+                row.push(ColumnValue::DateTime(stored_at));
+            }
+        }
+        result
+    }
+
+    /// Removes the oldest results of the template over `max_entries_per_template`
+    fn limit_template_entries(&self, template: &str) {
+        let max_entries = match self.options.max_entries_per_template {
+            Some(max_entries) => max_entries,
+            None => return,
+        };
+        let prefix = template_key_prefix(template);
+        let mut entries: Vec<CachedEntryInfo> = self
+            .storage
+            .list()
+            .into_iter()
+            .filter(|entry| entry.query.starts_with(&prefix))
+            .collect();
+        if entries.len() <= max_entries {
+            return;
+        }
+        entries.sort_by_key(|entry| entry.stored_at);
+        let oldest: HashSet<String> = entries
+            .into_iter()
+            .rev()
+            .skip(max_entries)
+            .map(|entry| entry.query)
+            .collect();
+        self.storage.remove_where(&|query| oldest.contains(query));
+    }
+
+    fn exceeds_entry_limits(&self, rows: usize, bytes: usize) -> bool {
+        matches!(self.options.max_entry_rows, Some(max_rows) if rows > max_rows)
+            || matches!(self.options.max_entry_bytes, Some(max_bytes) if bytes > max_bytes)
+    }
+}
+
+/// In memory storage. When created with a memory budget, the least recently used entries are
+/// evicted to keep the approximate size of the stored results under it.
 #[derive(Clone)]
 pub struct InMemoryQueryStorage {
-    dashmap: Arc<DashMap<String, CachedResult>>,
+    dashmap: Arc<DashMap<String, StoredResult>>,
+    used_bytes: Arc<AtomicUsize>,
+    clock: Arc<AtomicU64>,
+    max_bytes: Option<usize>,
+    high_water_bytes: Option<usize>,
+    compression: Option<Compression>,
+}
+
+struct StoredResult {
+    /// Without rows when they are compressed
+    result: CachedResult,
+    compressed_rows: Option<(Compression, Vec<u8>)>,
+    rows: usize,
+    size: usize,
+    last_access: u64,
+}
+
+impl StoredResult {
+    fn decompressed(&self) -> Result<CachedResult> {
+        let mut result = self.result.clone();
+        if let Some((compression, compressed_rows)) = &self.compressed_rows {
+            result.rows = compression.decompress(compressed_rows)?;
+        }
+        Ok(result)
+    }
 }
 
 impl QueryStorage for InMemoryQueryStorage {
-    fn store(&mut self, query: &str, columns: Columns, rows: Vec<Row>) {
-        self.dashmap
-            .insert(query.to_string(), CachedResult { columns, rows });
+    fn store(&self, query: &str, result: CachedResult) {
+        self.insert(query.to_string(), result);
     }
     fn get(&self, query: &str) -> Option<CachedResult> {
-        self.dashmap.get(query).map(|result| result.clone())
+        let result = self.get_stale(query)?;
+        if result.is_expired() {
+            self.remove(query);
+            return None;
+        }
+        Some(result)
+    }
+    fn get_stale(&self, query: &str) -> Option<CachedResult> {
+        let result = {
+            let mut stored = self.dashmap.get_mut(query)?;
+            stored.last_access = self.tick();
+            stored.decompressed()
+        };
+        match result {
+            Ok(result) => Some(result),
+            Err(error) => {
+                println!(
+                    "Error decompressing cached query {}: {error}",
+                    loggable(query)
+                );
+                self.remove(query);
+                None
+            }
+        }
+    }
+    fn remove_where(&self, predicate: &dyn Fn(&str) -> bool) -> usize {
+        let queries: Vec<String> = self
+            .dashmap
+            .iter()
+            .filter(|entry| predicate(entry.key()))
+            .map(|entry| entry.key().clone())
+            .collect();
+        for query in &queries {
+            self.remove(query);
+        }
+        queries.len()
+    }
+    fn list(&self) -> Vec<CachedEntryInfo> {
+        self.dashmap
+            .iter()
+            .map(|entry| CachedEntryInfo {
+                query: entry.key().clone(),
+                stored_at: entry.result.stored_at,
+                rows: entry.rows,
+                size: entry.size,
+            })
+            .collect()
+    }
+    fn entries(&self) -> Vec<(String, CachedResult)> {
+        self.dashmap
+            .iter()
+            .filter_map(|entry| {
+                let result = entry.decompressed().ok()?;
+                Some((entry.key().clone(), result))
+            })
+            .collect()
     }
 }
 
 impl InMemoryQueryStorage {
-    pub fn new() -> Self {
+    pub fn with_max_bytes(max_bytes: Option<usize>) -> Self {
         Self {
             dashmap: Arc::new(DashMap::new()),
+            used_bytes: Arc::new(AtomicUsize::new(0)),
+            clock: Arc::new(AtomicU64::new(0)),
+            max_bytes,
+            high_water_bytes: None,
+            compression: None,
+        }
+    }
+
+    /// Refuses to store new results while the cache uses more than `high_water_bytes`, instead
+    /// of evicting older ones
+    pub fn with_high_water_bytes(mut self, high_water_bytes: Option<usize>) -> Self {
+        self.high_water_bytes = high_water_bytes;
+        self
+    }
+
+    pub(super) fn entry_count(&self) -> usize {
+        self.dashmap.len()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Compresses the rows of the stored results, trading cpu for memory
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub(super) fn insert(&self, query: String, mut result: CachedResult) {
+        let rows = result.rows.len();
+        let compressed_rows = match self.compression {
+            Some(compression) => match compression.compress(&result.rows) {
+                Ok(compressed_rows) => {
+                    result.rows = Vec::new();
+                    Some((compression, compressed_rows))
+                }
+                Err(error) => {
+                    println!(
+                        "Error compressing cached query {}: {error}",
+                        loggable(&query)
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let size = result.approximate_size()
+            + compressed_rows
+                .as_ref()
+                .map_or(0, |(_, compressed_rows)| compressed_rows.len());
+        if matches!(self.max_bytes, Some(max_bytes) if size > max_bytes) {
+            println!("Not caching result of {size} bytes. It is bigger than the whole cache");
+            self.remove(&query);
+            return;
+        }
+        if matches!(self.high_water_bytes, Some(high_water_bytes) if self.used_bytes() + size > high_water_bytes)
+        {
+            println!(
+                "Not caching result of {size} bytes. The cache is at its high water mark using {} bytes",
+                self.used_bytes()
+            );
+            return;
+        }
+        let stored = StoredResult {
+            result,
+            compressed_rows,
+            rows,
+            size,
+            last_access: self.tick(),
+        };
+        if let Some(replaced) = self.dashmap.insert(query, stored) {
+            self.used_bytes.fetch_sub(replaced.size, Ordering::SeqCst);
+        }
+        self.used_bytes.fetch_add(size, Ordering::SeqCst);
+        self.evict_if_needed();
+    }
+
+    fn evict_if_needed(&self) {
+        let max_bytes = match self.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return,
+        };
+        while self.used_bytes.load(Ordering::SeqCst) > max_bytes {
+            let least_recently_used = self
+                .dashmap
+                .iter()
+                .min_by_key(|entry| entry.last_access)
+                .map(|entry| entry.key().clone());
+            match least_recently_used {
+                Some(query) => {
+                    println!("Evicting cached query {}", loggable(&query));
+                    self.remove(&query);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&self, query: &str) {
+        if let Some((_, removed)) = self.dashmap.remove(query) {
+            self.used_bytes.fetch_sub(removed.size, Ordering::SeqCst);
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::{reader_result, FakeQueryExecutor};
+
+    #[test]
+    fn test_only_matching_queries_are_cached() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n2\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:select id from cached*".to_string()]).unwrap(),
+            CacheOptions::default(),
+        );
+        for query in [
+            "select id from cached_table",
+            "select id from cached_table",
+            "select id from other_table",
+            "select id from other_table",
+        ] {
+            let (columns, rows) = query_cache.query(query).unwrap().unwrap().get_data();
+            assert_eq!("id", columns.unwrap()[0].name);
+            assert_eq!(1, rows.count());
         }
+        assert_eq!(3, executed_queries.borrow().len());
+    }
+
+    #[test]
+    fn test_cache_commands() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n2\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            CacheOptions::default(),
+        );
+        query_cache.query("select id from users").unwrap();
+        query_cache.query("select id from payments").unwrap();
+
+        let (_, rows) = query_cache
+            .query("fakesql cache list;")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        assert_eq!(2, rows.count());
+
+        query_cache
+            .query("FAKESQL CACHE CLEAR 'select id from user*'")
+            .unwrap();
+        query_cache.query("select id from users").unwrap();
+        query_cache.query("select id from payments").unwrap();
+        assert_eq!(3, executed_queries.borrow().len());
+
+        let (_, rows) = query_cache
+            .query("FAKESQL CACHE CLEAR")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        assert!(matches!(
+            rows.last().unwrap().unwrap()[0],
+            ColumnValue::I64(2)
+        ));
+    }
+
+    #[test]
+    fn test_export_and_import_commands() {
+        let name = format!("my-fake-sql-cache-snapshot-{}.cache", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+        let cache_options = CacheOptions {
+            snapshot_dir: Some(std::env::temp_dir()),
+            ..CacheOptions::default()
+        };
+        let fake_executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result("id\n2\n")))]);
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            cache_options.clone(),
+        );
+        query_cache.query("select id from users").unwrap();
+        query_cache
+            .query(&format!("FAKESQL CACHE EXPORT '{name}'"))
+            .unwrap();
+        for command in [
+            "FAKESQL CACHE EXPORT '/etc/cron.d/fakesql'",
+            "FAKESQL CACHE IMPORT '../secrets'",
+        ] {
+            let error = query_cache.query(command).err().unwrap();
+            assert!(error.is::<SqlError>(), "{}", command);
+        }
+
+        let fake_executor = FakeQueryExecutor::new(vec![]);
+        let executed_queries = fake_executor.get_query_list();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            cache_options,
+        );
+        query_cache
+            .query(&format!("fakesql cache import '{name}';"))
+            .unwrap();
+        let (_, rows) = query_cache
+            .query("select id from users")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        assert_eq!(1, rows.count());
+        assert!(executed_queries.borrow().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_negative_caching() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n"))),
+            Ok(None),
+            Ok(Some(reader_result("id\n"))),
+            Ok(None),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:*".to_string()]).unwrap(),
+            CacheOptions {
+                negative_ttl: Some(Duration::from_millis(50)),
+                ..CacheOptions::default()
+            },
+        );
+        assert!(query_cache.query("show triggers").unwrap().is_none());
+        assert!(query_cache.query("show triggers").unwrap().is_none());
+        let (_, rows) = query_cache
+            .query("select id from users")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        assert_eq!(0, rows.count());
+        query_cache.query("select id from users").unwrap();
+        assert_eq!(2, executed_queries.borrow().len());
+
+        std::thread::sleep(Duration::from_millis(60));
+        query_cache.query("show triggers").unwrap();
+        query_cache.query("select id from users").unwrap();
+        assert_eq!(4, executed_queries.borrow().len());
+    }
+
+    #[test]
+    fn test_stale_while_revalidate() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n2\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let refreshed_queries = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let refresher_queries = refreshed_queries.clone();
+        let storage = InMemoryQueryStorage::with_max_bytes(None);
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            storage.clone(),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            CacheOptions {
+                ttl: Some(Duration::from_millis(20)),
+                refresher: Some(Arc::new(move |query, _| {
+                    refresher_queries.lock().unwrap().push(query)
+                })),
+                ..CacheOptions::default()
+            },
+        );
+        query_cache.query("select id from users").unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        let (_, rows) = query_cache
+            .query("select id from users")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        assert_eq!(1, rows.count());
+        assert_eq!(1, executed_queries.borrow().len());
+        assert_eq!(
+            vec!["select id from users".to_string()],
+            *refreshed_queries.lock().unwrap()
+        );
+
+        query_cache.refresh("select id from users").unwrap();
+        assert_eq!(2, executed_queries.borrow().len());
+        assert!(!storage.get("select id from users").unwrap().is_expired());
+    }
+
+    #[test]
+    fn test_stale_while_revalidate_of_a_session() {
+        let refreshed_queries = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let refresher_queries = refreshed_queries.clone();
+        let storage = InMemoryQueryStorage::with_max_bytes(None);
+        let options = CacheOptions {
+            ttl: Some(Duration::from_millis(20)),
+            refresher: Some(Arc::new(move |query, statements| {
+                refresher_queries.lock().unwrap().push((query, statements))
+            })),
+            ..CacheOptions::default()
+        };
+        let session_state = SessionState::default();
+        session_state.use_schema("payments");
+        let mut query_cache = QueryCache::new(
+            FakeQueryExecutor::new(vec![Ok(Some(reader_result("id\n1\n")))]),
+            storage.clone(),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            options.clone(),
+        )
+        .with_session_state(session_state);
+        query_cache.query("select id from users").unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        query_cache.query("select id from users").unwrap();
+        let (query, statements) = refreshed_queries.lock().unwrap().pop().unwrap();
+        assert_eq!(vec!["USE `payments`".to_string()], statements);
+
+        // The refresh stores the result under the key of the same session
+        let mut refresh_cache = QueryCache::new(
+            FakeQueryExecutor::new(vec![Ok(Some(reader_result("id\n2\n")))]),
+            storage.clone(),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            options,
+        )
+        .with_session_state(SessionState::default().with_statements(&statements));
+        refresh_cache.refresh(&query).unwrap();
+        let key = "select id from users\n-- session: USE `payments`";
+        assert!(!storage.get(key).unwrap().is_expired());
+        assert_eq!(1, storage.list().len());
+    }
+
+    #[test]
+    fn test_results_over_the_entry_limits_are_not_cached() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n1\n"))),
+            Ok(Some(reader_result("id\n1\n2\n3\n"))),
+            Ok(Some(reader_result("id\n1\n2\n3\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            CacheOptions {
+                max_entry_rows: Some(2),
+                ..CacheOptions::default()
+            },
+        );
+        let (_, rows) = query_cache
+            .query("select id from users")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        let rows: Vec<Row> = rows.collect::<Result<_>>().unwrap();
+        assert_eq!(3, rows.len());
+        assert!(matches!(&rows[2][0], ColumnValue::String(id) if id == "3"));
+        query_cache.query("select id from users").unwrap();
+        assert_eq!(2, executed_queries.borrow().len());
+
+        query_cache.query("select id from payments").unwrap();
+        query_cache.query("select id from payments").unwrap();
+        assert_eq!(3, executed_queries.borrow().len());
+    }
+
+    #[test]
+    fn test_results_are_cached_per_session() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n2\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let session_state = SessionState::default();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            CacheOptions::default(),
+        )
+        .with_session_state(session_state.clone());
+        query_cache.query("select id from users").unwrap();
+        session_state.use_schema("payments");
+        query_cache.query("select id from users").unwrap();
+        query_cache.query("select id from users").unwrap();
+        assert_eq!(2, executed_queries.borrow().len());
+    }
+
+    #[test]
+    fn test_prepared_statements_are_cached_by_template() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n2\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let storage = InMemoryQueryStorage::with_max_bytes(None);
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            storage.clone(),
+            CacheRules::new(["select id from users where id = ?".to_string()]).unwrap(),
+            CacheOptions {
+                cache_prepared_statements: true,
+                max_entries_per_template: Some(1),
+                ..CacheOptions::default()
+            },
+        );
+        let prepared = |id: &str| PreparedQuery {
+            template: "select id from users where id = ?".to_string(),
+            parameters: vec![id.to_string()],
+            query: format!("select id from users where id = {id}"),
+        };
+        query_cache.query_prepared(&prepared("1")).unwrap();
+        query_cache.query_prepared(&prepared("1")).unwrap();
+        assert_eq!(1, executed_queries.borrow().len());
+        assert_eq!(
+            "select id from users where id = 1",
+            executed_queries.borrow()[0]
+        );
+
+        query_cache.query_prepared(&prepared("2")).unwrap();
+        assert_eq!(2, executed_queries.borrow().len());
+        assert_eq!(1, storage.list().len());
+    }
+
+    #[test]
+    fn test_compressed_storage() {
+        let storage =
+            InMemoryQueryStorage::with_max_bytes(None).with_compression(Some(Compression::Lz4));
+        let rows: Vec<Row> = (0..100)
+            .map(|id| vec![ColumnValue::String(format!("user number {}", id % 2))])
+            .collect();
+        let uncompressed_size =
+            CachedResult::new(Vec::new(), rows.clone(), None).approximate_size();
+        storage.store(
+            "select name from users",
+            CachedResult::new(Vec::new(), rows, None),
+        );
+
+        let entry = &storage.list()[0];
+        assert_eq!(100, entry.rows);
+        assert!(entry.size < uncompressed_size);
+        let result = storage.get("select name from users").unwrap();
+        assert_eq!(100, result.rows.len());
+        assert!(
+            matches!(&result.rows[99][0], ColumnValue::String(name) if name == "user number 1")
+        );
+    }
+
+    #[test]
+    fn test_results_of_another_schema_version_are_discarded() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n2\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let storage = InMemoryQueryStorage::with_max_bytes(None);
+        let version = Arc::new(AtomicU64::new(1));
+        let current_version = version.clone();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            storage.clone(),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            CacheOptions {
+                schema_version: Some(Arc::new(move || current_version.load(Ordering::SeqCst))),
+                ..CacheOptions::default()
+            },
+        );
+        query_cache.query("select id from users").unwrap();
+        query_cache.query("select id from users").unwrap();
+        assert_eq!(1, executed_queries.borrow().len());
+
+        // Connections already open see the replaced structure
+        version.store(2, Ordering::SeqCst);
+        query_cache.query("select id from users").unwrap();
+        query_cache.query("select id from users").unwrap();
+        assert_eq!(2, executed_queries.borrow().len());
+        assert_eq!(
+            Some(2),
+            storage.get("select id from users").unwrap().schema_version
+        );
+    }
+
+    #[test]
+    fn test_cache_provenance() {
+        let fake_executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result("id\n2\n")))]);
+        let session_state = SessionState::default();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            CacheOptions {
+                provenance_column: true,
+                ..CacheOptions::default()
+            },
+        )
+        .with_session_state(session_state.clone());
+        query_cache.query("select id from users").unwrap();
+        assert!(session_state.warnings().is_empty());
+
+        let (columns, rows) = query_cache
+            .query("select id from users")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        assert_eq!("fakesql_cached_at", columns.unwrap()[1].name);
+        assert!(matches!(
+            rows.last().unwrap().unwrap()[1],
+            ColumnValue::DateTime(_)
+        ));
+        assert!(session_state.warnings()[0]
+            .message
+            .starts_with("Served from cache"));
+    }
+
+    #[test]
+    fn test_high_water_mark_refuses_new_results() {
+        let result = || {
+            CachedResult::new(
+                Vec::new(),
+                vec![vec![ColumnValue::String("a".repeat(100))]],
+                None,
+            )
+        };
+        let size = result().approximate_size();
+        let storage =
+            InMemoryQueryStorage::with_max_bytes(None).with_high_water_bytes(Some(size * 2));
+        storage.store("select 1", result());
+        storage.store("select 2", result());
+        storage.store("select 3", result());
+        assert_eq!(size * 2, storage.used_bytes());
+        assert!(storage.get("select 1").is_some());
+        assert!(storage.get("select 3").is_none());
+        let stats = CacheStats::of(&storage);
+        assert_eq!(2, stats.entries);
+        assert_eq!(size * 2, stats.bytes);
+    }
+
+    #[test]
+    fn test_writes_invalidate_cached_results() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("id\n2\n"))),
+            Ok(None),
+            Ok(Some(reader_result("id\n1\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::new(["glob:select *".to_string()]).unwrap(),
+            CacheOptions::default(),
+        );
+        query_cache.query("select id from users").unwrap();
+        query_cache
+            .query("select p.id from payments p join other o on o.id = p.id")
+            .unwrap();
+        query_cache
+            .query("/* fakesql conn=1 */ update app.users set name = 'a' where id = 1")
+            .unwrap();
+        query_cache.query("select id from users").unwrap();
+        query_cache
+            .query("select p.id from payments p join other o on o.id = p.id")
+            .unwrap();
+        assert_eq!(4, executed_queries.borrow().len());
+        assert_eq!("select id from users", executed_queries.borrow()[3]);
+    }
+
+    #[test]
+    fn test_writes_are_found_after_their_comments() {
+        let dialect = SqlDialect::MySql;
+        assert!(is_write_statement(
+            "/* x */ UPDATE users SET a = 1",
+            dialect
+        ));
+        assert!(is_write_statement("-- x\ndelete from users", dialect));
+        assert!(is_write_statement(
+            "# x\ninsert into users values (1)",
+            dialect
+        ));
+        assert!(!is_write_statement("/* update */ select 1", dialect));
+        assert!(!is_write_statement(
+            "# x\ninsert into users",
+            SqlDialect::Postgres
+        ));
+    }
+
+    #[test]
+    fn test_cache_all_selects() {
+        let fake_executor = FakeQueryExecutor::new(vec![
+            Ok(Some(reader_result("Variable_name\tValue\n"))),
+            Ok(Some(reader_result("Variable_name\tValue\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+            Ok(Some(reader_result("id\n1\n"))),
+        ]);
+        let executed_queries = fake_executor.get_query_list();
+        let mut query_cache = QueryCache::new(
+            fake_executor,
+            InMemoryQueryStorage::with_max_bytes(None),
+            CacheRules::default(),
+            CacheOptions {
+                cache_all: true,
+                ..CacheOptions::default()
+            },
+        );
+        for query in [
+            "SELECT id FROM users",
+            "select id from users",
+            "show variables",
+            "show variables",
+        ] {
+            query_cache.query(query).unwrap();
+        }
+        assert_eq!(
+            vec![
+                "SELECT id FROM users",
+                "select id from users",
+                "show variables",
+                "show variables"
+            ],
+            *executed_queries.borrow()
+        );
+        query_cache.query("SELECT id FROM users").unwrap();
+        assert_eq!(4, executed_queries.borrow().len());
+    }
+
+    #[test]
+    fn test_expired_results_are_not_returned() {
+        let storage = InMemoryQueryStorage::with_max_bytes(None);
+        storage.store(
+            "select 1",
+            CachedResult::new(vec![], vec![], Some(Duration::ZERO)),
+        );
+        storage.store(
+            "select 2",
+            CachedResult::new(vec![], vec![], Some(Duration::from_secs(60))),
+        );
+        storage.store("select 3", CachedResult::new(vec![], vec![], None));
+        assert!(storage.get("select 1").is_none());
+        assert!(storage.get("select 2").is_some());
+        assert!(storage.get("select 3").is_some());
+    }
+
+    #[test]
+    fn test_least_recently_used_results_are_evicted() {
+        let result = CachedResult::new(
+            vec![],
+            vec![vec![ColumnValue::String("a".repeat(100))]],
+            None,
+        );
+        let storage = InMemoryQueryStorage::with_max_bytes(Some(result.approximate_size() * 2));
+        storage.store("select 1", result.clone());
+        storage.store("select 2", result.clone());
+        storage.get("select 1");
+        storage.store("select 3", result.clone());
+        assert!(storage.get("select 1").is_some());
+        assert!(storage.get("select 2").is_none());
+        assert!(storage.get("select 3").is_some());
+
+        let huge_result = CachedResult::new(
+            vec![],
+            vec![vec![ColumnValue::String("a".repeat(1000))]],
+            None,
+        );
+        storage.store("select 4", huge_result);
+        assert!(storage.get("select 4").is_none());
+        assert!(storage.get("select 3").is_some());
     }
 }
@@ -1,52 +1,453 @@
-use super::{Column, ColumnValue, QueryExecutor, QueryResult, Row};
+use super::comments::replace_outside_literals;
+use super::geometry::{wkt, GEOMETRY_TYPES};
+use super::information_schema::answer_information_schema;
+use super::log_redaction::loggable;
+use super::mysql_syntax::{parse_sql, used_schema};
+use super::table_references::{referenced_tables, table_name};
+use super::{
+    Column, ColumnAttributes, ColumnKey, ColumnValue, QueryExecutor, QueryResult, Row, Rows,
+    SessionState, SqlError,
+};
 use anyhow::{bail, Result};
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use itertools::Itertools;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlparser::ast::{
-    Expr, FunctionArg, FunctionArgExpr, SelectItem, SetExpr, SetOperator, Statement, TableFactor,
+    BinaryOperator, Cte, Expr, Function, FunctionArg, FunctionArgExpr, Ident, JoinOperator, Select,
+    SelectItem, SetExpr, SetOperator, Statement, TableFactor, UnaryOperator, Value,
 };
-use sqlparser::dialect::Dialect;
-use sqlparser::parser::Parser;
+use sqlparser::dialect::{Dialect, GenericDialect, MySqlDialect, PostgreSqlDialect};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use uuid::Uuid;
 
 type Schema = String;
 type TableName = String;
 type TableAlias = String;
 type ColumnName = String;
-type ColumnType = Option<String>;
+type DataType = Option<String>;
+/// Schema, name and `SELECT` of a view
+type ViewDefinition = (Schema, TableName, String);
+/// Columns and rows of a result answered without the database
+pub(super) type LocalResult = (Vec<(ColumnName, ColumnType)>, Vec<Row>);
+
+/// Data type of a column and what is known about its values. Only columns read straight from a
+/// table have attributes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ColumnType {
+    pub data_type: DataType,
+    pub attributes: ColumnAttributes,
+}
+
+impl ColumnType {
+    pub(super) fn of(data_type: &str) -> Self {
+        Some(data_type.to_string()).into()
+    }
+
+    /// Type as declared in `CREATE TABLE`, like `decimal(10,2)` or `int unsigned`
+    pub(super) fn definition(&self) -> Option<String> {
+        let attributes = &self.attributes;
+        let mut definition = match (self.data_type.as_deref()?, attributes.length) {
+            ("decimal", _) => match (attributes.precision, attributes.scale) {
+                (Some(precision), Some(scale)) => format!("decimal({precision},{scale})"),
+                _ => "decimal".to_string(),
+            },
+            (data_type @ ("char" | "varchar" | "binary" | "varbinary"), Some(length)) => {
+                format!("{data_type}({length})")
+            }
+            (data_type, _) => data_type.to_string(),
+        };
+        if attributes.unsigned {
+            definition.push_str(" unsigned");
+        }
+        Some(definition)
+    }
+
+    /// The same type, for expressions that can return NULL
+    fn nullable(mut self) -> Self {
+        self.attributes.not_null = false;
+        self
+    }
+
+    /// The same type, for values computed from the column, which are neither keys nor NOT NULL
+    fn derived(mut self) -> Self {
+        self.attributes.key = None;
+        self.nullable()
+    }
+}
+
+impl From<DataType> for ColumnType {
+    fn from(data_type: DataType) -> Self {
+        Self {
+            data_type,
+            attributes: ColumnAttributes::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DataTypeInfo {
+    columns: Vec<(Schema, TableName, ColumnName, ColumnType)>,
+    primary_keys: Vec<(Schema, TableName, ColumnName)>,
+    /// Schema of the connections that did not run `USE`
+    #[serde(default)]
+    default_schema: Schema,
+    #[serde(skip)]
+    identifier_case: IdentifierCase,
+    /// Return types of functions, like user defined ones, by their lowercase name
+    #[serde(skip)]
+    function_types: HashMap<String, String>,
+    /// Unknown tables already looked up by any connection, with when they were
+    #[serde(skip)]
+    looked_up_tables: HashMap<(Schema, TableName), Instant>,
+    /// Computed the first time it is read and kept until the structure is replaced, so tables
+    /// discovered later do not discard the cached results
+    #[serde(skip)]
+    version: OnceLock<u64>,
+}
+
+/// `USE` of a schema that is not in the database structure, answered like MySQL does
+#[derive(Error, Debug)]
+#[error("Unknown database '{0}'")]
+pub struct UnknownDatabase(pub String);
+
+/// Database structure shared by the connections of a listener. It is replaced when refreshed.
+pub type SharedDataTypeInfo = Arc<RwLock<DataTypeInfo>>;
+/// Unknown tables are looked up again after it, in case they were created since
+const LOOKED_UP_TABLE_TTL: Duration = Duration::from_secs(300);
+/// Discovers the database structure again for `FAKESQL SCHEMA REFRESH`
+pub type SchemaLoader = Arc<dyn Fn() -> Result<DataTypeInfo> + Send + Sync>;
+
+/// Types that results are parsed into. Values of other types are answered as text.
+const MAPPED_TYPES: &[&str] = &[
+    "bigint",
+    "int",
+    "mediumint",
+    "smallint",
+    "year",
+    "tinyint",
+    "boolean",
+    "bool",
+    "double",
+    "float",
+    "timestamp",
+    "datetime",
+    "date",
+    "time",
+    "interval",
+    "uuid",
+    "json",
+    "jsonb",
+    "decimal",
+    "numeric",
+    "text",
+    "char",
+    "tinytext",
+    "longtext",
+    "mediumtext",
+    "varchar",
+    "binary",
+    "varbinary",
+    "blob",
+    "tinyblob",
+    "mediumblob",
+    "longblob",
+];
+
+/// How values of spatial columns, like `geometry` and `point`, are answered
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GeometryFormat {
+    /// As bytes, the way the target writes them
+    #[default]
+    Wkb,
+    /// As WKT text, like `POINT(1 2)`
+    Wkt,
+}
+
+/// What to do with columns whose type is unknown or not mapped
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeStrictness {
+    /// Answer them as text
+    #[default]
+    Lenient,
+    /// Answer them as text with a warning for each of them
+    Warn,
+    /// Fail the query
+    Strict,
+}
+
+/// SQL dialect used to parse the queries of a target
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SqlDialect {
+    MySql,
+    /// Parsed like MySQL, since our parser has no dialect of its own for MariaDB
+    MariaDb,
+    Postgres,
+    Generic,
+}
+
+impl SqlDialect {
+    pub fn dialect(self) -> Box<dyn Dialect + Send + Sync> {
+        match self {
+            SqlDialect::MySql | SqlDialect::MariaDb => Box::new(MySqlDialect {}),
+            SqlDialect::Postgres => Box::new(PostgreSqlDialect {}),
+            SqlDialect::Generic => Box::new(GenericDialect {}),
+        }
+    }
+
+    /// Quoted string literal of the value. MySQL also reads backslashes as escapes, so they are
+    /// doubled too.
+    pub fn string_literal(self, value: &str) -> String {
+        let value = match self {
+            SqlDialect::MySql | SqlDialect::MariaDb => value.replace('\\', "\\\\"),
+            SqlDialect::Postgres | SqlDialect::Generic => value.to_string(),
+        };
+        format!("'{}'", value.replace('\'', "''"))
+    }
+}
+
+/// How identifiers of queries are compared with the names of the database structure
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentifierCase {
+    /// Names match regardless of their case, like in MySQL
+    #[default]
+    Insensitive,
+    /// Unquoted identifiers are folded to lowercase and then match exactly, like in Postgres
+    Lowercase,
+    /// Names only match exactly
+    Sensitive,
+}
+
+impl IdentifierCase {
+    fn identifier(self, ident: &Ident) -> String {
+        match (self, ident.quote_style) {
+            (IdentifierCase::Lowercase, None) => ident.value.to_lowercase(),
+            _ => ident.value.clone(),
+        }
+    }
+
+    fn same(self, a: &str, b: &str) -> bool {
+        match self {
+            IdentifierCase::Insensitive => a.eq_ignore_ascii_case(b),
+            IdentifierCase::Lowercase | IdentifierCase::Sensitive => a == b,
+        }
+    }
+}
+
+/// What typing a statement needs besides the tables it reads from
+struct Context<'a> {
+    default_schema: &'a str,
+    identifier_case: IdentifierCase,
+    function_types: &'a HashMap<String, String>,
+}
 
-#[derive(Clone, Debug)]
-pub struct DataTypeInfo(Vec<(Schema, TableName, ColumnName, ColumnType)>);
+impl Context<'_> {
+    fn identifier(&self, ident: &Ident) -> String {
+        self.identifier_case.identifier(ident)
+    }
+
+    fn same(&self, a: &str, b: &str) -> bool {
+        self.identifier_case.same(a, b)
+    }
+}
+
+/// Structure of a target saved to a file, so it is not loaded again on every start
+#[derive(Serialize, Deserialize)]
+struct SchemaFile {
+    target: String,
+    stored_at: DateTime<Utc>,
+    data_type_info: DataTypeInfo,
+}
 
 impl Deref for DataTypeInfo {
     type Target = Vec<(Schema, TableName, ColumnName, ColumnType)>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.columns
     }
 }
 
 impl DerefMut for DataTypeInfo {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.columns
     }
 }
 
 impl DataTypeInfo {
-    pub fn load<T, R>(executor: &mut T) -> Result<Self>
+    /// Views are typed from their definition, parsed with the dialect
+    pub fn load<T, R>(executor: &mut T, dialect: &dyn Dialect) -> Result<Self>
     where
         T: QueryExecutor<QueryResult = R>,
         R: QueryResult,
     {
         println!("Loading database structure");
+        let (_, mut rows) = executor.query("select database();")?.unwrap().get_data();
+        let default_schema = match rows.next().transpose()?.as_deref() {
+            Some([ColumnValue::String(schema), ..]) => schema.clone(),
+            _ => String::new(),
+        };
+        let mut data_type_info = Self {
+            default_schema,
+            ..Self::load_columns(executor, "")?
+        };
+        match Self::load_view_definitions(executor, "") {
+            Ok(views) => data_type_info.add_views(views, dialect),
+            Err(error) => println!("Failed to load the views. {:?}", error),
+        }
+        Ok(data_type_info)
+    }
+
+    /// Loads the structure of a single table. It is empty when the table does not exist.
+    pub fn load_table<T, R>(executor: &mut T, schema: &str, table_name: &str) -> Result<Self>
+    where
+        T: QueryExecutor<QueryResult = R>,
+        R: QueryResult,
+    {
+        println!("Loading structure of {schema}.{table_name}");
+        Self::load_columns(executor, &table_filter(schema, table_name))
+    }
+
+    /// Definition of a view whose columns are not in `INFORMATION_SCHEMA.COLUMNS`
+    pub fn load_view<T, R>(
+        executor: &mut T,
+        schema: &str,
+        view_name: &str,
+    ) -> Result<Vec<ViewDefinition>>
+    where
+        T: QueryExecutor<QueryResult = R>,
+        R: QueryResult,
+    {
+        Self::load_view_definitions(executor, &table_filter(schema, view_name))
+    }
+
+    fn load_view_definitions<T, R>(executor: &mut T, filter: &str) -> Result<Vec<ViewDefinition>>
+    where
+        T: QueryExecutor<QueryResult = R>,
+        R: QueryResult,
+    {
+        let (_, rows) = executor
+            .query(&format!(
+                "
+            SELECT TABLE_SCHEMA, TABLE_NAME, VIEW_DEFINITION
+            FROM INFORMATION_SCHEMA.VIEWS
+            {filter};
+        "
+            ))?
+            .unwrap()
+            .get_data();
+        let mut views = Vec::new();
+        for row in rows {
+            let row = row?;
+            if row.len() < 3 {
+                continue;
+            }
+            views.push((
+                to_string(&row[0]).clone(),
+                to_string(&row[1]).clone(),
+                to_string(&row[2]).clone(),
+            ));
+        }
+        Ok(views)
+    }
+
+    /// Types the columns of the views that are not known yet from their definition. Views of
+    /// other views are typed after them.
+    pub fn add_views(&mut self, views: Vec<ViewDefinition>, dialect: &dyn Dialect) {
+        let mut pending: Vec<ViewDefinition> = views
+            .into_iter()
+            .filter(|(schema, name, _)| !self.has_table(schema, name))
+            .collect();
+        let mut only_known_tables = true;
+        while !pending.is_empty() {
+            let pending_views = pending.len();
+            pending.retain(|(schema, name, definition)| {
+                !self.add_view(schema, name, definition, dialect, only_known_tables)
+            });
+            if pending.len() == pending_views {
+                if !only_known_tables {
+                    break;
+                }
+                // Views reading from tables we do not know are typed as far as possible
+                only_known_tables = false;
+            }
+        }
+        for (schema, name, _) in pending {
+            println!("Failed to type the view {schema}.{name}");
+        }
+    }
+
+    fn add_view(
+        &mut self,
+        schema: &str,
+        name: &str,
+        definition: &str,
+        dialect: &dyn Dialect,
+        only_known_tables: bool,
+    ) -> bool {
+        let ast = match parse_sql(dialect, definition) {
+            Ok(ast) => ast,
+            Err(_) => return false,
+        };
+        let reads_unknown_tables = ast
+            .iter()
+            .flat_map(referenced_tables)
+            .any(|table| match table.0.as_slice() {
+                [table_name] => !self.has_table(schema, &table_name.value),
+                [table_schema, table_name] => {
+                    !self.has_table(&table_schema.value, &table_name.value)
+                }
+                _ => true,
+            });
+        if only_known_tables && reads_unknown_tables {
+            return false;
+        }
+        let columns_types = match self.get_columns_types_from_ast(schema, ast) {
+            Ok(columns_types) if !columns_types.is_empty() => columns_types,
+            _ => return false,
+        };
+        for (column_name, mut column_type) in columns_types {
+            // Like MySQL, columns of views are not keys
+            column_type.attributes.key = None;
+            self.columns.push((
+                schema.to_string(),
+                name.to_string(),
+                column_name,
+                column_type,
+            ));
+        }
+        true
+    }
+
+    fn load_columns<T, R>(executor: &mut T, filter: &str) -> Result<Self>
+    where
+        T: QueryExecutor<QueryResult = R>,
+        R: QueryResult,
+    {
         let mut type_map = Vec::new();
+        let mut primary_keys = Vec::new();
         let (_, rows) = executor
-            .query(
+            .query(&format!(
                 "
-            SELECT TABLE_SCHEMA, TABLE_NAME, COLUMN_NAME, DATA_TYPE, IS_NULLABLE
+            SELECT TABLE_SCHEMA, TABLE_NAME, COLUMN_NAME, DATA_TYPE, IS_NULLABLE, COLUMN_KEY,
+                CHARACTER_MAXIMUM_LENGTH, NUMERIC_PRECISION, NUMERIC_SCALE, COLUMN_TYPE,
+                COLUMN_DEFAULT, EXTRA
             FROM INFORMATION_SCHEMA.COLUMNS
+            {filter}
             ORDER BY TABLE_SCHEMA, TABLE_NAME, ORDINAL_POSITION;
-        ",
-            )?
+        "
+            ))?
             .unwrap()
             .get_data();
         for row in rows {
@@ -54,76 +455,573 @@ impl DataTypeInfo {
             if row.len() < 4 {
                 continue;
             }
+            let key = match row.get(5) {
+                Some(ColumnValue::String(key)) => ColumnKey::from_name(key),
+                _ => None,
+            };
+            if key == Some(ColumnKey::Primary) {
+                primary_keys.push((
+                    to_string(&row[0]).into(),
+                    to_string(&row[1]).into(),
+                    to_string(&row[2]).into(),
+                ));
+            }
+            let not_null =
+                matches!(row.get(4), Some(ColumnValue::String(nullable)) if nullable == "NO");
+            let unsigned = matches!(
+                row.get(9),
+                Some(ColumnValue::String(column_type)) if column_type.contains("unsigned")
+            );
+            let text = |index: usize| match row.get(index) {
+                Some(ColumnValue::String(value)) if !value.is_empty() => Some(value.clone()),
+                _ => None,
+            };
             type_map.push((
                 to_string(&row[0]).into(),
                 to_string(&row[1]).into(),
                 to_string(&row[2]).into(),
-                Some(to_string(&row[3]).into()),
+                ColumnType {
+                    data_type: Some(to_string(&row[3]).into()),
+                    attributes: ColumnAttributes {
+                        not_null,
+                        length: to_number(row.get(6)),
+                        precision: to_number(row.get(7)),
+                        scale: to_number(row.get(8)),
+                        unsigned,
+                        key,
+                        default: text(10),
+                        extra: text(11),
+                    },
+                },
             ));
         }
-        Ok(Self(type_map))
+        Ok(Self {
+            columns: type_map,
+            primary_keys,
+            default_schema: String::new(),
+            identifier_case: IdentifierCase::default(),
+            function_types: HashMap::new(),
+            looked_up_tables: HashMap::new(),
+            version: OnceLock::new(),
+        })
+    }
+
+    /// Reads the structure saved for the target. It is `None` when there is no file, the file
+    /// belongs to another target or it is older than `max_age`.
+    pub fn read(
+        path: impl AsRef<Path>,
+        target: &str,
+        max_age: Option<Duration>,
+    ) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let schema_file: SchemaFile = serde_json::from_reader(BufReader::new(file))?;
+        if schema_file.target != target {
+            return Ok(None);
+        }
+        let age = Utc::now()
+            .signed_duration_since(schema_file.stored_at)
+            .to_std()
+            .unwrap_or_default();
+        if matches!(max_age, Some(max_age) if age > max_age) {
+            return Ok(None);
+        }
+        println!(
+            "Using database structure saved at {}",
+            schema_file.stored_at
+        );
+        Ok(Some(schema_file.data_type_info))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, target: &str) -> Result<()> {
+        let schema_file = SchemaFile {
+            target: target.to_string(),
+            stored_at: Utc::now(),
+            data_type_info: self.clone(),
+        };
+        serde_json::to_writer(BufWriter::new(File::create(path)?), &schema_file)?;
+        Ok(())
+    }
+
+    pub fn has_table(&self, schema: &str, table_name: &str) -> bool {
+        self.columns.iter().any(|(table_schema, name, _, _)| {
+            self.identifier_case.same(table_schema, schema)
+                && self.identifier_case.same(name, table_name)
+        })
+    }
+
+    /// Schemas with tables, the default one and `information_schema`. Schemas without tables are
+    /// not known.
+    pub fn has_schema(&self, schema: &str) -> bool {
+        self.identifier_case.same(&self.default_schema, schema)
+            || schema.eq_ignore_ascii_case("information_schema")
+            || self
+                .columns
+                .iter()
+                .any(|(table_schema, _, _, _)| self.identifier_case.same(table_schema, schema))
+    }
+
+    pub fn with_identifier_case(self, identifier_case: IdentifierCase) -> Self {
+        Self {
+            identifier_case,
+            ..self
+        }
+    }
+
+    /// Types of functions that are used instead of the built-in ones
+    pub fn with_function_types(self, function_types: HashMap<String, String>) -> Self {
+        Self {
+            function_types: function_types
+                .into_iter()
+                .map(|(name, ty)| (name.to_lowercase(), ty.to_lowercase()))
+                .collect(),
+            ..self
+        }
+    }
+
+    fn was_looked_up(&self, table: &(Schema, TableName)) -> bool {
+        self.looked_up_tables
+            .get(table)
+            .is_some_and(|looked_up| looked_up.elapsed() < LOOKED_UP_TABLE_TTL)
+    }
+
+    fn looked_up(&mut self, table: (Schema, TableName)) {
+        self.looked_up_tables
+            .retain(|_, looked_up| looked_up.elapsed() < LOOKED_UP_TABLE_TTL);
+        self.looked_up_tables.insert(table, Instant::now());
+    }
+
+    pub fn merge(&mut self, other: DataTypeInfo) {
+        self.columns.extend(other.columns);
+        self.primary_keys.extend(other.primary_keys);
+    }
+
+    /// Hash of the database structure. It changes when any column or table changes. It is kept
+    /// with the cached results, so it is the same for every build of fakesql.
+    pub fn version(&self) -> u64 {
+        *self.version.get_or_init(|| {
+            let structure = serde_json::to_vec(&(&self.columns, &self.primary_keys))
+                .expect("The database structure is always serializable");
+            let digest = Sha256::digest(structure);
+            u64::from_be_bytes(digest[..8].try_into().unwrap())
+        })
+    }
+
+    /// Answers `SHOW DATABASES`, `SHOW TABLES [FROM schema]`, `SHOW CREATE TABLE table`,
+    /// `DESCRIBE table` and `SHOW COLUMNS FROM table [FROM schema]` with the known structure.
+    /// Anything else, like filters, is left to the database.
+    pub fn answer_show(&self, default_schema: &str, query: &str) -> Option<LocalResult> {
+        let words: Vec<&str> = query
+            .trim()
+            .trim_end_matches(';')
+            .split_ascii_whitespace()
+            .map(|word| word.trim_matches('`'))
+            .collect();
+        let text = || ColumnType::of("varchar");
+        match words.as_slice() {
+            [show, databases]
+                if is(show, "show") && (is(databases, "databases") || is(databases, "schemas")) =>
+            {
+                let rows = self
+                    .columns
+                    .iter()
+                    .map(|(schema, _, _, _)| schema)
+                    .dedup()
+                    .map(|schema| vec![ColumnValue::String(schema.clone())])
+                    .collect();
+                Some((vec![("Database".to_string(), text())], rows))
+            }
+            [show, tables] if is(show, "show") && is(tables, "tables") => {
+                Some(self.show_tables(default_schema))
+            }
+            [show, tables, from, schema]
+                if is(show, "show")
+                    && is(tables, "tables")
+                    && (is(from, "from") || is(from, "in")) =>
+            {
+                Some(self.show_tables(schema))
+            }
+            [describe, name] if is(describe, "describe") || is(describe, "desc") => {
+                let (schema, table_name) = split_table_name(default_schema, name);
+                self.describe(schema, table_name)
+            }
+            [show, columns, from, name]
+                if is(show, "show")
+                    && (is(columns, "columns") || is(columns, "fields"))
+                    && (is(from, "from") || is(from, "in")) =>
+            {
+                let (schema, table_name) = split_table_name(default_schema, name);
+                self.describe(schema, table_name)
+            }
+            [show, columns, from, table_name, from_schema, schema]
+                if is(show, "show")
+                    && (is(columns, "columns") || is(columns, "fields"))
+                    && (is(from, "from") || is(from, "in"))
+                    && (is(from_schema, "from") || is(from_schema, "in")) =>
+            {
+                self.describe(schema, table_name)
+            }
+            [show, create, table, name]
+                if is(show, "show") && is(create, "create") && is(table, "table") =>
+            {
+                let (schema, table_name) = split_table_name(default_schema, name);
+                let create_table = self.create_table(schema, table_name)?;
+                Some((
+                    vec![
+                        ("Table".to_string(), text()),
+                        ("Create Table".to_string(), text()),
+                    ],
+                    vec![vec![
+                        ColumnValue::String(table_name.to_string()),
+                        ColumnValue::String(create_table),
+                    ]],
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Columns in the shape of `DESCRIBE`
+    fn describe(&self, schema: &str, table_name: &str) -> Option<LocalResult> {
+        let text = |value: &str| ColumnValue::String(value.to_string());
+        let rows: Vec<Row> = self
+            .columns
+            .iter()
+            .filter(|(table_schema, name, _, _)| {
+                self.identifier_case.same(table_schema, schema)
+                    && self.identifier_case.same(name, table_name)
+            })
+            .map(|(_, _, column_name, column_type)| {
+                let attributes = &column_type.attributes;
+                vec![
+                    text(column_name),
+                    match column_type.definition() {
+                        Some(definition) => text(&definition),
+                        None => ColumnValue::Null,
+                    },
+                    text(if attributes.not_null { "NO" } else { "YES" }),
+                    text(attributes.key.map(|key| key.name()).unwrap_or_default()),
+                    match &attributes.default {
+                        Some(default) => text(default),
+                        None => ColumnValue::Null,
+                    },
+                    text(attributes.extra.as_deref().unwrap_or_default()),
+                ]
+            })
+            .collect();
+        if rows.is_empty() {
+            return None;
+        }
+        Some((
+            ["Field", "Type", "Null", "Key", "Default", "Extra"]
+                .into_iter()
+                .map(|name| (name.to_string(), ColumnType::of("varchar")))
+                .collect(),
+            rows,
+        ))
+    }
+
+    fn show_tables(&self, schema: &str) -> LocalResult {
+        let rows = self
+            .columns
+            .iter()
+            .filter(|(table_schema, _, _, _)| self.identifier_case.same(table_schema, schema))
+            .map(|(_, table_name, _, _)| table_name)
+            .dedup()
+            .map(|table_name| vec![ColumnValue::String(table_name.clone())])
+            .collect();
+        (
+            vec![(format!("Tables_in_{schema}"), ColumnType::of("varchar"))],
+            rows,
+        )
+    }
+
+    /// A `CREATE TABLE` with the columns and primary key of the table. Types do not have their
+    /// length, since only the data type is discovered.
+    fn create_table(&self, schema: &str, table_name: &str) -> Option<String> {
+        let is_table = |table_schema: &str, name: &str| {
+            self.identifier_case.same(table_schema, schema)
+                && self.identifier_case.same(name, table_name)
+        };
+        let mut definitions: Vec<String> = self
+            .columns
+            .iter()
+            .filter(|(table_schema, name, _, _)| is_table(table_schema, name))
+            .map(|(_, _, column_name, column_type)| {
+                let mut definition = format!("  `{column_name}`");
+                if let Some(type_definition) = column_type.definition() {
+                    definition.push_str(&format!(" {type_definition}"));
+                }
+                if column_type.attributes.not_null {
+                    definition.push_str(" NOT NULL");
+                }
+                definition
+            })
+            .collect();
+        if definitions.is_empty() {
+            return None;
+        }
+        let primary_key = self
+            .primary_keys
+            .iter()
+            .filter(|(table_schema, name, _)| is_table(table_schema, name))
+            .map(|(_, _, column_name)| format!("`{column_name}`"))
+            .join(",");
+        if !primary_key.is_empty() {
+            definitions.push(format!("  PRIMARY KEY ({primary_key})"));
+        }
+        Some(format!(
+            "CREATE TABLE `{table_name}` (\n{}\n)",
+            definitions.join(",\n")
+        ))
     }
 
     pub fn get_columns_types_from_ast(
-        &mut self,
+        &self,
         default_schema: &str,
         ast: Vec<Statement>,
     ) -> Result<Vec<(ColumnName, ColumnType)>> {
-        if ast.is_empty() {
-            return Ok(vec![]);
-        }
-        if ast.len() > 1 {
-            bail!("We need to be able to handle multiple statements");
-        }
-        match &ast[0] {
+        // Statements like `SET` do not return rows, so only one statement of a batch is typed
+        let mut result_statements = ast.iter().filter(|statement| {
+            matches!(
+                statement,
+                Statement::Query(_) | Statement::ShowVariable { .. }
+            )
+        });
+        let statement = match (result_statements.next(), result_statements.next()) {
+            (None, _) => return Ok(vec![]),
+            (Some(statement), None) => statement,
+            (Some(_), Some(_)) => bail!("We can only type one result set per query"),
+        };
+        match statement {
             Statement::Query(query) => {
-                let table_with_aliases =
-                    get_tables_with_aliases_from_set_expr(&query.body, self, default_schema)?;
-                let alias_to_column_and_type =
-                    get_alias_with_clomuns_and_column_type(table_with_aliases, self);
-                get_columns_types(get_expr(&ast)?.unwrap(), alias_to_column_and_type)
+                // Derived tables and CTEs add their columns, so they work on a copy with only
+                // the tables of the statement
+                let tables: HashSet<String> = referenced_tables(statement)
+                    .iter()
+                    .map(table_name)
+                    .collect();
+                let mut data_type_info: Vec<_> = self
+                    .columns
+                    .iter()
+                    .filter(|(_, name, _, _)| tables.contains(&name.to_lowercase()))
+                    .cloned()
+                    .collect();
+                let context = Context {
+                    default_schema,
+                    identifier_case: self.identifier_case,
+                    function_types: &self.function_types,
+                };
+                if let Some(with) = &query.with {
+                    for cte in &with.cte_tables {
+                        process_cte(cte, &mut data_type_info, &context)?;
+                    }
+                }
+                get_columns_types_from_set_expr(&query.body, &mut data_type_info, &context)
             }
             Statement::ShowVariable { variable } => {
                 let name = variable.iter().map(|ident| ident.value.clone()).join("_");
-                Ok(vec![(name, Some("text".to_string()))])
+                Ok(vec![(name, ColumnType::of("text"))])
             }
             any => bail!("We cand parse {}", any),
         }
     }
 }
 
-pub struct QueryDataType<T, D> {
+pub struct QueryDataType<T> {
     executor: T,
-    dialect: D,
-    data_type_info: DataTypeInfo,
-    default_schema: Schema,
+    dialect: SqlDialect,
+    data_type_info: SharedDataTypeInfo,
+    schema_loader: Option<SchemaLoader>,
+    session_state: SessionState,
+    type_strictness: TypeStrictness,
+    geometry_format: GeometryFormat,
+    /// Lowercase names of `binary(16)` columns answered as uuids
+    uuid_columns: HashSet<String>,
 }
 
-impl<T, D> QueryDataType<T, D> {
-    pub fn new(executor: T, dialect: D, data_type_info: DataTypeInfo) -> Self {
+impl<T> QueryDataType<T> {
+    pub fn new(
+        executor: T,
+        dialect: SqlDialect,
+        data_type_info: SharedDataTypeInfo,
+        session_state: SessionState,
+    ) -> Self {
         Self {
             executor,
             dialect,
             data_type_info,
-            default_schema: String::new(),
+            schema_loader: None,
+            session_state,
+            type_strictness: TypeStrictness::default(),
+            geometry_format: GeometryFormat::default(),
+            uuid_columns: HashSet::new(),
+        }
+    }
+
+    /// Without a loader, `FAKESQL SCHEMA REFRESH` discovers the structure with this executor
+    pub fn with_schema_loader(mut self, schema_loader: SchemaLoader) -> Self {
+        self.schema_loader = Some(schema_loader);
+        self
+    }
+
+    pub fn with_type_strictness(mut self, type_strictness: TypeStrictness) -> Self {
+        self.type_strictness = type_strictness;
+        self
+    }
+
+    pub fn with_geometry_format(mut self, geometry_format: GeometryFormat) -> Self {
+        self.geometry_format = geometry_format;
+        self
+    }
+
+    /// Result columns with these names, of type `binary(16)` or `bytea`, are answered as uuids
+    pub fn with_uuid_columns(mut self, uuid_columns: Vec<String>) -> Self {
+        self.uuid_columns = uuid_columns
+            .into_iter()
+            .map(|column| column.to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Types of the result, with the uuid columns stored as bytes typed as uuids
+    fn with_binary_uuids(
+        &self,
+        columns_types: Vec<(ColumnName, ColumnType)>,
+    ) -> Vec<(ColumnName, ColumnType)> {
+        columns_types
+            .into_iter()
+            .map(|(column_name, column_type)| {
+                let is_binary_uuid = self.uuid_columns.contains(&column_name.to_lowercase())
+                    && match column_type.data_type.as_deref() {
+                        Some("binary") => column_type.attributes.length == Some(16),
+                        Some("bytea") => true,
+                        _ => false,
+                    };
+                match is_binary_uuid {
+                    true => (
+                        column_name,
+                        ColumnType {
+                            data_type: Some("uuid".to_string()),
+                            attributes: column_type.attributes,
+                        },
+                    ),
+                    false => (column_name, column_type),
+                }
+            })
+            .collect()
+    }
+
+    fn refresh_schema<R>(&mut self) -> Result<ResultWithCustomColumnTypes<R>>
+    where
+        T: QueryExecutor<QueryResult = R>,
+        R: QueryResult,
+    {
+        let data_type_info = match &self.schema_loader {
+            Some(schema_loader) => schema_loader()?,
+            None => DataTypeInfo::load(&mut self.executor, self.dialect.dialect().as_ref())?,
+        };
+        // The settings of the listener are kept
+        let data_type_info = {
+            let current = self.data_type_info.read().unwrap();
+            data_type_info
+                .with_identifier_case(current.identifier_case)
+                .with_function_types(current.function_types.clone())
+        };
+        let columns = data_type_info.len();
+        *self.data_type_info.write().unwrap() = data_type_info;
+        Ok(ResultWithCustomColumnTypes::local(
+            vec![("columns".to_string(), ColumnType::of("bigint"))],
+            vec![vec![ColumnValue::I64(columns as i64)]],
+        ))
+    }
+
+    /// Whether the target has a schema, even without tables
+    fn schema_exists<R>(&mut self, schema: &str) -> Result<bool>
+    where
+        T: QueryExecutor<QueryResult = R>,
+        R: QueryResult,
+    {
+        let result = self.executor.query(&format!(
+            "SELECT SCHEMA_NAME FROM INFORMATION_SCHEMA.SCHEMATA WHERE SCHEMA_NAME = {}",
+            self.dialect.string_literal(schema)
+        ))?;
+        match result {
+            Some(result) => {
+                let (_, mut rows) = result.get_data();
+                Ok(rows.next().transpose()?.is_some())
+            }
+            None => Ok(false),
         }
     }
 
-    fn load_internals<R>(&mut self) -> Result<()>
+    /// Schema of the `USE` statements of the session, or else the default of the database
+    fn default_schema(&self) -> Schema {
+        self.session_state
+            .default_schema()
+            .unwrap_or_else(|| self.data_type_info.read().unwrap().default_schema.clone())
+    }
+
+    /// Loads the structure of the tables of the query that are not known, like tables created
+    /// after the structure was loaded. Each table is only looked up once.
+    fn discover_unknown_tables<R>(&mut self, ast: &[Statement]) -> Result<()>
     where
         T: QueryExecutor<QueryResult = R>,
         R: QueryResult,
     {
-        if self.default_schema.is_empty() {
-            println!("Loading current schema");
-            let (_, mut rows) = self
-                .executor
-                .query("select database();")?
+        let default_schema = self.default_schema();
+        let identifier_case = self.data_type_info.read().unwrap().identifier_case;
+        let cte_names: HashSet<String> = ast
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Query(query) => query.with.as_ref(),
+                _ => None,
+            })
+            .flat_map(|with| &with.cte_tables)
+            .map(|cte| identifier_case.identifier(&cte.alias.name))
+            .collect();
+        let unknown_tables: Vec<(Schema, TableName)> = ast
+            .iter()
+            .flat_map(referenced_tables)
+            .filter_map(|name| match name.0.as_slice() {
+                [table_name] if !cte_names.contains(&identifier_case.identifier(table_name)) => {
+                    Some((
+                        default_schema.clone(),
+                        identifier_case.identifier(table_name),
+                    ))
+                }
+                [schema, table_name] => Some((
+                    identifier_case.identifier(schema),
+                    identifier_case.identifier(table_name),
+                )),
+                _ => None,
+            })
+            .unique()
+            .filter(|table| {
+                let data_type_info = self.data_type_info.read().unwrap();
+                !data_type_info.was_looked_up(table)
+                    && !data_type_info.has_table(&table.0, &table.1)
+            })
+            .collect();
+        for (schema, table_name) in unknown_tables {
+            let discovered = DataTypeInfo::load_table(&mut self.executor, &schema, &table_name)?;
+            if discovered.is_empty() {
+                let views = DataTypeInfo::load_view(&mut self.executor, &schema, &table_name)?;
+                self.data_type_info
+                    .write()
+                    .unwrap()
+                    .add_views(views, self.dialect.dialect().as_ref());
+            } else {
+                self.data_type_info.write().unwrap().merge(discovered);
+            }
+            self.data_type_info
+                .write()
                 .unwrap()
-                .get_data();
-            self.default_schema = to_string(&rows.next().unwrap()?[0]).clone();
+                .looked_up((schema, table_name));
         }
-
         Ok(())
     }
 
@@ -135,38 +1033,60 @@ impl<T, D> QueryDataType<T, D> {
         T: QueryExecutor<QueryResult = R>,
         R: QueryResult,
     {
-        self.load_internals()?;
-        let mut data_type_info = self.data_type_info.clone();
-        data_type_info.get_columns_types_from_ast(&self.default_schema, ast)
+        let default_schema = self.default_schema();
+        self.data_type_info
+            .read()
+            .unwrap()
+            .get_columns_types_from_ast(&default_schema, ast)
     }
 }
 
-impl<T, R, D> QueryExecutor for QueryDataType<T, D>
+impl<T, R> QueryExecutor for QueryDataType<T>
 where
     T: QueryExecutor<QueryResult = R>,
     R: QueryResult,
-    D: Dialect,
 {
     type QueryResult = ResultWithCustomColumnTypes<R>;
 
     fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
-        let query = query.to_lowercase();
-        if query.starts_with("use") {
-            return match self.executor.query(&query) {
-                Ok(option) => {
-                    self.default_schema = query.split_ascii_whitespace().skip(1).take(1).collect();
-                    match option {
-                        Some(result) => {
-                            Ok(Some(ResultWithCustomColumnTypes::new(Some(result), vec![])))
-                        }
-                        None => Ok(None),
-                    }
+        // Only used to recognise statements. The target gets the query as written, since
+        // literals and quoted identifiers can be case sensitive.
+        let lower_case_query = query.to_lowercase();
+        if lower_case_query.trim().trim_end_matches(';') == "fakesql schema refresh" {
+            return self.refresh_schema().map(Some);
+        }
+        // Answered by the session, so schemas that do not exist would only degrade the types
+        if let Some(schema) = used_schema(query) {
+            let schema = &schema?;
+            let has_schema = self.data_type_info.read().unwrap().has_schema(schema);
+            // Schemas without tables, like ones created after the structure was loaded, are
+            // only known by the target
+            if !has_schema {
+                if !self.schema_exists(schema)? {
+                    return Err(UnknownDatabase(schema.to_string()).into());
                 }
-                Err(error) => Err(error),
-            };
+                println!("Using schema {schema} without known tables");
+                self.session_state.add_warning(format!(
+                    "Schema {schema} has no known tables. Run FAKESQL SCHEMA REFRESH after creating them"
+                ));
+            }
+        }
+        if lower_case_query.starts_with("show") || lower_case_query.starts_with("desc") {
+            let default_schema = self.default_schema();
+            if let Some((column_types, rows)) = self
+                .data_type_info
+                .read()
+                .unwrap()
+                .answer_show(&default_schema, query)
+            {
+                return Ok(Some(ResultWithCustomColumnTypes::local(column_types, rows)));
+            }
         }
-        if !query.starts_with("select") {
-            return match self.executor.query(&query) {
+        let is_select = lower_case_query.starts_with("select")
+            || lower_case_query.starts_with("with")
+            || is_batch(query, self.dialect);
+        if !is_select || is_plan(&lower_case_query) {
+            return match self.executor.query(query) {
                 Ok(Some(result)) => {
                     Ok(Some(ResultWithCustomColumnTypes::new(Some(result), vec![])))
                 }
@@ -174,13 +1094,17 @@ where
                 Err(error) => Err(error),
             };
         }
-        let ast = Parser::parse_sql(
-            &self.dialect,
-            &query.to_lowercase().replace("straight_join", "join"), // Our parser does not recognise straight_join
-        );
-        if ast.is_err() {
-            println!("Failed to parse SQL. Result will not have types. {:?}", ast);
-            return match self.executor.query(&query) {
+        let ast = parse_sql(self.dialect.dialect().as_ref(), query);
+        if let Err(error) = &ast {
+            println!(
+                "Failed to parse SQL. Result will not have types. {}",
+                loggable(&error.to_string())
+            );
+            self.session_state.add_warning(format!(
+                "Result is not typed because the query could not be parsed. {}",
+                error
+            ));
+            return match self.executor.query(query) {
                 Ok(Some(result)) => {
                     Ok(Some(ResultWithCustomColumnTypes::new(Some(result), vec![])))
                 }
@@ -188,13 +1112,38 @@ where
                 Err(error) => Err(error),
             };
         }
-        let columns_types = self.get_columns_types_from_ast(ast.unwrap());
-        if columns_types.is_err() {
+        let ast = ast.unwrap();
+        if let [statement] = ast.as_slice() {
+            let default_schema = self.default_schema();
+            if let Some((column_types, rows)) = answer_information_schema(
+                &self.data_type_info.read().unwrap(),
+                &default_schema,
+                statement,
+            ) {
+                return Ok(Some(ResultWithCustomColumnTypes::local(column_types, rows)));
+            }
+        }
+        if let Err(error) = self.discover_unknown_tables(&ast) {
+            println!(
+                "Failed to load the structure of unknown tables. {:?}",
+                error
+            );
+            self.session_state.add_warning(format!(
+                "Structure of unknown tables could not be loaded, so some columns may not be typed. {}",
+                error
+            ));
+        }
+        let columns_types = self.get_columns_types_from_ast(ast);
+        if let Err(error) = &columns_types {
             println!(
                 "Failed to find proper types. Result will not have types. {:?}",
-                columns_types
+                error
             );
-            return match self.executor.query(&query) {
+            self.session_state.add_warning(format!(
+                "Result is not typed because the types of its columns could not be found. {}",
+                error
+            ));
+            return match self.executor.query(query) {
                 Ok(Some(result)) => {
                     Ok(Some(ResultWithCustomColumnTypes::new(Some(result), vec![])))
                 }
@@ -202,20 +1151,37 @@ where
                 Err(error) => Err(error),
             };
         }
-        let columns_types = columns_types?;
+        let columns_types = self.with_binary_uuids(columns_types?);
         println!("Expected column types : {:?}", columns_types);
-        let result = self.executor.query(&query)?;
-        Ok(Some(ResultWithCustomColumnTypes::new(
-            result,
-            columns_types,
-        )))
+        let problems = type_problems(&columns_types);
+        match self.type_strictness {
+            TypeStrictness::Warn => {
+                for problem in problems {
+                    self.session_state.add_warning(problem);
+                }
+            }
+            TypeStrictness::Strict if !problems.is_empty() => {
+                return Err(SqlError::new(format!(
+                    "Query not executed because of type_strictness. {}",
+                    problems.join(". ")
+                ))
+                .into());
+            }
+            _ => {}
+        }
+        let result = self.executor.query(query)?;
+        Ok(Some(
+            ResultWithCustomColumnTypes::new(result, columns_types)
+                .with_session_state(self.session_state.clone())
+                .with_geometry_format(self.geometry_format),
+        ))
     }
 }
 
 fn get_tables_with_aliases_from_set_expr(
     set_expr: &SetExpr,
     data_type_info: &mut Vec<(Schema, TableName, ColumnName, ColumnType)>,
-    default_schema: &str,
+    context: &Context,
 ) -> Result<Vec<(Schema, TableName, TableAlias)>> {
     let mut result = Vec::new();
     match set_expr {
@@ -224,13 +1190,13 @@ fn get_tables_with_aliases_from_set_expr(
                 result.push(process_table_factor(
                     &table_with_join.relation,
                     data_type_info,
-                    default_schema,
+                    context,
                 )?);
                 for join in &table_with_join.joins {
                     result.push(process_table_factor(
                         &join.relation,
                         data_type_info,
-                        default_schema,
+                        context,
                     )?);
                 }
             }
@@ -243,7 +1209,7 @@ fn get_tables_with_aliases_from_set_expr(
         } => result.append(&mut get_tables_with_aliases_from_set_expr(
             left,
             data_type_info,
-            default_schema,
+            context,
         )?),
         any => bail!("We can only parse selects - {:?}", any),
     }
@@ -253,6 +1219,7 @@ fn get_tables_with_aliases_from_set_expr(
 fn get_alias_with_clomuns_and_column_type(
     tables_with_aliases: Vec<(Schema, TableName, TableAlias)>,
     data_type_info: &[(Schema, TableName, ColumnName, ColumnType)],
+    context: &Context,
 ) -> Vec<(TableAlias, ColumnName, ColumnType)> {
     tables_with_aliases
         .into_iter()
@@ -261,7 +1228,8 @@ fn get_alias_with_clomuns_and_column_type(
                 .iter()
                 .filter(
                     move |(introspected_schema, introspected_table_name, _, _)| {
-                        *introspected_schema == schema && *introspected_table_name == table_name
+                        context.same(introspected_schema, &schema)
+                            && context.same(introspected_table_name, &table_name)
                     },
                 )
                 .map(move |(_, _, column_name, column_type)| {
@@ -274,7 +1242,7 @@ fn get_alias_with_clomuns_and_column_type(
 fn process_table_factor(
     table_factor: &TableFactor,
     data_type_info: &mut Vec<(Schema, TableName, ColumnName, ColumnType)>,
-    default_schema: &str,
+    context: &Context,
 ) -> Result<(Schema, TableName, TableAlias)> {
     match table_factor {
         TableFactor::Table {
@@ -284,21 +1252,32 @@ fn process_table_factor(
             with_hints: _,
         } => {
             let (schema, table_name) = match name.0.len() {
-                1 => (default_schema.to_string(), name.0[0].value.clone()),
-                2 => (name.0[0].value.clone(), name.0[1].value.clone()),
+                1 => (
+                    context.default_schema.to_string(),
+                    context.identifier(&name.0[0]),
+                ),
+                2 => (
+                    context.identifier(&name.0[0]),
+                    context.identifier(&name.0[1]),
+                ),
                 _ => bail!("To many namespaces in the table name {:?}", name),
             };
             let alias = match alias {
-                Some(table_alias) => table_alias.name.value.clone(),
+                Some(table_alias) => context.identifier(&table_alias.name),
                 None => table_name.clone(),
             };
             Ok((schema, table_name, alias))
         }
+        // Columns that lateral subqueries read from the tables before them are not typed
         TableFactor::Derived {
             lateral: _,
             subquery,
             alias,
         } => {
+            let alias = match alias {
+                Some(alias) => context.identifier(&alias.name),
+                None => bail!("Derived tables need an alias"),
+            };
             let mut result = Vec::new();
             let mut temp_data_type_info = data_type_info.clone();
             match &subquery.body {
@@ -307,13 +1286,13 @@ fn process_table_factor(
                         result.push(process_table_factor(
                             &table_with_join.relation,
                             &mut temp_data_type_info,
-                            default_schema,
+                            context,
                         )?);
                         for join in &table_with_join.joins {
                             result.push(process_table_factor(
                                 &join.relation,
                                 &mut temp_data_type_info,
-                                default_schema,
+                                context,
                             )?);
                         }
                     }
@@ -321,33 +1300,140 @@ fn process_table_factor(
                 any => bail!("We can only parse selects - {:?}", any),
             }
             let alias_to_column_and_type =
-                get_alias_with_clomuns_and_column_type(result, &temp_data_type_info);
+                get_alias_with_clomuns_and_column_type(result, &temp_data_type_info, context);
 
-            let alias = alias.as_ref().unwrap().name.value.clone();
-            for (column_name, column_type) in
-                get_columns_types(&subquery.body, alias_to_column_and_type)?
-            {
+            for (column_name, column_type) in get_columns_types(
+                &subquery.body,
+                alias_to_column_and_type,
+                &temp_data_type_info,
+                context,
+            )? {
                 data_type_info.push((alias.clone(), alias.clone(), column_name, column_type))
             }
             Ok((alias.clone(), alias.clone(), alias)) // We should probably create unique names here.
         }
+        // Only the alias is known, so the columns of table functions are not typed
+        TableFactor::TableFunction { expr: _, alias } => match alias {
+            Some(alias) => {
+                let alias = context.identifier(&alias.name);
+                Ok((alias.clone(), alias.clone(), alias))
+            }
+            None => bail!("Table functions need an alias"),
+        },
         any => bail!("We can only parse simple tables - {:?}", any),
     }
 }
 
-fn get_expr(ast: &[Statement]) -> Result<Option<&SetExpr>> {
-    if ast.len() != 1 {
-        bail!("We need to be able to handle multiple statements");
+/// Registers the columns of a CTE as a table of the default schema, hiding any real table with
+/// the same name
+fn process_cte(
+    cte: &Cte,
+    data_type_info: &mut Vec<(Schema, TableName, ColumnName, ColumnType)>,
+    context: &Context,
+) -> Result<()> {
+    let columns_types =
+        get_columns_types_from_set_expr(&cte.query.body, &mut data_type_info.clone(), context)?;
+
+    let name = context.identifier(&cte.alias.name);
+    data_type_info.retain(|(schema, table_name, _, _)| {
+        !(context.same(schema, context.default_schema) && context.same(table_name, &name))
+    });
+    for (index, (column_name, column_type)) in columns_types.into_iter().enumerate() {
+        let column_name = match cte.alias.columns.get(index) {
+            Some(renamed) => context.identifier(renamed),
+            None => column_name,
+        };
+        data_type_info.push((
+            context.default_schema.to_string(),
+            name.clone(),
+            column_name,
+            column_type,
+        ));
+    }
+    Ok(())
+}
+
+/// Types of the columns of a select or of a set operation, like `UNION`, merging the types of
+/// both sides
+fn get_columns_types_from_set_expr(
+    set_expr: &SetExpr,
+    data_type_info: &mut Vec<(Schema, TableName, ColumnName, ColumnType)>,
+    context: &Context,
+) -> Result<Vec<(ColumnName, ColumnType)>> {
+    match set_expr {
+        SetExpr::SetOperation {
+            op: _,
+            all: _,
+            left,
+            right,
+        } => {
+            let left = get_columns_types_from_set_expr(left, data_type_info, context)?;
+            let right = get_columns_types_from_set_expr(right, data_type_info, context)?;
+            if left.len() != right.len() {
+                bail!(
+                    "Both sides of the set operation need the same number of columns - {} and {}",
+                    left.len(),
+                    right.len()
+                );
+            }
+            Ok(left
+                .into_iter()
+                .zip(right)
+                .map(|((column_name, left_type), (_, right_type))| {
+                    (
+                        column_name,
+                        merge_types(left_type.data_type, right_type.data_type).into(),
+                    )
+                })
+                .collect())
+        }
+        SetExpr::Query(query) => {
+            get_columns_types_from_set_expr(&query.body, data_type_info, context)
+        }
+        set_expr => {
+            let tables_with_aliases =
+                get_tables_with_aliases_from_set_expr(set_expr, data_type_info, context)?;
+            let mut alias_to_column_and_type = get_alias_with_clomuns_and_column_type(
+                tables_with_aliases.clone(),
+                data_type_info,
+                context,
+            );
+            if let SetExpr::Select(select) = set_expr {
+                // `*` would miss the columns of tables we do not know, like table functions
+                let selects_all_of = |alias: &str| {
+                    select.projection.iter().any(|item| match item {
+                        SelectItem::Wildcard => true,
+                        SelectItem::QualifiedWildcard(name) => {
+                            context.same(&context.identifier(&name.0[0]), alias)
+                        }
+                        _ => false,
+                    })
+                };
+                for (_, _, alias) in &tables_with_aliases {
+                    let known = alias_to_column_and_type
+                        .iter()
+                        .any(|(table_alias, _, _)| table_alias == alias);
+                    if !known && selects_all_of(alias) {
+                        bail!("We do not know the columns of {}", alias);
+                    }
+                }
+                let outer_joined = outer_joined_aliases(select, context);
+                for (alias, _, column_type) in &mut alias_to_column_and_type {
+                    if outer_joined.contains(alias) {
+                        *column_type = column_type.clone().nullable();
+                    }
+                }
+            }
+            get_columns_types(set_expr, alias_to_column_and_type, data_type_info, context)
+        }
     }
-    Ok(match &ast[0] {
-        Statement::Query(query) => Some(&query.body),
-        _ => None,
-    })
 }
 
 fn get_columns_types(
     set_expr: &SetExpr,
     alias_to_column_and_type: Vec<(String, String, ColumnType)>,
+    data_type_info: &[(Schema, TableName, ColumnName, ColumnType)],
+    context: &Context,
 ) -> Result<Vec<(ColumnName, ColumnType)>> {
     let mut result = Vec::new();
     match set_expr {
@@ -355,12 +1441,18 @@ fn get_columns_types(
             for projection in &select.projection {
                 match &projection {
                     SelectItem::UnnamedExpr(expr) => {
-                        result.push(process_expr(expr, &alias_to_column_and_type)?);
+                        result.push(process_expr(
+                            expr,
+                            &alias_to_column_and_type,
+                            data_type_info,
+                            context,
+                        )?);
                     }
                     SelectItem::ExprWithAlias { expr, alias } => {
                         result.push((
                             alias.value.to_string(),
-                            process_expr(expr, &alias_to_column_and_type)?.1,
+                            process_expr(expr, &alias_to_column_and_type, data_type_info, context)?
+                                .1,
                         ));
                     }
                     SelectItem::QualifiedWildcard(obj_name) => {
@@ -370,9 +1462,10 @@ fn get_columns_types(
                                 obj_name.0
                             );
                         }
+                        let table = context.identifier(&obj_name.0[0]);
                         alias_to_column_and_type
                             .iter()
-                            .filter(|(table_alias, _, _)| *table_alias == obj_name.0[0].value)
+                            .filter(|(table_alias, _, _)| context.same(table_alias, &table))
                             .for_each(|(_, column_name, columnt_type)| {
                                 result.push((column_name.clone(), columnt_type.clone()))
                             })
@@ -390,7 +1483,12 @@ fn get_columns_types(
             all: _,
             left,
             right: _,
-        } => result.append(&mut get_columns_types(left, alias_to_column_and_type)?),
+        } => result.append(&mut get_columns_types(
+            left,
+            alias_to_column_and_type,
+            data_type_info,
+            context,
+        )?),
         any => bail!("We can only parse selects - {:?}", any),
     }
     Ok(result)
@@ -400,26 +1498,33 @@ fn find_type(
     alias_to_column_and_type: &[(String, String, ColumnType)],
     column_name: &str,
     table_name: Option<&str>,
+    context: &Context,
 ) -> (ColumnName, ColumnType) {
     match table_name {
         Some(table_name) => {
             alias_to_column_and_type
                 .iter()
                 .find(|(s_table_name, s_column_name, _)| {
-                    s_table_name == table_name && s_column_name == column_name
+                    context.same(s_table_name, table_name)
+                        && context.same(s_column_name, column_name)
                 })
         }
         None => alias_to_column_and_type
             .iter()
-            .find(|(_, s_column_name, _)| s_column_name == column_name),
+            .find(|(_, s_column_name, _)| context.same(s_column_name, column_name)),
     }
     .map(|(_, column_name, column_type)| (column_name.clone(), column_type.clone()))
-    .unwrap_or((column_name.to_string(), None)) // We should probably inform when this happens
+    .unwrap_or((column_name.to_string(), ColumnType::default())) // We should probably inform when this happens
 }
 
 pub struct ResultWithCustomColumnTypes<T> {
     result: Option<T>,
     column_types: Vec<(ColumnName, ColumnType)>,
+    /// Rows of results answered without the database
+    rows: Vec<Row>,
+    /// Where to warn when the types can not be used
+    session_state: Option<SessionState>,
+    geometry_format: GeometryFormat,
 }
 
 impl<T> ResultWithCustomColumnTypes<T> {
@@ -427,6 +1532,33 @@ impl<T> ResultWithCustomColumnTypes<T> {
         Self {
             result,
             column_types,
+            rows: Vec::new(),
+            session_state: None,
+            geometry_format: GeometryFormat::default(),
+        }
+    }
+
+    fn with_session_state(self, session_state: SessionState) -> Self {
+        Self {
+            session_state: Some(session_state),
+            ..self
+        }
+    }
+
+    fn with_geometry_format(self, geometry_format: GeometryFormat) -> Self {
+        Self {
+            geometry_format,
+            ..self
+        }
+    }
+
+    fn local(column_types: Vec<(ColumnName, ColumnType)>, rows: Vec<Row>) -> Self {
+        Self {
+            result: None,
+            column_types,
+            rows,
+            session_state: None,
+            geometry_format: GeometryFormat::default(),
         }
     }
 }
@@ -435,92 +1567,97 @@ impl<T> QueryResult for ResultWithCustomColumnTypes<T>
 where
     T: QueryResult,
 {
-    fn get_data(self) -> (Result<Vec<Column>>, Box<dyn Iterator<Item = Result<Row>>>) {
+    fn get_data(self) -> (Result<Vec<Column>>, Rows) {
         match self.result {
             Some(result) => {
                 let (columns, rows) = result.get_data();
                 if self.column_types.is_empty() {
                     return (columns, rows);
                 }
-                (
-                    match columns {
-                        Ok(columns) => {
-                            if columns.len() != self.column_types.len() {
-                                panic!(
-                                    "Wrong number of columns in result. Expected {}, found {}",
-                                    self.column_types.len(),
-                                    columns.len()
-                                )
-                            }
-                            Ok(columns
-                                .into_iter()
-                                .zip(&self.column_types)
-                                .map(|(mut column, column_type)| {
-                                    column.ty = column_type.1.clone();
-                                    column
-                                })
-                                .collect())
-                        }
-                        error => error,
-                    },
-                    Box::new(rows.map(move |row| {
-                        match row {
-                            Err(error) => Err(error),
-                            Ok(row) => Ok(row
-                                .into_iter()
-                                .zip(&self.column_types)
-                                .map(|(column_value, column_type)| match column_value {
-                                    ColumnValue::Null => ColumnValue::Null,
-                                    ColumnValue::String(value) => match column_type.1.as_deref() {
-                                        Some("bigint") => {
-                                            ColumnValue::I64(value.parse::<i64>().unwrap())
-                                        }
-                                        Some("int") | Some("mediumint") => {
-                                            ColumnValue::I32(value.parse::<i32>().unwrap())
-                                        }
-                                        Some("smallint") | Some("year") => {
-                                            ColumnValue::I16(value.parse::<i16>().unwrap())
-                                        }
-                                        Some("tinyint") => {
-                                            ColumnValue::I8(value.parse::<i8>().unwrap())
-                                        }
-                                        Some("double") => {
-                                            ColumnValue::Double(value.parse::<f64>().unwrap())
-                                        }
-                                        Some("float") => {
-                                            ColumnValue::Float(value.parse::<f32>().unwrap())
-                                        }
-                                        Some("timestamp") | Some("datetime") => {
-                                            ColumnValue::DateTime(
-                                                NaiveDateTime::parse_from_str(
-                                                    &value,
-                                                    "%Y-%m-%d %H:%M:%S%.f",
-                                                )
-                                                .unwrap(),
-                                            )
-                                        }
-                                        Some("date") => {
-                                            match NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
-                                                Ok(date) => ColumnValue::Date(date),
-                                                Err(_) => ColumnValue::String(value),
-                                            }
+                let found = columns.as_ref().map(Vec::len).unwrap_or_default();
+                if columns.is_ok() && found != self.column_types.len() {
+                    let warning = format!(
+                        "Column types were not used. Expected {} columns, found {}",
+                        self.column_types.len(),
+                        found
+                    );
+                    println!("{}", warning);
+                    if let Some(session_state) = &self.session_state {
+                        session_state.add_warning(warning);
+                    }
+                    return (columns, rows);
+                }
+                let columns = columns.map(|columns| {
+                    columns
+                        .into_iter()
+                        .zip(&self.column_types)
+                        .map(|(mut column, column_type)| {
+                            column.ty = match self.geometry_format {
+                                GeometryFormat::Wkt if is_geometry(&column_type.1) => {
+                                    Some("text".to_string())
+                                }
+                                _ => column_type.1.data_type.clone(),
+                            };
+                            column.attributes = column_type.1.attributes.clone();
+                            column
+                        })
+                        .collect()
+                });
+                let failures = Arc::new(AtomicUsize::new(0));
+                let counted_failures = failures.clone();
+                let column_types = self.column_types;
+                let geometry_format = self.geometry_format;
+                let rows = rows.map(move |row| {
+                    row.and_then(|row| {
+                        row.into_iter()
+                            .zip(&column_types)
+                            .map(
+                                |(column_value, (column_name, column_type))| match column_value {
+                                    // Broken documents, like the ones of truncated logs, fail the query
+                                    ColumnValue::String(value) if is_json(column_type) => {
+                                        match serde_json::from_str(&value) {
+                                            Ok(document) => Ok(ColumnValue::Json(document)),
+                                            Err(error) => Err(SqlError::new(format!(
+                                                "Invalid JSON in column {column_name}: {error}"
+                                            ))
+                                            .into()),
                                         }
-                                        Some("decimal") | Some("text") | Some("char")
-                                        | Some("tinytext") | Some("longtext")
-                                        | Some("mediumtext") | Some("varchar") | None => {
+                                    }
+                                    ColumnValue::String(value) if is_geometry(column_type) => Ok(
+                                        geometry(value, geometry_format).unwrap_or_else(|value| {
+                                            counted_failures.fetch_add(1, Ordering::Relaxed);
                                             ColumnValue::String(value)
-                                        }
-                                        Some(any) => {
-                                            println!("Type not mapped {}", any);
+                                        }),
+                                    ),
+                                    ColumnValue::String(value) => Ok(convert(value, column_type)
+                                        .unwrap_or_else(|value| {
+                                            counted_failures.fetch_add(1, Ordering::Relaxed);
                                             ColumnValue::String(value)
-                                        }
-                                    },
-                                    _ => panic!("We should only have string format here"),
-                                })
-                                .collect()),
+                                        })),
+                                    // Already typed, like the values of `jsonl` results
+                                    column_value => Ok(column_value),
+                                },
+                            )
+                            .collect()
+                    })
+                });
+                let session_state = self.session_state;
+                // Reported after the last row, before the client can ask for the warnings
+                let report = std::iter::from_fn(move || {
+                    let failures = failures.swap(0, Ordering::Relaxed);
+                    if failures > 0 {
+                        let warning = format!(
+                            "{} values did not fit the types of their columns and were answered as text",
+                            failures
+                        );
+                        println!("{}", warning);
+                        if let Some(session_state) = &session_state {
+                            session_state.add_warning(warning);
                         }
-                    })),
-                )
+                    }
+                    None
+                });
+                (columns, Box::new(rows.chain(report)))
             }
             None => (
                 Ok(self
@@ -528,92 +1665,1542 @@ where
                     .into_iter()
                     .map(|(column_name, column_type)| Column {
                         name: column_name,
-                        ty: column_type,
+                        ty: column_type.data_type,
+                        attributes: column_type.attributes,
                     })
                     .collect()),
-                Box::new(std::iter::empty()),
+                Box::new(self.rows.into_iter().map(Ok)),
             ),
         }
     }
+
+    /// Only untyped results are split, types are for a single result set
+    fn result_sets(self) -> Vec<(Result<Vec<Column>>, Rows)> {
+        match self.result {
+            Some(result) if self.column_types.is_empty() => result.result_sets(),
+            _ => vec![self.get_data()],
+        }
+    }
 }
 
 fn process_expr(
     expr: &Expr,
     alias_to_column_and_type: &Vec<(String, String, ColumnType)>,
+    data_type_info: &[(Schema, TableName, ColumnName, ColumnType)],
+    context: &Context,
 ) -> Result<(ColumnName, ColumnType)> {
     match &expr {
-        Expr::Identifier(ident) => Ok(find_type(alias_to_column_and_type, &ident.value, None)),
-        Expr::CompoundIdentifier(idents) => {
-            if idents.len() > 2 {
-                bail!(
-                    "We can only parse idents with table and column names - {:?}",
-                    idents
+        Expr::Identifier(ident) => Ok(find_type(
+            alias_to_column_and_type,
+            &context.identifier(ident),
+            None,
+            context,
+        )),
+        Expr::CompoundIdentifier(idents) => match idents.as_slice() {
+            // The schema is ignored, like in the definitions of views
+            [.., table_name, column_name] if idents.len() <= 3 => Ok(find_type(
+                alias_to_column_and_type,
+                &context.identifier(column_name),
+                Some(&context.identifier(table_name)),
+                context,
+            )),
+            _ => bail!(
+                "We can only parse idents with schema, table and column names - {:?}",
+                idents
+            ),
+        },
+        // Functions, like `MAX` of an empty table, can return NULL even for NOT NULL columns, and
+        // their values are not keys
+        Expr::Function(function) => {
+            process_function(function, alias_to_column_and_type, data_type_info, context)
+                .map(|(name, column_type)| (name, column_type.derived()))
+        }
+        Expr::Cast { expr, data_type } => Ok((
+            process_expr(expr, alias_to_column_and_type, data_type_info, context)?.0,
+            ColumnType::of(&data_type.to_string()),
+        )),
+        Expr::Case {
+            operand: _,
+            conditions: _,
+            results,
+            else_result,
+        } => {
+            let mut column_type = None;
+            for result in results.iter().chain(else_result.as_deref()) {
+                column_type = merge_types(
+                    column_type,
+                    process_expr(result, alias_to_column_and_type, data_type_info, context)?
+                        .1
+                        .data_type,
                 );
             }
-            Ok(find_type(
-                alias_to_column_and_type,
-                &idents[1].value,
-                Some(&idents[0].value),
+            Ok(("case".to_string(), column_type.into()))
+        }
+        Expr::BinaryOp { left, op, right } => {
+            let left_type = process_expr(left, alias_to_column_and_type, data_type_info, context)?
+                .1
+                .data_type;
+            let right_type =
+                process_expr(right, alias_to_column_and_type, data_type_info, context)?
+                    .1
+                    .data_type;
+            Ok((
+                expr.to_string(),
+                binary_op_type(op, left_type, right_type).into(),
             ))
         }
-        Expr::Function(function) => {
-            let name = function.name.0[0].value.clone();
-            match name.as_str() {
-                "if" => {
-                    let first = match &function.args[1] {
-                        FunctionArg::Unnamed(arg) => match arg {
-                            FunctionArgExpr::Expr(expr) => {
-                                process_expr(expr, alias_to_column_and_type)?
-                            }
-                            _ => bail!("Cant handle wildcards here"),
-                        },
-                        _ => bail!("Cant handle names function arg"),
-                    };
-                    let second = match &function.args[2] {
-                        FunctionArg::Unnamed(arg) => match arg {
-                            FunctionArgExpr::Expr(expr) => {
-                                process_expr(expr, alias_to_column_and_type)?
-                            }
-                            _ => bail!("Cant handle wildcards here"),
-                        },
-                        _ => bail!("Cant handle names function arg"),
-                    };
-                    if first.1.is_none() {
-                        Ok(second)
-                    } else {
-                        Ok(first)
-                    }
+        Expr::Subquery(query) => {
+            let mut temp_data_type_info = data_type_info.to_vec();
+            if let Some(with) = &query.with {
+                for cte in &with.cte_tables {
+                    process_cte(cte, &mut temp_data_type_info, context)?;
                 }
-                "max" => {
-                    let argument = match &function.args[0] {
-                        FunctionArg::Unnamed(arg) => match arg {
-                            FunctionArgExpr::Expr(expr) => {
-                                process_expr(expr, alias_to_column_and_type)?
-                            }
-                            _ => bail!("Cant handle wildcards here"),
-                        },
-                        _ => bail!("Cant handle names function arg"),
-                    };
-                    Ok(argument)
-                }
-                _ => Ok((name, None)), // We should probably warn this cases
+            }
+            match get_columns_types_from_set_expr(&query.body, &mut temp_data_type_info, context)?
+                .into_iter()
+                .next()
+            {
+                // Subqueries without rows return NULL
+                Some((column_name, column_type)) => Ok((column_name, column_type.derived())),
+                None => bail!("Scalar subquery without columns - {}", query),
             }
         }
-        Expr::Cast { expr, data_type } => Ok((
-            process_expr(expr, alias_to_column_and_type)?.0,
-            Some(data_type.to_string()),
+        Expr::Value(value) => Ok((value.to_string(), literal_type(value).into())),
+        Expr::TypedString { data_type, value } => Ok((
+            value.clone(),
+            ColumnType::of(&data_type.to_string().to_lowercase()),
+        )),
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => Ok((
+            process_expr(expr, alias_to_column_and_type, data_type_info, context)?.0,
+            ColumnType::of("tinyint"),
         )),
+        Expr::UnaryOp { op: _, expr } | Expr::Nested(expr) => {
+            process_expr(expr, alias_to_column_and_type, data_type_info, context)
+        }
+        // Built-in functions with a syntax of their own
+        Expr::Substring { .. } => Ok(("substring".to_string(), ColumnType::of("text"))),
+        Expr::Trim { .. } => Ok(("trim".to_string(), ColumnType::of("text"))),
+        Expr::Extract { .. } => Ok(("extract".to_string(), ColumnType::of("bigint"))),
         Expr::IsNull(expr) | Expr::IsNotNull(expr) => Ok((
-            process_expr(expr, alias_to_column_and_type)?.0,
-            Some("tinyint".to_string()),
+            process_expr(expr, alias_to_column_and_type, data_type_info, context)?.0,
+            ColumnType::of("tinyint"),
         )),
-        _ => Ok(dbg!(("unknown".to_string(), None))), // We should probably warn this cases
+        _ => Ok(dbg!(("unknown".to_string(), ColumnType::default()))), // We should probably warn this cases
+    }
+}
+
+fn process_function(
+    function: &Function,
+    alias_to_column_and_type: &Vec<(String, String, ColumnType)>,
+    data_type_info: &[(Schema, TableName, ColumnName, ColumnType)],
+    context: &Context,
+) -> Result<(ColumnName, ColumnType)> {
+    // Functions called with their schema, like `app.order_total(id)`, are found by their name
+    let name = function
+        .name
+        .0
+        .last()
+        .map(|name| name.value.to_lowercase())
+        .unwrap_or_default();
+    if let Some(ty) = context.function_types.get(&name) {
+        return Ok((name, ColumnType::of(ty)));
+    }
+    match name.as_str() {
+        "if" => {
+            let first = process_function_arg(
+                function,
+                1,
+                alias_to_column_and_type,
+                data_type_info,
+                context,
+            )?;
+            let second = process_function_arg(
+                function,
+                2,
+                alias_to_column_and_type,
+                data_type_info,
+                context,
+            )?;
+            if first.1.data_type.is_none() {
+                Ok(second)
+            } else {
+                Ok(first)
+            }
+        }
+        "coalesce" | "ifnull" => {
+            for index in 0..function.args.len() {
+                let argument = process_function_arg(
+                    function,
+                    index,
+                    alias_to_column_and_type,
+                    data_type_info,
+                    context,
+                )?;
+                if argument.1.data_type.is_some() {
+                    return Ok(argument);
+                }
+            }
+            Ok((name, ColumnType::default()))
+        }
+        "nullif" | "max" | "min" => process_function_arg(
+            function,
+            0,
+            alias_to_column_and_type,
+            data_type_info,
+            context,
+        ),
+        "count" => Ok((name, ColumnType::of("bigint"))),
+        "sum" | "avg" => {
+            let argument = process_function_arg(
+                function,
+                0,
+                alias_to_column_and_type,
+                data_type_info,
+                context,
+            )?;
+            Ok((name, aggregate_type(argument.1.data_type.as_deref()).into()))
+        }
+        "group_concat" => Ok((name, ColumnType::of("text"))),
+        "now" | "current_timestamp" | "sysdate" | "utc_timestamp" | "localtimestamp" => {
+            Ok((name, ColumnType::of("datetime")))
+        }
+        "curdate" | "current_date" | "utc_date" => Ok((name, ColumnType::of("date"))),
+        "curtime" | "current_time" | "utc_time" => Ok((name, ColumnType::of("time"))),
+        // Window functions. Aggregates used as windows follow the rules above.
+        "row_number" | "rank" | "dense_rank" | "ntile" => Ok((name, ColumnType::of("bigint"))),
+        "percent_rank" | "cume_dist" => Ok((name, ColumnType::of("double"))),
+        "lag" | "lead" | "first_value" | "last_value" | "nth_value" => process_function_arg(
+            function,
+            0,
+            alias_to_column_and_type,
+            data_type_info,
+            context,
+        ),
+        // Math functions that keep the type of their argument
+        "abs" | "round" | "truncate" | "floor" | "ceil" | "ceiling" | "greatest" | "least" => {
+            process_function_arg(
+                function,
+                0,
+                alias_to_column_and_type,
+                data_type_info,
+                context,
+            )
+        }
+        _ => {
+            let column_type = FUNCTION_TYPES
+                .iter()
+                .find(|(_, functions)| functions.contains(&name.as_str()))
+                .map(|(ty, _)| ColumnType::of(ty))
+                .unwrap_or_default(); // We should probably warn this cases
+            Ok((name, column_type))
+        }
+    }
+}
+
+/// Return types of common built-in functions of MySQL and Postgres, whatever their arguments
+const FUNCTION_TYPES: &[(&str, &[&str])] = &[
+    (
+        "text",
+        &[
+            "concat",
+            "concat_ws",
+            "lower",
+            "upper",
+            "lcase",
+            "ucase",
+            "substring",
+            "substr",
+            "left",
+            "right",
+            "trim",
+            "ltrim",
+            "rtrim",
+            "replace",
+            "lpad",
+            "rpad",
+            "reverse",
+            "repeat",
+            "space",
+            "date_format",
+            "time_format",
+            "format",
+            "hex",
+            "md5",
+            "sha1",
+            "sha2",
+            "uuid",
+            "monthname",
+            "dayname",
+            "to_char",
+            "initcap",
+            "string_agg",
+        ],
+    ),
+    (
+        "bigint",
+        &[
+            "length",
+            "char_length",
+            "character_length",
+            "octet_length",
+            "bit_length",
+            "locate",
+            "instr",
+            "position",
+            "strpos",
+            "field",
+            "find_in_set",
+            "ascii",
+            "ord",
+            "datediff",
+            "timestampdiff",
+            "year",
+            "month",
+            "day",
+            "dayofmonth",
+            "dayofweek",
+            "dayofyear",
+            "weekday",
+            "week",
+            "hour",
+            "minute",
+            "second",
+            "quarter",
+        ],
+    ),
+    (
+        "double",
+        &[
+            "rand", "random", "pi", "sqrt", "exp", "ln", "log", "log2", "log10", "pow", "power",
+            "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "cot", "degrees", "radians",
+        ],
+    ),
+    // Fractional seconds in the argument make it a decimal
+    ("decimal", &["unix_timestamp"]),
+    ("date", &["date", "last_day", "makedate", "from_days"]),
+    ("datetime", &["timestamp"]),
+    ("timestamp with time zone", &["to_timestamp", "date_trunc"]),
+];
+
+fn process_function_arg(
+    function: &Function,
+    index: usize,
+    alias_to_column_and_type: &Vec<(String, String, ColumnType)>,
+    data_type_info: &[(Schema, TableName, ColumnName, ColumnType)],
+    context: &Context,
+) -> Result<(ColumnName, ColumnType)> {
+    match function.args.get(index) {
+        Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))) => {
+            process_expr(expr, alias_to_column_and_type, data_type_info, context)
+        }
+        Some(FunctionArg::Unnamed(_)) => bail!("Cant handle wildcards here"),
+        Some(FunctionArg::Named { .. }) => bail!("Cant handle names function arg"),
+        None => bail!("Missing argument {} of {}", index, function.name),
+    }
+}
+
+/// Type of `SUM` and `AVG`. Like MySQL, exact numbers become decimals and approximate ones doubles.
+fn aggregate_type(argument_type: Option<&str>) -> DataType {
+    match argument_type {
+        Some("float") | Some("double") => Some("double".to_string()),
+        Some("tinyint") | Some("smallint") | Some("mediumint") | Some("int") | Some("bigint")
+        | Some("year") | Some("decimal") => Some("decimal".to_string()),
+        _ => None,
+    }
+}
+
+fn binary_op_type(op: &BinaryOperator, left: DataType, right: DataType) -> DataType {
+    match op {
+        BinaryOperator::Plus
+        | BinaryOperator::Minus
+        | BinaryOperator::Multiply
+        | BinaryOperator::Modulo => {
+            let (left, right) = (left?, right?);
+            match (numeric_rank(&left), numeric_rank(&right)) {
+                (Some(_), Some(_)) => merge_types(Some(left), Some(right)),
+                // MySQL converts strings to doubles in arithmetic
+                _ => Some("double".to_string()),
+            }
+        }
+        BinaryOperator::Divide => match merge_types(left, right)?.as_str() {
+            "float" | "double" => Some("double".to_string()),
+            column_type if numeric_rank(column_type).is_some() => Some("decimal".to_string()),
+            _ => Some("double".to_string()),
+        },
+        BinaryOperator::StringConcat => Some("varchar".to_string()),
+        _ => Some("bigint".to_string()),
+    }
+}
+
+/// Same types MySQL gives to literals
+fn literal_type(value: &Value) -> DataType {
+    match value {
+        Value::Number(number, _) if number.contains(['e', 'E']) => Some("double".to_string()),
+        Value::Number(number, _) if number.contains('.') => Some("decimal".to_string()),
+        Value::Number(_, _) => Some("bigint".to_string()),
+        Value::SingleQuotedString(_)
+        | Value::DoubleQuotedString(_)
+        | Value::NationalStringLiteral(_) => Some("varchar".to_string()),
+        Value::Boolean(_) => Some("tinyint".to_string()),
+        _ => None,
+    }
+}
+
+/// Type of a column that can hold values of both types. Numbers are widened and other different
+/// types become text.
+fn merge_types(left: DataType, right: DataType) -> DataType {
+    match (left, right) {
+        (None, column_type) | (column_type, None) => column_type,
+        (Some(left), Some(right)) if left == right => Some(left),
+        (Some(left), Some(right)) => match (numeric_rank(&left), numeric_rank(&right)) {
+            (Some(left_rank), Some(right_rank)) if left_rank.max(right_rank) >= 6 => {
+                Some("double".to_string())
+            }
+            (Some(left_rank), Some(right_rank)) if left_rank >= right_rank => Some(left),
+            (Some(_), Some(_)) => Some(right),
+            _ => Some("text".to_string()),
+        },
+    }
+}
+
+fn numeric_rank(column_type: &str) -> Option<u8> {
+    match column_type {
+        "tinyint" => Some(0),
+        "smallint" => Some(1),
+        "mediumint" => Some(2),
+        "int" => Some(3),
+        "bigint" => Some(4),
+        "decimal" => Some(5),
+        "float" | "double" => Some(6),
+        _ => None,
+    }
+}
+
+/// Tables of a select that can have no matching row, so their columns can be NULL
+fn outer_joined_aliases(select: &Select, context: &Context) -> HashSet<TableAlias> {
+    let alias = |table_factor: &TableFactor| match table_factor {
+        TableFactor::Table { name, alias, .. } => alias
+            .as_ref()
+            .map(|alias| context.identifier(&alias.name))
+            .or_else(|| name.0.last().map(|ident| context.identifier(ident))),
+        TableFactor::Derived { alias, .. } => {
+            alias.as_ref().map(|alias| context.identifier(&alias.name))
+        }
+        _ => None,
+    };
+    let mut outer_joined = HashSet::new();
+    for table_with_join in &select.from {
+        let mut joined: Vec<TableAlias> = alias(&table_with_join.relation).into_iter().collect();
+        for join in &table_with_join.joins {
+            let join_alias = alias(&join.relation);
+            match join.join_operator {
+                JoinOperator::LeftOuter(_) | JoinOperator::OuterApply => {
+                    outer_joined.extend(join_alias.clone())
+                }
+                JoinOperator::RightOuter(_) => outer_joined.extend(joined.iter().cloned()),
+                JoinOperator::FullOuter(_) => {
+                    outer_joined.extend(joined.iter().cloned());
+                    outer_joined.extend(join_alias.clone());
+                }
+                _ => {}
+            }
+            joined.extend(join_alias);
+        }
+    }
+    outer_joined
+}
+
+/// Columns that will be answered as text because their type is unknown or not mapped
+fn type_problems(columns_types: &[(ColumnName, ColumnType)]) -> Vec<String> {
+    columns_types
+        .iter()
+        .filter_map(
+            |(column_name, column_type)| match column_type.data_type.as_deref() {
+                None => Some(format!("Could not find the type of column {column_name}")),
+                Some(data_type)
+                    if !MAPPED_TYPES.contains(&data_type)
+                        && !GEOMETRY_TYPES.contains(&data_type) =>
+                {
+                    Some(format!(
+                        "Type {data_type} of column {column_name} is not mapped"
+                    ))
+                }
+                Some(_) => None,
+            },
+        )
+        .collect()
+}
+
+/// The value typed like its column, or the value back when it does not fit the type
+fn convert(value: String, column_type: &ColumnType) -> std::result::Result<ColumnValue, String> {
+    let unsigned = column_type.attributes.unsigned;
+    let converted = match column_type.data_type.as_deref() {
+        Some("bigint") if unsigned => value.parse().ok().map(ColumnValue::U64),
+        Some("int") | Some("mediumint") if unsigned => value.parse().ok().map(ColumnValue::U32),
+        Some("smallint") if unsigned => value.parse().ok().map(ColumnValue::U16),
+        Some("tinyint") if unsigned => value.parse().ok().map(ColumnValue::U8),
+        Some("bigint") => value.parse().ok().map(ColumnValue::I64),
+        Some("int") | Some("mediumint") => value.parse().ok().map(ColumnValue::I32),
+        Some("smallint") | Some("year") => value.parse().ok().map(ColumnValue::I16),
+        Some("tinyint") => value.parse().ok().map(ColumnValue::I8),
+        Some("boolean") | Some("bool") => parse_bool(&value).map(ColumnValue::Bool),
+        Some("double") => value.parse().ok().map(ColumnValue::Double),
+        Some("float") => value.parse().ok().map(ColumnValue::Float),
+        Some("timestamp") | Some("datetime") => {
+            NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M:%S%.f")
+                .ok()
+                .map(ColumnValue::DateTime)
+        }
+        // Dates that are not valid, like `0000-00-00`, are kept as text
+        Some("date") => match NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+            Ok(date) => Some(ColumnValue::Date(date)),
+            Err(_) => return Ok(ColumnValue::String(value)),
+        },
+        // Uuids stored as 16 bytes, written as hex
+        Some("uuid") if value.starts_with("0x") || value.starts_with("\\x") => {
+            Uuid::from_slice(&decode_binary(&value))
+                .ok()
+                .map(ColumnValue::Uuid)
+        }
+        Some("uuid") => Uuid::parse_str(&value).ok().map(ColumnValue::Uuid),
+        // Negative times and intervals with days, like `1 day 02:00:00`, are kept as text
+        Some("time") | Some("interval") => match parse_time(&value) {
+            Some(time) => Some(ColumnValue::Time(time)),
+            None if value.starts_with('-')
+                || column_type.data_type.as_deref() == Some("interval") =>
+            {
+                return Ok(ColumnValue::String(value))
+            }
+            None => None,
+        },
+        Some("binary") | Some("varbinary") | Some("blob") | Some("tinyblob")
+        | Some("mediumblob") | Some("longblob") => Some(ColumnValue::Bytes(decode_binary(&value))),
+        // Decimals with more digits than `Decimal` can have are kept as text
+        Some("decimal") | Some("numeric") => match Decimal::from_str_exact(&value) {
+            Ok(decimal) => Some(ColumnValue::Decimal(decimal)),
+            Err(_) if value.parse::<f64>().is_ok() => return Ok(ColumnValue::String(value)),
+            Err(_) => None,
+        },
+        Some("text") | Some("char") | Some("tinytext") | Some("longtext") | Some("mediumtext")
+        | Some("varchar") | None => return Ok(ColumnValue::String(value)),
+        Some(any) => {
+            println!("Type not mapped {}", any);
+            return Ok(ColumnValue::String(value));
+        }
+    };
+    converted.ok_or(value)
+}
+
+fn is_geometry(column_type: &ColumnType) -> bool {
+    column_type
+        .data_type
+        .as_deref()
+        .is_some_and(|data_type| GEOMETRY_TYPES.contains(&data_type))
+}
+
+/// Geometries written as hex, like `mysql --binary-as-hex` and PostGIS do, as bytes or as WKT
+fn geometry(value: String, format: GeometryFormat) -> std::result::Result<ColumnValue, String> {
+    // PostGIS writes them without a prefix
+    let bytes = match value.len() % 2 == 0 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => decode_binary(&format!("0x{value}")),
+        false => decode_binary(&value),
+    };
+    match format {
+        GeometryFormat::Wkb => Ok(ColumnValue::Bytes(bytes)),
+        GeometryFormat::Wkt => wkt(&bytes).map(ColumnValue::String).ok_or(value),
+    }
+}
+
+fn is_json(column_type: &ColumnType) -> bool {
+    matches!(
+        column_type.data_type.as_deref(),
+        Some("json") | Some("jsonb")
+    )
+}
+
+/// Booleans written like `t` and `f` by psql, or as words or numbers by other targets
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "t" | "true" | "1" => Some(true),
+        "f" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Times written as `HH:MM:SS`, with optional fractions of seconds. Hours can be more than 24.
+fn parse_time(value: &str) -> Option<Duration> {
+    let mut parts = value.splitn(3, ':');
+    let (hours, minutes, seconds) = (parts.next()?, parts.next()?, parts.next()?);
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let number = |part: &str| -> Option<u64> {
+        (!part.is_empty() && part.chars().all(|c| c.is_ascii_digit())).then(|| part.parse().ok())?
+    };
+    let (hours, minutes, seconds) = (number(hours)?, number(minutes)?, number(seconds)?);
+    if minutes > 59 || seconds > 59 || fraction.len() > 9 {
+        return None;
+    }
+    let nanos = match fraction {
+        "" => 0,
+        fraction => number(fraction)? * 10u64.pow(9 - fraction.len() as u32),
+    };
+    Some(Duration::new(
+        hours * 3600 + minutes * 60 + seconds,
+        nanos as u32,
+    ))
+}
+
+/// Binary values written as hex, like `0x0aff` by `mysql --binary-as-hex` or `\x0aff` by psql.
+/// Other values are taken as they are.
+fn decode_binary(value: &str) -> Vec<u8> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("\\x"));
+    let bytes = hex.filter(|hex| hex.len() % 2 == 0).and_then(|hex| {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+    });
+    bytes.unwrap_or_else(|| value.as_bytes().to_vec())
+}
+
+/// Statements like `EXPLAIN` answer with columns of their own, whatever the statement they
+/// describe, so they are not typed
+fn is_plan(lower_case_query: &str) -> bool {
+    let mut words = lower_case_query.split_ascii_whitespace();
+    match (words.next(), words.next()) {
+        (Some("explain"), _) | (Some("analyze"), _) => true,
+        (Some("desc"), Some(second)) | (Some("describe"), Some(second)) => [
+            "select",
+            "with",
+            "insert",
+            "update",
+            "delete",
+            "replace",
+            "format",
+            "analyze",
+            "extended",
+            "partitions",
+            "(",
+        ]
+        .iter()
+        .any(|keyword| second.starts_with(keyword)),
+        (Some("show"), Some(second)) => second.starts_with("profile"),
+        _ => false,
     }
 }
 
+/// Queries with more than one statement, like `SET ...; SELECT ...`
+fn is_batch(query: &str, dialect: SqlDialect) -> bool {
+    let mut statements = 0;
+    replace_outside_literals(query.trim().trim_end_matches(';'), dialect, |rest, _| {
+        if rest[0] == ';' {
+            statements += 1;
+        }
+        None
+    });
+    statements > 0
+}
+
+fn is(word: &str, keyword: &str) -> bool {
+    word.eq_ignore_ascii_case(keyword)
+}
+
+/// Numbers of the structure, like lengths, are NULL for the types without them
+fn to_number(value: Option<&ColumnValue>) -> Option<u64> {
+    match value {
+        Some(ColumnValue::String(number)) => number.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Schema and name of a table written as `table` or `schema.table`
+fn split_table_name<'a>(default_schema: &'a str, name: &'a str) -> (&'a str, &'a str) {
+    match name.split_once('.') {
+        Some((schema, table_name)) => (schema.trim_matches('`'), table_name.trim_matches('`')),
+        None => (default_schema, name),
+    }
+}
+
+fn table_filter(schema: &str, table_name: &str) -> String {
+    format!(
+        "WHERE TABLE_SCHEMA = '{}' AND TABLE_NAME = '{}'",
+        schema.replace('\'', "''"),
+        table_name.replace('\'', "''")
+    )
+}
+
 fn to_string(value: &ColumnValue) -> &String {
     match value {
         ColumnValue::String(string) => string,
         _ => panic!("We are expecting bytes here"),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::{reader_result, FakeQueryExecutor};
+    use sqlparser::parser::Parser;
+
+    fn data_type_info() -> DataTypeInfo {
+        let column = |table: &str, name: &str, ty: &str| {
+            ("shop".into(), table.into(), name.into(), ColumnType::of(ty))
+        };
+        DataTypeInfo {
+            columns: vec![
+                column("users", "id", "bigint"),
+                column("users", "name", "varchar"),
+                column("orders", "id", "bigint"),
+                column("orders", "user_id", "bigint"),
+                column("orders", "total", "decimal"),
+            ],
+            primary_keys: vec![("shop".into(), "users".into(), "id".into())],
+            default_schema: "shop".into(),
+            identifier_case: IdentifierCase::default(),
+            function_types: HashMap::new(),
+            looked_up_tables: HashMap::new(),
+            version: OnceLock::new(),
+        }
+    }
+
+    fn types_of_with(data_type_info: &DataTypeInfo, query: &str) -> Vec<(ColumnName, ColumnType)> {
+        let ast = Parser::parse_sql(&MySqlDialect {}, query).unwrap();
+        data_type_info
+            .get_columns_types_from_ast("shop", ast)
+            .unwrap()
+    }
+
+    fn types_of(query: &str) -> Vec<(ColumnName, ColumnType)> {
+        types_of_with(&data_type_info(), query)
+    }
+
+    fn typed(name: &str, ty: &str) -> (ColumnName, ColumnType) {
+        (name.to_string(), ColumnType::of(ty))
+    }
+
+    #[test]
+    fn test_aliases_of_table_functions_and_lateral_tables() {
+        assert_eq!(
+            vec![
+                typed("name", "varchar"),
+                ("value".to_string(), ColumnType::default()),
+                typed("total", "decimal"),
+            ],
+            types_of(
+                "select u.name, g.value, o.total from users u, table(generate_rows(3)) g, \
+                lateral (select total from orders where user_id = u.id) o"
+            )
+        );
+        let ast = Parser::parse_sql(&MySqlDialect {}, "select * from table(f()) t").unwrap();
+        assert!(data_type_info()
+            .get_columns_types_from_ast("shop", ast)
+            .is_err());
+    }
+
+    #[test]
+    fn test_plans_are_not_typed() {
+        for query in [
+            "explain select * from users; select 1",
+            "EXPLAIN ANALYZE select * from users",
+            "describe format=json select * from users",
+            "show profile for query 1",
+        ] {
+            assert!(is_plan(&query.to_lowercase()), "{query}");
+        }
+        for query in ["describe users", "select * from users", "show tables"] {
+            assert!(!is_plan(query), "{query}");
+        }
+    }
+
+    #[test]
+    fn test_types_of_cte_columns() {
+        assert_eq!(
+            vec![typed("name", "varchar"), typed("order_id", "bigint")],
+            types_of(
+                "with big_orders (order_id, buyer) as (select id, user_id from orders) \
+                select u.name, b.order_id from users u join big_orders b on b.buyer = u.id"
+            )
+        );
+    }
+
+    #[test]
+    fn test_types_of_aggregates() {
+        assert_eq!(
+            vec![
+                typed("user_id", "bigint"),
+                typed("count", "bigint"),
+                typed("sum", "decimal"),
+                typed("min", "decimal"),
+            ],
+            types_of(
+                "select user_id, count(*), sum(total), min(total) from orders group by user_id"
+            )
+        );
+    }
+
+    #[test]
+    fn test_types_of_case() {
+        assert_eq!(
+            vec![typed("kind", "decimal")],
+            types_of(
+                "select case when total > 100 then total when total > 10 then id else null end \
+                as kind from orders"
+            )
+        );
+    }
+
+    #[test]
+    fn test_types_of_null_handling_functions() {
+        assert_eq!(
+            vec![typed("total", "decimal"), typed("name", "varchar")],
+            types_of("select coalesce(null, total, id), nullif(u.name, '') from orders, users u")
+        );
+    }
+
+    #[test]
+    fn test_types_of_built_in_functions() {
+        assert_eq!(
+            vec![
+                typed("concat", "text"),
+                typed("length", "bigint"),
+                typed("total", "decimal"),
+                typed("sqrt", "double"),
+                ("unknown_function".to_string(), ColumnType::default()),
+            ],
+            types_of(
+                "select concat(name, '!'), length(name), round(total, 1), sqrt(total), \
+                unknown_function(name) from orders, users"
+            )
+        );
+    }
+
+    #[test]
+    fn test_configured_function_types() {
+        let data_type_info = data_type_info().with_function_types(HashMap::from([
+            ("Order_Total".to_string(), "DECIMAL".to_string()),
+            ("length".to_string(), "int".to_string()),
+        ]));
+        assert_eq!(
+            vec![
+                typed("order_total", "decimal"),
+                typed("order_total", "decimal"),
+                typed("length", "int")
+            ],
+            types_of_with(
+                &data_type_info,
+                "select ORDER_TOTAL(id), shop.order_total(id), length(name) from users"
+            )
+        );
+    }
+
+    #[test]
+    fn test_types_of_window_functions() {
+        assert_eq!(
+            vec![
+                typed("position", "bigint"),
+                typed("running_total", "decimal"),
+                typed("previous", "decimal"),
+            ],
+            types_of(
+                "select row_number() over (partition by user_id order by id) as position, \
+                sum(total) over (partition by user_id order by id) as running_total, \
+                lag(total) over (order by id) as previous from orders"
+            )
+        );
+    }
+
+    #[test]
+    fn test_types_of_literals() {
+        assert_eq!(
+            vec![
+                typed("1", "bigint"),
+                typed("'x'", "varchar"),
+                typed("1.5", "decimal"),
+                typed("now", "datetime"),
+                typed("1", "bigint"),
+            ],
+            types_of("select 1, 'x', 1.5, now(), -1")
+        );
+    }
+
+    #[test]
+    fn test_types_of_binary_operations() {
+        assert_eq!(
+            vec![
+                typed("with_tax", "decimal"),
+                typed("next_id", "bigint"),
+                typed("half", "decimal"),
+                typed("is_big", "bigint"),
+            ],
+            types_of(
+                "select total * 1.1 as with_tax, id + 1 as next_id, id / 2 as half, \
+                total > 100 as is_big from orders"
+            )
+        );
+    }
+
+    #[test]
+    fn test_types_of_unions_are_merged() {
+        assert_eq!(
+            vec![typed("id", "decimal"), typed("name", "text")],
+            types_of("select id, name from users union all select total, id from orders")
+        );
+    }
+
+    #[test]
+    fn test_types_of_scalar_subqueries() {
+        assert_eq!(
+            vec![typed("name", "varchar"), typed("latest", "bigint")],
+            types_of("select name, (select max(id) from orders) as latest from users")
+        );
+    }
+
+    #[test]
+    fn test_answer_show_statements() {
+        let data_type_info = data_type_info();
+        let (columns, rows) = data_type_info.answer_show("shop", "SHOW TABLES").unwrap();
+        assert_eq!("Tables_in_shop", columns[0].0);
+        assert_eq!(
+            vec!["users", "orders"],
+            rows.iter()
+                .map(|row| to_string(&row[0]))
+                .collect::<Vec<_>>()
+        );
+        let (_, rows) = data_type_info
+            .answer_show("other", "show create table `shop`.`users`;")
+            .unwrap();
+        assert_eq!(
+            "CREATE TABLE `users` (\n  `id` bigint,\n  `name` varchar,\n  PRIMARY KEY (`id`)\n)",
+            to_string(&rows[0][1])
+        );
+        assert!(data_type_info
+            .answer_show("shop", "show create table missing")
+            .is_none());
+        // Names only match like the identifiers of the queries
+        let case_sensitive = data_type_info
+            .clone()
+            .with_identifier_case(IdentifierCase::Sensitive);
+        assert!(case_sensitive
+            .answer_show("shop", "describe USERS")
+            .is_none());
+        assert!(case_sensitive
+            .answer_show("shop", "show create table Users")
+            .is_none());
+        let (_, rows) = case_sensitive
+            .answer_show("shop", "show tables from SHOP")
+            .unwrap();
+        assert!(rows.is_empty());
+        assert!(data_type_info
+            .answer_show("shop", "describe USERS")
+            .is_some());
+        assert!(data_type_info
+            .answer_show("shop", "show tables like 'user%'")
+            .is_none());
+    }
+
+    #[test]
+    fn test_types_of_the_result_statement_of_a_batch() {
+        assert_eq!(
+            vec![typed("id", "bigint")],
+            types_of("set @limit = 10; select id from users")
+        );
+    }
+
+    #[test]
+    fn test_semicolons_of_strings_do_not_make_a_batch() {
+        assert!(is_batch("set @limit = 10; select 1;", SqlDialect::MySql));
+        assert!(!is_batch(
+            "select 'a;b', `c;d` from users;",
+            SqlDialect::MySql
+        ));
+        assert!(!is_batch("select $$a;b$$", SqlDialect::Postgres));
+    }
+
+    #[test]
+    fn test_saved_structure_is_only_read_for_the_same_target() {
+        let path = std::env::temp_dir().join(format!("fakesql-schema-{}.json", std::process::id()));
+        data_type_info().save(&path, "shop-db").unwrap();
+        let read = DataTypeInfo::read(&path, "shop-db", Some(Duration::from_secs(60)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(data_type_info().version(), read.version());
+        // Versions kept by older builds must still match
+        assert_eq!(4327030119667015517, data_type_info().version());
+        // Discovered tables keep the version, so the cached results are not discarded
+        let mut discovered = data_type_info();
+        discovered.version();
+        discovered.merge(data_type_info());
+        assert_eq!(4327030119667015517, discovered.version());
+        assert!(DataTypeInfo::read(&path, "other-db", None)
+            .unwrap()
+            .is_none());
+        assert!(DataTypeInfo::read(&path, "shop-db", Some(Duration::ZERO))
+            .unwrap()
+            .is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_a_single_table() {
+        let mut executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(
+            "TABLE_SCHEMA\tTABLE_NAME\tCOLUMN_NAME\tDATA_TYPE\tIS_NULLABLE\tCOLUMN_KEY\n\
+            shop\tcoupons\tcode\tvarchar\tNO\tPRI\n",
+        )))]);
+        let executed_queries = executor.get_query_list();
+        let mut data_type_info = data_type_info();
+        assert!(!data_type_info.has_table("shop", "coupons"));
+        data_type_info.merge(DataTypeInfo::load_table(&mut executor, "shop", "coupons").unwrap());
+        assert!(executed_queries.borrow()[0]
+            .contains("WHERE TABLE_SCHEMA = 'shop' AND TABLE_NAME = 'coupons'"));
+        assert!(data_type_info.has_table("shop", "coupons"));
+        assert_eq!(
+            "CREATE TABLE `coupons` (\n  `code` varchar NOT NULL,\n  PRIMARY KEY (`code`)\n)",
+            data_type_info.create_table("shop", "coupons").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_zerofill_columns_are_unsigned() {
+        let mut executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(
+            "TABLE_SCHEMA\tTABLE_NAME\tCOLUMN_NAME\tDATA_TYPE\tIS_NULLABLE\tCOLUMN_KEY\t\
+            CHARACTER_MAXIMUM_LENGTH\tNUMERIC_PRECISION\tNUMERIC_SCALE\tCOLUMN_TYPE\n\
+            shop\tcoupons\tuses\tint\tNO\t\t\t10\t0\tint(10) unsigned zerofill\n",
+        )))]);
+        let data_type_info = DataTypeInfo::load_table(&mut executor, "shop", "coupons").unwrap();
+        assert!(data_type_info.columns[0].3.attributes.unsigned);
+    }
+
+    #[test]
+    fn test_looked_up_tables_expire() {
+        let mut data_type_info = data_type_info();
+        let coupons = ("shop".to_string(), "coupons".to_string());
+        assert!(!data_type_info.was_looked_up(&coupons));
+        data_type_info.looked_up(coupons.clone());
+        assert!(data_type_info.was_looked_up(&coupons));
+
+        let expired = Instant::now()
+            .checked_sub(LOOKED_UP_TABLE_TTL + Duration::from_secs(1))
+            .unwrap();
+        data_type_info
+            .looked_up_tables
+            .insert(coupons.clone(), expired);
+        assert!(!data_type_info.was_looked_up(&coupons));
+    }
+
+    #[test]
+    fn test_use_of_unknown_schemas_is_rejected() {
+        let executor = FakeQueryExecutor::new(vec![
+            Ok(None),
+            Ok(Some(reader_result("SCHEMA_NAME\nreports\n"))),
+            Ok(Some(reader_result("SCHEMA_NAME\n"))),
+            Ok(None),
+        ]);
+        let executed_queries = executor.get_query_list();
+        let session_state = SessionState::default();
+        let mut query_data_type = QueryDataType::new(
+            executor,
+            SqlDialect::MySql,
+            Arc::new(RwLock::new(data_type_info())),
+            session_state.clone(),
+        );
+        query_data_type.query("use `SHOP`;").unwrap();
+        let error = query_data_type.query("use typo_schema").err().unwrap();
+        assert_eq!("Unknown database 'typo_schema'", error.to_string());
+        assert!(error.is::<UnknownDatabase>());
+        assert!(session_state.warnings().is_empty());
+        // Schemas without tables are used with a warning
+        query_data_type.query("use reports").unwrap();
+        assert_eq!(1, session_state.warnings().len());
+        assert_eq!(
+            vec![
+                "use `SHOP`;",
+                "SELECT SCHEMA_NAME FROM INFORMATION_SCHEMA.SCHEMATA WHERE SCHEMA_NAME = 'typo_schema'",
+                "SELECT SCHEMA_NAME FROM INFORMATION_SCHEMA.SCHEMATA WHERE SCHEMA_NAME = 'reports'",
+                "use reports",
+            ],
+            *executed_queries.borrow()
+        );
+    }
+
+    #[test]
+    fn test_use_of_schemas_with_quotes_is_escaped() {
+        let executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result("SCHEMA_NAME\n")))]);
+        let executed_queries = executor.get_query_list();
+        let mut query_data_type = QueryDataType::new(
+            executor,
+            SqlDialect::MySql,
+            Arc::new(RwLock::new(data_type_info())),
+            SessionState::default(),
+        );
+        let error = query_data_type
+            .query("use `x\\' OR 1=1 -- `")
+            .err()
+            .unwrap();
+        assert!(error.is::<UnknownDatabase>());
+        assert!(query_data_type
+            .query("use x\\' OR 1=1")
+            .err()
+            .unwrap()
+            .is::<SqlError>());
+        assert_eq!(
+            vec![
+                "SELECT SCHEMA_NAME FROM INFORMATION_SCHEMA.SCHEMATA WHERE SCHEMA_NAME = 'x\\\\'' OR 1=1 -- '",
+            ],
+            *executed_queries.borrow()
+        );
+        assert_eq!("'a\\b''c'", SqlDialect::Postgres.string_literal("a\\b'c"));
+    }
+
+    #[test]
+    fn test_queries_are_sent_as_written() {
+        let executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result("name\nAna\n")))]);
+        let executed_queries = executor.get_query_list();
+        let mut query_data_type = QueryDataType::new(
+            executor,
+            SqlDialect::MySql,
+            Arc::new(RwLock::new(data_type_info())),
+            SessionState::default(),
+        );
+        let query = "SELECT Name FROM users WHERE name = 'Ana'";
+        query_data_type.query(query).unwrap();
+        assert_eq!(vec![query.to_string()], *executed_queries.borrow());
+    }
+
+    #[test]
+    fn test_untyped_results_add_a_warning() {
+        let executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result("name\nAna\n")))]);
+        let session_state = SessionState::default();
+        let mut query_data_type = QueryDataType::new(
+            executor,
+            SqlDialect::MySql,
+            Arc::new(RwLock::new(data_type_info())),
+            session_state.clone(),
+        );
+        query_data_type
+            .query("select name from users where")
+            .unwrap();
+        let warnings = session_state.warnings();
+        assert_eq!(1, warnings.len());
+        assert!(warnings[0].message.contains("could not be parsed"));
+    }
+
+    #[test]
+    fn test_outer_joined_columns_can_be_null() {
+        let mut data_type_info = data_type_info();
+        for (_, table_name, column_name, column_type) in data_type_info.iter_mut() {
+            column_type.attributes.not_null = table_name == "orders" || column_name == "id";
+        }
+        let ast = Parser::parse_sql(
+            &MySqlDialect {},
+            "select users.id, orders.id, max(users.id) \
+            from users left join orders on users.id = orders.user_id",
+        )
+        .unwrap();
+        let not_null: Vec<bool> = data_type_info
+            .get_columns_types_from_ast("shop", ast)
+            .unwrap()
+            .into_iter()
+            .map(|(_, column_type)| column_type.attributes.not_null)
+            .collect();
+        assert_eq!(vec![true, false, false], not_null);
+    }
+
+    #[test]
+    fn test_create_table_with_lengths() {
+        let mut executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(
+            "TABLE_SCHEMA\tTABLE_NAME\tCOLUMN_NAME\tDATA_TYPE\tIS_NULLABLE\tCOLUMN_KEY\t\
+            CHARACTER_MAXIMUM_LENGTH\tNUMERIC_PRECISION\tNUMERIC_SCALE\n\
+            shop\tprices\tid\tbigint\tNO\tPRI\tNULL\t19\t0\n\
+            shop\tprices\tcurrency\tchar\tNO\t\t3\tNULL\tNULL\n\
+            shop\tprices\tamount\tdecimal\tYES\t\tNULL\t10\t2\n",
+        )))]);
+        let data_type_info = DataTypeInfo::load_table(&mut executor, "shop", "prices").unwrap();
+        assert_eq!(
+            "CREATE TABLE `prices` (\n  `id` bigint NOT NULL,\n  `currency` char(3) NOT NULL,\n  \
+            `amount` decimal(10,2),\n  PRIMARY KEY (`id`)\n)",
+            data_type_info.create_table("shop", "prices").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unsigned_columns_are_not_parsed_as_signed() {
+        let result = ResultWithCustomColumnTypes::new(
+            Some(reader_result("id\tflags\n18446744073709551615\t255\n")),
+            vec![
+                (
+                    "id".to_string(),
+                    ColumnType {
+                        data_type: Some("bigint".to_string()),
+                        attributes: ColumnAttributes {
+                            unsigned: true,
+                            ..ColumnAttributes::default()
+                        },
+                    },
+                ),
+                (
+                    "flags".to_string(),
+                    ColumnType {
+                        data_type: Some("tinyint".to_string()),
+                        attributes: ColumnAttributes {
+                            unsigned: true,
+                            ..ColumnAttributes::default()
+                        },
+                    },
+                ),
+            ],
+        );
+        let (columns, mut rows) = result.get_data();
+        assert!(columns.unwrap()[0].attributes.unsigned);
+        assert!(matches!(
+            rows.next().unwrap().unwrap().as_slice(),
+            [ColumnValue::U64(u64::MAX), ColumnValue::U8(255)]
+        ));
+    }
+
+    #[test]
+    fn test_results_with_other_columns_than_their_types_are_untyped() {
+        let session_state = SessionState::default();
+        let result = ResultWithCustomColumnTypes::new(
+            Some(reader_result("id\tnote\n1\ta\n")),
+            vec![(
+                "id".to_string(),
+                ColumnType {
+                    data_type: Some("bigint".to_string()),
+                    attributes: ColumnAttributes::default(),
+                },
+            )],
+        )
+        .with_session_state(session_state.clone());
+        let (columns, mut rows) = result.get_data();
+        assert!(columns.unwrap().iter().all(|column| column.ty.is_none()));
+        assert!(matches!(
+            rows.next().unwrap().unwrap().as_slice(),
+            [ColumnValue::String(_), ColumnValue::String(_)]
+        ));
+        assert_eq!(1, session_state.warnings().len());
+    }
+
+    #[test]
+    fn test_values_that_do_not_fit_their_type_are_answered_as_text() {
+        let session_state = SessionState::default();
+        let bigint = ColumnType {
+            data_type: Some("bigint".to_string()),
+            attributes: ColumnAttributes::default(),
+        };
+        let result = ResultWithCustomColumnTypes::new(
+            Some(reader_result("id\n1\nn/a\n")),
+            vec![("id".to_string(), bigint)],
+        )
+        .with_session_state(session_state.clone());
+        let rows: Vec<Row> = result.get_data().1.map(Result::unwrap).collect();
+        assert!(matches!(rows[0][0], ColumnValue::I64(1)));
+        assert!(matches!(&rows[1][0], ColumnValue::String(id) if id == "n/a"));
+        assert_eq!(1, session_state.warnings().len());
+    }
+
+    #[test]
+    fn test_json_documents_are_checked() {
+        let json = || ("settings".to_string(), ColumnType::of("json"));
+        let result = ResultWithCustomColumnTypes::new(
+            Some(reader_result(
+                "settings\n{\"b\": 1.10, \"a\": [1, 2]}\n{\"a\": [1,\n",
+            )),
+            vec![json()],
+        );
+        let mut rows = result.get_data().1;
+        assert!(matches!(
+            &rows.next().unwrap().unwrap()[0],
+            ColumnValue::Json(document) if document.get() == "{\"b\": 1.10, \"a\": [1, 2]}"
+        ));
+        let error = rows.next().unwrap().unwrap_err();
+        assert!(error.downcast_ref::<SqlError>().is_some());
+    }
+
+    #[test]
+    fn test_geometries_as_bytes_or_wkt() {
+        let point = "0x00000000010100000000000000000000000000000000000040";
+        let result = |geometry_format| {
+            let result = ResultWithCustomColumnTypes::new(
+                Some(reader_result(&format!("location\n{}\n", point))),
+                vec![("location".to_string(), ColumnType::of("point"))],
+            )
+            .with_geometry_format(geometry_format);
+            let (columns, rows) = result.get_data();
+            let row = rows.map(Result::unwrap).next().unwrap();
+            (
+                columns.unwrap().remove(0).ty,
+                row.into_iter().next().unwrap(),
+            )
+        };
+        let (ty, value) = result(GeometryFormat::Wkb);
+        assert_eq!(Some("point".to_string()), ty);
+        assert!(matches!(value, ColumnValue::Bytes(bytes) if bytes.len() == 25));
+        let (ty, value) = result(GeometryFormat::Wkt);
+        assert_eq!(Some("text".to_string()), ty);
+        assert!(matches!(value, ColumnValue::String(wkt) if wkt == "POINT(0 2)"));
+    }
+
+    #[test]
+    fn test_keys_of_columns() {
+        let mut executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(
+            "TABLE_SCHEMA\tTABLE_NAME\tCOLUMN_NAME\tDATA_TYPE\tIS_NULLABLE\tCOLUMN_KEY\n\
+            shop\tcoupons\tid\tbigint\tNO\tPRI\n\
+            shop\tcoupons\tcode\tvarchar\tNO\tUNI\n\
+            shop\tcoupons\tuser_id\tbigint\tYES\tMUL\n\
+            shop\tcoupons\tnote\ttext\tYES\t\n",
+        )))]);
+        let data_type_info = DataTypeInfo::load_table(&mut executor, "shop", "coupons").unwrap();
+        let keys: Vec<Option<ColumnKey>> = data_type_info
+            .iter()
+            .map(|(_, _, _, column_type)| column_type.attributes.key)
+            .collect();
+        assert_eq!(
+            vec![
+                Some(ColumnKey::Primary),
+                Some(ColumnKey::Unique),
+                Some(ColumnKey::Multiple),
+                None
+            ],
+            keys
+        );
+    }
+
+    #[test]
+    fn test_identifiers_are_matched_by_identifier_case() {
+        let query = "select U.Name, u.ID from Users u";
+        assert_eq!(
+            vec![typed("name", "varchar"), typed("id", "bigint")],
+            types_of(query)
+        );
+        let lowercase = data_type_info().with_identifier_case(IdentifierCase::Lowercase);
+        assert_eq!(
+            vec![typed("name", "varchar"), typed("id", "bigint")],
+            types_of_with(&lowercase, query)
+        );
+        assert_eq!(
+            vec![("Name".to_string(), ColumnType::default())],
+            types_of_with(&lowercase, "select `Name` from users")
+        );
+        let sensitive = data_type_info().with_identifier_case(IdentifierCase::Sensitive);
+        assert_eq!(
+            vec![
+                ("Name".to_string(), ColumnType::default()),
+                ("ID".to_string(), ColumnType::default())
+            ],
+            types_of_with(&sensitive, query)
+        );
+    }
+
+    #[test]
+    fn test_views_are_typed_from_their_definition() {
+        let mut data_type_info = data_type_info();
+        data_type_info.add_views(
+            vec![
+                (
+                    "shop".into(),
+                    "buyer_names".into(),
+                    "select `shop`.`buyers`.`name` AS `name` from `shop`.`buyers`".into(),
+                ),
+                (
+                    "shop".into(),
+                    "buyers".into(),
+                    "select `shop`.`users`.`id` AS `id`,`shop`.`users`.`name` AS `name` \
+                from `shop`.`users` join `shop`.`orders` \
+                on `shop`.`users`.`id` = `shop`.`orders`.`user_id`"
+                        .into(),
+                ),
+            ],
+            &MySqlDialect {},
+        );
+        assert_eq!(
+            vec![typed("id", "bigint"), typed("name", "varchar")],
+            types_of_with(&data_type_info, "select * from buyers")
+        );
+        assert_eq!(
+            vec![typed("name", "varchar")],
+            types_of_with(&data_type_info, "select * from buyer_names")
+        );
+    }
+
+    #[test]
+    fn test_describe_a_table() {
+        let mut data_type_info = data_type_info();
+        for (_, table_name, column_name, column_type) in data_type_info.iter_mut() {
+            if table_name == "users" && column_name == "id" {
+                column_type.attributes.not_null = true;
+                column_type.attributes.key = Some(ColumnKey::Primary);
+            }
+        }
+        for query in [
+            "describe users",
+            "desc shop.users;",
+            "show columns from `users`",
+            "show fields in users from shop",
+        ] {
+            let (columns, rows) = data_type_info.answer_show("shop", query).unwrap();
+            assert_eq!(6, columns.len());
+            assert_eq!(2, rows.len());
+            assert!(matches!(
+                rows[0].as_slice(),
+                [
+                    ColumnValue::String(field),
+                    ColumnValue::String(ty),
+                    ColumnValue::String(null),
+                    ColumnValue::String(key),
+                    ColumnValue::Null,
+                    _,
+                ] if field == "id" && ty == "bigint" && null == "NO" && key == "PRI"
+            ));
+        }
+        assert!(data_type_info
+            .answer_show("shop", "describe coupons")
+            .is_none());
+    }
+
+    #[test]
+    fn test_describe_defaults_and_extras() {
+        let mut executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(
+            "TABLE_SCHEMA\tTABLE_NAME\tCOLUMN_NAME\tDATA_TYPE\tIS_NULLABLE\tCOLUMN_KEY\t\
+            CHARACTER_MAXIMUM_LENGTH\tNUMERIC_PRECISION\tNUMERIC_SCALE\tCOLUMN_TYPE\t\
+            COLUMN_DEFAULT\tEXTRA\n\
+            shop\tcoupons\tid\tbigint\tNO\tPRI\tNULL\t19\t0\tbigint\tNULL\tauto_increment\n\
+            shop\tcoupons\tstatus\tvarchar\tNO\t\t10\tNULL\tNULL\tvarchar(10)\tactive\t\n",
+        )))]);
+        let data_type_info = DataTypeInfo::load_table(&mut executor, "shop", "coupons").unwrap();
+        let (_, rows) = data_type_info
+            .answer_show("shop", "describe coupons")
+            .unwrap();
+        assert!(matches!(
+            &rows[0][4..],
+            [ColumnValue::Null, ColumnValue::String(extra)] if extra == "auto_increment"
+        ));
+        assert!(matches!(
+            &rows[1][4..],
+            [ColumnValue::String(default), ColumnValue::String(extra)]
+                if default == "active" && extra.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_type_problems() {
+        assert_eq!(
+            vec![
+                "Could not find the type of column missing".to_string(),
+                "Type xml of column settings is not mapped".to_string()
+            ],
+            type_problems(&[
+                typed("id", "bigint"),
+                ("missing".to_string(), ColumnType::default()),
+                typed("settings", "xml"),
+                typed("token", "varbinary"),
+                typed("avatar", "longblob"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_uuid_values() {
+        let uuid = |value: &str| convert(value.to_string(), &ColumnType::of("uuid"));
+        assert!(matches!(
+            uuid("67E55044-10B1-426F-9247-BB680E5FE0C8"),
+            Ok(ColumnValue::Uuid(uuid)) if uuid.to_string() == "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        ));
+        assert!(uuid("67e55044").is_err());
+        assert!(matches!(
+            uuid("0x67E5504410B1426F9247BB680E5FE0C8"),
+            Ok(ColumnValue::Uuid(uuid)) if uuid.to_string() == "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        ));
+        assert!(uuid("0x67E55044").is_err());
+        let query_data_type = QueryDataType::new(
+            FakeQueryExecutor::new(vec![]),
+            SqlDialect::MySql,
+            Arc::new(RwLock::new(data_type_info())),
+            SessionState::default(),
+        )
+        .with_uuid_columns(vec!["ID".to_string()]);
+        let binary = |length| ColumnType {
+            data_type: Some("binary".to_string()),
+            attributes: ColumnAttributes {
+                length: Some(length),
+                ..ColumnAttributes::default()
+            },
+        };
+        let types: Vec<Option<String>> = query_data_type
+            .with_binary_uuids(vec![
+                ("id".to_string(), binary(16)),
+                ("id".to_string(), binary(8)),
+                ("hash".to_string(), binary(16)),
+            ])
+            .into_iter()
+            .map(|(_, column_type)| column_type.data_type)
+            .collect();
+        assert_eq!(
+            vec![
+                Some("uuid".to_string()),
+                Some("binary".to_string()),
+                Some("binary".to_string())
+            ],
+            types
+        );
+    }
+
+    #[test]
+    fn test_boolean_values() {
+        let boolean = |value: &str| convert(value.to_string(), &ColumnType::of("boolean"));
+        assert!(matches!(boolean("t"), Ok(ColumnValue::Bool(true))));
+        assert!(matches!(boolean("FALSE"), Ok(ColumnValue::Bool(false))));
+        assert!(matches!(boolean("1"), Ok(ColumnValue::Bool(true))));
+        assert!(boolean("maybe").is_err());
+    }
+
+    #[test]
+    fn test_decimal_values() {
+        let decimal = |value: &str| convert(value.to_string(), &ColumnType::of("decimal"));
+        assert!(
+            matches!(decimal("10.50"), Ok(ColumnValue::Decimal(d)) if d.to_string() == "10.50")
+        );
+        assert!(matches!(decimal("-0.001"), Ok(ColumnValue::Decimal(d)) if d.scale() == 3));
+        let long = "1234567890123456789012345678901234.5";
+        assert!(matches!(decimal(long), Ok(ColumnValue::String(value)) if value == long));
+        assert!(decimal("ten").is_err());
+    }
+
+    #[test]
+    fn test_time_values() {
+        let time = |data_type: &str, value: &str| {
+            let column_type = ColumnType {
+                data_type: Some(data_type.to_string()),
+                attributes: ColumnAttributes::default(),
+            };
+            convert(value.to_string(), &column_type)
+        };
+        assert!(
+            matches!(time("time", "12:30:05"), Ok(ColumnValue::Time(t)) if t.as_secs() == 45005)
+        );
+        assert!(matches!(
+            time("time", "838:59:59.5"),
+            Ok(ColumnValue::Time(t)) if t.as_secs() == 3020399 && t.subsec_millis() == 500
+        ));
+        assert!(matches!(
+            time("time", "-01:00:00"),
+            Ok(ColumnValue::String(_))
+        ));
+        assert!(matches!(
+            time("interval", "1 day 02:00:00"),
+            Ok(ColumnValue::String(_))
+        ));
+        assert!(time("time", "12:61:00").is_err());
+    }
+
+    #[test]
+    fn test_binary_values_are_decoded() {
+        assert_eq!(vec![0x00, 0x0a, 0xff], decode_binary("0x000aff"));
+        assert_eq!(vec![0x00, 0x0a, 0xff], decode_binary("\\x000AFF"));
+        assert_eq!(b"0xz1".to_vec(), decode_binary("0xz1"));
+        assert_eq!(b"raw".to_vec(), decode_binary("raw"));
+    }
+}
@@ -1,11 +1,74 @@
-use super::QueryExecutor;
+use super::cache_rules::query_pattern;
+use super::{AnsweredResult, Column, ColumnValue, Columns, QueryExecutor, Row};
 use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
 
-pub struct QueryFilter<T>(T);
+/// Query answered by the filter without running it
+#[derive(Deserialize, Clone)]
+pub struct FilterRule {
+    /// Exact query, or a pattern starting with `regex:` or `glob:`, like the entries of cache lists
+    #[serde(deserialize_with = "deserialize_pattern")]
+    query: Regex,
+    /// Names of the columns of the answer. Without columns, the query is answered with no result.
+    #[serde(default)]
+    columns: Vec<String>,
+    /// Values of the answer, with `null` for NULL
+    #[serde(default)]
+    rows: Vec<Vec<Option<String>>>,
+}
+
+fn deserialize_pattern<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    query_pattern(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+impl FilterRule {
+    fn answer(&self) -> Option<(Columns, Vec<Row>)> {
+        if self.columns.is_empty() {
+            return None;
+        }
+        let columns = self
+            .columns
+            .iter()
+            .map(|name| Column {
+                name: name.clone(),
+                ty: None,
+                attributes: Default::default(),
+            })
+            .collect();
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| match value {
+                        Some(value) => ColumnValue::String(value.clone()),
+                        None => ColumnValue::Null,
+                    })
+                    .collect()
+            })
+            .collect();
+        Some((columns, rows))
+    }
+}
+
+pub struct QueryFilter<T> {
+    executor: T,
+    rules: Vec<FilterRule>,
+}
 
 impl<T> QueryFilter<T> {
     pub fn new(executor: T) -> Self {
-        Self(executor)
+        Self {
+            executor,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Rules are checked in order, before the queries filtered by default
+    pub fn with_rules(mut self, rules: Vec<FilterRule>) -> Self {
+        self.rules = rules;
+        self
     }
 }
 
@@ -13,17 +76,36 @@ impl<T> QueryExecutor for QueryFilter<T>
 where
     T: QueryExecutor,
 {
-    type QueryResult = T::QueryResult;
+    type QueryResult = AnsweredResult<T::QueryResult>;
     fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
-        if query == "SHOW WARNINGS"
-            || query == "BEGIN"
-            || query == "COMMIT"
-            || query.trim().is_empty()
-        {
+        if let Some(rule) = self.rules.iter().find(|rule| rule.query.is_match(query)) {
+            println!("Query answered by a filter rule");
+            return Ok(rule
+                .answer()
+                .map(|(columns, rows)| AnsweredResult::Local(columns, rows)));
+        }
+        if query == "SHOW WARNINGS" {
+            // Warnings are answered by the backend. Clients that still send it to the database
+            // expect its columns.
+            let columns = [
+                ("Level", "varchar"),
+                ("Code", "int"),
+                ("Message", "varchar"),
+            ]
+            .into_iter()
+            .map(|(name, ty)| Column {
+                name: name.to_string(),
+                ty: Some(ty.to_string()),
+                attributes: Default::default(),
+            })
+            .collect();
+            return Ok(Some(AnsweredResult::Local(columns, Vec::new())));
+        }
+        if query.trim().is_empty() {
             return Ok(None);
         }
 
-        self.0.query(query)
+        Ok(self.executor.query(query)?.map(AnsweredResult::Executed))
     }
 }
 
@@ -31,6 +113,7 @@ where
 mod test {
     use super::*;
     use crate::query_executor::test::FakeQueryExecutor;
+    use crate::query_executor::QueryResult;
     #[test]
     fn test_filter() {
         let query_filter_data_provider = [
@@ -44,13 +127,43 @@ mod test {
             let executed_queries = fake_executor.get_query_list();
             let mut filter = QueryFilter::new(fake_executor);
             filter.query(query).unwrap();
+            let executed_queries = executed_queries.borrow();
             if should_filter {
-                assert_eq!(true, executed_queries.is_empty());
+                assert!(executed_queries.is_empty());
             } else {
-                // let executed_queries = dbg!(executed_queries);
                 assert_eq!(1, executed_queries.len());
                 assert_eq!(query, executed_queries[0]);
             }
         }
     }
+
+    #[test]
+    fn test_filter_rules_answer_canned_results() {
+        let rules: Vec<FilterRule> = serde_yaml::from_str(
+            "
+            - query: 'glob:SELECT @@session.tx_read_only*'
+              columns: ['@@session.tx_read_only']
+              rows: [['0']]
+            - query: ROLLBACK
+            ",
+        )
+        .unwrap();
+        let fake_executor = FakeQueryExecutor::new(vec![]);
+        let executed_queries = fake_executor.get_query_list();
+        let mut filter = QueryFilter::new(fake_executor).with_rules(rules);
+        let (columns, rows) = filter
+            .query("SELECT @@session.tx_read_only LIMIT 1")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        assert_eq!("@@session.tx_read_only", columns.unwrap()[0].name);
+        let rows: Vec<Row> = rows.map(Result::unwrap).collect();
+        assert_eq!(1, rows.len());
+        assert!(matches!(&rows[0][0], ColumnValue::String(value) if value == "0"));
+        assert!(filter.query("ROLLBACK").unwrap().is_none());
+        let (columns, rows) = filter.query("SHOW WARNINGS").unwrap().unwrap().get_data();
+        assert_eq!(3, columns.unwrap().len());
+        assert_eq!(0, rows.count());
+        assert!(executed_queries.borrow().is_empty());
+    }
 }
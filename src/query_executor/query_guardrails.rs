@@ -0,0 +1,429 @@
+use super::comments::{unwrap_versioned_comments, words};
+use super::mysql_syntax::parse_sql;
+use super::table_references::{join_condition, subqueries_in_expr, TableList};
+use super::{PreparedQuery, QueryExecutor, SqlDialect, SqlError};
+use anyhow::Result;
+use sqlparser::ast::{
+    Expr, ObjectName, Query, SelectItem, SetExpr, Statement, TableFactor, TableWithJoins,
+};
+
+/// Limits of the queries sent to the target. Queries over them are rejected.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Guardrails {
+    /// Characters of the query
+    pub max_query_length: Option<usize>,
+    /// Joins in the whole query, including the tables listed with commas and the subqueries
+    pub max_joins: Option<usize>,
+    /// Selects combined by `UNION`, `EXCEPT` or `INTERSECT`
+    pub max_union_branches: Option<usize>,
+}
+
+/// Rejects runaway queries, like accidental analytics queries, before they become tasks. It only
+/// wraps the queries of the clients, so the queries of the type discovery are not rejected.
+pub struct QueryGuardrails<T> {
+    executor: T,
+    dialect: SqlDialect,
+    guardrails: Guardrails,
+    /// Tables that can only be read with a `WHERE` or a `LIMIT`
+    require_where: TableList,
+}
+
+impl<T> QueryGuardrails<T> {
+    pub fn new(executor: T, dialect: SqlDialect, guardrails: Guardrails) -> Self {
+        Self {
+            executor,
+            dialect,
+            guardrails,
+            require_where: TableList::default(),
+        }
+    }
+
+    pub fn with_require_where(mut self, tables: Vec<String>) -> Self {
+        self.require_where = TableList::new(tables);
+        self
+    }
+
+    /// Queries that can not be parsed are only checked by their length, and rejected when they
+    /// mention a table of `require_where`. Statements are checked as the target runs them, with
+    /// the text of their versioned comments.
+    fn check(&self, query: &str) -> Result<(), SqlError> {
+        let length = query.chars().count();
+        check_limit(
+            "max_query_length",
+            "characters",
+            length,
+            self.guardrails.max_query_length,
+        )?;
+        if self.guardrails.max_joins.is_none()
+            && self.guardrails.max_union_branches.is_none()
+            && self.require_where.is_empty()
+        {
+            return Ok(());
+        }
+        let query = unwrap_versioned_comments(query, self.dialect);
+        let statements = match parse_sql(self.dialect.dialect().as_ref(), &query) {
+            // Text the parser skips could still be run by the target
+            Ok(statements) if !statements.is_empty() || query.trim_matches([';', ' ', '\n']).is_empty() => statements,
+            _ => {
+                return match words(&query.to_lowercase())
+                    .iter()
+                    .find_map(|parts| self.protected_parts(parts))
+                {
+                    Some(table) => Err(SqlError::new(format!(
+                        "Query rejected by the require_where guardrail. It can not be parsed and uses {table}"
+                    ))),
+                    None => Ok(()),
+                }
+            }
+        };
+        if let Some(table) = statements
+            .iter()
+            .find_map(|statement| self.full_scan(statement))
+        {
+            return Err(SqlError::new(format!(
+                "Query rejected by the require_where guardrail. It reads every row of {table}. Add a WHERE or a LIMIT"
+            )));
+        }
+        let mut complexity = Complexity::default();
+        for statement in &statements {
+            if let Statement::Query(query) = statement {
+                complexity.add_query(query);
+            }
+        }
+        check_limit(
+            "max_joins",
+            "joins",
+            complexity.joins,
+            self.guardrails.max_joins,
+        )?;
+        check_limit(
+            "max_union_branches",
+            "union branches",
+            complexity.union_branches,
+            self.guardrails.max_union_branches,
+        )
+    }
+
+    /// Protected table read, updated or deleted without a `WHERE`, or read without a `LIMIT`,
+    /// including in subqueries and in the selects of `INSERT` and `CREATE TABLE`
+    fn full_scan(&self, statement: &Statement) -> Option<String> {
+        match statement {
+            Statement::Query(query) => self.full_scan_in_query(query),
+            Statement::Insert { source, .. } => self.full_scan_in_query(source),
+            Statement::CreateTable {
+                query: Some(query), ..
+            } => self.full_scan_in_query(query),
+            Statement::Update {
+                table, selection, ..
+            } => match (&table.relation, selection) {
+                (TableFactor::Table { name, .. }, None) => self.protected(name),
+                (_, Some(selection)) => self.full_scan_in_expr(selection),
+                _ => None,
+            },
+            Statement::Delete {
+                table_name,
+                selection,
+                ..
+            } => match selection {
+                Some(selection) => self.full_scan_in_expr(selection),
+                None => self.protected(table_name),
+            },
+            _ => None,
+        }
+    }
+
+    fn full_scan_in_query(&self, query: &Query) -> Option<String> {
+        let ctes = query.with.iter().flat_map(|with| &with.cte_tables);
+        ctes.map(|cte| self.full_scan_in_query(&cte.query))
+            .find(Option::is_some)
+            .flatten()
+            .or_else(|| self.full_scan_in_set_expr(&query.body, query.limit.is_some()))
+    }
+
+    fn full_scan_in_set_expr(&self, set_expr: &SetExpr, limited: bool) -> Option<String> {
+        match set_expr {
+            SetExpr::Select(select) => {
+                let unfiltered = !limited && select.selection.is_none();
+                let projection = select.projection.iter().filter_map(|item| match item {
+                    SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                        Some(expr)
+                    }
+                    _ => None,
+                });
+                select
+                    .from
+                    .iter()
+                    .find_map(|table_with_joins| {
+                        self.full_scan_in_table_with_joins(table_with_joins, unfiltered)
+                    })
+                    .or_else(|| {
+                        projection
+                            .chain(&select.selection)
+                            .chain(&select.group_by)
+                            .chain(&select.having)
+                            .find_map(|expr| self.full_scan_in_expr(expr))
+                    })
+            }
+            SetExpr::Query(query) => self.full_scan_in_query(query),
+            SetExpr::SetOperation { left, right, .. } => self
+                .full_scan_in_set_expr(left, limited)
+                .or_else(|| self.full_scan_in_set_expr(right, limited)),
+            _ => None,
+        }
+    }
+
+    fn full_scan_in_table_with_joins(
+        &self,
+        table_with_joins: &TableWithJoins,
+        unfiltered: bool,
+    ) -> Option<String> {
+        std::iter::once(&table_with_joins.relation)
+            .chain(table_with_joins.joins.iter().map(|join| &join.relation))
+            .find_map(|relation| match relation {
+                TableFactor::Table { name, .. } if unfiltered => self.protected(name),
+                TableFactor::Derived { subquery, .. } => self.full_scan_in_query(subquery),
+                TableFactor::NestedJoin(table_with_joins) => {
+                    self.full_scan_in_table_with_joins(table_with_joins, unfiltered)
+                }
+                _ => None,
+            })
+            .or_else(|| {
+                table_with_joins
+                    .joins
+                    .iter()
+                    .filter_map(|join| join_condition(&join.join_operator))
+                    .find_map(|condition| self.full_scan_in_expr(condition))
+            })
+    }
+
+    /// Full scan in the subqueries of the expression, like the ones of `IN` or `EXISTS`
+    fn full_scan_in_expr(&self, expr: &Expr) -> Option<String> {
+        let mut subqueries = Vec::new();
+        subqueries_in_expr(expr, &mut subqueries);
+        subqueries
+            .into_iter()
+            .find_map(|subquery| self.full_scan_in_query(subquery))
+    }
+
+    /// The name of the table when it is protected. Tables without a schema could be any of them.
+    fn protected(&self, name: &ObjectName) -> Option<String> {
+        let parts: Vec<String> = name
+            .0
+            .iter()
+            .map(|ident| ident.value.to_lowercase())
+            .collect();
+        self.protected_parts(&parts)
+    }
+
+    /// Same as `protected`, with the lowercase parts of the name
+    fn protected_parts(&self, parts: &[String]) -> Option<String> {
+        let (schema, table) = match parts {
+            [.., schema, table] => (Some(schema), table),
+            [table] => (None, table),
+            [] => return None,
+        };
+        self.require_where
+            .contains(schema.map(String::as_str), table)
+            .then(|| parts.join("."))
+    }
+}
+
+fn check_limit(
+    guardrail: &str,
+    what: &str,
+    value: usize,
+    limit: Option<usize>,
+) -> Result<(), SqlError> {
+    match limit {
+        Some(limit) if value > limit => Err(SqlError::new(format!(
+            "Query rejected by the {guardrail} guardrail. It has {value} {what} and the limit is {limit}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default)]
+struct Complexity {
+    joins: usize,
+    /// Branches of the biggest set operation
+    union_branches: usize,
+}
+
+impl Complexity {
+    fn add_query(&mut self, query: &Query) {
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                self.add_query(&cte.query);
+            }
+        }
+        let branches = self.add_set_expr(&query.body);
+        self.union_branches = self.union_branches.max(branches);
+        for order_by in &query.order_by {
+            self.add_expr(&order_by.expr);
+        }
+    }
+
+    /// Counts the selects of the set expression, and adds their joins
+    fn add_set_expr(&mut self, set_expr: &SetExpr) -> usize {
+        match set_expr {
+            SetExpr::Select(select) => {
+                self.joins += select.from.len().saturating_sub(1);
+                for table_with_joins in &select.from {
+                    self.add_table_with_joins(table_with_joins);
+                }
+                for projection in &select.projection {
+                    if let SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } =
+                        projection
+                    {
+                        self.add_expr(expr);
+                    }
+                }
+                for expr in select
+                    .selection
+                    .iter()
+                    .chain(&select.group_by)
+                    .chain(&select.having)
+                {
+                    self.add_expr(expr);
+                }
+                1
+            }
+            SetExpr::Query(query) => {
+                self.add_query(query);
+                1
+            }
+            SetExpr::SetOperation { left, right, .. } => {
+                self.add_set_expr(left) + self.add_set_expr(right)
+            }
+            _ => 1,
+        }
+    }
+
+    fn add_table_with_joins(&mut self, table_with_joins: &TableWithJoins) {
+        self.joins += table_with_joins.joins.len();
+        self.add_table_factor(&table_with_joins.relation);
+        for join in &table_with_joins.joins {
+            self.add_table_factor(&join.relation);
+            if let Some(condition) = join_condition(&join.join_operator) {
+                self.add_expr(condition);
+            }
+        }
+    }
+
+    fn add_table_factor(&mut self, table_factor: &TableFactor) {
+        match table_factor {
+            TableFactor::Derived { subquery, .. } => self.add_query(subquery),
+            TableFactor::NestedJoin(table_with_joins) => {
+                self.add_table_with_joins(table_with_joins)
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds the subqueries of the expression, like the ones of `EXISTS` or function arguments
+    fn add_expr(&mut self, expr: &Expr) {
+        let mut subqueries = Vec::new();
+        subqueries_in_expr(expr, &mut subqueries);
+        for subquery in subqueries {
+            self.add_query(subquery);
+        }
+    }
+}
+
+impl<T> QueryExecutor for QueryGuardrails<T>
+where
+    T: QueryExecutor,
+{
+    type QueryResult = T::QueryResult;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        self.check(query)?;
+        self.executor.query(query)
+    }
+
+    fn query_prepared(&mut self, prepared: &PreparedQuery) -> Result<Option<Self::QueryResult>> {
+        self.check(&prepared.query)?;
+        self.executor.query_prepared(prepared)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::FakeQueryExecutor;
+
+    #[test]
+    fn test_queries_over_the_guardrails_are_rejected() {
+        let executor = FakeQueryExecutor::new(vec![Ok(None)]);
+        let executed_queries = executor.get_query_list();
+        let mut guardrails = QueryGuardrails::new(
+            executor,
+            SqlDialect::MySql,
+            Guardrails {
+                max_query_length: Some(120),
+                max_joins: Some(2),
+                max_union_branches: Some(2),
+            },
+        );
+        let long_query = format!("select '{}'", "a".repeat(120));
+        let rejected_data_provider = [
+            (
+                "select * from a join b on a.id = b.id, c where a.id in (select id from d join e on d.id = e.id)",
+                "max_joins",
+            ),
+            (
+                "select * from a where exists (select 1 from b join c on b.id = c.id join d on c.id = d.id join e on d.id = e.id)",
+                "max_joins",
+            ),
+            (
+                "select a.id, count(*) from a group by a.id having count(*) > coalesce((select 1 union select 2 union select 3), 0)",
+                "max_union_branches",
+            ),
+            (
+                "select 1 union select 2 union all select 3",
+                "max_union_branches",
+            ),
+            (long_query.as_str(), "max_query_length"),
+        ];
+        for (query, guardrail) in rejected_data_provider {
+            let error = guardrails.query(query).err().unwrap();
+            assert!(error.to_string().contains(guardrail), "{}", error);
+        }
+        guardrails
+            .query("select * from a join b on a.id = b.id union select * from c")
+            .unwrap();
+        assert_eq!(1, executed_queries.borrow().len());
+    }
+
+    #[test]
+    fn test_protected_tables_are_not_read_without_where_or_limit() {
+        let executor = FakeQueryExecutor::new(vec![Ok(None), Ok(None), Ok(None)]);
+        let executed_queries = executor.get_query_list();
+        let mut guardrails =
+            QueryGuardrails::new(executor, SqlDialect::MySql, Guardrails::default())
+                .with_require_where(vec!["bigschema.events".to_string()]);
+        for query in [
+            "select * from bigschema.events",
+            "select * from users u join events e on u.id = e.user_id",
+            "select * from (select * from events) e where e.id = 1",
+            "update bigschema.events set seen = 1",
+            "delete from events",
+            "select * from users where id in (select user_id from events)",
+            "select * from users u where exists (select 1 from bigschema.events)",
+            "delete from users where id in (select user_id from events)",
+            "insert into archive select * from events",
+            "create table archive as select * from events",
+            "select * from events where 'no' = 'parse",
+            "/*!50000 select * from bigschema.events */",
+        ] {
+            let error = guardrails.query(query).err().unwrap();
+            assert!(error.to_string().contains("require_where"), "{}", query);
+        }
+        guardrails
+            .query("select * from bigschema.events where id = 1")
+            .unwrap();
+        guardrails.query("select * from events limit 10").unwrap();
+        guardrails.query("delete from other.events").unwrap();
+        assert_eq!(3, executed_queries.borrow().len());
+    }
+}
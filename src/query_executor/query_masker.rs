@@ -0,0 +1,547 @@
+use super::comments::{unwrap_versioned_comments, words};
+use super::mysql_syntax::parse_sql;
+use super::table_references::referenced_tables;
+use super::{ColumnValue, Columns, QueryExecutor, QueryResult, Rows, SqlDialect, SqlError};
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlparser::ast::{
+    ObjectName, Query, Select, SelectItem, SetExpr, Statement, TableFactor, TableWithJoins,
+};
+use std::collections::HashMap;
+
+/// How the values of a masked column are answered. NULL values are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Masking {
+    /// HMAC-SHA256 of the value with the masking secret, in hex, so equal values can still be
+    /// compared but not be found by hashing guesses
+    Hash,
+    /// `[REDACTED]`
+    Redact,
+    /// The last 4 characters, with the others replaced by `*`
+    Last4,
+}
+
+impl Masking {
+    fn mask(&self, value: ColumnValue, secret: &[u8]) -> ColumnValue {
+        let text = match value {
+            ColumnValue::Null => return ColumnValue::Null,
+            value => text(value),
+        };
+        ColumnValue::String(match self {
+            Masking::Hash => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+                mac.update(text.as_bytes());
+                format!("{:x}", mac.finalize().into_bytes())
+            }
+            Masking::Redact => "[REDACTED]".to_string(),
+            Masking::Last4 => {
+                let length = text.chars().count();
+                // Values this short would be shown whole
+                let shown = if length > 4 { 4 } else { 0 };
+                "*".repeat(length - shown) + &text.chars().skip(length - shown).collect::<String>()
+            }
+        })
+    }
+}
+
+fn text(value: ColumnValue) -> String {
+    match value {
+        ColumnValue::Null => String::new(),
+        ColumnValue::String(value) => value,
+        ColumnValue::Bool(value) => (value as u8).to_string(),
+        ColumnValue::I64(value) => value.to_string(),
+        ColumnValue::I32(value) => value.to_string(),
+        ColumnValue::I16(value) => value.to_string(),
+        ColumnValue::I8(value) => value.to_string(),
+        ColumnValue::U64(value) => value.to_string(),
+        ColumnValue::U32(value) => value.to_string(),
+        ColumnValue::U16(value) => value.to_string(),
+        ColumnValue::U8(value) => value.to_string(),
+        ColumnValue::Double(value) => value.to_string(),
+        ColumnValue::Float(value) => value.to_string(),
+        ColumnValue::Decimal(value) => value.to_string(),
+        ColumnValue::DateTime(value) => value.to_string(),
+        ColumnValue::Date(value) => value.to_string(),
+        ColumnValue::Json(value) => value.get().to_string(),
+        ColumnValue::Uuid(value) => value.to_string(),
+        ColumnValue::Time(value) => value.as_secs().to_string(),
+        ColumnValue::Bytes(value) => String::from_utf8_lossy(&value).into_owned(),
+    }
+}
+
+/// Column masked by a rule, written as `schema.table.column`, `table.column` or `column`
+struct MaskedColumn {
+    schema: Option<String>,
+    table: Option<String>,
+    column: String,
+    masking: Masking,
+}
+
+impl MaskedColumn {
+    fn new(name: &str, masking: Masking) -> Self {
+        let mut parts = name.rsplitn(3, '.').map(|part| part.trim().to_lowercase());
+        Self {
+            column: parts.next().unwrap_or_default(),
+            table: parts.next(),
+            schema: parts.next(),
+            masking,
+        }
+    }
+
+    /// Tables of the query without a schema can be of any schema
+    fn is_read_by(&self, tables: &[ObjectName]) -> bool {
+        let table = match &self.table {
+            Some(table) => table,
+            None => return true,
+        };
+        tables.iter().any(|name| {
+            let parts: Vec<String> = name
+                .0
+                .iter()
+                .map(|ident| ident.value.to_lowercase())
+                .collect();
+            match parts.as_slice() {
+                [.., schema, name] => {
+                    name == table && self.schema.as_ref().map_or(true, |mask| mask == schema)
+                }
+                [name] => name == table,
+                [] => false,
+            }
+        })
+    }
+}
+
+/// Masks the values of configured columns in the results, so their raw values never leave the
+/// proxy. Columns are found by their names in the query: a result column is masked when its
+/// expression uses a masked column, or when it has the name of a masked column or of an alias of
+/// one, even if the query reads other tables, since they can be filled with masked values. Queries
+/// that can not be parsed are masked by the names of the result columns alone.
+pub struct QueryMasker<T> {
+    executor: T,
+    dialect: SqlDialect,
+    masked_columns: Vec<MaskedColumn>,
+    /// Key of the hashes of `Masking::Hash`
+    secret: Vec<u8>,
+}
+
+impl<T> QueryMasker<T> {
+    pub fn new(executor: T, dialect: SqlDialect, masked_columns: HashMap<String, Masking>) -> Self {
+        Self {
+            executor,
+            dialect,
+            masked_columns: masked_columns
+                .iter()
+                .map(|(name, masking)| MaskedColumn::new(name, *masking))
+                .collect(),
+            secret: Vec::new(),
+        }
+    }
+
+    pub fn with_secret(mut self, secret: String) -> Self {
+        self.secret = secret.into_bytes();
+        self
+    }
+
+    /// Variables set from a subquery would hold the values of masked columns under another name,
+    /// so they can not be set from subqueries that read the table of a masked column
+    fn check(&self, query: &str) -> Result<(), SqlError> {
+        let words = words(&unwrap_versioned_comments(query, self.dialect).to_lowercase());
+        if words.first().map_or(true, |word| word != &["set"])
+            || !words.iter().any(|word| word == &["select"])
+        {
+            return Ok(());
+        }
+        let masked = self.masked_columns.iter().find(|masked| {
+            let name = masked.table.as_ref().unwrap_or(&masked.column);
+            words.iter().any(|word| word.contains(name))
+        });
+        match masked {
+            Some(masked) => Err(SqlError::new(format!(
+                "Variables can not be set from queries reading the masked column {}",
+                masked.column
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Maskings of the result columns of the query, or `None` when there are no masked columns
+    fn maskings(&self, query: &str) -> Option<ResultMaskings> {
+        if self.masked_columns.is_empty() {
+            return None;
+        }
+        // Columns of versioned comments, like `/*!50000 email */`, are read by the target
+        let query = unwrap_versioned_comments(query, self.dialect);
+        let statement = match parse_sql(self.dialect.dialect().as_ref(), &query) {
+            Ok(mut statements) if statements.len() == 1 => Some(statements.remove(0)),
+            _ => None,
+        };
+        let tables = statement.as_ref().map(referenced_tables);
+        // Lowercase names of masked columns and of their aliases
+        let mut names: HashMap<String, Masking> = self
+            .masked_columns
+            .iter()
+            .filter(|masked| {
+                tables
+                    .as_ref()
+                    .map_or(true, |tables| masked.is_read_by(tables))
+            })
+            .map(|masked| (masked.column.clone(), masked.masking))
+            .collect();
+        let query = match &statement {
+            Some(Statement::Query(query)) => Some(query),
+            _ => None,
+        };
+        let mut selects = Vec::new();
+        if let Some(query) = query {
+            selects_in_query(query, &mut selects);
+        }
+        // Aliases of aliases are found by going over the selects again
+        loop {
+            let aliases: Vec<(String, Masking)> = selects
+                .iter()
+                .flat_map(|select| &select.projection)
+                .filter_map(|item| match item {
+                    SelectItem::ExprWithAlias { expr, alias } => {
+                        let alias = alias.value.to_lowercase();
+                        let masking = masking_of(&expr.to_string(), &names)?;
+                        (!names.contains_key(&alias)).then_some((alias, masking))
+                    }
+                    _ => None,
+                })
+                .collect();
+            if aliases.is_empty() {
+                break;
+            }
+            names.extend(aliases);
+        }
+        let items = query
+            .and_then(|query| projection_items(&query.body))
+            .map(|items| {
+                items
+                    .iter()
+                    .map(|branches| {
+                        branches
+                            .iter()
+                            .find_map(|item| masking_of(&item.to_string(), &names))
+                    })
+                    .collect()
+            });
+        // Result columns named like any masked column are masked by their name
+        let names = self
+            .masked_columns
+            .iter()
+            .map(|masked| (masked.column.clone(), masked.masking))
+            .chain(names)
+            .collect();
+        Some(ResultMaskings {
+            names,
+            items,
+            secret: self.secret.clone(),
+        })
+    }
+}
+
+/// Maskings of the result columns of a query, applied to each of its result sets
+pub struct ResultMaskings {
+    /// Lowercase names of masked columns and of their aliases
+    names: HashMap<String, Masking>,
+    /// Masking of each result column, from the projection of the query
+    items: Option<Vec<Option<Masking>>>,
+    secret: Vec<u8>,
+}
+
+impl ResultMaskings {
+    fn of(&self, columns: &Columns) -> Vec<Option<Masking>> {
+        let items = self
+            .items
+            .as_ref()
+            .filter(|items| items.len() == columns.len());
+        columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                items
+                    .and_then(|items| items[index])
+                    .or_else(|| self.names.get(&column.name.to_lowercase()).copied())
+            })
+            .collect()
+    }
+
+    fn mask(&self, columns: Result<Columns>, rows: Rows) -> (Result<Columns>, Rows) {
+        let mut columns = match columns {
+            Ok(columns) => columns,
+            columns => return (columns, rows),
+        };
+        let maskings = self.of(&columns);
+        if maskings.iter().all(Option::is_none) {
+            return (Ok(columns), rows);
+        }
+        println!("Masking columns of the result: {:?}", maskings);
+        for (column, _) in columns
+            .iter_mut()
+            .zip(&maskings)
+            .filter(|(_, masking)| masking.is_some())
+        {
+            column.ty = Some("text".to_string());
+            column.attributes = Default::default();
+        }
+        let secret = self.secret.clone();
+        let rows = rows.map(move |row| {
+            row.map(|row| {
+                row.into_iter()
+                    .enumerate()
+                    .map(
+                        |(index, value)| match maskings.get(index).copied().flatten() {
+                            Some(masking) => masking.mask(value, &secret),
+                            None => value,
+                        },
+                    )
+                    .collect()
+            })
+        });
+        (Ok(columns), Box::new(rows))
+    }
+}
+
+/// Masking of the first masked name used by the expression
+fn masking_of(expr: &str, names: &HashMap<String, Masking>) -> Option<Masking> {
+    expr.to_lowercase()
+        .split(|character: char| !(character.is_alphanumeric() || character == '_'))
+        .find_map(|word| names.get(word).copied())
+}
+
+/// Every select of the query, including the ones of CTEs and tables read from subqueries
+fn selects_in_query<'a>(query: &'a Query, selects: &mut Vec<&'a Select>) {
+    if let Some(with) = &query.with {
+        for cte in &with.cte_tables {
+            selects_in_query(&cte.query, selects);
+        }
+    }
+    selects_in_set_expr(&query.body, selects);
+}
+
+fn selects_in_set_expr<'a>(set_expr: &'a SetExpr, selects: &mut Vec<&'a Select>) {
+    match set_expr {
+        SetExpr::Select(select) => {
+            selects.push(select);
+            for table_with_joins in &select.from {
+                selects_in_table_with_joins(table_with_joins, selects);
+            }
+        }
+        SetExpr::Query(query) => selects_in_query(query, selects),
+        SetExpr::SetOperation { left, right, .. } => {
+            selects_in_set_expr(left, selects);
+            selects_in_set_expr(right, selects);
+        }
+        _ => {}
+    }
+}
+
+fn selects_in_table_with_joins<'a>(
+    table_with_joins: &'a TableWithJoins,
+    selects: &mut Vec<&'a Select>,
+) {
+    for relation in std::iter::once(&table_with_joins.relation)
+        .chain(table_with_joins.joins.iter().map(|join| &join.relation))
+    {
+        match relation {
+            TableFactor::Derived { subquery, .. } => selects_in_query(subquery, selects),
+            TableFactor::NestedJoin(table_with_joins) => {
+                selects_in_table_with_joins(table_with_joins, selects)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Items of each result column, from every select of a union, or `None` with wildcards
+fn projection_items(set_expr: &SetExpr) -> Option<Vec<Vec<&SelectItem>>> {
+    match set_expr {
+        SetExpr::Select(select) => select
+            .projection
+            .iter()
+            .map(|item| match item {
+                SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => None,
+                item => Some(vec![item]),
+            })
+            .collect(),
+        SetExpr::Query(query) => projection_items(&query.body),
+        SetExpr::SetOperation { left, right, .. } => {
+            let mut items = projection_items(left)?;
+            let right = projection_items(right)?;
+            if items.len() != right.len() {
+                return None;
+            }
+            for (items, right) in items.iter_mut().zip(right) {
+                items.extend(right);
+            }
+            Some(items)
+        }
+        _ => None,
+    }
+}
+
+impl<T, R> QueryExecutor for QueryMasker<T>
+where
+    T: QueryExecutor<QueryResult = R>,
+    R: QueryResult,
+{
+    type QueryResult = MaskedResult<R>;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        self.check(query)?;
+        let result = match self.executor.query(query)? {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+        Ok(Some(match self.maskings(query) {
+            Some(maskings) => MaskedResult::Masked { result, maskings },
+            None => MaskedResult::Unmasked(result),
+        }))
+    }
+}
+
+pub enum MaskedResult<R> {
+    /// Results of queries that read no masked column, which are answered as they are
+    Unmasked(R),
+    Masked {
+        result: R,
+        maskings: ResultMaskings,
+    },
+}
+
+impl<R: QueryResult> QueryResult for MaskedResult<R> {
+    fn get_data(self) -> (Result<Columns>, Rows) {
+        match self {
+            MaskedResult::Unmasked(result) => result.get_data(),
+            MaskedResult::Masked { result, maskings } => {
+                let (columns, rows) = result.get_data();
+                maskings.mask(columns, rows)
+            }
+        }
+    }
+
+    fn result_sets(self) -> Vec<(Result<Columns>, Rows)> {
+        match self {
+            MaskedResult::Unmasked(result) => result.result_sets(),
+            MaskedResult::Masked { result, maskings } => result
+                .result_sets()
+                .into_iter()
+                .map(|(columns, rows)| maskings.mask(columns, rows))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::{reader_result, FakeQueryExecutor};
+
+    fn masker(result: &str) -> QueryMasker<FakeQueryExecutor> {
+        let executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(result)))]);
+        QueryMasker::new(
+            executor,
+            SqlDialect::MySql,
+            HashMap::from([
+                ("app.users.email".to_string(), Masking::Hash),
+                ("users.ssn".to_string(), Masking::Last4),
+                ("card".to_string(), Masking::Redact),
+            ]),
+        )
+        .with_secret("secret".to_string())
+    }
+
+    fn strings(rows: Rows) -> Vec<Vec<String>> {
+        rows.map(|row| {
+            row.unwrap()
+                .into_iter()
+                .map(|value| match value {
+                    ColumnValue::String(value) => value,
+                    _ => "NULL".to_string(),
+                })
+                .collect()
+        })
+        .collect()
+    }
+
+    fn masked(query: &str, result: &str) -> Vec<Vec<String>> {
+        let (_, rows) = masker(result).query(query).unwrap().unwrap().get_data();
+        strings(rows)
+    }
+
+    #[test]
+    fn test_masked_columns_are_masked() {
+        assert_eq!(
+            vec![vec![
+                "1".to_string(),
+                "*******6789".to_string(),
+                "49e43229ee99dca2565241719b8341b04e71dd4de0628f991b5bea30a526e153".to_string(),
+                "[REDACTED]".to_string(),
+            ]],
+            masked(
+                "select u.id, ssn as s, lower(u.email), card from app.users u",
+                "id\ts\tlower(u.email)\tcard\n1\t123-45-6789\ttest@example.com\t4111\n",
+            )
+        );
+        assert_eq!(
+            vec![vec!["1".to_string(), "*******6789".to_string()]],
+            masked(
+                "select * from (select id, ssn as s from users) t",
+                "id\ts\n1\t123-45-6789\n",
+            )
+        );
+        assert_eq!(
+            vec![vec![
+                "test@example.com".to_string(),
+                "49e43229ee99dca2565241719b8341b04e71dd4de0628f991b5bea30a526e153".to_string(),
+            ]],
+            masked(
+                "select email as address, email from other.users",
+                "address\temail\ntest@example.com\ttest@example.com\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_variables_can_not_be_set_from_masked_tables() {
+        let mut masker = masker("@x\n1\n");
+        assert!(masker
+            .query("SET @x = (SELECT email FROM app.users LIMIT 1)")
+            .is_err());
+        assert!(masker
+            .query("SET @x = (/*!50000 SELECT email FROM app.users */ LIMIT 1)")
+            .is_err());
+        assert!(masker.query("SET @x = (SELECT id FROM orders)").is_ok());
+    }
+
+    #[test]
+    fn test_every_result_set_is_masked() {
+        let result = masker("ssn\n123-45-6789\n\ncard\n4111\n")
+            .query("select ssn from users; select card from users")
+            .unwrap()
+            .unwrap();
+        let rows: Vec<Vec<Vec<String>>> = result
+            .result_sets()
+            .into_iter()
+            .map(|(_, rows)| strings(rows))
+            .collect();
+        assert_eq!(
+            vec![
+                vec![vec!["*******6789".to_string()]],
+                vec![vec!["[REDACTED]".to_string()]],
+            ],
+            rows
+        );
+        let executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result("id\n1\n")))]);
+        let mut masker = QueryMasker::new(executor, SqlDialect::MySql, HashMap::new());
+        assert!(matches!(
+            masker.query("select id from users").unwrap(),
+            Some(MaskedResult::Unmasked(_))
+        ));
+    }
+}
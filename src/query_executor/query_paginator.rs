@@ -0,0 +1,226 @@
+use super::log_redaction::loggable;
+use super::{Columns, QueryExecutor, QueryResult, Row, Rows};
+use anyhow::Result;
+
+/// Runs selects in pages of `page_size` rows, for targets that truncate long outputs. The pages
+/// are run one after the other while the rows are read, and answered as one result.
+pub struct QueryPaginator<T> {
+    executor: T,
+    page_size: usize,
+}
+
+impl<T> QueryPaginator<T> {
+    /// Without a page size, queries are run as they are
+    pub fn new(executor: T, page_size: usize) -> Self {
+        Self {
+            executor,
+            page_size,
+        }
+    }
+}
+
+impl<T, R> QueryExecutor for QueryPaginator<T>
+where
+    T: QueryExecutor<QueryResult = R> + Clone + Send + 'static,
+    R: QueryResult,
+{
+    type QueryResult = PaginatedResult<T, R>;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        if self.page_size == 0 || !is_pageable(query) {
+            return Ok(self.executor.query(query)?.map(|result| PaginatedResult {
+                result,
+                pages: None,
+            }));
+        }
+        let query = query.trim().trim_end_matches(';').to_string();
+        let result = match self.executor.query(&page(&query, self.page_size, 0))? {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+        Ok(Some(PaginatedResult {
+            result,
+            pages: Some(Pages {
+                executor: self.executor.clone(),
+                query,
+                page_size: self.page_size,
+                offset: 0,
+                rows: Box::new(std::iter::empty()),
+                rows_in_page: 0,
+                finished: false,
+            }),
+        }))
+    }
+}
+
+pub struct PaginatedResult<T, R> {
+    /// First page, or the whole result when it is not paginated
+    result: R,
+    pages: Option<Pages<T>>,
+}
+
+impl<T, R> QueryResult for PaginatedResult<T, R>
+where
+    T: QueryExecutor<QueryResult = R> + Send + 'static,
+    R: QueryResult,
+{
+    fn get_data(self) -> (Result<Columns>, Rows) {
+        let (columns, rows) = self.result.get_data();
+        match self.pages {
+            Some(pages) => (columns, Box::new(Pages { rows, ..pages })),
+            None => (columns, rows),
+        }
+    }
+
+    fn result_sets(self) -> Vec<(Result<Columns>, Rows)> {
+        match self.pages {
+            Some(_) => vec![self.get_data()],
+            None => self.result.result_sets(),
+        }
+    }
+}
+
+struct Pages<T> {
+    executor: T,
+    query: String,
+    page_size: usize,
+    offset: usize,
+    rows: Rows,
+    rows_in_page: usize,
+    finished: bool,
+}
+
+impl<T, R> Iterator for Pages<T>
+where
+    T: QueryExecutor<QueryResult = R>,
+    R: QueryResult,
+{
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.rows.next() {
+                Some(Ok(row)) => {
+                    self.rows_in_page += 1;
+                    return Some(Ok(row));
+                }
+                Some(Err(error)) => {
+                    self.finished = true;
+                    return Some(Err(error));
+                }
+                // A page with less rows than the page size is the last one
+                None if self.finished || self.rows_in_page < self.page_size => return None,
+                None => {
+                    self.offset += self.page_size;
+                    println!(
+                        "Running the page at offset {} of {}",
+                        self.offset,
+                        loggable(&self.query)
+                    );
+                    match self
+                        .executor
+                        .query(&page(&self.query, self.page_size, self.offset))
+                    {
+                        Ok(Some(result)) => {
+                            self.rows = result.get_data().1;
+                            self.rows_in_page = 0;
+                        }
+                        Ok(None) => return None,
+                        Err(error) => {
+                            self.finished = true;
+                            return Some(Err(error));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Single selects that do not choose their own rows. Selects without `ORDER BY` are not, since
+/// their pages could overlap or miss rows, and neither are selects with locks, like `FOR SHARE`,
+/// that must end the query.
+fn is_pageable(query: &str) -> bool {
+    let lower_case_query = query.trim().trim_end_matches(';').to_lowercase();
+    let words: Vec<&str> = lower_case_query.split_whitespace().collect();
+    lower_case_query.starts_with("select")
+        && !lower_case_query.contains(';')
+        && words.windows(2).any(|pair| pair == ["order", "by"])
+        && !words.iter().any(|word| {
+            matches!(
+                *word,
+                "limit" | "offset" | "into" | "update" | "fetch" | "share" | "lock"
+            )
+        })
+}
+
+fn page(query: &str, page_size: usize, offset: usize) -> String {
+    format!("{}\nLIMIT {} OFFSET {}", query, page_size, offset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::{ColumnValue, ReaderQueryResult};
+    use std::sync::{Arc, Mutex};
+
+    /// Table with the ids from 0 to 4, answering the pages asked for
+    #[derive(Clone, Default)]
+    struct Table {
+        queries: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl QueryExecutor for Table {
+        type QueryResult = ReaderQueryResult;
+
+        fn query(&mut self, query: &str) -> Result<Option<ReaderQueryResult>> {
+            self.queries.lock().unwrap().push(query.to_string());
+            let words: Vec<&str> = query.split_whitespace().collect();
+            let number = |keyword: &str| -> Option<usize> {
+                let index = words.iter().position(|word| *word == keyword)?;
+                words.get(index + 1)?.parse().ok()
+            };
+            let (limit, offset) = (number("LIMIT").unwrap_or(5), number("OFFSET").unwrap_or(0));
+            let ids: String = (offset..5)
+                .take(limit)
+                .map(|id| format!("{}\n", id))
+                .collect();
+            Ok(Some(ReaderQueryResult::new(std::io::Cursor::new(
+                "id\n".to_string() + &ids,
+            ))))
+        }
+    }
+
+    #[test]
+    fn test_selects_are_run_in_pages() {
+        let table = Table::default();
+        let queries = table.queries.clone();
+        let mut paginator = QueryPaginator::new(table, 2);
+        let (columns, rows) = paginator
+            .query("select id from t order by id;")
+            .unwrap()
+            .unwrap()
+            .get_data();
+        assert_eq!(1, columns.unwrap().len());
+        let rows: Vec<Row> = rows.map(Result::unwrap).collect();
+        assert_eq!(5, rows.len());
+        assert!(matches!(&rows[4][0], ColumnValue::String(id) if id == "4"));
+        assert_eq!(
+            vec![
+                "select id from t order by id\nLIMIT 2 OFFSET 0",
+                "select id from t order by id\nLIMIT 2 OFFSET 2",
+                "select id from t order by id\nLIMIT 2 OFFSET 4",
+            ],
+            *queries.lock().unwrap()
+        );
+        for query in [
+            "select id from t order by id limit 1",
+            "select id from t",
+            "select id from t order by id lock in share mode",
+            "select id from t order by id for share",
+        ] {
+            paginator.query(query).unwrap();
+            assert_eq!(query, queries.lock().unwrap().last().unwrap());
+        }
+    }
+}
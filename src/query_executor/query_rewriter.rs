@@ -0,0 +1,127 @@
+use super::log_redaction::loggable;
+use super::mysql_syntax::{is_keyword, parse_tokens, tokenize};
+use super::QueryExecutor;
+use anyhow::Result;
+use itertools::Itertools;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use sqlparser::dialect::Dialect;
+use sqlparser::tokenizer::Token;
+
+/// One entry of the `rewrites` of a listener
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Rewrite {
+    /// Replaces the matches of the regex in the text of the query. The replacement can use the
+    /// groups of the regex, like `${1}`.
+    Regex {
+        #[serde(deserialize_with = "deserialize_regex")]
+        regex: Regex,
+        replacement: String,
+    },
+    /// Replaces an unquoted keyword, function or identifier. The query is parsed and written again,
+    /// so literals and quoted identifiers are never changed.
+    Word { word: String, replacement: String },
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    Regex::new(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Rewrites queries with the constructs the target can not run, before they are sent to it
+pub struct QueryRewriter<T> {
+    executor: T,
+    dialect: Box<dyn Dialect + Send + Sync>,
+    rewrites: Vec<Rewrite>,
+}
+
+impl<T> QueryRewriter<T> {
+    pub fn new(
+        executor: T,
+        dialect: Box<dyn Dialect + Send + Sync>,
+        rewrites: Vec<Rewrite>,
+    ) -> Self {
+        Self {
+            executor,
+            dialect,
+            rewrites,
+        }
+    }
+
+    /// The query with the word replaced, or `None` when it does not have the word or can not be
+    /// parsed
+    fn replace_word(&self, query: &str, word: &str, replacement: &str) -> Option<String> {
+        let tokens = tokenize(self.dialect.as_ref(), query).ok()?;
+        if !tokens.iter().any(|token| is_keyword(token, &[word])) {
+            return None;
+        }
+        let tokens = tokens
+            .into_iter()
+            .map(|token| match is_keyword(&token, &[word]) {
+                true => Token::make_keyword(replacement),
+                false => token,
+            })
+            .collect();
+        let statements = parse_tokens(self.dialect.as_ref(), tokens).ok()?;
+        Some(statements.iter().join(";\n"))
+    }
+}
+
+impl<T> QueryExecutor for QueryRewriter<T>
+where
+    T: QueryExecutor,
+{
+    type QueryResult = T::QueryResult;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        let mut rewritten = query.to_string();
+        for rewrite in &self.rewrites {
+            rewritten = match rewrite {
+                Rewrite::Regex { regex, replacement } => regex
+                    .replace_all(&rewritten, replacement.as_str())
+                    .into_owned(),
+                Rewrite::Word { word, replacement } => self
+                    .replace_word(&rewritten, word, replacement)
+                    .unwrap_or(rewritten),
+            };
+        }
+        if rewritten != query {
+            println!("Query rewritten to: {}", loggable(&rewritten));
+        }
+        self.executor.query(&rewritten)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::FakeQueryExecutor;
+    use sqlparser::dialect::MySqlDialect;
+
+    #[test]
+    fn test_queries_are_rewritten() {
+        let executor = FakeQueryExecutor::new((0..2).map(|_| Ok(None)).collect());
+        let executed_queries = executor.get_query_list();
+        let rewrites: Vec<Rewrite> = serde_yaml::from_str(
+            "
+            - regex: '@@(\\w+)_timeout'
+              replacement: '60 as ${1}_timeout'
+            - word: ifnull
+              replacement: coalesce
+            ",
+        )
+        .unwrap();
+        let mut rewriter = QueryRewriter::new(executor, Box::new(MySqlDialect {}), rewrites);
+        rewriter.query("select @@wait_timeout").unwrap();
+        rewriter
+            .query("select IFNULL(name, 'ifnull') from users")
+            .unwrap();
+        assert_eq!(
+            vec![
+                "select 60 as wait_timeout",
+                "SELECT coalesce(name, 'ifnull') FROM users",
+            ],
+            *executed_queries.borrow()
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use super::log_redaction::loggable;
+use super::mysql_syntax::parse_sql;
+use super::{QueryExecutor, SessionState};
+use anyhow::Result;
+use sqlparser::ast::Statement;
+use sqlparser::dialect::Dialect;
+
+/// Adds `LIMIT max_rows` to selects without a limit, so a careless `SELECT *` does not make a
+/// huge task. Limits and offsets of the query are kept. The `SQL_SELECT_LIMIT` of the session is
+/// applied the same way, since the CLIs of some targets ignore it. It goes above the type
+/// discovery, so only the queries of the clients are limited.
+pub struct QueryRowGuard<T> {
+    executor: T,
+    dialect: Box<dyn Dialect + Send + Sync>,
+    max_rows: usize,
+    session_state: SessionState,
+}
+
+impl<T> QueryRowGuard<T> {
+    /// Without max rows, queries are run as they are
+    pub fn new(
+        executor: T,
+        dialect: Box<dyn Dialect + Send + Sync>,
+        max_rows: usize,
+        session_state: SessionState,
+    ) -> Self {
+        Self {
+            executor,
+            dialect,
+            max_rows,
+            session_state,
+        }
+    }
+
+    /// The query with the limit, or `None` when it is not a single select without one. The limit
+    /// is appended to the text of the query, so the syntax the parser drops, like `SQL_NO_CACHE`,
+    /// is kept. Selects with locks are left alone, since the limit would have to go before them.
+    fn guarded(&self, query: &str, limit: usize) -> Option<String> {
+        let statements = parse_sql(self.dialect.as_ref(), query).ok()?;
+        match statements.as_slice() {
+            [Statement::Query(select)]
+                if select.limit.is_none() && select.fetch.is_none() && select.lock.is_none() =>
+            {
+                let query = query.trim_end().trim_end_matches(';').trim_end();
+                Some(format!("{} LIMIT {}", query, limit))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<T> QueryExecutor for QueryRowGuard<T>
+where
+    T: QueryExecutor,
+{
+    type QueryResult = T::QueryResult;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        let select_limit = self
+            .session_state
+            .set_value("sql_select_limit")
+            .and_then(|value| value.parse::<usize>().ok());
+        let (limit, limited_by) = match (select_limit, self.max_rows) {
+            (Some(select_limit), max_rows) if max_rows == 0 || select_limit <= max_rows => {
+                (select_limit, "sql_select_limit")
+            }
+            (_, 0) => return self.executor.query(query),
+            (_, max_rows) => (max_rows, "max_rows_guard"),
+        };
+        match self.guarded(query, limit) {
+            Some(guarded) => {
+                println!(
+                    "Query limited to {} rows by {}: {}",
+                    limit,
+                    limited_by,
+                    loggable(&guarded)
+                );
+                // Clients that set SQL_SELECT_LIMIT expect their results to be limited
+                if limited_by == "max_rows_guard" {
+                    self.session_state.add_warning(format!(
+                        "Result limited to {} rows by max_rows_guard",
+                        limit
+                    ));
+                }
+                self.executor.query(&guarded)
+            }
+            None => self.executor.query(query),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::FakeQueryExecutor;
+    use sqlparser::dialect::{MySqlDialect, PostgreSqlDialect};
+
+    #[test]
+    fn test_selects_without_limit_are_limited() {
+        let executor = FakeQueryExecutor::new((0..4).map(|_| Ok(None)).collect());
+        let executed_queries = executor.get_query_list();
+        let session_state = SessionState::default();
+        let mut guard = QueryRowGuard::new(
+            executor,
+            Box::new(PostgreSqlDialect {}),
+            100,
+            session_state.clone(),
+        );
+        guard.query("select * from payments;").unwrap();
+        guard.query("select * from payments offset 5").unwrap();
+        guard.query("select * from payments limit 5000").unwrap();
+        guard.query("delete from payments").unwrap();
+        assert_eq!(
+            vec![
+                "select * from payments LIMIT 100",
+                "select * from payments offset 5 LIMIT 100",
+                "select * from payments limit 5000",
+                "delete from payments",
+            ],
+            *executed_queries.borrow()
+        );
+        assert_eq!(2, session_state.warnings().len());
+    }
+
+    #[test]
+    fn test_selects_are_limited_by_sql_select_limit() {
+        let executor = FakeQueryExecutor::new((0..4).map(|_| Ok(None)).collect());
+        let executed_queries = executor.get_query_list();
+        let session_state = SessionState::default();
+        let mut guard = QueryRowGuard::new(
+            executor,
+            Box::new(MySqlDialect {}),
+            1000,
+            session_state.clone(),
+        );
+        session_state.add_set_statement("SET SQL_SELECT_LIMIT=501");
+        guard.query("select * from payments").unwrap();
+        guard.query("select * from payments limit 600").unwrap();
+        session_state.add_set_statement("SET SQL_SELECT_LIMIT=5000");
+        guard.query("select * from payments").unwrap();
+        session_state.add_set_statement("SET SQL_SELECT_LIMIT=DEFAULT");
+        guard.query("select * from payments").unwrap();
+        assert_eq!(
+            vec![
+                "select * from payments LIMIT 501",
+                "select * from payments limit 600",
+                "select * from payments LIMIT 1000",
+                "select * from payments LIMIT 1000",
+            ],
+            *executed_queries.borrow()
+        );
+        assert_eq!(2, session_state.warnings().len());
+    }
+}
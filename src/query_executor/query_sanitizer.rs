@@ -1,12 +1,69 @@
-use super::QueryExecutor;
+use super::comments::{replace_outside_literals, strip_comments};
+use super::{QueryExecutor, SqlDialect};
 use anyhow::Result;
-use itertools::Itertools;
+use std::collections::HashMap;
 
-pub struct QuerySanitizer<T>(T);
+pub struct QuerySanitizer<T> {
+    executor: T,
+    /// How comments and strings are written
+    dialect: SqlDialect,
+    /// If versioned comments of MySQL, like `/*!40101 SET NAMES utf8 */`, are sent to the target
+    forward_versioned_comments: bool,
+    /// Replacements of system variables the target does not have, by their lowercase name
+    variable_substitutions: HashMap<String, String>,
+}
 
 impl<T> QuerySanitizer<T> {
     pub fn new(executor: T) -> Self {
-        Self(executor)
+        Self {
+            executor,
+            dialect: SqlDialect::Generic,
+            forward_versioned_comments: true,
+            variable_substitutions: HashMap::from([(
+                "language".to_string(),
+                "'english'".to_string(),
+            )]),
+        }
+    }
+
+    pub fn with_dialect(mut self, dialect: SqlDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn with_versioned_comments(mut self, forward_versioned_comments: bool) -> Self {
+        self.forward_versioned_comments = forward_versioned_comments;
+        self
+    }
+
+    /// Variables can be written with or without `@@`. They are added to the default ones.
+    pub fn with_variable_substitutions(mut self, substitutions: HashMap<String, String>) -> Self {
+        self.variable_substitutions
+            .extend(substitutions.into_iter().map(|(variable, replacement)| {
+                (
+                    variable.trim_start_matches("@@").to_lowercase(),
+                    replacement,
+                )
+            }));
+        self
+    }
+
+    /// The query with the system variables replaced, outside of quoted strings and identifiers
+    fn substitute_variables(&self, query: &str) -> String {
+        if !query.contains("@@") {
+            return query.to_string();
+        }
+        replace_outside_literals(query, self.dialect, |rest, _| {
+            let name: String = match rest {
+                ['@', '@', name @ ..] => name
+                    .iter()
+                    .take_while(|character| character.is_alphanumeric() || **character == '_')
+                    .collect(),
+                _ => return None,
+            };
+            let replacement = self.variable_substitutions.get(&name.to_lowercase())?;
+            Some((2 + name.chars().count(), replacement.clone()))
+        })
     }
 }
 
@@ -16,29 +73,29 @@ where
 {
     type QueryResult = T::QueryResult;
     fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
-        let mut query: String = String::from(query.trim());
-        if query.starts_with('/') {
-            query = remove_comments_from_the_start(query);
-        }
-        query = query
-            .trim()
-            .lines()
-            .map(|line| line.trim())
-            .filter(|line| !line.trim().starts_with("--"))
-            .filter(|line| !line.trim().starts_with('#'))
-            .collect::<Vec<&str>>()
-            .join("\n");
-        self.0.query(&query.replace("@@language", "'english'"))
+        let query = strip_comments(query, self.forward_versioned_comments, self.dialect);
+        let query = self.substitute_variables(query.trim());
+        self.executor.query(&query)
     }
 }
 
-fn remove_comments_from_the_start(query: String) -> String {
-    // There are some very crazy allocations happening here. We can probably clean it up later
-    query
-        .chars()
-        .tuple_windows()
-        .skip_while(|(prev, next)| !(*prev == '*' && *next == '/'))
-        .skip(1)
-        .map(|(_, next)| next)
-        .collect()
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::FakeQueryExecutor;
+
+    #[test]
+    fn test_variables_are_substituted() {
+        let executor = FakeQueryExecutor::new(vec![Ok(None)]);
+        let executed_queries = executor.get_query_list();
+        let mut sanitizer = QuerySanitizer::new(executor)
+            .with_variable_substitutions(HashMap::from([("@@SPID".to_string(), "1".to_string())]));
+        sanitizer
+            .query("SELECT @@language, @@spid, @@spidx, '@@language' -- @@spid")
+            .unwrap();
+        assert_eq!(
+            "SELECT 'english', 1, @@spidx, '@@language'",
+            executed_queries.borrow()[0]
+        );
+    }
 }
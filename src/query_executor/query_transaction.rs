@@ -0,0 +1,177 @@
+use super::{QueryExecutor, SessionState};
+use anyhow::Result;
+use serde::Deserialize;
+
+/// What is done with the transactions of clients, since each query is a task of its own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionMode {
+    /// Transaction statements are answered locally, and other statements run as they arrive
+    #[default]
+    Emulate,
+    /// Statements of a transaction, except reads, are sent as one task on `COMMIT`
+    Batch,
+}
+
+/// Statements that run right away inside a batched transaction
+const PASS_THROUGH_STATEMENTS: &[&str] = &[
+    "select", "with", "show", "describe", "desc", "explain", "set", "use", "reset", "fakesql",
+];
+
+enum TransactionStatement {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+/// Handles `BEGIN`, `COMMIT` and `ROLLBACK`, which would otherwise be tasks of their own
+pub struct QueryTransaction<T> {
+    executor: T,
+    mode: TransactionMode,
+    session_state: SessionState,
+    /// Statements of the open transaction, in batch mode
+    batch: Option<Vec<String>>,
+}
+
+impl<T> QueryTransaction<T> {
+    pub fn new(executor: T, mode: TransactionMode, session_state: SessionState) -> Self {
+        Self {
+            executor,
+            mode,
+            session_state,
+            batch: None,
+        }
+    }
+}
+
+impl<T> QueryExecutor for QueryTransaction<T>
+where
+    T: QueryExecutor,
+{
+    type QueryResult = T::QueryResult;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        match (transaction_statement(query), self.mode) {
+            (Some(TransactionStatement::Begin), TransactionMode::Emulate) => {
+                self.session_state.add_warning(
+                    "Transactions are not supported by the target. Statements are committed as they run",
+                );
+                Ok(None)
+            }
+            (Some(TransactionStatement::Rollback), TransactionMode::Emulate) => {
+                self.session_state
+                    .add_warning("Nothing was rolled back. Statements were committed as they ran");
+                Ok(None)
+            }
+            (Some(TransactionStatement::Commit), TransactionMode::Emulate) => Ok(None),
+            (Some(TransactionStatement::Begin), TransactionMode::Batch) => {
+                if let Some(batch) = self.batch.replace(Vec::new()) {
+                    println!(
+                        "Transaction started again. Discarding {} statements",
+                        batch.len()
+                    );
+                }
+                Ok(None)
+            }
+            (Some(TransactionStatement::Rollback), TransactionMode::Batch) => {
+                if let Some(batch) = self.batch.take() {
+                    println!(
+                        "Transaction rolled back. Discarding {} statements",
+                        batch.len()
+                    );
+                }
+                Ok(None)
+            }
+            (Some(TransactionStatement::Commit), TransactionMode::Batch) => {
+                match self.batch.take().filter(|batch| !batch.is_empty()) {
+                    Some(batch) => {
+                        println!(
+                            "Transaction committed. Running its {} statements",
+                            batch.len()
+                        );
+                        self.executor
+                            .query(&format!("BEGIN;\n{};\nCOMMIT", batch.join(";\n")))
+                    }
+                    None => Ok(None),
+                }
+            }
+            (None, _) => match &mut self.batch {
+                Some(batch) if !is_pass_through(query) => {
+                    batch.push(query.trim().trim_end_matches(';').to_string());
+                    Ok(None)
+                }
+                _ => self.executor.query(query),
+            },
+        }
+    }
+}
+
+fn transaction_statement(query: &str) -> Option<TransactionStatement> {
+    let query = query.trim().trim_end_matches(';').to_lowercase();
+    let words: Vec<&str> = query.split_whitespace().collect();
+    match words.as_slice() {
+        ["begin"] | ["begin", "work" | "transaction"] | ["start", "transaction", ..] => {
+            Some(TransactionStatement::Begin)
+        }
+        ["commit" | "end"] | ["commit" | "end", "work" | "transaction"] => {
+            Some(TransactionStatement::Commit)
+        }
+        ["rollback" | "abort"] | ["rollback" | "abort", "work" | "transaction"] => {
+            Some(TransactionStatement::Rollback)
+        }
+        _ => None,
+    }
+}
+
+fn is_pass_through(query: &str) -> bool {
+    let first_word = query
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    PASS_THROUGH_STATEMENTS.contains(&first_word.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::FakeQueryExecutor;
+
+    #[test]
+    fn test_transactions_are_emulated_or_batched() {
+        let executor = FakeQueryExecutor::new(vec![Ok(None)]);
+        let executed_queries = executor.get_query_list();
+        let session_state = SessionState::default();
+        let mut transaction =
+            QueryTransaction::new(executor, TransactionMode::Emulate, session_state.clone());
+        transaction.query("BEGIN").unwrap();
+        transaction.query("UPDATE a SET b = 1").unwrap();
+        transaction.query("COMMIT").unwrap();
+        assert_eq!(vec!["UPDATE a SET b = 1"], *executed_queries.borrow());
+        assert_eq!(1, session_state.warnings().len());
+
+        let executor = FakeQueryExecutor::new(vec![Ok(None), Ok(None)]);
+        let executed_queries = executor.get_query_list();
+        let mut transaction =
+            QueryTransaction::new(executor, TransactionMode::Batch, SessionState::default());
+        for query in [
+            "START TRANSACTION",
+            "UPDATE a SET b = 1;",
+            "select * from a",
+            "DELETE FROM c",
+            "commit",
+            "begin",
+            "delete from a",
+            "rollback",
+        ] {
+            transaction.query(query).unwrap();
+        }
+        assert_eq!(
+            vec![
+                "select * from a",
+                "BEGIN;\nUPDATE a SET b = 1;\nDELETE FROM c;\nCOMMIT",
+            ],
+            *executed_queries.borrow()
+        );
+    }
+}
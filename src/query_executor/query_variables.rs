@@ -0,0 +1,397 @@
+use super::comments::{literal_length, replace_outside_literals};
+use super::{AnsweredResult, Column, ColumnValue, Columns, QueryExecutor, Row, SqlDialect};
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Values of the system variables read by MySQL clients and drivers when they connect, answered
+/// when they are not configured
+const DEFAULT_VARIABLES: &[(&str, &str)] = &[
+    ("version", "8.0.36"),
+    ("version_comment", "my-fake-sql"),
+    ("sql_mode", "ONLY_FULL_GROUP_BY,STRICT_TRANS_TABLES,NO_ZERO_IN_DATE,NO_ZERO_DATE,ERROR_FOR_DIVISION_BY_ZERO,NO_ENGINE_SUBSTITUTION"),
+    ("max_allowed_packet", "67108864"),
+    ("net_buffer_length", "16384"),
+    ("autocommit", "1"),
+    ("auto_increment_increment", "1"),
+    ("character_set_client", "utf8mb4"),
+    ("character_set_connection", "utf8mb4"),
+    ("character_set_results", "utf8mb4"),
+    ("character_set_server", "utf8mb4"),
+    ("collation_connection", "utf8mb4_0900_ai_ci"),
+    ("collation_server", "utf8mb4_0900_ai_ci"),
+    ("time_zone", "SYSTEM"),
+    ("system_time_zone", "UTC"),
+    ("transaction_isolation", "REPEATABLE-READ"),
+    ("tx_isolation", "REPEATABLE-READ"),
+    ("transaction_read_only", "0"),
+    ("tx_read_only", "0"),
+    ("lower_case_table_names", "0"),
+    ("wait_timeout", "28800"),
+    ("interactive_timeout", "28800"),
+    ("net_write_timeout", "60"),
+    ("performance_schema", "0"),
+    ("query_cache_size", "0"),
+    ("query_cache_type", "OFF"),
+    ("license", "GPL"),
+    ("init_connect", ""),
+];
+
+/// Answers selects of system variables, like `SELECT @@version`, and of user variables, like
+/// `SELECT @x`, without running them. User variables set to a literal are replaced by their value
+/// in the other queries, so their types can be found.
+pub struct QueryVariables<T> {
+    executor: T,
+    /// Global values, used by the sessions until they set them and after `SET name = DEFAULT`
+    system_variables: HashMap<String, String>,
+    /// Values set by the session
+    session_variables: HashMap<String, String>,
+    /// Values of user variables, with `None` for NULL
+    user_variables: HashMap<String, Option<Literal>>,
+    set_variable: Regex,
+    selected_variable: Regex,
+}
+
+impl<T> QueryVariables<T> {
+    /// Configured values replace the default ones
+    pub fn new(executor: T, variables: HashMap<String, String>) -> Self {
+        let mut system_variables: HashMap<String, String> = DEFAULT_VARIABLES
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        system_variables.extend(
+            variables
+                .into_iter()
+                .map(|(name, value)| (name.to_lowercase(), value)),
+        );
+        Self {
+            executor,
+            system_variables,
+            session_variables: HashMap::new(),
+            user_variables: HashMap::new(),
+            set_variable: Regex::new(
+                r"(?is)^set\s+(?:(?:session|local)\s+|@@(?:session\.|local\.)?)?(@?[\w$]+)\s*:?=\s*(.*?)\s*;?$",
+            )
+            .unwrap(),
+            selected_variable: Regex::new(
+                r#"(?is)^(@@(?:(?:session|global|local)\.)?([\w$]+)|@([\w$]+))(?:\s+(?:as\s+)?([\w$]+|`[^`]+`|'[^']+'|"[^"]+"))?$"#,
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Answer of selects that only have variables, or `None` when a system variable is unknown
+    fn answer(&self, query: &str) -> Option<(Columns, Vec<Row>)> {
+        let query = query.trim().trim_end_matches(';').trim_end();
+        let items = match query.get(..6) {
+            Some(select) if select.eq_ignore_ascii_case("select") => &query[6..],
+            _ => return None,
+        };
+        if !items.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let mut columns = Vec::new();
+        let mut row = Vec::new();
+        for item in items.split(',') {
+            let captures = self.selected_variable.captures(item.trim())?;
+            let value = match (captures.get(2), captures.get(3)) {
+                (Some(system), _) => {
+                    let name = system.as_str().to_lowercase();
+                    ColumnValue::String(
+                        self.session_variables
+                            .get(&name)
+                            .or_else(|| self.system_variables.get(&name))?
+                            .clone(),
+                    )
+                }
+                (_, Some(user)) => match self.user_variables.get(&user.as_str().to_lowercase()) {
+                    Some(Some(value)) => ColumnValue::String(value.text.clone()),
+                    // Unknown user variables are NULL
+                    _ => ColumnValue::Null,
+                },
+                _ => return None,
+            };
+            let name = match captures.get(4) {
+                Some(alias) => alias.as_str().trim_matches(['`', '\'', '"']),
+                None => &captures[1],
+            };
+            columns.push(Column {
+                name: name.to_string(),
+                ty: None,
+                attributes: Default::default(),
+            });
+            row.push(value);
+        }
+        Some((columns, vec![row]))
+    }
+
+    /// Keeps the value of `SET @x = value` and `SET sql_mode = value` when it is a literal
+    fn remember(&mut self, statement: &str) {
+        let captures = match self.set_variable.captures(statement.trim()) {
+            Some(captures) => captures,
+            None => return,
+        };
+        let name = captures[1].to_lowercase();
+        // The global value of system variables
+        if !name.starts_with('@') && captures[2].eq_ignore_ascii_case("default") {
+            self.session_variables.remove(&name);
+            return;
+        }
+        let value = self.value(&captures[2], !name.starts_with('@'));
+        match (name.strip_prefix('@'), value) {
+            (Some(user), Some(value)) => {
+                self.user_variables.insert(user.to_string(), value);
+            }
+            // Values that are not literals are left to the target
+            (Some(user), None) => {
+                self.user_variables.remove(user);
+            }
+            (None, Some(Some(value))) => {
+                self.session_variables.insert(name, value.text);
+            }
+            (None, _) => {}
+        }
+    }
+
+    /// Value of a literal, a user variable or, for system variables, a word like `ON`
+    fn value(&self, literal: &str, words: bool) -> Option<Option<Literal>> {
+        let first = literal.chars().next()?;
+        if literal.eq_ignore_ascii_case("null") {
+            Some(None)
+        } else if is_number(literal) {
+            Some(Some(Literal {
+                text: literal.to_string(),
+                quoted: false,
+            }))
+        } else if matches!(first, '\'' | '"')
+            && literal_length(
+                &literal.chars().collect::<Vec<char>>(),
+                None,
+                SqlDialect::MySql,
+            ) == Some(literal.chars().count())
+            && literal.ends_with(first)
+        {
+            let quote = first.to_string();
+            let text = &literal[1..literal.len() - 1];
+            Some(Some(Literal {
+                text: text
+                    .replace(&quote.repeat(2), &quote)
+                    .replace(&format!("\\{}", quote), &quote),
+                quoted: true,
+            }))
+        } else if let Some(user) = literal.strip_prefix('@') {
+            self.user_variables.get(&user.to_lowercase()).cloned()
+        } else if words
+            && literal
+                .chars()
+                .all(|character| character.is_alphanumeric() || character == '_')
+        {
+            Some(Some(Literal {
+                text: literal.to_string(),
+                quoted: false,
+            }))
+        } else {
+            None
+        }
+    }
+
+    /// The query with the known user variables replaced by their values, and the variables it
+    /// assigns, like `@x` of `SELECT @x := 1` and `SELECT 1 INTO @x`. They are left as written.
+    fn substituted(&self, query: &str) -> (String, Vec<String>) {
+        let mut assigned = Vec::new();
+        if self.user_variables.is_empty() || !query.contains('@') {
+            return (query.to_string(), assigned);
+        }
+        // Inside the variables after `INTO`
+        let mut into = false;
+        let substituted = replace_outside_literals(query, SqlDialect::MySql, |rest, previous| {
+            let starts_word = !previous.is_some_and(|character| character.is_alphanumeric());
+            if starts_word
+                && rest.len() > 4
+                && rest[..4]
+                    .iter()
+                    .collect::<String>()
+                    .eq_ignore_ascii_case("into")
+                && rest[4].is_whitespace()
+            {
+                into = true;
+                return None;
+            }
+            let name: String = match rest {
+                ['@', '@', ..] => return None,
+                ['@', name @ ..] if previous != Some('@') => name
+                    .iter()
+                    .take_while(|character| character.is_alphanumeric() || **character == '_')
+                    .collect(),
+                [character, ..] if character.is_whitespace() || *character == ',' => return None,
+                _ => {
+                    into = into && !starts_word;
+                    return None;
+                }
+            };
+            let length = 1 + name.chars().count();
+            let mut after = rest[length..]
+                .iter()
+                .skip_while(|character| character.is_whitespace());
+            let is_assigned = into || (after.next() == Some(&':') && after.next() == Some(&'='));
+            if is_assigned {
+                assigned.push(name.to_lowercase());
+                return Some((length, rest[..length].iter().collect()));
+            }
+            let value = self.user_variables.get(&name.to_lowercase())?;
+            Some((length, sql_literal(value)))
+        });
+        (substituted, assigned)
+    }
+}
+
+/// Value of a user variable, kept with whether it was written as a string. Strings that look like
+/// numbers, like `'007'`, are written as strings again.
+#[derive(Clone)]
+struct Literal {
+    text: String,
+    quoted: bool,
+}
+
+/// Numbers written without quotes, unlike words that Rust parses as numbers, like `nan` and `inf`
+fn is_number(literal: &str) -> bool {
+    literal
+        .chars()
+        .all(|character| character.is_ascii_digit() || ".+-eE".contains(character))
+        && literal.chars().any(|character| character.is_ascii_digit())
+        && literal.parse::<f64>().is_ok()
+}
+
+fn sql_literal(value: &Option<Literal>) -> String {
+    match value {
+        None => "NULL".to_string(),
+        Some(Literal {
+            text,
+            quoted: false,
+        }) => text.clone(),
+        Some(Literal { text, quoted: true }) => format!("'{}'", text.replace('\'', "''")),
+    }
+}
+
+impl<T> QueryExecutor for QueryVariables<T>
+where
+    T: QueryExecutor,
+{
+    type QueryResult = AnsweredResult<T::QueryResult>;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        if let Some((columns, rows)) = self.answer(query) {
+            println!("Variables answered locally");
+            return Ok(Some(AnsweredResult::Local(columns, rows)));
+        }
+        let query = match query.trim_start().get(..3) {
+            // SET statements are kept by the session as they are
+            Some(set) if set.eq_ignore_ascii_case("set") => {
+                self.remember(query);
+                query.to_string()
+            }
+            _ => {
+                let (substituted, assigned) = self.substituted(query);
+                // Their values are only known by the target
+                for name in assigned {
+                    self.user_variables.remove(&name);
+                }
+                substituted
+            }
+        };
+        Ok(self.executor.query(&query)?.map(AnsweredResult::Executed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::FakeQueryExecutor;
+    use crate::query_executor::QueryResult;
+
+    fn answered(variables: &mut QueryVariables<FakeQueryExecutor>, query: &str) -> Vec<String> {
+        let (columns, rows) = variables.query(query).unwrap().unwrap().get_data();
+        let row = rows.map(Result::unwrap).next().unwrap();
+        columns
+            .unwrap()
+            .into_iter()
+            .zip(row)
+            .map(|(column, value)| match value {
+                ColumnValue::String(value) => format!("{}={}", column.name, value),
+                _ => format!("{}=NULL", column.name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_variables_are_answered_locally() {
+        let executor = FakeQueryExecutor::new((0..4).map(|_| Ok(None)).collect());
+        let executed_queries = executor.get_query_list();
+        let mut variables = QueryVariables::new(
+            executor,
+            HashMap::from([("version".to_string(), "5.7.0-test".to_string())]),
+        );
+        variables.query("SET sql_mode = 'ANSI_QUOTES'").unwrap();
+        assert_eq!(
+            vec!["@@version=5.7.0-test", "mode=ANSI_QUOTES"],
+            answered(
+                &mut variables,
+                "SELECT @@version, @@session.sql_mode AS mode"
+            )
+        );
+        variables.query("SET @x := 'it''s'").unwrap();
+        assert_eq!(
+            vec!["@x=it's", "@y=NULL"],
+            answered(&mut variables, "select @x, @y;")
+        );
+        variables
+            .query("select * from t where name = @x and email = 'a@x.com'")
+            .unwrap();
+        variables.query("SELECT @@unknown").unwrap();
+        assert_eq!(
+            vec![
+                "SET sql_mode = 'ANSI_QUOTES'",
+                "SET @x := 'it''s'",
+                "select * from t where name = 'it''s' and email = 'a@x.com'",
+                "SELECT @@unknown",
+            ],
+            *executed_queries.borrow()
+        );
+    }
+
+    #[test]
+    fn test_variables_are_substituted_as_written() {
+        let executor = FakeQueryExecutor::new((0..9).map(|_| Ok(None)).collect());
+        let executed_queries = executor.get_query_list();
+        let mut variables = QueryVariables::new(executor, HashMap::new());
+        variables.query("SET sql_mode = 'ANSI_QUOTES'").unwrap();
+        variables.query("SET sql_mode = DEFAULT").unwrap();
+        assert_eq!(
+            vec!["@@sql_mode=ONLY_FULL_GROUP_BY,STRICT_TRANS_TABLES,NO_ZERO_IN_DATE,NO_ZERO_DATE,ERROR_FOR_DIVISION_BY_ZERO,NO_ENGINE_SUBSTITUTION"],
+            answered(&mut variables, "SELECT @@sql_mode")
+        );
+        variables.query("SET @code = '007'").unwrap();
+        variables.query("SET @word = 'nan'").unwrap();
+        variables.query("SET @n = 7").unwrap();
+        variables
+            .query("select * from t where code = @code and word = @word and n = @n")
+            .unwrap();
+        // Assigned variables are left as written and their values are forgotten
+        variables.query("select @n := @n + 1").unwrap();
+        variables
+            .query("select code, n into @code, @n from t where word = @word")
+            .unwrap();
+        variables
+            .query("select * from t where code = @code and n = @n")
+            .unwrap();
+        assert_eq!(
+            &[
+                "select * from t where code = '007' and word = 'nan' and n = 7",
+                "select @n := 7 + 1",
+                "select code, n into @code, @n from t where word = 'nan'",
+                "select * from t where code = @code and n = @n",
+            ],
+            &executed_queries.borrow()[5..]
+        );
+    }
+}
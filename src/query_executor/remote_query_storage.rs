@@ -0,0 +1,284 @@
+use super::query_cache::{CachedEntryInfo, CachedResult, QueryStorage};
+use super::SharedQueryStorage;
+use crate::http::{write_response, BodyTooLarge, HttpRequest, DEFAULT_MAX_BODY_BYTES};
+use anyhow::Result;
+use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+/// Query storage kept by another instance of the proxy, running a `QueryStorageServer`.
+/// The cache is best effort, so errors talking to the server are logged and treated as misses.
+pub struct RemoteQueryStorage {
+    url: String,
+    /// Bearer token of the server
+    token: String,
+    client: Client,
+}
+
+/// Exposes a query storage over HTTP, so other instances can share it with `RemoteQueryStorage`.
+/// Every request must have the bearer token of the server.
+pub struct QueryStorageServer {
+    listener: TcpListener,
+    storage: SharedQueryStorage,
+    token: String,
+    /// Largest request accepted, like the results stored by other instances
+    max_body_bytes: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetRequest {
+    query: String,
+    stale: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoreRequest {
+    query: String,
+    result: CachedResult,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RemoveRequest {
+    queries: Vec<String>,
+}
+
+impl RemoteQueryStorage {
+    pub fn new(url: &str, token: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    fn post<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T> {
+        Ok(self
+            .client
+            .post(format!("{}/{}", self.url, path))
+            .bearer_auth(&self.token)
+            .json(body)
+            .send()?
+            .error_for_status()?
+            .json()?)
+    }
+
+    fn fetch<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        Ok(self
+            .client
+            .get(format!("{}/{}", self.url, path))
+            .bearer_auth(&self.token)
+            .send()?
+            .error_for_status()?
+            .json()?)
+    }
+
+    fn get_from_server(&self, query: &str, stale: bool) -> Option<CachedResult> {
+        let request = GetRequest {
+            query: query.to_string(),
+            stale,
+        };
+        self.post("get", &request).unwrap_or_else(|error| {
+            println!("Error reading from the cache server: {error}");
+            None
+        })
+    }
+}
+
+impl QueryStorage for RemoteQueryStorage {
+    fn store(&self, query: &str, result: CachedResult) {
+        let request = StoreRequest {
+            query: query.to_string(),
+            result,
+        };
+        if let Err(error) = self.post::<()>("store", &request) {
+            println!("Error storing in the cache server: {error}");
+        }
+    }
+
+    fn get(&self, query: &str) -> Option<CachedResult> {
+        self.get_from_server(query, false)
+    }
+
+    fn get_stale(&self, query: &str) -> Option<CachedResult> {
+        self.get_from_server(query, true)
+    }
+
+    fn remove_where(&self, predicate: &dyn Fn(&str) -> bool) -> usize {
+        // The predicate can not be sent, so it is applied to the list of cached queries
+        let queries = self
+            .list()
+            .into_iter()
+            .map(|entry| entry.query)
+            .filter(|query| predicate(query))
+            .collect();
+        self.post("remove", &RemoveRequest { queries })
+            .unwrap_or_else(|error| {
+                println!("Error removing from the cache server: {error}");
+                0
+            })
+    }
+
+    fn list(&self) -> Vec<CachedEntryInfo> {
+        self.fetch("list").unwrap_or_else(|error| {
+            println!("Error listing the cache server: {error}");
+            Vec::new()
+        })
+    }
+
+    fn entries(&self) -> Vec<(String, CachedResult)> {
+        self.fetch("entries").unwrap_or_else(|error| {
+            println!("Error reading the cache server entries: {error}");
+            Vec::new()
+        })
+    }
+}
+
+impl QueryStorageServer {
+    pub fn bind(address: &str, storage: SharedQueryStorage, token: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            storage,
+            token: token.to_string(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+        })
+    }
+
+    /// Results can take as much as the whole cache, so its budget is accepted on top of the
+    /// default limit
+    pub fn with_cache_max_bytes(mut self, cache_max_bytes: Option<usize>) -> Self {
+        self.max_body_bytes = DEFAULT_MAX_BODY_BYTES + cache_max_bytes.unwrap_or(0);
+        self
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(self) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            let storage = self.storage.clone();
+            let token = self.token.clone();
+            let max_body_bytes = self.max_body_bytes;
+            thread::spawn(move || {
+                if let Err(error) = handle_connection(&storage, stream, &token, max_body_bytes) {
+                    println!("Cache server error: {error}");
+                }
+            });
+        }
+    }
+}
+
+fn handle_connection(
+    storage: &SharedQueryStorage,
+    mut stream: TcpStream,
+    token: &str,
+    max_body_bytes: usize,
+) -> Result<()> {
+    let request = match HttpRequest::read(&stream, max_body_bytes) {
+        Err(error) if error.is::<BodyTooLarge>() => {
+            write_response(&mut stream, "413 Payload Too Large", "")?;
+            return Err(error);
+        }
+        request => request?,
+    };
+    if !request.has_bearer_token(token) {
+        return write_response(&mut stream, "401 Unauthorized", "");
+    }
+    let response = match (request.method.as_str(), request.segments().as_slice()) {
+        ("POST", ["get"]) => {
+            let request: GetRequest = serde_json::from_slice(&request.body)?;
+            let result = match request.stale {
+                true => storage.get_stale(&request.query),
+                false => storage.get(&request.query),
+            };
+            serde_json::to_string(&result)?
+        }
+        ("POST", ["store"]) => {
+            let request: StoreRequest = serde_json::from_slice(&request.body)?;
+            storage.store(&request.query, request.result);
+            serde_json::to_string(&())?
+        }
+        ("POST", ["remove"]) => {
+            let request: RemoveRequest = serde_json::from_slice(&request.body)?;
+            let removed =
+                storage.remove_where(&|query| request.queries.iter().any(|remove| remove == query));
+            serde_json::to_string(&removed)?
+        }
+        ("GET", ["list"]) => serde_json::to_string(&storage.list())?,
+        ("GET", ["entries"]) => serde_json::to_string(&storage.entries())?,
+        _ => return write_response(&mut stream, "404 Not Found", ""),
+    };
+    write_response(&mut stream, "200 OK", &response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::{Column, ColumnValue, InMemoryQueryStorage};
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_remote_storage_shares_the_server_storage() {
+        let server_storage = InMemoryQueryStorage::with_max_bytes(None);
+        let server =
+            QueryStorageServer::bind("127.0.0.1:0", Arc::new(server_storage.clone()), "secret")
+                .unwrap();
+        let url = format!("http://{}/", server.local_addr().unwrap());
+        let remote = RemoteQueryStorage::new(&url, "secret");
+        thread::spawn(move || server.run());
+
+        remote.store(
+            "select id from users",
+            CachedResult::new(
+                vec![Column {
+                    name: "id".to_string(),
+                    ty: Some("bigint".to_string()),
+                    attributes: Default::default(),
+                }],
+                vec![vec![ColumnValue::I64(1)]],
+                None,
+            ),
+        );
+        assert!(server_storage.get("select id from users").is_some());
+        let result = remote.get("select id from users").unwrap();
+        assert!(matches!(result.rows[0][0], ColumnValue::I64(1)));
+        assert!(remote.get("select id from payments").is_none());
+        assert_eq!(1, remote.list().len());
+
+        assert_eq!(1, remote.remove_where(&|query| query.contains("users")));
+        assert!(server_storage.get("select id from users").is_none());
+
+        remote.store("select 1", CachedResult::new(vec![], vec![], None));
+        let intruder = RemoteQueryStorage::new(&url, "guess");
+        intruder.store("select 2", CachedResult::new(vec![], vec![], None));
+        assert!(intruder.entries().is_empty());
+        assert!(intruder.get("select 1").is_none());
+        assert_eq!(1, server_storage.list().len());
+    }
+
+    #[test]
+    fn test_requests_over_the_body_limit_are_rejected() {
+        let server = QueryStorageServer::bind(
+            "127.0.0.1:0",
+            Arc::new(InMemoryQueryStorage::with_max_bytes(None)),
+            "secret",
+        )
+        .unwrap()
+        .with_cache_max_bytes(Some(1024));
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST /store HTTP/1.1\r\nContent-Length: 999999999999\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    }
+}
@@ -1,14 +1,19 @@
-use super::{QueryExecutor, ReaderQueryResult};
+use super::encoding::DecodingReader;
+use super::progress::ProgressReader;
+use super::{
+    QueryExecutor, ReaderQueryResult, ResultFormat, ResultLayout, SessionState, DEFAULT_NULL_TOKEN,
+};
 use anyhow::{anyhow, Result};
+use encoding_rs::{Encoding, UTF_8};
 use reqwest::{
     blocking::{get, Client, ClientBuilder},
     header::HeaderMap,
     StatusCode,
 };
 use serde::{Deserialize, Serialize};
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[derive(Deserialize)]
@@ -16,31 +21,94 @@ struct LogsResult {
     logs_url: String,
 }
 
+const RUNOPS_API_URL: &str = "https://api.runops.io";
+
+#[derive(Clone)]
 pub struct RunopsApi {
     target: String,
+    api_url: String,
     client: Client,
+    result_format: ResultFormat,
+    null_token: String,
+    result_layout: ResultLayout,
+    /// Encoding of the output of the target, when it is not UTF-8
+    output_encoding: Option<&'static Encoding>,
+    /// Session where the tasks are recorded for the audit log, and whose comment is prepended to
+    /// the queries
+    session_state: Option<SessionState>,
 }
 
 impl RunopsApi {
-    pub fn new(target: String) -> Result<Self> {
+    pub fn new(target: String, api_url: Option<String>) -> Result<Self> {
         let mut headers = HeaderMap::new();
-        headers.insert(
-            "Authorization",
-            std::fs::read_to_string(format!(
-                "{}/.runops/config",
-                dirs::home_dir().unwrap().to_string_lossy()
-            ))?
-            .parse()?,
-        );
+        let token = std::fs::read_to_string(format!(
+            "{}/.runops/config",
+            dirs::home_dir().unwrap().to_string_lossy()
+        ));
+        match (token, &api_url) {
+            (Ok(token), _) => {
+                headers.insert("Authorization", token.parse()?);
+            }
+            // Custom APIs (like the mock server) can be used without runops credentials
+            (Err(_), Some(_)) => {}
+            (Err(error), None) => return Err(error.into()),
+        }
         headers.insert("Accept", "application/json".parse()?);
         Ok(Self {
             target,
+            api_url: api_url.unwrap_or_else(|| RUNOPS_API_URL.to_string()),
             client: ClientBuilder::new()
                 .default_headers(headers)
                 .timeout(Duration::from_secs(120))
                 .build()?,
+            result_format: ResultFormat::default(),
+            null_token: DEFAULT_NULL_TOKEN.to_string(),
+            result_layout: ResultLayout::default(),
+            output_encoding: None,
+            session_state: None,
         })
     }
+
+    pub fn with_result_format(mut self, result_format: ResultFormat) -> Self {
+        self.result_format = result_format;
+        self
+    }
+
+    pub fn with_null_token(mut self, null_token: String) -> Self {
+        self.null_token = null_token;
+        self
+    }
+
+    pub fn with_result_layout(mut self, result_layout: ResultLayout) -> Self {
+        self.result_layout = result_layout;
+        self
+    }
+
+    pub fn with_output_encoding(mut self, output_encoding: &'static Encoding) -> Self {
+        self.output_encoding = Some(output_encoding).filter(|encoding| *encoding != UTF_8);
+        self
+    }
+
+    pub fn with_session_state(mut self, session_state: SessionState) -> Self {
+        self.session_state = Some(session_state);
+        self
+    }
+
+    /// Logs written by the target for the task, in its encoding
+    fn download(&self, url: String, task_id: u64) -> Result<ReaderQueryResult> {
+        let body = ProgressReader::new(get(url)?, task_id);
+        Ok(match self.output_encoding {
+            Some(encoding) => self.result(DecodingReader::new(body, encoding)),
+            None => self.result(body),
+        })
+    }
+
+    fn result(&self, reader: impl Read + Send + 'static) -> ReaderQueryResult {
+        ReaderQueryResult::new(reader)
+            .with_format(self.result_format)
+            .with_null_token(self.null_token.clone())
+            .with_layout(self.result_layout)
+    }
 }
 
 #[derive(Deserialize)]
@@ -65,15 +133,25 @@ impl QueryExecutor for RunopsApi {
     type QueryResult = ReaderQueryResult;
 
     fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        let query = match self
+            .session_state
+            .as_ref()
+            .and_then(SessionState::query_comment)
+        {
+            Some(comment) => format!("{comment}\n{query}"),
+            None => query.to_string(),
+        };
         let result: RunopsTaskResponse = self
             .client
-            .post("https://api.runops.io/v1/tasks")
-            .json(&RunopsTaskRequest::new(&self.target, query))
+            .post(format!("{}/v1/tasks", self.api_url))
+            .json(&RunopsTaskRequest::new(&self.target, &query))
             .send()?
             .json()?;
-        if result.task_logs.starts_with("https://") {
-            let body = get(result.task_logs)?;
-            return Ok(Some(ReaderQueryResult::new(body)));
+        if let Some(session_state) = &self.session_state {
+            session_state.add_task(result.id, &query);
+        }
+        if result.task_logs.starts_with("https://") || result.task_logs.starts_with("http://") {
+            return Ok(Some(self.download(result.task_logs, result.id)?));
         }
         if result.task_logs == "Task returned empty logs" {
             return Ok(None);
@@ -89,18 +167,31 @@ impl QueryExecutor for RunopsApi {
                 "Task {} is taking too long. We will need to pool for the result",
                 result.id
             );
+            let started = Instant::now();
+            let mut polls = 0;
             loop {
                 sleep(Duration::from_secs(5));
+                polls += 1;
                 let response = self
                     .client
-                    .get(format!("https://api.runops.io/v1/tasks/{}/logs", result.id))
+                    .get(format!("{}/v1/tasks/{}/logs", self.api_url, result.id))
                     .send()?;
                 match response.status() {
-                    StatusCode::BAD_REQUEST => continue,
+                    StatusCode::BAD_REQUEST => println!(
+                        "Task {} is still running after {}s and {} polls",
+                        result.id,
+                        started.elapsed().as_secs(),
+                        polls
+                    ),
                     StatusCode::OK => {
-                        let result: LogsResult = response.json()?;
-                        let body = get(result.logs_url)?;
-                        return Ok(Some(ReaderQueryResult::new(body)));
+                        println!(
+                            "Task {} finished after {}s and {} polls",
+                            result.id,
+                            started.elapsed().as_secs(),
+                            polls
+                        );
+                        let logs: LogsResult = response.json()?;
+                        return Ok(Some(self.download(logs.logs_url, result.id)?));
                     }
                     another_status => {
                         return Err(anyhow!("Invalid status code from Runops {another_status}"))
@@ -108,7 +199,8 @@ impl QueryExecutor for RunopsApi {
                 }
             }
         }
-        Ok(Some(ReaderQueryResult::new(Cursor::new(result.task_logs))))
+        // Logs in the response were already read as UTF-8
+        Ok(Some(self.result(Cursor::new(result.task_logs))))
     }
 }
 
@@ -117,8 +209,56 @@ pub struct SqlError {
     error: String,
 }
 
+impl SqlError {
+    /// An error answered to the client without going to the database
+    pub fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+        }
+    }
+}
+
 impl std::fmt::Display for SqlError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         write!(f, "{}", self.error)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::{write_response, HttpRequest, DEFAULT_MAX_BODY_BYTES};
+    use crate::query_executor::AuditedConnection;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_queries_are_sent_with_the_comment_of_the_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = HttpRequest::read(&stream, DEFAULT_MAX_BODY_BYTES).unwrap();
+            let response = r#"{"task_logs": "Task returned empty logs", "id": 1}"#;
+            write_response(&mut stream, "200 OK", response).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&request.body).unwrap()
+        });
+        let session_state = SessionState::default().with_annotated_connection(AuditedConnection {
+            listener: 3307,
+            client_address: "127.0.0.1:50000".to_string(),
+            connection_id: 42,
+        });
+        session_state.set_client_user("alice");
+        let mut runops = RunopsApi::new("mysql-prod".to_string(), Some(api_url))
+            .unwrap()
+            .with_session_state(session_state);
+
+        assert!(runops.query("select 1").unwrap().is_none());
+        let request = server.join().unwrap();
+        assert_eq!("mysql-prod", request["target"]);
+        assert_eq!(
+            "/* fakesql conn=42 user=alice client=127.0.0.1:50000 listener=3307 */\nselect 1",
+            request["script"]
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use super::audit_log::AuditedConnection;
+use super::comments::literal_length;
+use super::log_redaction::loggable;
+use super::mysql_syntax::{backquoted, used_schema};
+use super::SqlDialect;
+use itertools::Itertools;
+use ritelinked::LinkedHashSet;
+use std::sync::{Arc, Mutex};
+
+/// State of a client connection that changes the result of the queries. It is shared by the
+/// executors of the same connection.
+#[derive(Clone, Default)]
+pub struct SessionState(Arc<Mutex<Session>>);
+
+#[derive(Default)]
+struct Session {
+    default_schema: Option<String>,
+    /// Last SET statement of each variable, in the order they were run
+    set_statements: LinkedHashSet<String>,
+    max_set_statements: Option<usize>,
+    warnings: Vec<Warning>,
+    audit: QueryAudit,
+    /// Connection named by the comment prepended to the queries sent to the target
+    annotated_connection: Option<AuditedConnection>,
+    /// User the client logged in with
+    client_user: Option<String>,
+}
+
+/// Where the result of the current statement came from, for the audit log
+#[derive(Clone, Debug, Default)]
+pub struct QueryAudit {
+    /// Ids of the Runops tasks run, with the queries sent to them
+    pub tasks: Vec<(u64, String)>,
+    /// If the result was answered from the cache
+    pub cached: bool,
+}
+
+/// A warning of the last statement, answered by `SHOW WARNINGS`
+#[derive(Clone)]
+pub struct Warning {
+    pub code: u16,
+    pub message: String,
+}
+
+/// Generic code used by MySQL for errors without a specific one
+const UNKNOWN_ERROR_CODE: u16 = 1105;
+/// Same as the default `max_error_count` of MySQL
+const MAX_WARNINGS: usize = 64;
+/// SET statements kept by a session that does not configure a maximum
+pub const DEFAULT_MAX_SET_STATEMENTS: usize = 64;
+
+impl SessionState {
+    /// When there are more SET statements, the oldest ones are forgotten
+    pub fn with_max_set_statements(self, max_set_statements: usize) -> Self {
+        self.0.lock().unwrap().max_set_statements = Some(max_set_statements);
+        self
+    }
+
+    /// Recreates a session from its `statements`, like to refresh its cached results in the
+    /// background
+    pub fn with_statements(self, statements: &[String]) -> Self {
+        for statement in statements {
+            match used_schema(statement) {
+                Some(Ok(schema)) => self.use_schema(&schema),
+                _ => self.add_set_statement(statement),
+            }
+        }
+        self
+    }
+
+    /// The queries sent to the target start with a comment naming the connection
+    pub fn with_annotated_connection(self, connection: AuditedConnection) -> Self {
+        self.0.lock().unwrap().annotated_connection = Some(connection);
+        self
+    }
+
+    pub fn set_client_user(&self, user: &str) {
+        self.0.lock().unwrap().client_user = Some(user.to_string());
+    }
+
+    pub fn query_comment(&self) -> Option<String> {
+        let session = self.0.lock().unwrap();
+        session
+            .annotated_connection
+            .as_ref()
+            .map(|connection| connection.query_comment(session.client_user.as_deref()))
+    }
+
+    pub fn use_schema(&self, schema: &str) {
+        self.0.lock().unwrap().default_schema = Some(schema.to_string());
+    }
+
+    pub fn default_schema(&self) -> Option<String> {
+        self.0.lock().unwrap().default_schema.clone()
+    }
+
+    /// Replaces the SET statement of the same variable. Setting a variable to `DEFAULT` forgets it.
+    /// Statements setting several variables, like `SET @x = 1, @y = 2`, are kept as a statement
+    /// per variable.
+    pub fn add_set_statement(&self, statement: &str) {
+        for statement in split_set_statement(statement) {
+            self.add_single_set_statement(&statement);
+        }
+    }
+
+    fn add_single_set_statement(&self, statement: &str) {
+        let mut session = self.0.lock().unwrap();
+        let variable = set_variable(statement);
+        session
+            .set_statements
+            .retain(|set_statement| set_variable(set_statement) != variable);
+        if is_set_to_default(statement) {
+            return;
+        }
+        session.set_statements.insert(statement.to_string());
+        let max_set_statements = session
+            .max_set_statements
+            .unwrap_or(DEFAULT_MAX_SET_STATEMENTS);
+        while session.set_statements.len() > max_set_statements {
+            if let Some(forgotten) = session.set_statements.pop_front() {
+                println!(
+                    "Too many SET statements in the session. Forgetting {}",
+                    loggable(&forgotten)
+                );
+            }
+        }
+    }
+
+    /// Value of the last `SET name = value` of the variable, without quotes
+    pub fn set_value(&self, variable: &str) -> Option<String> {
+        let variable = variable.to_lowercase();
+        let session = self.0.lock().unwrap();
+        let statement = session
+            .set_statements
+            .iter()
+            .find(|set_statement| set_variable(set_statement) == variable)?;
+        let (_, value) = statement.split_once('=')?;
+        let value = value.split([',', ';']).next().unwrap_or_default();
+        Some(value.trim().trim_matches(['\'', '"']).to_string())
+    }
+
+    /// Forgets the SET statements of the variable, or all of them for `all`. Answers if it was a
+    /// variable of the session.
+    pub fn reset_variable(&self, variable: &str) -> bool {
+        let variable = variable.to_lowercase();
+        let set_statements = &mut self.0.lock().unwrap().set_statements;
+        match variable.as_str() {
+            "all" => set_statements.clear(),
+            _ => {
+                let count = set_statements.len();
+                set_statements.retain(|set_statement| set_variable(set_statement) != variable);
+                return set_statements.len() < count;
+            }
+        }
+        true
+    }
+
+    /// Forgets the default schema and the SET statements
+    pub fn reset(&self) {
+        let mut session = self.0.lock().unwrap();
+        session.default_schema = None;
+        session.set_statements.clear();
+    }
+
+    pub fn add_warning(&self, message: impl Into<String>) {
+        let warnings = &mut self.0.lock().unwrap().warnings;
+        if warnings.len() >= MAX_WARNINGS {
+            return;
+        }
+        warnings.push(Warning {
+            code: UNKNOWN_ERROR_CODE,
+            message: message.into(),
+        });
+    }
+
+    pub fn warnings(&self) -> Vec<Warning> {
+        self.0.lock().unwrap().warnings.clone()
+    }
+
+    /// Warnings only belong to the statement that generated them
+    pub fn clear_warnings(&self) {
+        self.0.lock().unwrap().warnings.clear();
+    }
+
+    pub fn add_task(&self, id: u64, query: &str) {
+        let tasks = &mut self.0.lock().unwrap().audit.tasks;
+        tasks.push((id, query.to_string()));
+    }
+
+    pub fn set_cached(&self) {
+        self.0.lock().unwrap().audit.cached = true;
+    }
+
+    /// Takes what was recorded since the last call
+    pub fn take_audit(&self) -> QueryAudit {
+        std::mem::take(&mut self.0.lock().unwrap().audit)
+    }
+
+    /// Statements that recreate this session before running a query
+    pub fn statements(&self) -> Vec<String> {
+        let session = self.0.lock().unwrap();
+        session
+            .default_schema
+            .iter()
+            .map(|schema| format!("USE {}", backquoted(schema)))
+            .chain(session.set_statements.iter().cloned())
+            .collect()
+    }
+
+    /// Statements sent to the target before each query. The `SQL_SELECT_LIMIT` is applied by the
+    /// row guard to the selects of the client alone, so the queries of the type discovery are not
+    /// limited by the target.
+    pub fn target_statements(&self) -> Vec<String> {
+        self.statements()
+            .into_iter()
+            .filter(|statement| set_variable(statement) != "sql_select_limit")
+            .collect()
+    }
+
+    /// Identifies the session in cache keys. It is empty for a new session.
+    pub fn cache_key(&self) -> String {
+        self.statements().iter().join("; ")
+    }
+}
+
+/// Scopes that can be written before the variables of a SET statement
+const SET_MODIFIERS: [&str; 5] = ["session", "local", "global", "persist", "persist_only"];
+
+/// Name of the variable of a SET statement, like `sql_mode` for `SET SESSION sql_mode = ''` or
+/// `SET @@global.sql_mode = ''`
+fn set_variable(statement: &str) -> String {
+    let statement = statement.trim().to_lowercase();
+    let assignment = statement
+        .strip_prefix("set")
+        .unwrap_or(&statement)
+        .trim_start();
+    let assignment = SET_MODIFIERS
+        .iter()
+        .find_map(|modifier| {
+            assignment
+                .strip_prefix(modifier)
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+        })
+        .unwrap_or(assignment)
+        .trim_start();
+    let assignment = SET_MODIFIERS
+        .iter()
+        .find_map(|modifier| assignment.strip_prefix(&format!("@@{modifier}.")))
+        .or_else(|| assignment.strip_prefix("@@"))
+        .unwrap_or(assignment);
+    assignment
+        .split(|character: char| character.is_whitespace() || matches!(character, '=' | ':' | ';'))
+        .next()
+        .unwrap_or_default()
+        .trim_matches('`')
+        .to_string()
+}
+
+/// A SET statement per variable of a MySQL statement setting several ones, like
+/// `SET @x = 1, @y = 2`. A scope, like `GLOBAL`, applies to the next variables without one.
+/// Lists that are not all assignments, like `SET search_path TO a, b` of Postgres, are one value.
+fn split_set_statement(statement: &str) -> Vec<String> {
+    let trimmed = statement.trim().trim_end_matches(';');
+    let assignments = match trimmed.get(..3) {
+        Some(set) if set.eq_ignore_ascii_case("set") => &trimmed[3..],
+        _ => return vec![statement.to_string()],
+    };
+    let chars: Vec<char> = assignments.chars().collect();
+    let mut parts = vec![String::new()];
+    let mut depth = 0usize;
+    let mut index = 0;
+    while index < chars.len() {
+        let previous = index.checked_sub(1).map(|previous| chars[previous]);
+        if let Some(length) = literal_length(&chars[index..], previous, SqlDialect::MySql) {
+            parts
+                .last_mut()
+                .unwrap()
+                .extend(&chars[index..index + length]);
+            index += length;
+            continue;
+        }
+        match chars[index] {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(String::new());
+                index += 1;
+                continue;
+            }
+            _ => {}
+        }
+        parts.last_mut().unwrap().push(chars[index]);
+        index += 1;
+    }
+    if parts.len() == 1 || !parts.iter().all(|part| part.contains('=')) {
+        return vec![statement.to_string()];
+    }
+    let mut scope: Option<String> = None;
+    parts
+        .iter()
+        .map(|part| {
+            let part = part.trim();
+            let word = part.split_whitespace().next().unwrap_or_default();
+            if SET_MODIFIERS.contains(&word.to_lowercase().as_str()) {
+                scope = Some(word.to_string());
+                return format!("SET {part}");
+            }
+            match &scope {
+                Some(scope) => format!("SET {scope} {part}"),
+                None => format!("SET {part}"),
+            }
+        })
+        .collect()
+}
+
+/// `SET name = DEFAULT` of MySQL and `SET name TO DEFAULT` of Postgres
+fn is_set_to_default(statement: &str) -> bool {
+    let statement = statement.trim().trim_end_matches(';').to_lowercase();
+    match statement.strip_suffix("default") {
+        Some(assignment) => {
+            let assignment = assignment.trim_end();
+            assignment.ends_with('=') || assignment.ends_with(" to")
+        }
+        None => false,
+    }
+}
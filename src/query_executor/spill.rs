@@ -0,0 +1,100 @@
+use super::{Row, Rows};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+
+/// Rows that are kept in memory until they are approximately bigger than the limit. After it,
+/// every row is written to a temporary file instead. The file has no name, so other processes can
+/// not open it, and it is removed when it is closed.
+pub struct RowBuffer {
+    rows: Vec<Row>,
+    bytes: usize,
+    limit: Option<usize>,
+    spilled: Option<BufWriter<File>>,
+    len: usize,
+}
+
+impl RowBuffer {
+    /// Without a limit, every row is kept in memory
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            rows: Vec::new(),
+            bytes: 0,
+            limit,
+            spilled: None,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, row: Row, size: usize) -> Result<()> {
+        self.len += 1;
+        if let Some(writer) = &mut self.spilled {
+            return write_row(writer, &row);
+        }
+        self.bytes += size;
+        self.rows.push(row);
+        if matches!(self.limit, Some(limit) if self.bytes > limit) {
+            println!("Spilling {} rows to a temporary file", self.rows.len());
+            let mut writer = BufWriter::new(tempfile::tempfile()?);
+            for row in self.rows.drain(..) {
+                write_row(&mut writer, &row)?;
+            }
+            self.spilled = Some(writer);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The rows in the order they were pushed. The temporary file is removed after they are read.
+    pub fn into_rows(self) -> Result<Rows> {
+        let writer = match self.spilled {
+            Some(writer) => writer,
+            None => return Ok(Box::new(self.rows.into_iter().map(Ok))),
+        };
+        let mut file = writer.into_inner()?;
+        file.seek(SeekFrom::Start(0))?;
+        let lines = BufReader::new(file).lines();
+        Ok(Box::new(
+            lines.map(|line| Ok(serde_json::from_str(&line?)?)),
+        ))
+    }
+
+    pub fn into_vec(self) -> Result<Vec<Row>> {
+        self.into_rows()?.collect()
+    }
+}
+
+fn write_row(writer: &mut BufWriter<File>, row: &Row) -> Result<()> {
+    serde_json::to_writer(&mut *writer, row)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::ColumnValue;
+
+    #[test]
+    fn test_rows_over_the_limit_are_spilled_to_a_file() {
+        let mut buffer = RowBuffer::new(Some(10));
+        for index in 0..5 {
+            buffer
+                .push(vec![ColumnValue::String(format!("row {}", index))], 4)
+                .unwrap();
+        }
+        assert!(buffer.spilled.is_some());
+        assert!(buffer.rows.is_empty());
+        assert_eq!(5, buffer.len());
+        let rows = buffer.into_vec().unwrap();
+        assert_eq!(5, rows.len());
+        assert!(matches!(&rows[4][0], ColumnValue::String(value) if value == "row 4"));
+    }
+}
@@ -0,0 +1,361 @@
+use sqlparser::ast::{
+    Expr, FunctionArg, FunctionArgExpr, JoinConstraint, JoinOperator, ObjectName, Query,
+    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins,
+};
+
+/// Tables of a setting of the listener, like `deny_tables`. They are written as `table`, for a
+/// table of any schema, or `schema.table`, and compared in lowercase.
+#[derive(Clone, Debug, Default)]
+pub struct TableList(Vec<(Option<String>, String)>);
+
+impl TableList {
+    pub fn new(tables: Vec<String>) -> Self {
+        Self(
+            tables
+                .iter()
+                .map(|table| {
+                    let table = table.trim().to_lowercase();
+                    match table.rsplit_once('.') {
+                        Some((schema, table)) => (Some(schema.to_string()), table.to_string()),
+                        None => (None, table),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// If the lowercase table is in the list. Tables without a known schema could be any of the
+    /// listed ones.
+    pub fn contains(&self, schema: Option<&str>, table: &str) -> bool {
+        self.0.iter().any(|(listed_schema, listed_table)| {
+            listed_table == table
+                && match (listed_schema, schema) {
+                    (Some(listed_schema), Some(schema)) => listed_schema == schema,
+                    _ => true,
+                }
+        })
+    }
+}
+
+/// Every table read or written by the statement, including the ones in subqueries and CTEs
+pub fn referenced_tables(statement: &Statement) -> Vec<ObjectName> {
+    let mut tables = Vec::new();
+    match statement {
+        Statement::Query(query) => tables_in_query(query, &mut tables),
+        Statement::Insert {
+            table_name, source, ..
+        } => {
+            tables.push(table_name.clone());
+            tables_in_query(source, &mut tables);
+        }
+        Statement::Update {
+            table,
+            assignments,
+            from,
+            selection,
+            ..
+        } => {
+            tables_in_table_with_joins(table, &mut tables);
+            if let Some(from) = from {
+                tables_in_table_with_joins(from, &mut tables);
+            }
+            for assignment in assignments {
+                tables_in_expr(&assignment.value, &mut tables);
+            }
+            if let Some(selection) = selection {
+                tables_in_expr(selection, &mut tables);
+            }
+        }
+        Statement::Delete {
+            table_name,
+            selection,
+            ..
+        } => {
+            tables.push(table_name.clone());
+            if let Some(selection) = selection {
+                tables_in_expr(selection, &mut tables);
+            }
+        }
+        Statement::CreateView { name, query, .. } => {
+            tables.push(name.clone());
+            tables_in_query(query, &mut tables);
+        }
+        Statement::CreateTable { name, query, .. } => {
+            tables.push(name.clone());
+            if let Some(query) = query {
+                tables_in_query(query, &mut tables);
+            }
+        }
+        Statement::ShowCreate { obj_name, .. } => tables.push(obj_name.clone()),
+        Statement::ShowColumns { table_name, .. } | Statement::ExplainTable { table_name, .. } => {
+            tables.push(table_name.clone())
+        }
+        Statement::Explain { statement, .. } => tables.append(&mut referenced_tables(statement)),
+        statement => tables.append(&mut modified_tables(statement)),
+    }
+    tables
+}
+
+/// If `referenced_tables` finds every table of the statement. The tables of other statements, like
+/// `CALL` or `PREPARE`, can only be found in their words.
+pub fn has_known_tables(statement: &Statement) -> bool {
+    match statement {
+        Statement::Explain { statement, .. } => has_known_tables(statement),
+        statement => matches!(
+            statement,
+            Statement::Query(_)
+                | Statement::Insert { .. }
+                | Statement::Update { .. }
+                | Statement::Delete { .. }
+                | Statement::CreateView { .. }
+                | Statement::CreateTable { .. }
+                | Statement::AlterTable { .. }
+                | Statement::Drop { .. }
+                | Statement::Truncate { .. }
+                | Statement::ShowCreate { .. }
+                | Statement::ShowColumns { .. }
+                | Statement::ExplainTable { .. }
+                | Statement::SetVariable { .. }
+                | Statement::ShowVariable { .. }
+                | Statement::StartTransaction { .. }
+                | Statement::Commit { .. }
+                | Statement::Rollback { .. }
+                | Statement::Use { .. }
+        ),
+    }
+}
+
+/// Tables whose data or structure is changed by the statement
+pub fn modified_tables(statement: &Statement) -> Vec<ObjectName> {
+    match statement {
+        Statement::Insert { table_name, .. }
+        | Statement::Delete { table_name, .. }
+        | Statement::Truncate { table_name, .. } => vec![table_name.clone()],
+        Statement::Update { table, .. } => {
+            let mut tables = Vec::new();
+            tables_in_table_factor(&table.relation, &mut tables);
+            tables
+        }
+        Statement::CreateTable { name, .. }
+        | Statement::CreateView { name, .. }
+        | Statement::AlterTable { name, .. } => vec![name.clone()],
+        Statement::Drop { names, .. } => names.clone(),
+        _ => vec![],
+    }
+}
+
+/// The unqualified, lowercase name of the table
+pub fn table_name(name: &ObjectName) -> String {
+    name.0
+        .last()
+        .map(|ident| ident.value.to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Subqueries of the expression, in any of its operands, function arguments or `CASE` branches.
+/// Subqueries nested in those subqueries are not included.
+pub fn subqueries_in_expr<'a>(expr: &'a Expr, queries: &mut Vec<&'a Query>) {
+    match expr {
+        Expr::Subquery(subquery) | Expr::Exists { subquery, .. } => queries.push(subquery),
+        Expr::InSubquery { expr, subquery, .. } => {
+            subqueries_in_expr(expr, queries);
+            queries.push(subquery);
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            subqueries_in_expr(left, queries);
+            subqueries_in_expr(right, queries);
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::TryCast { expr, .. }
+        | Expr::Substring { expr, .. }
+        | Expr::Trim { expr, .. }
+        | Expr::Extract { expr, .. } => subqueries_in_expr(expr, queries),
+        Expr::InList { expr, list, .. } => {
+            subqueries_in_expr(expr, queries);
+            for expr in list {
+                subqueries_in_expr(expr, queries);
+            }
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            for expr in [expr, low, high] {
+                subqueries_in_expr(expr, queries);
+            }
+        }
+        Expr::Function(function) => {
+            for arg in &function.args {
+                match arg {
+                    FunctionArg::Named {
+                        arg: FunctionArgExpr::Expr(expr),
+                        ..
+                    }
+                    | FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => {
+                        subqueries_in_expr(expr, queries)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            for expr in operand.iter().chain(else_result) {
+                subqueries_in_expr(expr, queries);
+            }
+            for expr in conditions.iter().chain(results) {
+                subqueries_in_expr(expr, queries);
+            }
+        }
+        Expr::Tuple(exprs) => {
+            for expr in exprs {
+                subqueries_in_expr(expr, queries);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Condition of the join, like `a.id = b.id` in `JOIN b ON a.id = b.id`
+pub fn join_condition(join_operator: &JoinOperator) -> Option<&Expr> {
+    match join_operator {
+        JoinOperator::Inner(JoinConstraint::On(expr))
+        | JoinOperator::LeftOuter(JoinConstraint::On(expr))
+        | JoinOperator::RightOuter(JoinConstraint::On(expr))
+        | JoinOperator::FullOuter(JoinConstraint::On(expr)) => Some(expr),
+        _ => None,
+    }
+}
+
+fn tables_in_query(query: &Query, tables: &mut Vec<ObjectName>) {
+    if let Some(with) = &query.with {
+        for cte in &with.cte_tables {
+            tables_in_query(&cte.query, tables);
+        }
+    }
+    tables_in_set_expr(&query.body, tables);
+    for order_by in &query.order_by {
+        tables_in_expr(&order_by.expr, tables);
+    }
+}
+
+fn tables_in_set_expr(set_expr: &SetExpr, tables: &mut Vec<ObjectName>) {
+    match set_expr {
+        SetExpr::Select(select) => {
+            for table_with_joins in &select.from {
+                tables_in_table_with_joins(table_with_joins, tables);
+            }
+            for projection in &select.projection {
+                match projection {
+                    SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                        tables_in_expr(expr, tables)
+                    }
+                    _ => {}
+                }
+            }
+            for expr in select
+                .selection
+                .iter()
+                .chain(&select.group_by)
+                .chain(&select.having)
+            {
+                tables_in_expr(expr, tables);
+            }
+        }
+        SetExpr::Query(query) => tables_in_query(query, tables),
+        SetExpr::SetOperation { left, right, .. } => {
+            tables_in_set_expr(left, tables);
+            tables_in_set_expr(right, tables);
+        }
+        _ => {}
+    }
+}
+
+fn tables_in_table_with_joins(table_with_joins: &TableWithJoins, tables: &mut Vec<ObjectName>) {
+    tables_in_table_factor(&table_with_joins.relation, tables);
+    for join in &table_with_joins.joins {
+        tables_in_table_factor(&join.relation, tables);
+        if let Some(condition) = join_condition(&join.join_operator) {
+            tables_in_expr(condition, tables);
+        }
+    }
+}
+
+fn tables_in_table_factor(table_factor: &TableFactor, tables: &mut Vec<ObjectName>) {
+    match table_factor {
+        TableFactor::Table { name, .. } => tables.push(name.clone()),
+        TableFactor::Derived { subquery, .. } => tables_in_query(subquery, tables),
+        TableFactor::NestedJoin(table_with_joins) => {
+            tables_in_table_with_joins(table_with_joins, tables)
+        }
+        TableFactor::TableFunction { expr, .. } => tables_in_expr(expr, tables),
+    }
+}
+
+fn tables_in_expr(expr: &Expr, tables: &mut Vec<ObjectName>) {
+    let mut subqueries = Vec::new();
+    subqueries_in_expr(expr, &mut subqueries);
+    for subquery in subqueries {
+        tables_in_query(subquery, tables);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sqlparser::dialect::MySqlDialect;
+    use sqlparser::parser::Parser;
+
+    fn parse(query: &str) -> Statement {
+        Parser::parse_sql(&MySqlDialect {}, query)
+            .unwrap()
+            .remove(0)
+    }
+
+    fn names(tables: Vec<ObjectName>) -> Vec<String> {
+        tables.iter().map(table_name).collect()
+    }
+
+    #[test]
+    fn test_referenced_tables() {
+        let statement = parse(
+            "select a.id, (select max(id) from c) from a join b.b on a.id = b.id where a.id in (select id from d)",
+        );
+        assert_eq!(
+            vec!["a", "b", "c", "d"],
+            names(referenced_tables(&statement))
+        );
+    }
+
+    #[test]
+    fn test_modified_tables() {
+        let statement = parse("update users set name = 'a' where id in (select id from other)");
+        assert_eq!(vec!["users"], names(modified_tables(&statement)));
+        let statement = parse("delete from app.Payments where id = 1");
+        assert_eq!(vec!["payments"], names(modified_tables(&statement)));
+        let statement = parse("select * from users");
+        assert!(modified_tables(&statement).is_empty());
+    }
+
+    #[test]
+    fn test_table_list() {
+        let tables = TableList::new(vec![" App.Secrets ".to_string(), "audit".to_string()]);
+        assert!(tables.contains(Some("app"), "secrets"));
+        assert!(tables.contains(None, "secrets"));
+        assert!(!tables.contains(Some("other"), "secrets"));
+        assert!(tables.contains(Some("other"), "audit"));
+        assert!(!tables.contains(None, "users"));
+        assert!(TableList::default().is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use super::{ColumnValue, Columns, QueryExecutor, QueryResult, Row, Rows, SessionState};
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Guesses the types of the columns of results from their first rows, for targets without
+/// `with_type_discovery`. Values that do not fit the guessed type are answered as text.
+pub struct QueryTypeSniffer<T> {
+    executor: T,
+    sample_rows: usize,
+    session_state: SessionState,
+}
+
+impl<T> QueryTypeSniffer<T> {
+    /// Without sample rows, results are answered untyped
+    pub fn new(executor: T, sample_rows: usize, session_state: SessionState) -> Self {
+        Self {
+            executor,
+            sample_rows,
+            session_state,
+        }
+    }
+}
+
+impl<T, R> QueryExecutor for QueryTypeSniffer<T>
+where
+    T: QueryExecutor<QueryResult = R>,
+    R: QueryResult,
+{
+    type QueryResult = SniffedResult;
+
+    fn query(&mut self, query: &str) -> Result<Option<Self::QueryResult>> {
+        let result = match self.executor.query(query)? {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+        let (columns, mut rows) = result.get_data();
+        let mut columns = match columns {
+            Ok(columns) if self.sample_rows > 0 => columns,
+            columns => return Ok(Some(SniffedResult { columns, rows })),
+        };
+        let sample: Vec<Result<Row>> = rows.by_ref().take(self.sample_rows).collect();
+        let types: Vec<Option<&'static str>> = (0..columns.len())
+            .map(|index| {
+                sample
+                    .iter()
+                    .filter_map(|row| match row.as_ref().ok()?.get(index)? {
+                        ColumnValue::String(value) => Some(guess_type(value)),
+                        _ => None,
+                    })
+                    .reduce(merge_guesses)
+                    .filter(|ty| *ty != "text")
+            })
+            .collect();
+        if types.iter().any(Option::is_some) {
+            println!(
+                "Column types guessed from {} rows: {:?}",
+                sample.len(),
+                types
+            );
+            self.session_state.add_warning(format!(
+                "Column types were guessed from the first {} rows of the result",
+                sample.len()
+            ));
+        }
+        for (column, ty) in columns.iter_mut().zip(&types) {
+            match ty {
+                Some("bigint unsigned") => {
+                    column.ty = Some("bigint".to_string());
+                    column.attributes.unsigned = true;
+                }
+                Some(ty) => column.ty = Some(ty.to_string()),
+                None => {}
+            }
+        }
+        let rows = sample.into_iter().chain(rows).map(move |row| {
+            row.map(|row| {
+                row.into_iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        let ty = types.get(index).copied().flatten();
+                        match (value, ty) {
+                            (ColumnValue::String(value), Some(ty)) => convert(value, ty),
+                            (value, _) => value,
+                        }
+                    })
+                    .collect()
+            })
+        });
+        Ok(Some(SniffedResult {
+            columns: Ok(columns),
+            rows: Box::new(rows),
+        }))
+    }
+}
+
+pub struct SniffedResult {
+    columns: Result<Columns>,
+    rows: Rows,
+}
+
+impl QueryResult for SniffedResult {
+    fn get_data(self) -> (Result<Columns>, Rows) {
+        (self.columns, self.rows)
+    }
+}
+
+/// Numbers with leading zeros, like zip codes, are kept as text
+fn guess_type(value: &str) -> &'static str {
+    let digits = value.trim_start_matches(['-', '+']);
+    let is_number = !digits.is_empty()
+        && digits
+            .chars()
+            .all(|character| character.is_ascii_digit() || ".eE-+".contains(character))
+        && !(digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0."));
+    if is_number && value.parse::<i64>().is_ok() {
+        "bigint"
+    } else if is_number && value.parse::<u64>().is_ok() {
+        // Like auto increment ids near the top of the range of `bigint unsigned`
+        "bigint unsigned"
+    } else if is_number && value.parse::<f64>().is_ok() {
+        "double"
+    } else if NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").is_ok() {
+        "datetime"
+    } else if NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok() {
+        "date"
+    } else {
+        "text"
+    }
+}
+
+fn merge_guesses(left: &'static str, right: &'static str) -> &'static str {
+    match (left, right) {
+        (left, right) if left == right => left,
+        ("bigint", "double") | ("double", "bigint") => "double",
+        // Negative values are answered as text
+        ("bigint", "bigint unsigned") | ("bigint unsigned", "bigint") => "bigint unsigned",
+        ("bigint unsigned", "double") | ("double", "bigint unsigned") => "double",
+        _ => "text",
+    }
+}
+
+fn convert(value: String, ty: &str) -> ColumnValue {
+    let converted = match ty {
+        "bigint" => value.parse().ok().map(ColumnValue::I64),
+        "bigint unsigned" => value.parse().ok().map(ColumnValue::U64),
+        "double" => value.parse().ok().map(ColumnValue::Double),
+        "datetime" => NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M:%S%.f")
+            .ok()
+            .map(ColumnValue::DateTime),
+        "date" => NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+            .ok()
+            .map(ColumnValue::Date),
+        _ => None,
+    };
+    converted.unwrap_or(ColumnValue::String(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::test::{reader_result, FakeQueryExecutor};
+
+    #[test]
+    fn test_types_are_guessed_from_the_first_rows() {
+        let executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(
+            "id\ttotal\tzip\tcreated\tnote\n\
+            1\t10\t01234\t2022-01-02\tNULL\n\
+            2\t10.5\t12345\t2022-01-03\tNULL\n\
+            x\t3\t23456\t2022-01-04\tNULL\n",
+        )))]);
+        let session_state = SessionState::default();
+        let mut sniffer = QueryTypeSniffer::new(executor, 2, session_state.clone());
+        let (columns, rows) = sniffer.query("select").unwrap().unwrap().get_data();
+        let types: Vec<Option<String>> = columns.unwrap().into_iter().map(|c| c.ty).collect();
+        assert_eq!(
+            vec![
+                Some("bigint".to_string()),
+                Some("double".to_string()),
+                None,
+                Some("date".to_string()),
+                None
+            ],
+            types
+        );
+        let rows: Vec<Row> = rows.map(Result::unwrap).collect();
+        assert!(matches!(rows[0][0], ColumnValue::I64(1)));
+        assert!(matches!(rows[1][1], ColumnValue::Double(total) if total == 10.5));
+        assert!(matches!(&rows[2][0], ColumnValue::String(id) if id == "x"));
+        assert!(matches!(rows[2][4], ColumnValue::Null));
+        assert_eq!(1, session_state.warnings().len());
+    }
+
+    #[test]
+    fn test_ids_above_the_range_of_bigint_are_unsigned() {
+        let executor = FakeQueryExecutor::new(vec![Ok(Some(reader_result(
+            "id
+1
+18446744073709551615
+",
+        )))]);
+        let mut sniffer = QueryTypeSniffer::new(executor, 2, SessionState::default());
+        let (columns, rows) = sniffer.query("select").unwrap().unwrap().get_data();
+        let column = columns.unwrap().remove(0);
+        assert_eq!(Some("bigint".to_string()), column.ty);
+        assert!(column.attributes.unsigned);
+        let rows: Vec<Row> = rows.map(Result::unwrap).collect();
+        assert!(matches!(rows[0][0], ColumnValue::U64(1)));
+        assert!(matches!(rows[1][0], ColumnValue::U64(u64::MAX)));
+    }
+}
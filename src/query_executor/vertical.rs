@@ -0,0 +1,155 @@
+use super::{Column, ColumnAttributes, ColumnValue, Columns, Rows};
+use anyhow::Result;
+use std::io::BufRead;
+
+type Record = Vec<(String, String)>;
+
+/// If the result starts like the vertical output of mysql (`\G` or `--vertical`) or the expanded
+/// output of psql, instead of having a header with the columns.
+pub fn is_vertical(start: &[u8]) -> bool {
+    String::from_utf8_lossy(start)
+        .lines()
+        .find(|line| *line != "SET")
+        .is_some_and(|line| record_mark(line).is_some())
+}
+
+/// Reads results with one `name: value` line per column of each row, for mysql, or `name | value`,
+/// for psql. The columns are the ones of the first row. The whole result is read at once.
+pub fn get_data(reader: Box<dyn BufRead + Send>, null_token: String) -> (Result<Columns>, Rows) {
+    let records = match read_records(reader) {
+        Ok(records) => records,
+        Err(error) => return (Err(error), Box::new(std::iter::empty())),
+    };
+    let names: Vec<String> = records
+        .first()
+        .map(|record| record.iter().map(|(name, _)| name.clone()).collect())
+        .unwrap_or_default();
+    let columns = names
+        .iter()
+        .map(|name| Column {
+            name: name.clone(),
+            ty: None,
+            attributes: ColumnAttributes::default(),
+        })
+        .collect();
+    let rows = records.into_iter().map(move |record| {
+        Ok(names
+            .iter()
+            .map(|name| {
+                let value = record.iter().find(|(field, _)| field == name);
+                match value {
+                    Some((_, value)) if *value != null_token => ColumnValue::String(value.clone()),
+                    _ => ColumnValue::Null,
+                }
+            })
+            .collect())
+    });
+    (Ok(columns), Box::new(rows))
+}
+
+/// The character between the names and the values of the rows that start with this line
+fn record_mark(line: &str) -> Option<char> {
+    if line.starts_with("***") && line.contains(". row ***") {
+        Some(':')
+    } else if line.starts_with("-[ RECORD ") {
+        Some('|')
+    } else {
+        None
+    }
+}
+
+fn read_records(reader: Box<dyn BufRead>) -> Result<Vec<Record>> {
+    let mut records: Vec<Record> = Vec::new();
+    let mut mark = ':';
+    // Names are padded, so the mark is at the same position in every line of a row
+    let mut mark_position = None;
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(record_mark) = record_mark(&line) {
+            records.push(Vec::new());
+            mark = record_mark;
+            mark_position = None;
+            continue;
+        }
+        let record = match records.last_mut() {
+            Some(record) if !line.is_empty() => record,
+            _ => continue,
+        };
+        let position = *mark_position.get_or_insert_with(|| find_mark(&line, mark));
+        let field = position.and_then(|position| {
+            let name = line.get(..position)?.trim();
+            let value = line.get(position..)?.strip_prefix(mark)?;
+            (!name.is_empty()).then(|| (name, value.strip_prefix(' ').unwrap_or(value)))
+        });
+        match (field, record.last_mut()) {
+            (Some((name, value)), _) => record.push((name.to_string(), value.to_string())),
+            // Values of mysql with new lines continue in the next lines
+            (None, Some((_, value))) => {
+                value.push('\n');
+                value.push_str(&line);
+            }
+            (None, None) => {}
+        }
+    }
+    Ok(records)
+}
+
+fn find_mark(line: &str, mark: char) -> Option<usize> {
+    match mark {
+        ':' => line
+            .find(": ")
+            .or_else(|| line.strip_suffix(':').map(str::len)),
+        _ => line
+            .find(" | ")
+            .map(|position| position + 1)
+            .or_else(|| line.strip_suffix(" |").map(|name| name.len() + 1)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_executor::Row;
+    use std::io::{BufReader, Cursor};
+
+    fn rows(output: &str, null_token: &str) -> (Vec<String>, Vec<Row>) {
+        assert!(is_vertical(output.as_bytes()));
+        let reader = Box::new(BufReader::new(Cursor::new(output.to_string())));
+        let (columns, rows) = get_data(reader, null_token.to_string());
+        let names = columns.unwrap().into_iter().map(|c| c.name).collect();
+        (names, rows.map(Result::unwrap).collect())
+    }
+
+    #[test]
+    fn test_vertical_output_of_mysql_and_psql() {
+        let (names, mysql_rows) = rows(
+            "*************************** 1. row ***************************\n\
+            \x20    id: 1\n\
+            note_id: NULL\n\
+            *************************** 2. row ***************************\n\
+            \x20    id: 2\n\
+            note_id: a: b\n\
+            second line\n",
+            "NULL",
+        );
+        assert_eq!(vec!["id".to_string(), "note_id".to_string()], names);
+        assert!(matches!(&mysql_rows[0][0], ColumnValue::String(id) if id == "1"));
+        assert!(matches!(mysql_rows[0][1], ColumnValue::Null));
+        assert!(
+            matches!(&mysql_rows[1][1], ColumnValue::String(note) if note == "a: b\nsecond line")
+        );
+        let (names, psql_rows) = rows(
+            "-[ RECORD 1 ]-\n\
+            id      | 1\n\
+            note_id | a | b\n\
+            -[ RECORD 2 ]-\n\
+            id      | 2\n\
+            note_id |\n",
+            "",
+        );
+        assert_eq!(vec!["id".to_string(), "note_id".to_string()], names);
+        assert!(matches!(&psql_rows[0][1], ColumnValue::String(note) if note == "a | b"));
+        assert!(matches!(psql_rows[1][1], ColumnValue::Null));
+        assert!(!is_vertical(b"id\tnote\n1\t*** 1. row ***\n"));
+    }
+}